@@ -3,6 +3,11 @@ use std::io::Result;
 fn main() -> Result<()> {
   let mut compile_config = prost_build::Config::default();
   compile_config.type_attribute(".", "#[derive(typed_builder::TypedBuilder)]");
+  // the generated builder of Profile would clash with pprof::ProfileBuilder
+  compile_config.type_attribute(
+    ".pprof.profiles.Profile",
+    "#[builder(builder_type(name = ProfileTypedBuilder))]",
+  );
   compile_config.compile_protos(&["src/profile.proto"], &["src/"])?;
   Ok(())
 }
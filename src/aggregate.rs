@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use fst_native::FstSignalHandle;
+use serde::{Deserialize, Serialize};
+
+use crate::window::TimeWindow;
+use crate::SignalLookup;
+
+/// A summary of a 1-bit signal over the time window, emitted in its own sample type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+  /// Time the signal is high, in nanoseconds
+  Duration,
+  /// Number of times the signal changes value
+  Toggles,
+  /// Share of the window the signal is high, in rounded percents
+  DutyCycle,
+}
+
+impl Aggregation {
+  /// Every aggregation, in the order their sample types are added to a profile.
+  pub const ALL: [Aggregation; 3] = [Self::Duration, Self::Toggles, Self::DutyCycle];
+
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Self::Duration => "duration",
+      Self::Toggles => "toggles",
+      Self::DutyCycle => "duty_cycle",
+    }
+  }
+
+  /// Return the type and the unit of the sample values of this aggregation.
+  pub fn sample_type(self) -> (&'static str, &'static str) {
+    match self {
+      Self::Duration => ("high", "nanoseconds"),
+      Self::Toggles => ("toggles", "count"),
+      Self::DutyCycle => ("duty_cycle", "percent"),
+    }
+  }
+}
+
+/// An aggregation of the signal given by its full dot separated path.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AggregationConfig {
+  pub signal: String,
+  pub aggregation: Aggregation,
+}
+
+/// An aggregation whose signal is resolved to a handle.
+#[derive(Debug, Clone)]
+pub struct Aggregate {
+  pub signal: String,
+  pub handle: FstSignalHandle,
+  pub aggregation: Aggregation,
+}
+
+/// Find the signal of every aggregation in the hierarchy. Every signal must exist and be 1-bit.
+pub fn resolve(
+  signals: &SignalLookup,
+  configs: &[AggregationConfig],
+) -> anyhow::Result<Vec<Aggregate>> {
+  configs
+    .iter()
+    .map(|c| -> anyhow::Result<Aggregate> {
+      let path = &c.signal;
+      let (handle, width) = signals
+        .find(path)
+        .with_context(|| format!("aggregated signal {path} is not found"))?;
+      anyhow::ensure!(
+        width == 1,
+        "aggregated signal {path} should be 1-bit, got {width} bits"
+      );
+      Ok(Aggregate {
+        signal: path.clone(),
+        handle,
+        aggregation: c.aggregation,
+      })
+    })
+    .collect()
+}
+
+/// Return the aggregations used by `aggregates`, in the order of [`Aggregation::ALL`].
+pub fn kinds(aggregates: &[Aggregate]) -> Vec<Aggregation> {
+  Aggregation::ALL
+    .into_iter()
+    .filter(|kind| aggregates.iter().any(|a| a.aggregation == *kind))
+    .collect()
+}
+
+/// Latest value, high time and toggles of an aggregated signal.
+#[derive(Debug, Clone, Default)]
+struct Tracked {
+  value: Option<String>,
+  high_since: Option<u64>,
+  high: u64,
+  toggles: i64,
+}
+
+/// Sum the high time and the toggles of the aggregated signals inside the window.
+#[derive(Debug, Clone)]
+pub struct Aggregator<'a> {
+  aggregates: &'a [Aggregate],
+  window: TimeWindow,
+  /// Bounds of the window, within the waveform time range
+  start: u64,
+  end: u64,
+  /// Every aggregated signal, by handle index
+  signals: HashMap<usize, Tracked>,
+}
+
+impl<'a> Aggregator<'a> {
+  /// Aggregate over `window`, whose bounds within the waveform time range are `start..end`.
+  pub fn new(aggregates: &'a [Aggregate], window: TimeWindow, (start, end): (u64, u64)) -> Self {
+    let signals = aggregates
+      .iter()
+      .map(|a| (a.handle.get_index(), Tracked::default()))
+      .collect();
+    Self {
+      aggregates,
+      window,
+      start,
+      end,
+      signals,
+    }
+  }
+
+  /// Move the end of the window bounds, for a waveform still growing.
+  pub fn extend(&mut self, end: u64) {
+    self.end = end;
+  }
+
+  /// Return the handles of all the aggregated signals.
+  pub fn handles(&self) -> Vec<FstSignalHandle> {
+    self.aggregates.iter().map(|a| a.handle).collect()
+  }
+
+  /// Feed a value change at time `t`.
+  pub fn update(&mut self, t: u64, handle: FstSignalHandle, value: &str) {
+    let Some(tracked) = self.signals.get_mut(&handle.get_index()) else {
+      return;
+    };
+    let prev = tracked.value.replace(value.to_string());
+    if prev.is_some_and(|prev| prev != value) && self.window.contains(t) {
+      tracked.toggles += 1;
+    }
+    match (value == "1", tracked.high_since) {
+      (true, None) => tracked.high_since = Some(t),
+      (false, Some(since)) => {
+        tracked.high += t.min(self.end).saturating_sub(since.max(self.start));
+        tracked.high_since = None;
+      }
+      _ => (),
+    }
+  }
+
+  /// Return the value of every aggregation of each aggregated signal at the end of the window,
+  /// with the first aggregate of the signal, in config order.
+  pub fn finish(mut self) -> Vec<(&'a Aggregate, Vec<(Aggregation, i64)>)> {
+    let (start, end) = (self.start, self.end);
+    for tracked in self.signals.values_mut() {
+      if let Some(since) = tracked.high_since.take() {
+        tracked.high += end.saturating_sub(since.max(start));
+      }
+    }
+    let mut signals: Vec<(&'a Aggregate, Vec<(Aggregation, i64)>)> = Vec::new();
+    for a in self.aggregates {
+      let tracked = &self.signals[&a.handle.get_index()];
+      let value = match a.aggregation {
+        Aggregation::Duration => tracked.high as i64,
+        Aggregation::Toggles => tracked.toggles,
+        Aggregation::DutyCycle if end > start => {
+          (tracked.high as f64 * 100.0 / (end - start) as f64).round() as i64
+        }
+        Aggregation::DutyCycle => 0,
+      };
+      match signals
+        .iter_mut()
+        .find(|(first, _)| first.handle == a.handle)
+      {
+        Some((_, values)) => values.push((a.aggregation, value)),
+        None => signals.push((a, vec![(a.aggregation, value)])),
+      }
+    }
+    signals
+  }
+}
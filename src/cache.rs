@@ -0,0 +1,309 @@
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::Context;
+use fst_native::FstSignalHandle;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::wave::{HierarchyEntry, SignalValue, WaveHeader, Waveform};
+
+/// Identify one version of a waveform file by its size and modification time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct FileStamp {
+  len: u64,
+  mtime_secs: u64,
+  mtime_nanos: u32,
+}
+
+impl FileStamp {
+  fn of(path: &Path) -> anyhow::Result<Self> {
+    let metadata =
+      std::fs::metadata(path).with_context(|| format!("fail to stat {}", path.display()))?;
+    let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+    Ok(Self {
+      len: metadata.len(),
+      mtime_secs: mtime.as_secs(),
+      mtime_nanos: mtime.subsec_nanos(),
+    })
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum CachedEntry {
+  Scope(String),
+  UpScope,
+  Var {
+    name: String,
+    length: u32,
+    handle: usize,
+  },
+  EnumTable {
+    handle: u64,
+    mapping: Vec<(String, String)>,
+  },
+  EnumTableRef(u64),
+}
+
+/// The on disk cache: the hierarchy of a waveform file and the stamp of that file.
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+  stamp: FileStamp,
+  entries: Vec<CachedEntry>,
+}
+
+/// Wrap a waveform and serve its hierarchy from a cache file, so the hierarchy walk can be
+/// skipped on subsequent runs over the same file. The cache is rebuilt when the size or the
+/// modification time of the waveform file changes. Without a cache path the hierarchy is only
+/// kept in memory, so the selections of one run, like the signals of every profile, still walk
+/// the waveform once.
+pub struct HierarchyCache<W> {
+  inner: W,
+  cache_path: Option<PathBuf>,
+  stamp: Option<FileStamp>,
+  entries: Option<Vec<HierarchyEntry>>,
+}
+
+impl<W: Waveform> HierarchyCache<W> {
+  /// Wrap `inner`, which is read from `wave_path`, using the cache at `cache_path` if any.
+  pub fn new(inner: W, wave_path: &str, cache_path: Option<&str>) -> anyhow::Result<Self> {
+    let Some(cache_path) = cache_path else {
+      return Ok(Self {
+        inner,
+        cache_path: None,
+        stamp: None,
+        entries: None,
+      });
+    };
+
+    let stamp = FileStamp::of(Path::new(wave_path))?;
+    let entries = match load(Path::new(cache_path)) {
+      Ok(cache) if cache.stamp == stamp => {
+        info!("Reusing hierarchy cache {cache_path}");
+        Some(
+          cache
+            .entries
+            .into_iter()
+            .map(HierarchyEntry::from)
+            .collect(),
+        )
+      }
+      Ok(_) => {
+        info!("Hierarchy cache {cache_path} is stale, rebuilding");
+        None
+      }
+      Err(err) => {
+        info!("No usable hierarchy cache at {cache_path}: {err:#}");
+        None
+      }
+    };
+
+    Ok(Self {
+      inner,
+      cache_path: Some(PathBuf::from(cache_path)),
+      stamp: Some(stamp),
+      entries,
+    })
+  }
+}
+
+fn load(path: &Path) -> anyhow::Result<CacheFile> {
+  let raw = std::fs::read(path)?;
+  Ok(serde_json::from_slice(&raw)?)
+}
+
+impl From<CachedEntry> for HierarchyEntry {
+  fn from(entry: CachedEntry) -> Self {
+    match entry {
+      CachedEntry::Scope(name) => HierarchyEntry::Scope { name },
+      CachedEntry::UpScope => HierarchyEntry::UpScope,
+      CachedEntry::Var {
+        name,
+        length,
+        handle,
+      } => HierarchyEntry::Var {
+        name,
+        length,
+        handle: FstSignalHandle::from_index(handle),
+      },
+      CachedEntry::EnumTable { handle, mapping } => HierarchyEntry::EnumTable { handle, mapping },
+      CachedEntry::EnumTableRef(handle) => HierarchyEntry::EnumTableRef { handle },
+    }
+  }
+}
+
+impl From<&HierarchyEntry> for CachedEntry {
+  fn from(entry: &HierarchyEntry) -> Self {
+    match entry {
+      HierarchyEntry::Scope { name } => CachedEntry::Scope(name.clone()),
+      HierarchyEntry::UpScope => CachedEntry::UpScope,
+      HierarchyEntry::Var {
+        name,
+        length,
+        handle,
+      } => CachedEntry::Var {
+        name: name.clone(),
+        length: *length,
+        handle: handle.get_index(),
+      },
+      HierarchyEntry::EnumTable { handle, mapping } => CachedEntry::EnumTable {
+        handle: *handle,
+        mapping: mapping.clone(),
+      },
+      HierarchyEntry::EnumTableRef { handle } => CachedEntry::EnumTableRef(*handle),
+    }
+  }
+}
+
+impl<W: Waveform> Waveform for HierarchyCache<W> {
+  fn header(&self) -> WaveHeader {
+    self.inner.header()
+  }
+
+  fn for_each_hierarchy(&mut self, mut callback: impl FnMut(HierarchyEntry)) -> anyhow::Result<()> {
+    if let Some(entries) = &self.entries {
+      entries.iter().cloned().for_each(&mut callback);
+      return Ok(());
+    }
+
+    let mut entries = Vec::new();
+    self.inner.for_each_hierarchy(|entry| {
+      entries.push(entry.clone());
+      callback(entry);
+    })?;
+    let (Some(cache_path), Some(stamp)) = (&self.cache_path, self.stamp) else {
+      self.entries = Some(entries);
+      return Ok(());
+    };
+
+    // a broken cache only costs a hierarchy walk next time, so don't fail the run for it
+    let cache = CacheFile {
+      stamp,
+      entries: entries.iter().map(CachedEntry::from).collect(),
+    };
+    // later walks of this run are served from memory like the ones of a reused cache
+    self.entries = Some(entries);
+    let written = serde_json::to_vec(&cache)
+      .map_err(anyhow::Error::from)
+      .and_then(|raw| Ok(std::fs::write(cache_path, raw)?));
+    match written {
+      Ok(()) => info!("Hierarchy cached into {}", cache_path.display()),
+      Err(err) => warn!(
+        "fail to write hierarchy cache {}: {err:#}",
+        cache_path.display()
+      ),
+    }
+    Ok(())
+  }
+
+  fn for_each_change(
+    &mut self,
+    handles: &[FstSignalHandle],
+    callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    self.inner.for_each_change(handles, callback)
+  }
+
+  fn for_each_change_in(
+    &mut self,
+    handles: &[FstSignalHandle],
+    range: std::ops::Range<u64>,
+    callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    self.inner.for_each_change_in(handles, range, callback)
+  }
+}
+
+#[test]
+fn reuse_and_invalidate_hierarchy_cache() {
+  use crate::fixture::FixtureWave;
+  use crate::{collect_signals, CollectOptions};
+
+  let dir = std::env::temp_dir().join(format!("toyfst-cache-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let wave_path = dir.join("wave.fst");
+  let cache_path = dir.join("wave.hier.json");
+  let wave_path = wave_path.to_str().unwrap();
+  let cache_path = cache_path.to_str().unwrap();
+  std::fs::write(wave_path, b"first").unwrap();
+
+  let expected = vec!["valid".to_string()];
+  let mut wave = FixtureWave::new(0, 10);
+  wave.scope("top");
+  let valid = wave.var("valid", 1);
+  wave.upscope();
+
+  // first run walks the hierarchy and writes the cache
+  let mut cached = HierarchyCache::new(wave, wave_path, Some(cache_path)).unwrap();
+  let metadata = collect_signals(&mut cached, &expected, &CollectOptions::default()).unwrap();
+  assert_eq!(metadata.handle, [valid]);
+
+  // second run over an empty hierarchy is served from the cache
+  let empty = FixtureWave::new(0, 10);
+  let mut cached = HierarchyCache::new(empty, wave_path, Some(cache_path)).unwrap();
+  let metadata = collect_signals(&mut cached, &expected, &CollectOptions::default()).unwrap();
+  assert_eq!(metadata.handle, [valid]);
+
+  // changing the file size invalidates the cache
+  std::fs::write(wave_path, b"second run").unwrap();
+  let empty = FixtureWave::new(0, 10);
+  let mut cached = HierarchyCache::new(empty, wave_path, Some(cache_path)).unwrap();
+  let metadata = collect_signals(&mut cached, &expected, &CollectOptions::default()).unwrap();
+  assert!(metadata.handle.is_empty());
+
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn walk_the_hierarchy_once() {
+  use crate::fixture::FixtureWave;
+
+  /// Count the hierarchy walks of the wrapped waveform.
+  struct Walks {
+    inner: FixtureWave,
+    walks: usize,
+  }
+
+  impl Waveform for Walks {
+    fn header(&self) -> WaveHeader {
+      self.inner.header()
+    }
+
+    fn for_each_hierarchy(&mut self, callback: impl FnMut(HierarchyEntry)) -> anyhow::Result<()> {
+      self.walks += 1;
+      self.inner.for_each_hierarchy(callback)
+    }
+
+    fn for_each_change(
+      &mut self,
+      handles: &[FstSignalHandle],
+      callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+    ) -> anyhow::Result<()> {
+      self.inner.for_each_change(handles, callback)
+    }
+  }
+
+  let dir = std::env::temp_dir().join(format!("toyfst-cache-once-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  let wave_path = dir.join("wave.fst");
+  let cache_path = dir.join("wave.hier.json");
+  let wave_path = wave_path.to_str().unwrap();
+  let cache_path = cache_path.to_str().unwrap();
+  std::fs::write(wave_path, b"first").unwrap();
+
+  let mut inner = FixtureWave::new(0, 10);
+  inner.scope("top");
+  inner.var("valid", 1);
+  inner.upscope();
+  let walks = Walks { inner, walks: 0 };
+
+  let mut cached = HierarchyCache::new(walks, wave_path, Some(cache_path)).unwrap();
+  let mut entries = [0; 2];
+  for count in &mut entries {
+    cached.for_each_hierarchy(|_| *count += 1).unwrap();
+  }
+  assert_eq!(entries, [3, 3]);
+  assert_eq!(cached.inner.walks, 1);
+
+  std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,104 @@
+//! Checkpoints of the pprof conversion, so an interrupted extraction resumes where it stopped
+//! instead of starting over.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::properties::Bin;
+use crate::wave::SignalValue;
+
+/// Where and how often the pprof conversion saves its checkpoint.
+#[derive(Debug, Clone)]
+pub struct CheckpointOptions {
+  pub path: String,
+  /// Wall clock time between two checkpoints
+  pub every: Duration,
+}
+
+/// The state of a pprof conversion partway through the value changes.
+///
+/// Only what the conversion accumulates sample by sample is saved. The handshakes, transactions,
+/// state machines, aggregations, cycle clock and trigger are rebuilt on resume by replaying the
+/// changes before `time` without emitting any sample, as they hold little state but would need
+/// every one of their signals saved.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+  /// The changes before this time are converted
+  pub time: u64,
+  /// End of the simulation, full path of every converted signal and fingerprint of the options,
+  /// to refuse the checkpoint of another extraction
+  pub end_time: u64,
+  pub signals: Vec<String>,
+  pub options: u64,
+  /// The profile built so far, encoded with its string table, in base64
+  #[serde(with = "base64_bytes")]
+  pub profile: Vec<u8>,
+  pub latest: Option<u64>,
+  pub backwards: usize,
+  pub last_values: HashMap<usize, String>,
+  pub final_values: Vec<Option<String>>,
+  pub emitted: Vec<usize>,
+  pub capped: Vec<usize>,
+  pub held: HashMap<String, (u64, usize)>,
+  /// Samples held when the trigger stopped holding, and the latest changes since then
+  #[serde(default)]
+  pub paused: BTreeMap<String, usize>,
+  #[serde(default)]
+  pub untriggered: BTreeMap<usize, SignalValue>,
+  pub sampled: HashMap<String, String>,
+  pub coalesced: usize,
+  pub bucket: Option<u64>,
+  pub unknown: Vec<u64>,
+  /// The bins of every histogram with their count, as a list since JSON keys are strings
+  pub histograms: BTreeMap<usize, Vec<(Bin, u64)>>,
+  /// Distinct label values let in under the cap, and values labeled past it
+  #[serde(default)]
+  pub label_values: usize,
+  #[serde(default)]
+  pub capped_values: u64,
+}
+
+impl Checkpoint {
+  pub fn load(path: &str) -> anyhow::Result<Self> {
+    let raw = std::fs::read(path).with_context(|| format!("fail to read checkpoint {path}"))?;
+    serde_json::from_slice(&raw).with_context(|| format!("fail to parse checkpoint {path}"))
+  }
+
+  /// Write the checkpoint next to `path` then move it there, so a crash while writing keeps the
+  /// previous checkpoint.
+  pub fn save(&self, path: &str) -> anyhow::Result<()> {
+    let partial = format!("{path}.partial");
+    let raw = serde_json::to_vec(self)?;
+    std::fs::write(&partial, raw).with_context(|| format!("fail to write {partial}"))?;
+    std::fs::rename(&partial, path).with_context(|| format!("fail to move {partial} to {path}"))
+  }
+}
+
+/// Return the FNV-1a hash of the debug output of `value`. Unlike the std hasher it is the same
+/// from one run to the next, as long as the output has no hash map.
+pub fn fingerprint(value: &impl std::fmt::Debug) -> u64 {
+  format!("{value:?}")
+    .bytes()
+    .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+      (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Bytes saved as a base64 string instead of an array of numbers.
+mod base64_bytes {
+  use base64::engine::general_purpose::STANDARD;
+  use base64::Engine;
+  use serde::{Deserialize, Deserializer, Serializer};
+
+  pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&STANDARD.encode(bytes))
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+  }
+}
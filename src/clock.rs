@@ -0,0 +1,192 @@
+//! Clock driven sampling: one sample per cycle instead of one per value change.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use fst_native::FstSignalHandle;
+
+use crate::edge::Edge;
+use crate::handshake::Sampled;
+use crate::pprof::{self, ProfileBuilder};
+use crate::sink::OutputSink;
+use crate::wave::{SignalValue, WaveHeader};
+use crate::window::TimeWindow;
+use crate::{SignalLookup, SignalMetadata};
+
+/// Find the clock signal at the dot separated `path`, which must be 1-bit.
+pub fn resolve(signals: &SignalLookup, path: &str) -> anyhow::Result<FstSignalHandle> {
+  let (handle, width) = signals
+    .find(path)
+    .with_context(|| format!("clock signal {path} is not found in the waveform"))?;
+  anyhow::ensure!(
+    width == 1,
+    "clock signal {path} should be 1-bit, got {width} bits"
+  );
+  Ok(handle)
+}
+
+/// Builds a profile with one sample per rising edge of a clock.
+///
+/// Each sample is weighted by its cycle number and labeled with the value every signal had right
+/// before the edge, like a flip-flop would sample it.
+pub struct ClockSink {
+  metadata: SignalMetadata,
+  clock: FstSignalHandle,
+  window: TimeWindow,
+  /// Positions in `metadata` of each signal, by handle index
+  index: HashMap<usize, Vec<usize>>,
+  values: HashMap<usize, Sampled>,
+  cycle: i64,
+  builder: ProfileBuilder,
+}
+
+impl ClockSink {
+  pub fn new(
+    header: &WaveHeader,
+    metadata: SignalMetadata,
+    clock: FstSignalHandle,
+    window: TimeWindow,
+  ) -> anyhow::Result<Self> {
+    anyhow::ensure!(
+      header.end_time >= header.start_time,
+      "header end time {} is before start time {}",
+      header.end_time,
+      header.start_time
+    );
+    let mut builder = ProfileBuilder::new();
+    builder.profile.period_type = Some(pprof::ValueType {
+      r#type: builder.str_tbl.id("cycle"),
+      unit: builder.str_tbl.id("number"),
+    });
+    builder.profile.period = 1;
+    let (start, end) = window.clamp(header.start_time, header.end_time);
+    builder.profile.duration_nanos = header.ticks_to_nanos(end - start).try_into()?;
+    builder.profile.sample_type = vec![pprof::ValueType {
+      r#type: builder.str_tbl.id("cycle"),
+      unit: builder.str_tbl.id("number"),
+    }];
+
+    Ok(Self {
+      index: metadata.positions(),
+      metadata,
+      clock,
+      window,
+      values: HashMap::new(),
+      cycle: 0,
+      builder,
+    })
+  }
+
+  pub fn finish(self) -> pprof::Profile {
+    self.builder.finish()
+  }
+
+  fn before(&self, handle: FstSignalHandle, t: u64) -> Option<&str> {
+    self
+      .values
+      .get(&handle.get_index())
+      .and_then(|sampled| sampled.before(t))
+  }
+}
+
+impl OutputSink for ClockSink {
+  fn handles(&self) -> Vec<FstSignalHandle> {
+    let mut handles = self.metadata.handle.clone();
+    if !handles.contains(&self.clock) {
+      handles.push(self.clock);
+    }
+    handles
+  }
+
+  fn change(&mut self, t: u64, handle: FstSignalHandle, value: &SignalValue) {
+    let value = match value {
+      SignalValue::String(s) => s.clone(),
+      SignalValue::Real(r) => r.to_string(),
+    };
+
+    let rising = handle == self.clock
+      && self
+        .before(handle, t)
+        .and_then(|prev| Edge::classify(prev, &value))
+        == Some(Edge::Pos);
+    if rising && self.window.contains(t) {
+      self.cycle += 1;
+      let values: Vec<_> = (0..self.metadata.handle.len())
+        .filter_map(|i| {
+          let value = self.before(self.metadata.handle[i], t)?;
+          let value = match self.metadata.slices[i] {
+            Some(slice) => slice.extract(value),
+            None => value.to_string(),
+          };
+          Some((self.metadata.full_name(i), value))
+        })
+        .collect();
+      let label = values
+        .iter()
+        .map(|(name, value)| self.builder.label(name, value))
+        .collect();
+      let location_id = self.builder.stack(&["cycle"]);
+      self.builder.profile.sample.push(pprof::Sample {
+        location_id,
+        value: vec![self.cycle],
+        label,
+      });
+    }
+
+    if handle == self.clock || self.index.contains_key(&handle.get_index()) {
+      match self.values.get_mut(&handle.get_index()) {
+        Some(sampled) => sampled.set(t, &value),
+        None => {
+          self
+            .values
+            .insert(handle.get_index(), Sampled::new(t, &value));
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixture::{labels, FixtureWave};
+  use crate::sink::{drive, Feedback};
+  use crate::wave::Waveform;
+
+  #[test]
+  fn sample_on_rising_clock_edges() {
+    let mut wave = FixtureWave::new(0, 100);
+    wave.scope("top");
+    let clock = wave.var("clock", 1);
+    let valid = wave.var("valid", 1);
+    wave.upscope();
+    wave
+      .change(0, clock, "0")
+      .change(0, valid, "0")
+      .change(10, clock, "1")
+      // changes with the edge are only seen by the next edge
+      .change(10, valid, "1")
+      .change(15, clock, "0")
+      .change(20, clock, "1");
+
+    let metadata = wave.collect(&["valid"]);
+    let clock = resolve(&SignalLookup::new(&mut wave).unwrap(), "top.clock").unwrap();
+    let mut sink = ClockSink::new(&wave.header(), metadata, clock, TimeWindow::default()).unwrap();
+    drive(
+      &mut wave,
+      std::slice::from_mut(&mut sink),
+      Feedback::default(),
+    )
+    .unwrap();
+    let profile = sink.finish();
+
+    let cycles: Vec<_> = profile
+      .sample
+      .iter()
+      .map(|sample| (sample.value[0], labels(&profile, sample)[0].1))
+      .collect();
+    assert_eq!(cycles, [(1, "0"), (2, "1")]);
+
+    assert!(resolve(&SignalLookup::new(&mut wave).unwrap(), "top.clk").is_err());
+  }
+}
@@ -0,0 +1,141 @@
+//! Compression of the profile outputs.
+
+use std::io::Write;
+
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+
+/// How the pprof output is compressed. go's pprof only reads gzip and raw protobuf, a zstd
+/// output has to be decompressed before it is opened there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Codec {
+  /// Gzip, what pprof writes itself
+  #[default]
+  Gzip,
+  /// Zstandard, much faster on large outputs but not read by go's pprof
+  Zstd,
+  /// Raw protobuf
+  None,
+}
+
+impl Codec {
+  /// Return the extension telling the codec, added after the one of the format.
+  pub fn extension(self) -> Option<&'static str> {
+    match self {
+      Codec::Gzip => Some("gz"),
+      Codec::Zstd => Some("zst"),
+      Codec::None => None,
+    }
+  }
+
+  /// Return the range of the compression levels of the codec.
+  pub fn levels(self) -> std::ops::RangeInclusive<u32> {
+    match self {
+      Codec::Gzip => 0..=9,
+      Codec::Zstd => 1..=22,
+      Codec::None => 0..=0,
+    }
+  }
+
+  /// Check `level` is one of the compression levels of the codec.
+  pub fn check_level(self, level: u32) -> anyhow::Result<()> {
+    anyhow::ensure!(
+      self.levels().contains(&level),
+      "{self:?} compression level should be within {:?}, got {level}",
+      self.levels()
+    );
+    Ok(())
+  }
+
+  /// Return a writer compressing into `out` at `level`, or at the default level of the codec.
+  pub fn encoder<W: Write>(self, out: W, level: Option<u32>) -> anyhow::Result<Encoder<W>> {
+    if let Some(level) = level {
+      self.check_level(level)?;
+    }
+    Ok(match self {
+      Codec::Gzip => {
+        let level = level.map_or_else(flate2::Compression::default, flate2::Compression::new);
+        Encoder::Gzip(GzEncoder::new(out, level))
+      }
+      Codec::Zstd => {
+        // 0 is zstd's own default level
+        let level = level.map_or(0, |level| level as i32);
+        Encoder::Zstd(zstd::Encoder::new(out, level)?)
+      }
+      Codec::None => Encoder::None(out),
+    })
+  }
+}
+
+/// A writer compressing what it is written with a [`Codec`]. It must be finished to write the
+/// end of the compressed stream.
+pub enum Encoder<W: Write> {
+  Gzip(GzEncoder<W>),
+  Zstd(zstd::Encoder<'static, W>),
+  None(W),
+}
+
+impl<W: Write> Encoder<W> {
+  /// Write the end of the compressed stream and return the inner writer.
+  pub fn finish(self) -> std::io::Result<W> {
+    match self {
+      Encoder::Gzip(encoder) => encoder.finish(),
+      Encoder::Zstd(encoder) => encoder.finish(),
+      Encoder::None(out) => Ok(out),
+    }
+  }
+}
+
+impl<W: Write> Write for Encoder<W> {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    match self {
+      Encoder::Gzip(encoder) => encoder.write(buf),
+      Encoder::Zstd(encoder) => encoder.write(buf),
+      Encoder::None(out) => out.write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    match self {
+      Encoder::Gzip(encoder) => encoder.flush(),
+      Encoder::Zstd(encoder) => encoder.flush(),
+      Encoder::None(out) => out.flush(),
+    }
+  }
+}
+
+#[test]
+fn compress_round_trip() {
+  use std::io::Read;
+
+  let text = b"value changes ".repeat(100);
+  let compress = |codec: Codec| {
+    let mut encoder = codec.encoder(Vec::new(), None).unwrap();
+    encoder.write_all(&text).unwrap();
+    encoder.finish().unwrap()
+  };
+
+  let mut raw = Vec::new();
+  flate2::read::GzDecoder::new(&compress(Codec::Gzip)[..])
+    .read_to_end(&mut raw)
+    .unwrap();
+  assert_eq!(raw, text);
+  assert_eq!(zstd::decode_all(&compress(Codec::Zstd)[..]).unwrap(), text);
+  assert_eq!(compress(Codec::None), text);
+
+  assert!(Codec::Gzip.encoder(Vec::new(), Some(12)).is_err());
+  assert!(Codec::Zstd.encoder(Vec::new(), Some(12)).is_ok());
+}
+
+#[test]
+fn check_compression_levels() {
+  Codec::Gzip.check_level(9).unwrap();
+  Codec::Zstd.check_level(22).unwrap();
+  let err = Codec::Gzip.check_level(10).unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "Gzip compression level should be within 0..=9, got 10"
+  );
+  assert!(Codec::Zstd.check_level(0).is_err());
+  assert!(Codec::None.check_level(1).is_err());
+}
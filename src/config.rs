@@ -0,0 +1,678 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::aggregate::AggregationConfig;
+use crate::derived::Expr;
+use crate::failure::FailureKind;
+use crate::fsm::{Encoding, StateMachineConfig, StateRegisterConfig};
+use crate::handshake::HandshakeConfig;
+use crate::instret::InstretConfig;
+use crate::transaction::TransactionConfig;
+use crate::trigger::TriggerConfig;
+use crate::xz::XzConfig;
+
+/// Timeout of the whole request when fetching a remote config.
+#[cfg(feature = "remote-config")]
+const REMOTE_CONFIG_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+  /// Signals to extract, by bare name or full path, see [`crate::pattern::SignalPattern`]
+  pub signals: Vec<String>,
+  /// Signals to extract by regex, matched against the full dot separated path and against the
+  /// bare name, like `^top\.lane\[\d+\]\.valid$` or `^req_.*_ready$`
+  #[serde(default)]
+  pub regex_signals: Vec<String>,
+  /// Scopes whose signals are all extracted, like `{ scope = "top.dut.fpu", recursive = true }`.
+  /// Only the signals declared right in the scope are, unless it is recursive
+  #[serde(default)]
+  pub scopes: Vec<ScopeConfig>,
+  /// Virtual 1-bit signals computed from other signals, by dot separated path, like
+  /// `"top.stall": "top.fifo_full && !top.grant"`. They can be selected like any other signal
+  #[serde(default)]
+  pub derived: BTreeMap<String, String>,
+  /// Valid/ready handshakes to count, each one emits a sample weighted by its count
+  #[serde(default)]
+  pub handshakes: Vec<HandshakeConfig>,
+  /// Transactions going from a start condition to an end condition, each one that ends emits a
+  /// sample stacked under its latency in cycles
+  #[serde(default)]
+  pub transactions: Vec<TransactionConfig>,
+  /// State machines decoded from several signals, each one is emitted as a signal holding the
+  /// state name
+  #[serde(default)]
+  pub state_machines: Vec<StateMachineConfig>,
+  /// State machines held in a single register whose values are named, emitted like the state
+  /// machines
+  #[serde(default)]
+  pub state_registers: Vec<StateRegisterConfig>,
+  /// Summaries of 1-bit signals, each aggregation in its own sample type. The aggregations of
+  /// one signal are values of a single sample
+  #[serde(default)]
+  pub aggregations: Vec<AggregationConfig>,
+  /// Only sample the signals while this condition holds
+  #[serde(default)]
+  pub trigger: Option<TriggerConfig>,
+  /// How values with `x` or `z` bits are read, they are kept as they are without it
+  #[serde(default)]
+  pub unknown_values: Option<XzConfig>,
+  /// Signals of the retired instructions, profiled with `--mode instret`
+  #[serde(default)]
+  pub instret: Option<InstretConfig>,
+  /// Profiles written in the same run as the main outputs, each from its own signals, like
+  /// `{ name = "lsu", signals = ["top.lsu.*"] }`. The main outputs are left out when the config
+  /// selects no signal of its own
+  #[serde(default)]
+  pub profiles: Vec<ProfileConfig>,
+  /// Drop value changes before this time, in raw FST ticks
+  #[serde(default)]
+  pub time_start: Option<u64>,
+  /// Drop value changes after this time, in raw FST ticks
+  #[serde(default)]
+  pub time_end: Option<u64>,
+}
+
+/// A scope selecting every signal declared in it, by its full dot separated path.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScopeConfig {
+  pub scope: String,
+  /// Also select the signals of every scope nested in it
+  #[serde(default)]
+  pub recursive: bool,
+}
+
+impl ScopeConfig {
+  /// Return true if a signal declared under `module_path` is selected by the scope.
+  pub fn contains(&self, module_path: &[String]) -> bool {
+    let scope: Vec<&str> = self.scope.split('.').collect();
+    let deep_enough = if self.recursive {
+      module_path.len() >= scope.len()
+    } else {
+      module_path.len() == scope.len()
+    };
+    deep_enough
+      && module_path
+        .iter()
+        .zip(&scope)
+        .all(|(have, want)| have == want)
+  }
+}
+
+/// Output path of a profile without one.
+pub const DEFAULT_PROFILE_OUTPUT: &str = "{fst_stem}.{profile_name}.{ext}";
+
+/// A named profile of its own signals, selected like the top level ones. Every output format is
+/// written for it, from the same read of the waveform as the main outputs.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+  pub name: String,
+  #[serde(default)]
+  pub signals: Vec<String>,
+  #[serde(default)]
+  pub regex_signals: Vec<String>,
+  #[serde(default)]
+  pub scopes: Vec<ScopeConfig>,
+  /// Path template of the outputs, where `{fst_stem}` is the waveform file name without its
+  /// extension, `{profile_name}` the name of the profile and `{ext}` the extension of the format.
+  /// Defaults to [`DEFAULT_PROFILE_OUTPUT`]
+  #[serde(default)]
+  pub output: Option<String>,
+}
+
+impl ProfileConfig {
+  /// Compile the regexes of `regex_signals`.
+  pub fn regexes(&self) -> anyhow::Result<Vec<regex::Regex>> {
+    compile_regexes(&self.regex_signals)
+  }
+
+  /// Return the path of the output of the profile with the extension `ext`.
+  pub fn output_path(&self, fst_stem: &str, ext: &str) -> String {
+    self
+      .output
+      .as_deref()
+      .unwrap_or(DEFAULT_PROFILE_OUTPUT)
+      .replace("{fst_stem}", fst_stem)
+      .replace("{profile_name}", &self.name)
+      .replace("{ext}", ext)
+  }
+}
+
+impl Config {
+  /// Load the config from a file path, its format is given by the extension. HTTP(S) URLs are
+  /// fetched when the crate is built with the `remote-config` feature, the request times out
+  /// after 30 seconds.
+  pub fn from_path(path: &str) -> anyhow::Result<Self> {
+    let (raw, format) = if is_url(path) {
+      let (raw, content_type) = fetch(path)?;
+      // an endpoint may serve the config under a path without extension, its content type then
+      // tells the format, and JSON is the format of any other config
+      let format = ConfigFormat::from_extension(path)
+        .or_else(|| {
+          content_type
+            .as_deref()
+            .and_then(ConfigFormat::from_content_type)
+        })
+        .unwrap_or(ConfigFormat::Json);
+      (raw, format)
+    } else {
+      let format = ConfigFormat::from_path(path)?;
+      let context = || FailureKind::Config.with(format!("fail to read config {path}"));
+      (std::fs::read(path).with_context(context)?, format)
+    };
+    let context = || FailureKind::Config.with(format!("fail to parse config {path}"));
+    let config = Self::parse(&raw, format).with_context(context)?;
+    config
+      .validate()
+      .with_context(|| FailureKind::Config.with(format!("invalid config {path}")))?;
+    Ok(config)
+  }
+
+  /// Parse a config written in the given format.
+  pub fn parse(raw: &[u8], format: ConfigFormat) -> anyhow::Result<Self> {
+    Ok(match format {
+      ConfigFormat::Json => serde_json::from_slice(raw)?,
+      ConfigFormat::Toml => toml::from_str(std::str::from_utf8(raw)?)?,
+      ConfigFormat::Yaml => serde_yaml::from_slice(raw)?,
+    })
+  }
+}
+
+/// The file formats a config can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+  Json,
+  Toml,
+  Yaml,
+}
+
+impl ConfigFormat {
+  /// Return the format of a config file from its extension.
+  pub fn from_path(path: &str) -> anyhow::Result<Self> {
+    Self::from_extension(path).ok_or_else(|| {
+      FailureKind::Config
+        .with(format!(
+          "unknown config format of {path}, supported extensions are .json, .toml, .yaml and \
+           .yml"
+        ))
+        .into()
+    })
+  }
+
+  /// Return the format named by the extension of a path, or of the path part of a URL.
+  fn from_extension(path: &str) -> Option<Self> {
+    // the query and the fragment of a URL aren't part of its path
+    let path = if is_url(path) {
+      path.split(['?', '#']).next().unwrap_or(path)
+    } else {
+      path
+    };
+    let extension = std::path::Path::new(path).extension()?.to_str()?;
+    match extension.to_ascii_lowercase().as_str() {
+      "json" => Some(Self::Json),
+      "toml" => Some(Self::Toml),
+      "yaml" | "yml" => Some(Self::Yaml),
+      _ => None,
+    }
+  }
+
+  /// Return the format named by a `Content-Type` like `application/toml` or
+  /// `application/x-yaml; charset=utf-8`.
+  fn from_content_type(content_type: &str) -> Option<Self> {
+    let mime = content_type.split(';').next()?.trim().to_ascii_lowercase();
+    let (_, subtype) = mime.split_once('/')?;
+    match subtype.strip_prefix("x-").unwrap_or(subtype) {
+      "json" => Some(Self::Json),
+      "toml" => Some(Self::Toml),
+      "yaml" | "yml" => Some(Self::Yaml),
+      subtype if subtype.ends_with("+json") => Some(Self::Json),
+      _ => None,
+    }
+  }
+}
+
+impl Config {
+  /// Compile the regexes of `regex_signals`.
+  pub fn regexes(&self) -> anyhow::Result<Vec<regex::Regex>> {
+    compile_regexes(&self.regex_signals)
+  }
+
+  /// Return true if the config selects signals of its own, outside of its profiles.
+  pub fn selects_signals(&self) -> bool {
+    !self.signals.is_empty() || !self.regex_signals.is_empty() || !self.scopes.is_empty()
+  }
+
+  /// Check everything that can be checked without a waveform, and report all the problems at
+  /// once. Whether the signals exist and have the right width is only known when resolving them.
+  pub fn validate(&self) -> anyhow::Result<()> {
+    fn check_path(problems: &mut Vec<String>, what: &str, path: &str) {
+      if path.is_empty() || path.split('.').any(str::is_empty) {
+        problems.push(format!("{what} `{path}` is not a valid dot separated path"));
+      }
+    }
+
+    let mut problems = Vec::new();
+
+    for path in &self.signals {
+      check_path(&mut problems, "signal", path);
+    }
+    for scope in &self.scopes {
+      check_path(&mut problems, "scope", &scope.scope);
+    }
+    for pattern in &self.regex_signals {
+      if let Err(err) = regex::Regex::new(pattern) {
+        problems.push(format!("signal regex `{pattern}` is invalid: {err}"));
+      }
+    }
+    for profile in &self.profiles {
+      for path in &profile.signals {
+        check_path(
+          &mut problems,
+          &format!("profile {} signal", profile.name),
+          path,
+        );
+      }
+      for scope in &profile.scopes {
+        check_path(
+          &mut problems,
+          &format!("profile {} scope", profile.name),
+          &scope.scope,
+        );
+      }
+    }
+    for (path, expr) in &self.derived {
+      check_path(&mut problems, "derived signal", path);
+      match Expr::parse(expr) {
+        Ok((_, operands)) => {
+          for operand in &operands {
+            check_path(
+              &mut problems,
+              &format!("derived signal {path} operand"),
+              operand,
+            );
+          }
+        }
+        Err(err) => problems.push(format!("derived signal {path} is invalid: {err}")),
+      }
+    }
+    for hs in &self.handshakes {
+      for path in [&hs.clock, &hs.valid, &hs.ready] {
+        check_path(
+          &mut problems,
+          &format!("handshake {} signal", hs.name),
+          path,
+        );
+      }
+    }
+    for tx in &self.transactions {
+      check_path(
+        &mut problems,
+        &format!("transaction {} clock", tx.name),
+        &tx.clock,
+      );
+      for (what, expr) in [("start", &tx.start), ("end", &tx.end)] {
+        match Expr::parse(expr) {
+          Ok((_, operands)) => {
+            for operand in &operands {
+              check_path(
+                &mut problems,
+                &format!("transaction {} {what} operand", tx.name),
+                operand,
+              );
+            }
+          }
+          Err(err) => problems.push(format!("transaction {} {what} is invalid: {err}", tx.name)),
+        }
+      }
+    }
+    for machine in &self.state_machines {
+      for path in &machine.members {
+        check_path(
+          &mut problems,
+          &format!("state machine {} member", machine.name),
+          path,
+        );
+      }
+    }
+    for register in &self.state_registers {
+      check_path(&mut problems, "state register", &register.fsm);
+    }
+    for aggregation in &self.aggregations {
+      check_path(&mut problems, "aggregated signal", &aggregation.signal);
+    }
+    if let Some(trigger) = &self.trigger {
+      check_path(&mut problems, "trigger signal", &trigger.signal);
+      if trigger.value.is_empty() {
+        problems.push(format!("trigger of {} has an empty value", trigger.signal));
+      }
+    }
+    if let Some(xz) = &self.unknown_values {
+      for path in xz.overrides.keys() {
+        check_path(&mut problems, "x/z override signal", path);
+      }
+    }
+    if let Some(instret) = &self.instret {
+      let signals = std::iter::once(&instret.instruction).chain(&instret.pc);
+      for path in signals.chain(&instret.clock).chain(&instret.valid) {
+        check_path(&mut problems, "instret signal", path);
+      }
+      if instret.valid.is_some() && instret.clock.is_none() {
+        problems.push("the instret valid signal needs an instret clock".to_string());
+      }
+    }
+
+    if let (Some(start), Some(end)) = (self.time_start, self.time_end) {
+      if start > end {
+        problems.push(format!("time_start {start} is after time_end {end}"));
+      }
+    }
+    for (i, a) in self.aggregations.iter().enumerate() {
+      let same =
+        |other: &AggregationConfig| other.signal == a.signal && other.aggregation == a.aggregation;
+      if self.aggregations[..i].iter().any(same) {
+        problems.push(format!(
+          "aggregation {} of {} is declared twice",
+          a.aggregation.as_str(),
+          a.signal
+        ));
+      }
+    }
+    for hs in &self.handshakes {
+      if hs.name.is_empty() {
+        problems.push("a handshake has an empty name".to_string());
+      }
+    }
+    for tx in &self.transactions {
+      if tx.name.is_empty() {
+        problems.push("a transaction has an empty name".to_string());
+      }
+    }
+    for machine in &self.state_machines {
+      if machine.name.is_empty() {
+        problems.push("a state machine has an empty name".to_string());
+      }
+      if machine.members.is_empty() {
+        problems.push(format!("state machine {} has no member", machine.name));
+      }
+      if machine.states.is_empty() {
+        problems.push(format!("state machine {} has no state", machine.name));
+      }
+      if machine.encoding == Encoding::OneHot && machine.states.len() != machine.members.len() {
+        problems.push(format!(
+          "one-hot state machine {} has {} members but {} states",
+          machine.name,
+          machine.members.len(),
+          machine.states.len()
+        ));
+      }
+    }
+
+    for register in &self.state_registers {
+      if register.states.is_empty() {
+        problems.push(format!("state register {} has no state", register.fsm));
+      }
+    }
+    let placeholders = regex::Regex::new(r"\{[^}]*\}").unwrap();
+    for (i, profile) in self.profiles.iter().enumerate() {
+      if profile.name.is_empty() {
+        problems.push("a profile has an empty name".to_string());
+      }
+      if self.profiles[..i].iter().any(|p| p.name == profile.name) {
+        problems.push(format!("profile {} is declared twice", profile.name));
+      }
+      if profile.signals.is_empty() && profile.regex_signals.is_empty() && profile.scopes.is_empty()
+      {
+        problems.push(format!("profile {} selects no signal", profile.name));
+      }
+      for pattern in &profile.regex_signals {
+        if let Err(err) = regex::Regex::new(pattern) {
+          problems.push(format!(
+            "profile {} signal regex `{pattern}` is invalid: {err}",
+            profile.name
+          ));
+        }
+      }
+      let output = profile.output.as_deref().unwrap_or_default();
+      for placeholder in placeholders.find_iter(output) {
+        if !matches!(
+          placeholder.as_str(),
+          "{fst_stem}" | "{profile_name}" | "{ext}"
+        ) {
+          problems.push(format!(
+            "profile {} output has an unknown placeholder {}",
+            profile.name,
+            placeholder.as_str()
+          ));
+        }
+      }
+    }
+
+    anyhow::ensure!(
+      problems.is_empty(),
+      "{} problems found:\n  {}",
+      problems.len(),
+      problems.join("\n  ")
+    );
+    Ok(())
+  }
+}
+
+fn compile_regexes(patterns: &[String]) -> anyhow::Result<Vec<regex::Regex>> {
+  patterns
+    .iter()
+    .map(|pattern| {
+      regex::Regex::new(pattern).with_context(|| format!("invalid signal regex `{pattern}`"))
+    })
+    .collect()
+}
+
+fn is_url(path: &str) -> bool {
+  path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Fetch the config at `url`, returning its body and its content type if the server gives one.
+#[cfg(feature = "remote-config")]
+fn fetch(url: &str) -> anyhow::Result<(Vec<u8>, Option<String>)> {
+  let client = reqwest::blocking::Client::builder()
+    .timeout(REMOTE_CONFIG_TIMEOUT)
+    .build()
+    .context("fail to build HTTP client")?;
+  let response = client
+    .get(url)
+    .send()
+    .and_then(|response| response.error_for_status())
+    .with_context(|| FailureKind::Config.with(format!("fail to fetch config from {url}")))?;
+  let content_type = response
+    .headers()
+    .get(reqwest::header::CONTENT_TYPE)
+    .and_then(|value| value.to_str().ok())
+    .map(str::to_string);
+  let body = response
+    .bytes()
+    .with_context(|| FailureKind::Config.with(format!("fail to read config body from {url}")))?;
+  Ok((body.to_vec(), content_type))
+}
+
+#[cfg(not(feature = "remote-config"))]
+fn fetch(url: &str) -> anyhow::Result<(Vec<u8>, Option<String>)> {
+  let message = format!("can't fetch config from {url}, rebuild with the `remote-config` feature");
+  Err(FailureKind::Config.with(message).into())
+}
+
+#[test]
+fn remote_config_detection() {
+  assert!(is_url("https://ci.example.com/config.json"));
+  assert!(is_url("http://localhost/config.json"));
+  assert!(!is_url("assets/config.json"));
+
+  let format = ConfigFormat::from_extension;
+  assert_eq!(
+    format("https://ci.example.com/config.toml?ref=main"),
+    Some(ConfigFormat::Toml)
+  );
+  assert_eq!(format("https://ci.example.com/api/config"), None);
+  let format = ConfigFormat::from_content_type;
+  assert_eq!(
+    format("application/x-yaml; charset=utf-8"),
+    Some(ConfigFormat::Yaml)
+  );
+  assert_eq!(format("application/vnd.ci+json"), Some(ConfigFormat::Json));
+  assert_eq!(format("text/plain"), None);
+
+  let config = Config::parse(br#"{"signals": ["valid"]}"#, ConfigFormat::Json).unwrap();
+  assert_eq!(config.signals, ["valid"]);
+}
+
+#[test]
+fn validate_config() {
+  let config = Config::parse(
+    br#"{
+      "signals": ["top.valid", "top..ready"],
+      "regex_signals": ["^top\\.lane\\[\\d+\\]$", "top\\.lane["],
+      "time_start": 100,
+      "time_end": 10,
+      "handshakes": [{"name": "req", "clock": "clock", "valid": "valid", "ready": ""}],
+      "transactions": [{"name": "get", "clock": "clock", "start": "a_valid &&", "end": "d_valid"}],
+      "state_machines": [
+        {"name": "fsm", "encoding": "one_hot", "members": ["idle", "busy"], "states": ["IDLE"]}
+      ],
+      "aggregations": [
+        {"signal": "top.valid", "aggregation": "toggles"},
+        {"signal": "top.valid", "aggregation": "duration"},
+        {"signal": "top.valid", "aggregation": "toggles"}
+      ]
+    }"#,
+    ConfigFormat::Json,
+  )
+  .unwrap();
+  let err = format!("{:#}", config.validate().unwrap_err());
+  assert!(err.starts_with("7 problems found"));
+  assert!(err.contains("aggregation toggles of top.valid is declared twice"));
+  assert!(err.contains("time_start 100 is after time_end 10"));
+  assert!(err.contains("signal `top..ready`"));
+  assert!(err.contains("signal regex `top\\.lane[` is invalid"));
+  assert!(err.contains("handshake req signal ``"));
+  assert!(err.contains("transaction get start is invalid"));
+  assert!(err.contains("one-hot state machine fsm has 2 members but 1 states"));
+
+  let config = Config::parse(
+    br#"{"signals": ["valid", "top.ready"]}"#,
+    ConfigFormat::Json,
+  )
+  .unwrap();
+  assert!(config.validate().is_ok());
+}
+
+#[test]
+fn validate_profiles() {
+  let config = Config::parse(
+    br#"
+      signals = []
+
+      [[profiles]]
+      name = "lsu"
+      signals = ["top.lsu.*"]
+
+      [[profiles]]
+      name = "fpu"
+      scopes = [{ scope = "top.fpu", recursive = true }]
+      output = "out/{fst_stem}-{profile_name}.{ext}"
+    "#,
+    ConfigFormat::Toml,
+  )
+  .unwrap();
+  assert!(config.validate().is_ok());
+  assert!(!config.selects_signals());
+  assert_eq!(
+    config.profiles[0].output_path("sim", "pprof.gz"),
+    "sim.lsu.pprof.gz"
+  );
+  assert_eq!(
+    config.profiles[1].output_path("sim", "folded"),
+    "out/sim-fpu.folded"
+  );
+
+  let config = Config::parse(
+    br#"{"signals": ["valid"], "profiles": [
+      {"name": "lsu", "signals": ["top.lsu.*"], "output": "{stem}.{ext}"},
+      {"name": "lsu", "regex_signals": ["lsu["]},
+      {"name": "empty"}
+    ]}"#,
+    ConfigFormat::Json,
+  )
+  .unwrap();
+  let err = format!("{:#}", config.validate().unwrap_err());
+  assert!(err.starts_with("4 problems found"), "{err}");
+  assert!(err.contains("profile lsu output has an unknown placeholder {stem}"));
+  assert!(err.contains("profile lsu is declared twice"));
+  assert!(err.contains("profile lsu signal regex `lsu[` is invalid"));
+  assert!(err.contains("profile empty selects no signal"));
+}
+
+#[test]
+fn parse_config_formats() {
+  let json = br#"{"signals": ["top.valid"], "handshakes": [
+    {"name": "req", "clock": "clock", "valid": "valid", "ready": "ready"}
+  ]}"#;
+  let toml = br#"
+    signals = ["top.valid"]
+
+    [[handshakes]]
+    name = "req"
+    clock = "clock"
+    valid = "valid"
+    ready = "ready"
+  "#;
+  let yaml = br#"
+signals:
+  - top.valid
+handshakes:
+  - name: req
+    clock: clock
+    valid: valid
+    ready: ready
+"#;
+  for (raw, format) in [
+    (&json[..], ConfigFormat::Json),
+    (&toml[..], ConfigFormat::Toml),
+    (&yaml[..], ConfigFormat::Yaml),
+  ] {
+    let config = Config::parse(raw, format).unwrap();
+    assert_eq!(config.signals, ["top.valid"]);
+    assert_eq!(config.handshakes[0].name, "req");
+  }
+
+  assert_eq!(
+    ConfigFormat::from_path("sim/config.yml").unwrap(),
+    ConfigFormat::Yaml
+  );
+  assert_eq!(
+    ConfigFormat::from_path("https://ci.example.com/config.TOML").unwrap(),
+    ConfigFormat::Toml
+  );
+  assert!(ConfigFormat::from_path("config.ini").is_err());
+}
+
+#[test]
+fn reject_unknown_fields() {
+  let json = b"{\"signals\": [],\n  \"handshake\": []}";
+  let toml = b"signals = []\nhandshake = []";
+  let yaml = b"signals: []\nhandshake: []";
+  for (raw, format) in [
+    (&json[..], ConfigFormat::Json),
+    (&toml[..], ConfigFormat::Toml),
+    (&yaml[..], ConfigFormat::Yaml),
+  ] {
+    let err = Config::parse(raw, format).unwrap_err().to_string();
+    assert!(err.contains("unknown field `handshake`"), "{err}");
+    assert!(err.contains("line 2"), "{err}");
+  }
+
+  let nested = br#"{"signals": [], "trigger": {"signal": "stall", "val": "1"}}"#;
+  let err = Config::parse(nested, ConfigFormat::Json).unwrap_err();
+  assert!(err.to_string().contains("unknown field `val`"));
+}
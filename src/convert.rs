@@ -0,0 +1,2076 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use anyhow::Context;
+use clap::ValueEnum;
+use fst_native::FstSignalHandle;
+use prost::Message;
+use tracing::{info, trace, warn};
+
+use crate::aggregate::{self, Aggregate, Aggregation, Aggregator};
+use crate::checkpoint::{self, Checkpoint, CheckpointOptions};
+use crate::config::Config;
+use crate::edge::{Edge, EdgeFilter};
+use crate::fsm::{self, StateDecoder, StateMachine};
+use crate::handshake::{self, Handshake, HandshakeCounter};
+use crate::pattern::BitSlice;
+use crate::pprof::{self, ProfileBuilder};
+use crate::properties::{Bin, Properties};
+use crate::sink::{self, OutputSink};
+use crate::transaction::{self, Transaction, TransactionTracker};
+use crate::trigger::{self, Trigger};
+use crate::wave::{SignalValue, WaveHeader, Waveform};
+use crate::window::TimeWindow;
+use crate::xz::{self, XzHandling};
+use crate::{SignalLookup, SignalMetadata};
+
+/// The order of the samples of a profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum SampleOrder {
+  /// In the order the changes happen
+  #[default]
+  Time,
+  /// Grouped by signal, in the order the changes happen within one signal
+  Signal,
+}
+
+/// Options controlling how value changes are turned into samples.
+#[derive(Debug, Default)]
+pub struct ConvertOptions {
+  /// Only emit these edges of 1-bit signals
+  pub edges: Option<EdgeFilter>,
+  /// Add a `name=value` leaf frame to every sample
+  pub group_by_value: bool,
+  /// Merge adjacent identical samples
+  pub coalesce: bool,
+  /// Fail instead of warning on malformed input
+  pub strict: bool,
+  /// Emit an extra sample holding the last value of every signal at the end of simulation
+  pub final_state: bool,
+  /// Leave the function table empty
+  pub no_functions: bool,
+  /// Leave the location table empty, samples then have no stack
+  pub no_locations: bool,
+  /// Stop emitting changes of a signal once it has emitted this many of them
+  pub max_changes_per_signal: Option<usize>,
+  /// Label the values past this many distinct ones as [`pprof::OTHER_VALUE`]
+  pub max_label_values: Option<usize>,
+  /// Only emit changes inside this window
+  pub window: TimeWindow,
+  /// Handshakes to count along the signals
+  pub handshakes: Vec<Handshake>,
+  /// Transactions whose latency is measured, each one emitted as a sample
+  pub transactions: Vec<Transaction>,
+  /// State machines emitted as derived signals holding the state name
+  pub state_machines: Vec<StateMachine>,
+  /// Order of the value change samples
+  pub sample_order: SampleOrder,
+  /// Add a comment with the number of samples each signal emitted
+  pub embed_counts: bool,
+  /// Display metadata of the signals
+  pub properties: Properties,
+  /// Name buses with their bit range, like `top.data [7:0]`
+  pub bus_ranges: bool,
+  /// Stack the scopes of every signal under it, so the call tree follows the module hierarchy
+  pub hierarchy_stacks: bool,
+  /// Also label the samples of multi-bit signals with each of their bits, like `top.state[0]`
+  pub bit_labels: bool,
+  /// Count the held time of the samples in rising edges of this 1-bit clock instead of nanoseconds
+  pub cycle_clock: Option<FstSignalHandle>,
+  /// Label real values with a number, scaled by this factor since label numbers are integers
+  pub real_scale: Option<f64>,
+  /// Only emit changes while this condition holds
+  pub trigger: Option<Trigger>,
+  /// Summaries of 1-bit signals, emitted as one sample each in extra sample types
+  pub aggregations: Vec<Aggregate>,
+  /// Label the value change samples with the start of the time bucket of this many ticks they
+  /// fall in
+  pub bucket: Option<u64>,
+  /// How values with `x` or `z` bits are read, kept as they are without a policy
+  pub unknown_values: Option<XzHandling>,
+  /// Save the state of the conversion periodically
+  pub checkpoint: Option<CheckpointOptions>,
+}
+
+impl ConvertOptions {
+  /// Return the options given by `config`: its handshakes, transactions, state machines, trigger,
+  /// aggregations and x/z policy resolved to handles, and its time window. The others are left to
+  /// their default.
+  pub fn from_config(signals: &SignalLookup, config: &Config) -> anyhow::Result<Self> {
+    Ok(Self {
+      handshakes: handshake::resolve(signals, &config.handshakes)?,
+      transactions: transaction::resolve(signals, &config.transactions)?,
+      state_machines: fsm::resolve(signals, &config.state_machines, &config.state_registers)?,
+      trigger: trigger::resolve(signals, config.trigger.as_ref())?,
+      aggregations: aggregate::resolve(signals, &config.aggregations)?,
+      unknown_values: xz::resolve(signals, config.unknown_values.as_ref())?,
+      window: TimeWindow {
+        start: config.time_start,
+        end: config.time_end,
+        ..Default::default()
+      },
+      ..Default::default()
+    })
+  }
+
+  /// Return the cycle clock, the trigger, and the clocks and operands of the handshakes and
+  /// transactions. Unlike the profiled signals, every one of their changes matters.
+  pub fn control_handles(&self) -> Vec<FstSignalHandle> {
+    let mut handles = HandshakeCounter::new(&self.handshakes).handles();
+    for handle in TransactionTracker::new(&self.transactions)
+      .handles()
+      .into_iter()
+      .chain(self.cycle_clock)
+      .chain(self.trigger.as_ref().map(|trigger| trigger.handle))
+    {
+      if !handles.contains(&handle) {
+        handles.push(handle);
+      }
+    }
+    handles
+  }
+
+  /// Return a hash of every option but the checkpoint ones, so a checkpoint is only resumed by
+  /// a conversion with the same options.
+  pub fn fingerprint(&self) -> u64 {
+    // destructured so a new option can't be left out
+    let Self {
+      edges,
+      group_by_value,
+      coalesce,
+      strict,
+      final_state,
+      no_functions,
+      no_locations,
+      max_changes_per_signal,
+      max_label_values,
+      window,
+      handshakes,
+      transactions,
+      state_machines,
+      sample_order,
+      embed_counts,
+      properties,
+      bus_ranges,
+      hierarchy_stacks,
+      bit_labels,
+      cycle_clock,
+      real_scale,
+      trigger,
+      aggregations,
+      bucket,
+      unknown_values,
+      checkpoint: _,
+    } = self;
+    checkpoint::fingerprint(&(
+      (
+        edges,
+        group_by_value,
+        coalesce,
+        strict,
+        final_state,
+        no_functions,
+      ),
+      (
+        no_locations,
+        max_changes_per_signal,
+        max_label_values,
+        window,
+      ),
+      (
+        handshakes,
+        transactions,
+        state_machines,
+        sample_order,
+        embed_counts,
+      ),
+      (
+        properties,
+        bus_ranges,
+        hierarchy_stacks,
+        bit_labels,
+        cycle_clock,
+      ),
+      (real_scale, trigger, aggregations, bucket, unknown_values),
+    ))
+  }
+}
+
+/// Read the value changes of all the signals in `metadata` and convert them into a profile.
+///
+/// This reads the value changes once for this output only, use [`sink::drive`] to feed several
+/// outputs from the same read.
+pub fn convert(
+  reader: &mut impl Waveform,
+  metadata: &SignalMetadata,
+  options: &ConvertOptions,
+) -> anyhow::Result<pprof::Profile> {
+  let mut sink = PprofSink::new(&reader.header(), metadata.clone(), options)?;
+  sink::drive(
+    reader,
+    std::slice::from_mut(&mut sink),
+    sink::Feedback::default(),
+  )?;
+  sink.finish()
+}
+
+/// Builds a profile out of the value changes it is fed.
+#[derive(Clone)]
+pub struct PprofSink<'a> {
+  metadata: SignalMetadata,
+  options: &'a ConvertOptions,
+  /// Positions in `metadata` of each signal, by handle index
+  index: HashMap<usize, Vec<usize>>,
+  builder: ProfileBuilder,
+  timeline: Timeline,
+  /// Latest value of every signal, by position in `metadata`
+  last_values: HashMap<usize, String>,
+  final_values: Vec<Option<String>>,
+  emitted: Vec<usize>,
+  capped: Vec<usize>,
+  handshakes: HandshakeCounter<'a>,
+  transactions: TransactionTracker<'a>,
+  states: StateDecoder<'a>,
+  aggregator: Aggregator<'a>,
+  /// Time and sample index of the latest sample of every signal, waiting for the signal to change
+  /// again to know how long it held its value
+  held: HashMap<String, (u64, usize)>,
+  /// Sample index of the signals held when the trigger stopped holding, restarted when it holds
+  /// again unless they changed in between
+  paused: BTreeMap<String, usize>,
+  /// Latest change of every signal while the trigger doesn't hold, by position in `metadata`,
+  /// sampled when it holds again
+  untriggered: BTreeMap<usize, SignalValue>,
+  /// Value of the latest sample of every signal, to know if the next one is a transition
+  sampled: HashMap<String, String>,
+  /// Header of the waveform, for the end of the simulation and the length of its time ticks
+  header: WaveHeader,
+  /// Latest value of the cycle clock, and the number of its rising edges inside the window
+  clock_value: Option<String>,
+  cycles: u64,
+  /// Value change samples merged into the previous one as they were emitted
+  coalesced: usize,
+  /// Whether the trigger condition holds, always true without a trigger
+  triggered: bool,
+  /// Start of the time bucket of the latest change
+  bucket: Option<u64>,
+  /// Number of changes to a value with `x` or `z` bits of every signal inside the window
+  unknown: Vec<u64>,
+  /// First unknown value refused by the x/z policy
+  refused: Option<String>,
+  /// Number of changes to every bin of the signals counted in a histogram, by position in
+  /// `metadata`
+  histograms: BTreeMap<usize, BTreeMap<Bin, u64>>,
+  /// Time of the resumed checkpoint, the changes before it are already converted
+  resume: Option<u64>,
+  /// When the latest checkpoint was saved
+  saved: Instant,
+}
+
+impl<'a> PprofSink<'a> {
+  pub fn new(
+    header: &WaveHeader,
+    metadata: SignalMetadata,
+    options: &'a ConvertOptions,
+  ) -> anyhow::Result<Self> {
+    anyhow::ensure!(
+      header.end_time >= header.start_time,
+      "header end time {} is before start time {}",
+      header.end_time,
+      header.start_time
+    );
+    if options.edges.is_some() {
+      let buses = metadata
+        .widths
+        .iter()
+        .enumerate()
+        .filter(|(_, width)| **width != 1)
+        .map(|(i, _)| metadata.names[i].as_str())
+        .collect::<Vec<_>>();
+      anyhow::ensure!(
+        buses.is_empty(),
+        "edge mode only supports 1-bit signals, got multi-bit signals: {}",
+        buses.join(", ")
+      );
+    }
+
+    let mut builder = ProfileBuilder::new();
+    builder.with_functions = !options.no_functions;
+    builder.with_locations = !options.no_locations;
+    builder.value_cap = options.max_label_values;
+    builder.profile.time_nanos = 10000;
+    builder.profile.period_type = Some(builder.str_tbl.value_type("cycle", "number"));
+    builder.profile.period = 1;
+    let (start, end) = options.window.clamp(header.start_time, header.end_time);
+    builder.profile.duration_nanos = header.ticks_to_nanos(end - start).try_into()?;
+    // every sample counts one change, how long the value was held after it, and whether the value
+    // differs from the previous sample of the signal
+    let time_unit = match options.cycle_clock {
+      Some(_) => "cycles",
+      None => "nanoseconds",
+    };
+    builder.profile.sample_type = vec![
+      builder.str_tbl.value_type("changes", "count"),
+      builder.str_tbl.value_type("time", time_unit),
+      builder.str_tbl.value_type("transitions", "count"),
+    ];
+    // then the latency of the transactions in cycles of their clock, and one value per
+    // aggregation in use, zero in the samples they don't measure
+    if !options.transactions.is_empty() {
+      let latency = builder.str_tbl.value_type("latency", "cycles");
+      builder.profile.sample_type.push(latency);
+    }
+    for kind in aggregate::kinds(&options.aggregations) {
+      // the high time is measured in nanoseconds, even with a cycle clock
+      let (r#type, unit) = kind.sample_type();
+      let value_type = builder.str_tbl.value_type(r#type, unit);
+      builder.profile.sample_type.push(value_type);
+    }
+    builder.profile.default_sample_type = builder.str_tbl.id("changes");
+
+    let signals = metadata.handle.len();
+    Ok(Self {
+      index: metadata.positions(),
+      metadata,
+      options,
+      builder,
+      timeline: Timeline::default(),
+      last_values: HashMap::new(),
+      final_values: vec![None; signals],
+      emitted: vec![0; signals],
+      capped: Vec::new(),
+      handshakes: HandshakeCounter::new(&options.handshakes),
+      transactions: TransactionTracker::new(&options.transactions),
+      states: StateDecoder::new(&options.state_machines),
+      aggregator: Aggregator::new(&options.aggregations, options.window, (start, end)),
+      held: HashMap::new(),
+      paused: BTreeMap::new(),
+      untriggered: BTreeMap::new(),
+      sampled: HashMap::new(),
+      header: header.clone(),
+      clock_value: None,
+      cycles: 0,
+      coalesced: 0,
+      triggered: options.trigger.is_none(),
+      bucket: None,
+      unknown: vec![0; signals],
+      refused: None,
+      histograms: BTreeMap::new(),
+      resume: None,
+      saved: Instant::now(),
+    })
+  }
+
+  /// Take the new time range of a waveform still being written, the latest values are then held
+  /// until its new end.
+  pub fn follow(&mut self, header: &WaveHeader) -> anyhow::Result<()> {
+    let (start, end) = self
+      .options
+      .window
+      .clamp(header.start_time, header.end_time);
+    self.builder.profile.duration_nanos = header.ticks_to_nanos(end - start).try_into()?;
+    self.aggregator.extend(end);
+    self.header = header.clone();
+    Ok(())
+  }
+
+  /// Return the profile of the changes fed so far, as [`PprofSink::finish`] would, while the sink
+  /// keeps going.
+  pub fn snapshot(&self) -> anyhow::Result<pprof::Profile> {
+    self.clone().finish()
+  }
+
+  /// Return the state of the conversion of the changes before time `t`.
+  pub fn checkpoint(&self, t: u64) -> Checkpoint {
+    let mut profile = self.builder.profile.clone();
+    profile.string_table = self.builder.str_tbl.to_string_table();
+    // the resumed conversion has its own duration already
+    profile.duration_nanos = 0;
+    Checkpoint {
+      time: t,
+      end_time: self.header.end_time,
+      signals: self.signals(),
+      options: self.options.fingerprint(),
+      profile: profile.encode_to_vec(),
+      latest: self.timeline.latest,
+      backwards: self.timeline.backwards,
+      last_values: self.last_values.clone(),
+      final_values: self.final_values.clone(),
+      emitted: self.emitted.clone(),
+      capped: self.capped.clone(),
+      held: self.held.clone(),
+      paused: self.paused.clone(),
+      untriggered: self.untriggered.clone(),
+      sampled: self.sampled.clone(),
+      coalesced: self.coalesced,
+      bucket: self.bucket,
+      unknown: self.unknown.clone(),
+      histograms: self
+        .histograms
+        .iter()
+        .map(|(i, bins)| (*i, bins.clone().into_iter().collect()))
+        .collect(),
+      label_values: self.builder.values,
+      capped_values: self.builder.capped_values,
+    }
+  }
+
+  /// Go on from a checkpoint of the same signals. All the changes must then be fed again from
+  /// the start, the ones before the checkpoint only go to the trackers.
+  pub fn resume(&mut self, checkpoint: Checkpoint) -> anyhow::Result<()> {
+    anyhow::ensure!(
+      checkpoint.end_time == self.header.end_time && checkpoint.signals == self.signals(),
+      "checkpoint is of another waveform or of other signals"
+    );
+    anyhow::ensure!(
+      checkpoint.options == self.options.fingerprint(),
+      "checkpoint is of a conversion with other options"
+    );
+    anyhow::ensure!(
+      !self.options.no_functions && !self.options.no_locations,
+      "a checkpoint can't be resumed without the function and location tables"
+    );
+    let profile =
+      pprof::Profile::decode(&checkpoint.profile[..]).context("malformed checkpoint profile")?;
+    self.builder.append(&profile);
+    self.timeline = Timeline {
+      latest: checkpoint.latest,
+      backwards: checkpoint.backwards,
+    };
+    self.last_values = checkpoint.last_values;
+    self.final_values = checkpoint.final_values;
+    self.emitted = checkpoint.emitted;
+    self.capped = checkpoint.capped;
+    self.held = checkpoint.held;
+    self.paused = checkpoint.paused;
+    self.untriggered = checkpoint.untriggered;
+    self.sampled = checkpoint.sampled;
+    self.coalesced = checkpoint.coalesced;
+    self.bucket = checkpoint.bucket;
+    self.unknown = checkpoint.unknown;
+    self.histograms = checkpoint
+      .histograms
+      .into_iter()
+      .map(|(i, bins)| (i, bins.into_iter().collect()))
+      .collect();
+    self.builder.values = checkpoint.label_values;
+    self.builder.capped_values = checkpoint.capped_values;
+    self.resume = Some(checkpoint.time);
+    Ok(())
+  }
+
+  /// Save a checkpoint before converting the changes at time `t`, if the previous one is old
+  /// enough. Failing to save it only warns, the conversion goes on.
+  fn save_checkpoint(&mut self, t: u64) {
+    let Some(checkpoint) = &self.options.checkpoint else {
+      return;
+    };
+    if self.saved.elapsed() < checkpoint.every {
+      return;
+    }
+    match self.checkpoint(t).save(&checkpoint.path) {
+      Ok(()) => info!("Saved checkpoint at time {t} to {}", checkpoint.path),
+      Err(err) => warn!("{err:#}"),
+    }
+    self.saved = Instant::now();
+  }
+
+  fn signals(&self) -> Vec<String> {
+    (0..self.metadata.handle.len())
+      .map(|i| self.metadata.full_name(i))
+      .collect()
+  }
+
+  /// Emit the summary samples and return the profile.
+  pub fn finish(mut self) -> anyhow::Result<pprof::Profile> {
+    // a bucket starting at the end of the window holds nothing
+    let end_time = self.header.end_time;
+    let last = self
+      .options
+      .window
+      .end
+      .map_or(end_time, |end| end.min(end_time));
+    self.roll_bucket(last.saturating_sub(1));
+    if let Some(err) = self.refused.take() {
+      anyhow::bail!(err);
+    }
+    let Self {
+      metadata,
+      options,
+      mut builder,
+      timeline,
+      final_values,
+      emitted,
+      capped,
+      handshakes,
+      transactions,
+      aggregator,
+      held,
+      header,
+      cycles,
+      coalesced,
+      unknown,
+      histograms,
+      ..
+    } = self;
+
+    // the latest values are held until the end of the window
+    let end = match options.cycle_clock {
+      Some(_) => cycles,
+      None => header.ticks_to_nanos(options.window.end.map_or(end_time, |end| end.min(end_time))),
+    };
+    for (since, idx) in held.into_values() {
+      builder.profile.sample[idx].value[1] += i64::try_from(end.saturating_sub(since))?;
+    }
+
+    if options.sample_order == SampleOrder::Signal {
+      // every value change sample starts with the label of its signal, and the sort is stable
+      builder
+        .profile
+        .sample
+        .sort_by_key(|sample| sample.label.first().map(|label| label.key));
+    }
+
+    for (handshake, count) in handshakes.counts() {
+      let location_id = builder.stack(&[&handshake.name]);
+      builder.profile.sample.push(pprof::Sample {
+        location_id,
+        value: vec![count, 0, 0],
+        label: Vec::new(),
+      });
+    }
+
+    let columns = builder.profile.sample_type.len();
+    let column = |builder: &ProfileBuilder, r#type: &str| {
+      let id = builder.str_tbl.get(r#type)?;
+      let sample_type = &builder.profile.sample_type;
+      sample_type.iter().position(|t| t.r#type == id)
+    };
+
+    // one sample per transaction, stacked under its latency so the flame graph is a histogram
+    let latency = column(&builder, "latency");
+    for done in transactions.completed() {
+      if !options.window.contains(done.start) {
+        continue;
+      }
+      let name = &options.transactions[done.transaction].name;
+      let location_id = builder.stack(&[name, &format!("{} cycles", done.latency)]);
+      let label = vec![builder.num_label("start", i64::try_from(done.start)?, "ticks")];
+      let mut value = vec![0; columns];
+      value[0] = 1;
+      value[latency.expect("transactions have a latency sample type")] =
+        i64::try_from(done.latency)?;
+      builder.profile.sample.push(pprof::Sample {
+        location_id,
+        value,
+        label,
+      });
+    }
+    for (transaction, n) in transactions.outstanding() {
+      if n > 0 {
+        info!("{n} {} transactions never ended", transaction.name);
+      }
+    }
+
+    if !capped.is_empty() {
+      let names: Vec<_> = capped.iter().map(|i| metadata.full_name(*i)).collect();
+      info!(
+        "{} signals hit the change limit: {}",
+        names.len(),
+        names.join(", ")
+      );
+    }
+
+    let unknown: Vec<_> = unknown
+      .iter()
+      .enumerate()
+      .filter(|(_, n)| **n > 0)
+      .map(|(i, n)| format!("{}: {n}", metadata.full_name(i)))
+      .collect();
+    if !unknown.is_empty() {
+      info!(
+        "{} signals changed to x/z values: {}",
+        unknown.len(),
+        unknown.join(", ")
+      );
+    }
+
+    if timeline.backwards > 0 {
+      anyhow::ensure!(
+        !options.strict,
+        "{} value changes go back in time",
+        timeline.backwards
+      );
+      warn!(
+        "{} value changes go back in time, they are clamped to the latest seen time",
+        timeline.backwards
+      );
+    }
+
+    if options.final_state {
+      let location_id = builder.stack(&["final state"]);
+      let label = final_values
+        .iter()
+        .enumerate()
+        .filter_map(|(i, value)| Some((metadata.full_name(i), value.as_ref()?)))
+        .map(|(name, value)| {
+          let value = builder.value(value);
+          builder.label(&name, value)
+        })
+        .collect();
+      builder.profile.sample.push(pprof::Sample {
+        location_id,
+        value: vec![1, 0, 0],
+        label,
+      });
+    }
+
+    // one sample per bin, under the signal so its bins add up to its changes
+    for (i, bins) in histograms {
+      let name = metadata.full_name(i);
+      let properties = options.properties.get(&name, &metadata.names[i]);
+      let display = properties
+        .and_then(|p| p.label.as_deref())
+        .unwrap_or(name.as_str());
+      let location_id = builder.stack(&[display, "histogram"]);
+      let histogram = properties.and_then(|p| p.histogram).unwrap_or_default();
+      let radix = properties.and_then(|p| p.radix);
+      for (bin, count) in bins {
+        let bin = histogram.label(&bin, metadata.widths[i], radix);
+        let label = vec![builder.label(display, &bin)];
+        builder.profile.sample.push(pprof::Sample {
+          location_id: location_id.clone(),
+          value: vec![i64::try_from(count)?, 0, 0],
+          label,
+        });
+      }
+    }
+
+    // one sample per aggregated signal, with every aggregation of it side by side
+    for (aggregate, aggregations) in aggregator.finish() {
+      let location_id = builder.stack(&[&aggregate.signal]);
+      let mut values = vec![0; columns];
+      for (aggregation, value) in aggregations {
+        let value = match aggregation {
+          Aggregation::Duration => i64::try_from(header.ticks_to_nanos(value as u64))?,
+          _ => value,
+        };
+        let column = column(&builder, aggregation.sample_type().0);
+        values[column.expect("every aggregation in use has a sample type")] = value;
+      }
+      builder.profile.sample.push(pprof::Sample {
+        location_id,
+        value: values,
+        label: Vec::new(),
+      });
+    }
+    for sample in &mut builder.profile.sample {
+      sample.value.resize(columns, 0);
+    }
+
+    if options.coalesce {
+      let samples = std::mem::take(&mut builder.profile.sample);
+      let before = samples.len() + coalesced;
+      builder.profile.sample = pprof::coalesce(samples);
+      info!(
+        "Coalesced {} samples into {}",
+        before,
+        builder.profile.sample.len()
+      );
+    }
+
+    if builder.capped_values > 0 {
+      info!(
+        "{} values past the first {} distinct ones are labeled {}",
+        builder.capped_values,
+        builder.values,
+        pprof::OTHER_VALUE
+      );
+    }
+    let mut profile = builder.finish();
+    info!(
+      "The string table of the profile holds {} strings",
+      profile.string_table.len()
+    );
+    if options.embed_counts {
+      let counts: Vec<_> = emitted
+        .iter()
+        .enumerate()
+        // a capped signal counts one extra change to warn only once
+        .map(|(i, n)| {
+          let n = options
+            .max_changes_per_signal
+            .map_or(*n, |max| (*n).min(max));
+          format!("{}: {n}", metadata.full_name(i))
+        })
+        .collect();
+      pprof::add_comment(
+        &mut profile,
+        format!("sample counts: {}", counts.join(", ")),
+      );
+    }
+    Ok(profile)
+  }
+}
+
+impl OutputSink for PprofSink<'_> {
+  fn handles(&self) -> Vec<FstSignalHandle> {
+    let mut handles = self.metadata.handle.clone();
+    for handle in self
+      .handshakes
+      .handles()
+      .into_iter()
+      .chain(self.transactions.handles())
+      .chain(self.states.handles())
+      .chain(self.options.cycle_clock)
+      .chain(self.options.trigger.as_ref().map(|trigger| trigger.handle))
+      .chain(self.aggregator.handles())
+    {
+      if !handles.contains(&handle) {
+        handles.push(handle);
+      }
+    }
+    handles
+  }
+
+  fn change(&mut self, t: u64, handle: FstSignalHandle, value: &SignalValue) {
+    // the changes before a resumed checkpoint only rebuild the state of the trackers
+    let replay = self.resume.is_some_and(|resume| t < resume);
+    if !replay && self.timeline.latest.map_or(true, |latest| t > latest) {
+      self.save_checkpoint(t);
+    }
+    let t = match replay {
+      true => t,
+      false => self.timeline.advance(t),
+    };
+    if !replay {
+      self.roll_bucket(t);
+    }
+    let options = self.options;
+    let applied;
+    let value = match value {
+      SignalValue::String(v) if xz::is_unknown(v) => {
+        if options.window.contains(t) && !replay {
+          for i in self.index.get(&handle.get_index()).into_iter().flatten() {
+            self.unknown[*i] += 1;
+          }
+        }
+        match options
+          .unknown_values
+          .as_ref()
+          .map(|xz| xz.apply(handle, v))
+        {
+          None => value,
+          Some(Ok(Some(v))) => {
+            applied = SignalValue::String(v);
+            &applied
+          }
+          Some(Ok(None)) => return,
+          Some(Err(err)) => {
+            if self.refused.is_none() {
+              let name = match self.index.get(&handle.get_index()) {
+                Some(positions) => self.metadata.full_name(positions[0]),
+                None => format!("handle {}", handle.get_index()),
+              };
+              self.refused = Some(format!("signal {name} at {t}: {err:#}"));
+            }
+            return;
+          }
+        }
+      }
+      _ => value,
+    };
+    if let (Some(clock), SignalValue::String(v)) = (self.options.cycle_clock, value) {
+      if handle == clock {
+        let prev = self.clock_value.replace(v.clone());
+        let rising = prev.and_then(|prev| Edge::classify(&prev, v)) == Some(Edge::Pos);
+        // past the window the cycle count stays where the window ends
+        if rising && self.options.window.end.map_or(true, |end| t <= end) {
+          self.cycles += 1;
+        }
+      }
+    }
+    if let (Some(trigger), SignalValue::String(v)) = (&options.trigger, value) {
+      if handle == trigger.handle {
+        let holds = trigger.holds(v);
+        if self.triggered && !holds && !replay {
+          // the latest values are only held while the trigger holds
+          let mut held: Vec<_> = self
+            .held
+            .iter()
+            .map(|(k, (_, i))| (k.clone(), *i))
+            .collect();
+          held.sort();
+          for (name, i) in held {
+            self.hold_until(&name, t);
+            self.paused.insert(name, i);
+          }
+        }
+        let asserted = !self.triggered && holds;
+        self.triggered = holds;
+        if asserted && !replay {
+          // the values held across the untriggered time go on from `t`
+          for (name, i) in std::mem::take(&mut self.paused) {
+            self.restart(name, i, t);
+          }
+          for (i, value) in std::mem::take(&mut self.untriggered) {
+            self.signal_change(i, t, &value);
+          }
+        }
+      }
+    }
+    if let SignalValue::String(v) = value {
+      self.handshakes.update(t, handle, v);
+      self.transactions.update(t, handle, v);
+      self.aggregator.update(t, handle, v);
+      for (machine, state) in self.states.update(handle, v) {
+        if replay {
+          continue;
+        }
+        self.hold_until(&machine.name, t);
+        if self.options.window.contains(t) && self.triggered {
+          let name = &machine.name;
+          // every state is a frame of its own under its state machine
+          let sample = SampleOf {
+            key: name,
+            name,
+            frames: &[&state, name],
+            value: &state,
+            num: None,
+            extra: &[],
+          };
+          self.sample(sample, t);
+        }
+      }
+    }
+    if replay {
+      return;
+    }
+    // aliases kept by `--keep-aliases` share the handle, every one of them gets the change
+    let Some(aliases) = self.index.get(&handle.get_index()).map(Vec::len) else {
+      return;
+    };
+    for alias in 0..aliases {
+      let i = self.index[&handle.get_index()][alias];
+      self.signal_change(i, t, value);
+    }
+  }
+}
+
+impl PprofSink<'_> {
+  /// Handle the change of the i-th signal of the metadata at time `t`.
+  fn signal_change(&mut self, i: usize, t: u64, value: &SignalValue) {
+    let name = self.metadata.full_name(i);
+    self.hold_until(&name, t);
+    let (v, real) = match &*self.metadata.value_of(i, value) {
+      SignalValue::String(s) => (s.clone(), None),
+      SignalValue::Real(r) => (r.to_string(), Some(*r)),
+    };
+    if self.options.final_state {
+      self.final_values[i] = Some(v.clone());
+    }
+    let mut extra = Vec::new();
+    if let Some(filter) = self.options.edges {
+      let prev = self.last_values.insert(i, v.clone());
+      match prev.and_then(|prev| Edge::classify(&prev, &v)) {
+        Some(edge) if filter.accept(edge) => extra.push(("edge".to_string(), edge.as_str().into())),
+        _ => return,
+      }
+    }
+    if !self.triggered {
+      self.paused.remove(&name);
+      self.untriggered.insert(i, value.clone());
+      return;
+    }
+    if !self.options.window.contains(t) {
+      return;
+    }
+    let properties = self.options.properties.get(&name, &self.metadata.names[i]);
+    if let Some(histogram) = properties.and_then(|p| p.histogram) {
+      let bin = histogram.bin(&v, self.metadata.widths[i]);
+      *self
+        .histograms
+        .entry(i)
+        .or_default()
+        .entry(bin)
+        .or_default() += 1;
+      return;
+    }
+    if let Some(max) = self.options.max_changes_per_signal {
+      if self.emitted[i] >= max {
+        if self.emitted[i] == max {
+          warn!("signal {name} reaches {max} changes, further changes are dropped");
+          self.capped.push(i);
+          // only warn once
+          self.emitted[i] += 1;
+        }
+        return;
+      }
+    }
+    self.emitted[i] += 1;
+    trace!(
+      "time: {} module: {} signal: {} value: {}",
+      t,
+      self.metadata.module_paths[i].join("."),
+      self.metadata.names[i],
+      v
+    );
+
+    if self.options.bit_labels && real.is_none() && self.metadata.widths[i] > 1 {
+      // a slice is labeled with the bit numbers of the signal it is cut from
+      let (base, lsb) = match self.metadata.slices[i] {
+        Some(slice) => (
+          BitSlice::split(&name).map_or(name.as_str(), |(base, _)| base),
+          slice.lsb,
+        ),
+        None => (name.as_str(), 0),
+      };
+      for (bit, value) in v.chars().rev().enumerate() {
+        extra.push((format!("{base}[{}]", lsb as usize + bit), value.to_string()));
+      }
+    }
+
+    let unit = properties.and_then(|p| p.unit.as_deref());
+    let real_unit;
+    let symbol = self.metadata.enum_name(i, &v).map(str::to_string);
+    let (v, num) = match (real, symbol, properties.and_then(|p| p.radix)) {
+      (Some(real), _, _) => {
+        let num = match self.options.real_scale {
+          Some(scale) => {
+            real_unit = format!("{}/{scale}", unit.unwrap_or("1"));
+            scale_real(real, scale).map(|num| (num, real_unit.as_str()))
+          }
+          None => None,
+        };
+        (v, num)
+      }
+      // the names of an enum table win over any radix
+      (None, Some(symbol), _) => (symbol, None),
+      (None, None, Some(radix)) => (radix.format(&v), None),
+      // a unit tells a 1-bit signal is a number too
+      (None, None, None) if self.metadata.widths[i] > 1 || unit.is_some() => {
+        let unit = unit.unwrap_or("bit");
+        let num = parse_bus(&v, self.metadata.widths[i]).map(|num| (num, unit));
+        (v, num)
+      }
+      (None, None, None) => (v, None),
+    };
+    let ranged;
+    let display = match properties.and_then(|p| p.label.as_deref()) {
+      Some(label) => label,
+      None if self.options.bus_ranges => {
+        ranged = self.metadata.ranged_name(i);
+        &ranged
+      }
+      None => &name,
+    };
+    let scopes = match self.options.hierarchy_stacks {
+      true => self.metadata.module_paths[i].clone(),
+      false => Vec::new(),
+    };
+    // `TOP -> core -> lsu -> valid`, the scopes being frames of their own
+    let prefix = format!("{}.", scopes.join("."));
+    let leaf = display.strip_prefix(&prefix).unwrap_or(display);
+    let frames: Vec<&str> = std::iter::once(leaf)
+      .chain(scopes.iter().rev().map(String::as_str))
+      .chain(properties.and_then(|p| p.group.as_deref()))
+      .collect();
+    let sample = SampleOf {
+      key: &name,
+      name: display,
+      frames: &frames,
+      value: &v,
+      num,
+      extra: &extra,
+    };
+    self.sample(sample, t);
+  }
+
+  /// Push the sample of a signal changing at time `t`.
+  fn sample(&mut self, sample: SampleOf, t: u64) {
+    let SampleOf {
+      key,
+      name,
+      frames,
+      value,
+      num,
+      extra,
+    } = sample;
+    let since = self.held_time(t);
+    let builder = &mut self.builder;
+    // a number label uses no string, only string labels are capped
+    let shown = match num {
+      Some(_) => value,
+      None => builder.value(value),
+    };
+    let location_id = if self.options.group_by_value {
+      // leaf first, so the call tree groups samples of one signal by its value
+      let leaf = format!("{name}={shown}");
+      let stack: Vec<&str> = std::iter::once(leaf.as_str())
+        .chain(frames.iter().copied())
+        .collect();
+      builder.stack(&stack)
+    } else {
+      builder.stack(frames)
+    };
+    let mut label = vec![match num {
+      Some((num, unit)) => builder.num_label(name, num, unit),
+      None => builder.label(name, shown),
+    }];
+    for (key, value) in extra {
+      label.push(builder.label(key, value));
+    }
+    // last, so sorting the samples by signal still looks at the signal label
+    if let Some(len) = self.options.bucket {
+      label.push(builder.num_label("bucket", (t / len * len) as i64, "ticks"));
+    }
+    let transition = self
+      .sampled
+      .insert(key.to_string(), value.to_string())
+      .is_some_and(|prev| prev != value);
+    let sample = pprof::Sample {
+      location_id,
+      value: vec![1, 0, transition.into()],
+      label,
+    };
+    // in time order the samples to coalesce are adjacent as soon as they are emitted, so merge
+    // them right away instead of holding every change until the end. Held times are added to
+    // the merged sample when they are known.
+    let incremental = self.options.coalesce && self.options.sample_order == SampleOrder::Time;
+    let last = builder.profile.sample.last_mut();
+    if incremental && last.is_some_and(|last| pprof::merge(last, &sample)) {
+      self.coalesced += 1;
+    } else {
+      builder.profile.sample.push(sample);
+    }
+    self
+      .held
+      .insert(key.to_string(), (since, builder.profile.sample.len() - 1));
+  }
+
+  /// Record that signal `name` changes at time `t`, so its latest sample held its value until
+  /// then, or until the end of the window.
+  fn hold_until(&mut self, name: &str, t: u64) {
+    if let Some((since, idx)) = self.held.remove(name) {
+      let until = self.held_time(t);
+      self.builder.profile.sample[idx].value[1] += until.saturating_sub(since) as i64;
+    }
+  }
+
+  /// Move to the time bucket of `t`. The values held across the start of every bucket passed go
+  /// on in a new sample of that bucket, counting no change. Without any held value the buckets
+  /// passed are skipped at once.
+  fn roll_bucket(&mut self, t: u64) {
+    let Some(len) = self.options.bucket else {
+      return;
+    };
+    let Some(mut bucket) = self.bucket else {
+      self.bucket = Some(t / len * len);
+      return;
+    };
+    if self.held.is_empty() {
+      self.bucket = Some(bucket.max(t / len * len));
+      return;
+    }
+    while bucket + len <= t {
+      bucket += len;
+      let mut held: Vec<_> = self
+        .held
+        .iter()
+        .map(|(k, (_, i))| (k.clone(), *i))
+        .collect();
+      // in the order of the samples held, not the one of the map
+      held.sort_by_key(|(_, i)| *i);
+      for (name, i) in held {
+        self.hold_until(&name, bucket);
+        self.restart(name, i, bucket);
+      }
+    }
+    self.bucket = Some(bucket);
+  }
+
+  /// Go on holding the value of the i-th sample, of signal `name`, in a new sample from time `t`
+  /// counting no change.
+  fn restart(&mut self, name: String, i: usize, t: u64) {
+    let mut sample = self.builder.profile.sample[i].clone();
+    sample.value.iter_mut().for_each(|v| *v = 0);
+    if let Some(len) = self.options.bucket {
+      *sample
+        .label
+        .last_mut()
+        .expect("samples end with their bucket") =
+        self
+          .builder
+          .num_label("bucket", (t / len * len) as i64, "ticks");
+    }
+    self.builder.profile.sample.push(sample);
+    let since = self.held_time(t);
+    self
+      .held
+      .insert(name, (since, self.builder.profile.sample.len() - 1));
+  }
+
+  /// Return the time a value held from or until `t` is measured at: the number of cycles so far
+  /// with a cycle clock, `t` within the window in nanoseconds otherwise. Converting the times
+  /// instead of the lengths keeps the lengths of sub-nanosecond ticks adding up.
+  fn held_time(&self, t: u64) -> u64 {
+    match self.options.cycle_clock {
+      Some(_) => self.cycles,
+      None => self
+        .header
+        .ticks_to_nanos(self.options.window.end.map_or(t, |end| t.min(end))),
+    }
+  }
+}
+
+/// Parse the binary value of a `width` bits wide bus. Return `None` if it has unknown bits or
+/// doesn't fit in a label number.
+fn parse_bus(value: &str, width: u32) -> Option<i64> {
+  // from_str_radix would also accept a sign
+  if width > 63 || value.len() > 63 || !value.bytes().all(|b| b == b'0' || b == b'1') {
+    return None;
+  }
+  i64::from_str_radix(value, 2).ok()
+}
+
+/// Scale a real value into a label number, rounding away the precision below `1 / scale`. Return
+/// `None` if the scaled value doesn't fit in a label number.
+fn scale_real(value: f64, scale: f64) -> Option<i64> {
+  let scaled = (value * scale).round();
+  // i64::MAX isn't representable as a f64, its nearest value is 2^63 which is out of range
+  (scaled.is_finite() && scaled >= i64::MIN as f64 && scaled < i64::MAX as f64)
+    .then_some(scaled as i64)
+}
+
+/// What the sample of one value change is made of.
+struct SampleOf<'s> {
+  /// Signal changing, holding its value until its next change
+  key: &'s str,
+  /// Displayed name of the signal, labeling the value
+  name: &'s str,
+  /// Stack of the sample, leaf first
+  frames: &'s [&'s str],
+  value: &'s str,
+  /// Number and unit the value is labeled with instead of its string
+  num: Option<(i64, &'s str)>,
+  /// Labels added after the one of the value
+  extra: &'s [(String, String)],
+}
+
+/// Keep the time of value changes monotonic. A change going back in time is clamped to the
+/// latest time seen so far, so every delta computed from it is zero instead of negative.
+#[derive(Debug, Clone, Default)]
+struct Timeline {
+  latest: Option<u64>,
+  /// Number of changes that went back in time
+  backwards: usize,
+}
+
+impl Timeline {
+  /// Return the monotonic time of a change that happens at `t`.
+  fn advance(&mut self, t: u64) -> u64 {
+    match self.latest {
+      Some(latest) if t < latest => {
+        if self.backwards == 0 {
+          warn!("value change at time {t} is before the previous change at time {latest}");
+        }
+        self.backwards += 1;
+        latest
+      }
+      _ => {
+        self.latest = Some(t);
+        t
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::aggregate::{self, Aggregation, AggregationConfig};
+  use crate::fixture::{labels, FixtureWave};
+  use crate::transaction::{self, TransactionConfig};
+  use crate::trigger::{self, TriggerConfig};
+  use crate::xz::{XzConfig, XzPolicy};
+  use crate::{collect_signals, CollectOptions};
+
+  #[test]
+  fn clamp_non_monotonic_timestamps() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    wave.upscope();
+    wave
+      .change(0, valid, "0")
+      .change(5, valid, "1")
+      .change(3, valid, "0")
+      .change(7, valid, "1");
+
+    let metadata = wave.collect(&["valid"]);
+
+    let profile = convert(&mut wave, &metadata, &ConvertOptions::default()).unwrap();
+    assert_eq!(profile.sample.len(), 4);
+
+    let strict = ConvertOptions {
+      strict: true,
+      ..Default::default()
+    };
+    assert!(convert(&mut wave, &metadata, &strict).is_err());
+
+    let mut timeline = Timeline::default();
+    let times: Vec<_> = [0, 5, 3, 7]
+      .into_iter()
+      .map(|t| timeline.advance(t))
+      .collect();
+    assert_eq!(times, [0, 5, 5, 7]);
+    assert_eq!(timeline.backwards, 1);
+  }
+
+  #[test]
+  fn final_state_snapshot() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    let state = wave.var("state", 2);
+    wave.upscope();
+    wave
+      .change(0, valid, "0")
+      .change(0, state, "00")
+      .change(4, valid, "1")
+      .change(6, state, "10");
+
+    let metadata = wave.collect(&["valid", "state"]);
+    let options = ConvertOptions {
+      final_state: true,
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+
+    let snapshot = profile.sample.last().unwrap();
+    assert_eq!(
+      labels(&profile, snapshot),
+      [("top.valid", "1"), ("top.state", "10")]
+    );
+  }
+
+  #[test]
+  fn cap_changes_per_signal() {
+    let mut wave = FixtureWave::new(0, 100);
+    wave.scope("top");
+    let glitch = wave.var("glitch", 1);
+    let valid = wave.var("valid", 1);
+    wave.upscope();
+    for t in 0..50 {
+      wave.change(t, glitch, if t % 2 == 0 { "0" } else { "1" });
+    }
+    wave.change(60, valid, "1");
+
+    let metadata = wave.collect(&["glitch", "valid"]);
+    let options = ConvertOptions {
+      max_changes_per_signal: Some(10),
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    assert_eq!(profile.sample.len(), 11);
+
+    let options = ConvertOptions {
+      embed_counts: true,
+      ..options
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    let comment = &profile.string_table[*profile.comment.last().unwrap() as usize];
+    assert_eq!(comment, "sample counts: top.glitch: 10, top.valid: 1");
+  }
+
+  #[test]
+  fn group_samples_by_signal() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    let ready = wave.var("ready", 1);
+    wave.upscope();
+    wave
+      .change(0, valid, "0")
+      .change(0, ready, "0")
+      .change(2, valid, "1")
+      .change(3, ready, "1")
+      .change(4, valid, "0");
+
+    let metadata = wave.collect(&["valid", "ready"]);
+    let options = ConvertOptions {
+      sample_order: SampleOrder::Signal,
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+
+    let order: Vec<_> = profile
+      .sample
+      .iter()
+      .map(|sample| labels(&profile, sample)[0])
+      .collect();
+    assert_eq!(
+      order,
+      [
+        ("top.valid", "0"),
+        ("top.valid", "1"),
+        ("top.valid", "0"),
+        ("top.ready", "0"),
+        ("top.ready", "1"),
+      ]
+    );
+  }
+
+  #[test]
+  fn weight_samples_by_held_time() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    wave.upscope();
+    wave
+      .change(0, valid, "0")
+      .change(4, valid, "1")
+      .change(6, valid, "0");
+
+    let metadata = wave.collect(&["valid"]);
+    let profile = convert(&mut wave, &metadata, &ConvertOptions::default()).unwrap();
+    let values: Vec<_> = profile.sample.iter().map(|s| s.value.clone()).collect();
+    assert_eq!(values, [[1, 4, 0], [1, 2, 1], [1, 4, 1]]);
+  }
+
+  #[test]
+  fn label_bus_samples_with_their_bits() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let state = wave.var("state", 4);
+    wave.upscope();
+    wave.change(0, state, "0010");
+
+    let metadata = wave.collect(&["state", "top.state[3:2]"]);
+    let options = ConvertOptions {
+      bit_labels: true,
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    let bits: Vec<_> = profile
+      .sample
+      .iter()
+      .map(|sample| labels(&profile, sample)[1..].to_vec())
+      .collect();
+    assert_eq!(
+      bits,
+      [
+        vec![
+          ("top.state[0]", "0"),
+          ("top.state[1]", "1"),
+          ("top.state[2]", "0"),
+          ("top.state[3]", "0")
+        ],
+        vec![("top.state[2]", "0"), ("top.state[3]", "0")],
+      ]
+    );
+  }
+
+  #[test]
+  fn label_samples_with_their_bucket() {
+    let mut wave = FixtureWave::new(0, 40);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    wave.upscope();
+    wave.change(0, valid, "0").change(15, valid, "1");
+
+    let metadata = wave.collect(&["valid"]);
+    let options = ConvertOptions {
+      bucket: Some(10),
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    let samples: Vec<_> = profile
+      .sample
+      .iter()
+      .map(|s| (s.label[1].num, s.value.clone()))
+      .collect();
+    // the held values go on in every bucket they span, without counting a change
+    assert_eq!(
+      samples,
+      [
+        (0, vec![1, 10, 0]),
+        (10, vec![0, 5, 0]),
+        (10, vec![1, 5, 1]),
+        (20, vec![0, 10, 0]),
+        (30, vec![0, 10, 0]),
+      ]
+    );
+  }
+
+  #[test]
+  fn hold_values_in_nanoseconds() {
+    // ticks of 100ps
+    let mut wave = FixtureWave::new(0, 40);
+    wave.timescale(-10);
+    let valid = wave.var("valid", 1);
+    wave.change(0, valid, "0").change(15, valid, "1");
+
+    let metadata = wave.collect(&["valid"]);
+    let profile = convert(&mut wave, &metadata, &ConvertOptions::default()).unwrap();
+    let unit = profile.sample_type[1].unit;
+    assert_eq!(profile.string_table[unit as usize], "nanoseconds");
+    assert_eq!(profile.duration_nanos, 4);
+    // the held times still add up to the duration
+    let values: Vec<_> = profile.sample.iter().map(|s| s.value.clone()).collect();
+    assert_eq!(values, [[1, 1, 0], [1, 3, 1]]);
+  }
+
+  #[test]
+  fn coalesce_samples_as_they_are_emitted() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    wave.upscope();
+    wave
+      .change(0, valid, "0")
+      .change(2, valid, "1")
+      .change(4, valid, "1")
+      .change(7, valid, "1")
+      .change(9, valid, "0");
+
+    let metadata = wave.collect(&["valid"]);
+    // signal order only coalesces once every sample is emitted
+    for sample_order in [SampleOrder::Time, SampleOrder::Signal] {
+      let options = ConvertOptions {
+        coalesce: true,
+        sample_order,
+        ..Default::default()
+      };
+      let profile = convert(&mut wave, &metadata, &options).unwrap();
+      let values: Vec<_> = profile.sample.iter().map(|s| s.value.clone()).collect();
+      assert_eq!(values, [[1, 2, 0], [3, 7, 1], [1, 1, 1]]);
+    }
+  }
+
+  #[test]
+  fn sample_while_triggered() {
+    let mut wave = FixtureWave::new(0, 14);
+    wave.scope("top");
+    let stall = wave.var("stall", 1);
+    let data = wave.var("data", 1);
+    let mode = wave.var("mode", 1);
+    wave.upscope();
+    wave
+      .change(0, stall, "0")
+      .change(0, data, "0")
+      .change(0, mode, "0")
+      .change(2, data, "1")
+      .change(4, stall, "1")
+      .change(5, data, "0")
+      .change(5, mode, "1")
+      .change(7, data, "1")
+      .change(8, stall, "0")
+      .change(9, data, "0")
+      .change(11, stall, "1");
+
+    let metadata = wave.collect(&["data", "mode"]);
+    let config = TriggerConfig {
+      signal: "top.stall".to_string(),
+      value: "1".to_string(),
+    };
+    let options = ConvertOptions {
+      trigger: trigger::resolve(&crate::SignalLookup::new(&mut wave).unwrap(), Some(&config))
+        .unwrap(),
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    let values: Vec<_> = profile.sample.iter().map(|s| s.value.clone()).collect();
+    // the values at 4 are sampled as the trigger holds, then at 11 the unchanged mode goes on
+    // and data is sampled with the value it changed to meanwhile
+    let expected = [
+      [1, 1, 0],
+      [1, 1, 0],
+      [1, 2, 1],
+      [1, 3, 1],
+      [1, 1, 1],
+      [0, 3, 0],
+      [1, 3, 1],
+    ];
+    assert_eq!(values, expected);
+
+    let config = TriggerConfig {
+      value: "10".to_string(),
+      ..config
+    };
+    assert!(
+      trigger::resolve(&crate::SignalLookup::new(&mut wave).unwrap(), Some(&config)).is_err()
+    );
+  }
+
+  #[test]
+  fn apply_xz_policy_to_changes() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    let data = wave.var("data", 4);
+    wave.upscope();
+    wave
+      .change(0, valid, "0")
+      .change(0, data, "0001")
+      .change(3, valid, "x")
+      .change(4, data, "0z01")
+      .change(6, valid, "1");
+
+    let metadata = wave.collect(&["valid", "data"]);
+    let mut config = XzConfig {
+      policy: XzPolicy::TreatAsPrevious,
+      overrides: [("top.data".to_string(), XzPolicy::SeparateBucket)].into(),
+    };
+    let options = ConvertOptions {
+      unknown_values: xz::resolve(&crate::SignalLookup::new(&mut wave).unwrap(), Some(&config))
+        .unwrap(),
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    let values: Vec<_> = profile.sample.iter().map(|s| s.value.clone()).collect();
+    // the x of valid is dropped, so its first value is held until it rises
+    assert_eq!(values, [[1, 6, 0], [1, 4, 0], [1, 6, 1], [1, 4, 1]]);
+    let label = &profile.sample[2].label[0];
+    assert_eq!(profile.string_table[label.str as usize], "xxxx");
+
+    config.policy = XzPolicy::Error;
+    let options = ConvertOptions {
+      unknown_values: xz::resolve(&crate::SignalLookup::new(&mut wave).unwrap(), Some(&config))
+        .unwrap(),
+      ..Default::default()
+    };
+    assert!(convert(&mut wave, &metadata, &options).is_err());
+  }
+
+  #[test]
+  fn aggregate_signals_in_their_sample_types() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    wave.upscope();
+    wave
+      .change(0, valid, "0")
+      .change(2, valid, "1")
+      .change(5, valid, "0")
+      .change(8, valid, "1");
+
+    let metadata = wave.collect(&["valid"]);
+    let configs: Vec<_> = [
+      Aggregation::DutyCycle,
+      Aggregation::Duration,
+      Aggregation::Toggles,
+    ]
+    .into_iter()
+    .map(|aggregation| AggregationConfig {
+      signal: "top.valid".to_string(),
+      aggregation,
+    })
+    .collect();
+    let options = ConvertOptions {
+      aggregations: aggregate::resolve(&crate::SignalLookup::new(&mut wave).unwrap(), &configs)
+        .unwrap(),
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+
+    let types: Vec<_> = profile
+      .sample_type
+      .iter()
+      .map(|t| profile.string_table[t.r#type as usize].as_str())
+      .collect();
+    assert_eq!(
+      types,
+      [
+        "changes",
+        "time",
+        "transitions",
+        "high",
+        "toggles",
+        "duty_cycle"
+      ]
+    );
+    let values: Vec<_> = profile.sample.iter().map(|s| s.value.clone()).collect();
+    assert_eq!(
+      values,
+      [
+        [1, 2, 0, 0, 0, 0],
+        [1, 3, 1, 0, 0, 0],
+        [1, 3, 1, 0, 0, 0],
+        [1, 2, 1, 0, 0, 0],
+        [0, 0, 0, 5, 3, 50],
+      ]
+    );
+  }
+
+  #[test]
+  fn measure_transaction_latency_in_its_sample_type() {
+    let mut wave = FixtureWave::new(0, 40);
+    wave.scope("top");
+    let clock = wave.var("clock", 1);
+    let req = wave.var("req", 1);
+    let resp = wave.var("resp", 1);
+    wave.upscope();
+    let mut changes = vec![(0, req, "0"), (0, resp, "0")];
+    // rising edges at 5, 15, 25 and 35
+    for t in (0..40).step_by(5) {
+      changes.push((t, clock, if t % 10 == 0 { "0" } else { "1" }));
+    }
+    changes.extend([(2, req, "1"), (8, req, "0"), (22, resp, "1")]);
+    changes.sort_by_key(|(t, _, _)| *t);
+    for (t, handle, value) in changes {
+      wave.change(t, handle, value);
+    }
+
+    let configs = vec![TransactionConfig {
+      name: "get".to_string(),
+      clock: "top.clock".to_string(),
+      start: "top.req".to_string(),
+      end: "top.resp".to_string(),
+    }];
+    let options = ConvertOptions {
+      transactions: transaction::resolve(&crate::SignalLookup::new(&mut wave).unwrap(), &configs)
+        .unwrap(),
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &SignalMetadata::default(), &options).unwrap();
+
+    let types: Vec<_> = profile
+      .sample_type
+      .iter()
+      .map(|t| profile.string_table[t.r#type as usize].as_str())
+      .collect();
+    assert_eq!(types, ["changes", "time", "transitions", "latency"]);
+    let values: Vec<_> = profile.sample.iter().map(|s| s.value.clone()).collect();
+    assert_eq!(values, [[1, 0, 0, 2]]);
+  }
+
+  #[test]
+  fn cap_distinct_label_values() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    // too wide for a number label
+    let data = wave.var("data", 80);
+    wave.upscope();
+    let bits = |n: u8| format!("{n:080b}");
+    for (t, n) in [0, 1, 2, 1, 3].into_iter().enumerate() {
+      wave.change(t as u64, data, &bits(n));
+    }
+
+    let metadata = wave.collect(&["data"]);
+    let options = ConvertOptions {
+      max_label_values: Some(2),
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    let labels: Vec<_> = profile
+      .sample
+      .iter()
+      .map(|s| profile.string_table[s.label[0].str as usize].as_str())
+      .collect();
+    let other = pprof::OTHER_VALUE.to_string();
+    assert_eq!(labels, [bits(0), bits(1), other.clone(), bits(1), other]);
+    assert!(!profile.string_table.contains(&bits(2)));
+
+    // every table has its own hash seed, the output still doesn't depend on it
+    let again = convert(&mut wave, &metadata, &options).unwrap();
+    assert_eq!(again.encode_to_vec(), profile.encode_to_vec());
+
+    // a narrow bus is labeled by number, its values don't count against the cap
+    let mut wave = FixtureWave::new(0, 10);
+    let narrow = wave.var("narrow", 4);
+    for (t, value) in ["0011", "0100", "0011", "0100"].into_iter().enumerate() {
+      wave.change(t as u64, narrow, value);
+    }
+    let metadata = wave.collect(&["narrow"]);
+    let options = ConvertOptions {
+      max_label_values: Some(1),
+      group_by_value: true,
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    let leaves: Vec<_> = profile
+      .sample
+      .iter()
+      .map(|s| {
+        let function = &profile.function[s.location_id[0] as usize - 1];
+        profile.string_table[function.name as usize].as_str()
+      })
+      .collect();
+    let leaves = &leaves[..4];
+    assert_eq!(
+      leaves,
+      ["narrow=0011", "narrow=0100", "narrow=0011", "narrow=0100"]
+    );
+  }
+
+  #[test]
+  fn weight_samples_by_held_cycles() {
+    let mut wave = FixtureWave::new(0, 40);
+    wave.scope("top");
+    let clock = wave.var("clock", 1);
+    let valid = wave.var("valid", 1);
+    wave.upscope();
+    wave.change(0, valid, "0");
+    for t in 0..40 {
+      wave.change(t, clock, if t % 2 == 0 { "0" } else { "1" });
+      if t == 9 {
+        wave.change(t, valid, "1");
+      }
+    }
+
+    let metadata = wave.collect(&["valid"]);
+    let options = ConvertOptions {
+      cycle_clock: Some(clock),
+      window: TimeWindow {
+        end: Some(30),
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    let held: Vec<_> = profile.sample.iter().map(|s| s.value[1]).collect();
+    // rising edges at 1, 3, 5, 7 then 9 up to 29
+    assert_eq!(held, [5, 10]);
+  }
+
+  #[test]
+  fn count_transitions_per_signal() {
+    let mut wave = FixtureWave::new(0, 20);
+    wave.scope("top");
+    let clock = wave.var("clock", 1);
+    let reset = wave.var("reset", 1);
+    wave.upscope();
+    for t in 0..20 {
+      wave.change(t, clock, if t % 2 == 0 { "0" } else { "1" });
+      // the same value recorded again isn't a transition
+      wave.change(t, reset, "1");
+    }
+
+    let metadata = wave.collect(&["clock", "reset"]);
+    let profile = convert(&mut wave, &metadata, &ConvertOptions::default()).unwrap();
+    assert_eq!(
+      profile.string_table[profile.sample_type[2].r#type as usize],
+      "transitions"
+    );
+
+    let transitions = |name: &str| -> i64 {
+      profile
+        .sample
+        .iter()
+        .filter(|s| profile.string_table[s.label[0].key as usize] == name)
+        .map(|s| s.value[2])
+        .sum()
+    };
+    assert_eq!(transitions("top.clock"), 19);
+    assert_eq!(transitions("top.reset"), 0);
+  }
+
+  #[test]
+  fn label_numbers_with_their_unit() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let retired = wave.var("retired", 4);
+    let stall = wave.var("stall", 1);
+    let valid = wave.var("valid", 1);
+    wave.upscope();
+    wave
+      .change(0, retired, "0101")
+      .change(0, stall, "1")
+      .change(0, valid, "1");
+
+    let metadata = wave.collect(&["retired", "stall", "valid"]);
+    let options = ConvertOptions {
+      properties: serde_json::from_str(
+        r#"{ "top.retired": { "unit": "instructions" }, "stall": { "unit": "count" } }"#,
+      )
+      .unwrap(),
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    let labels: Vec<_> = profile
+      .sample
+      .iter()
+      .map(|sample| {
+        let label = &sample.label[0];
+        let string = |id: i64| profile.string_table[id as usize].as_str();
+        (string(label.str), label.num, string(label.num_unit))
+      })
+      .collect();
+    assert_eq!(
+      labels,
+      [("", 5, "instructions"), ("", 1, "count"), ("1", 0, "")]
+    );
+  }
+
+  #[test]
+  fn count_values_in_histograms() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let addr = wave.var("addr", 8);
+    let data = wave.var("data", 2);
+    let count = wave.var("count", 4);
+    wave.upscope();
+    wave
+      .change(0, addr, "00010000")
+      .change(0, data, "01")
+      .change(0, count, "1010")
+      .change(1, count, "1001")
+      .change(2, addr, "00100000")
+      .change(3, data, "11")
+      .change(4, addr, "11111111")
+      .change(5, data, "01")
+      .change(6, addr, "0011xxxx");
+
+    let metadata = wave.collect(&["addr", "data", "count"]);
+    let options = ConvertOptions {
+      properties: serde_json::from_str(
+        r#"{
+        "top.addr": { "histogram": { "bins": 4 }, "radix": "hex" },
+        "data": { "histogram": {}, "label": "bus data" },
+        "count": { "histogram": {}, "radix": "dec" }
+      }"#,
+      )
+      .unwrap(),
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    let string = |id: i64| profile.string_table[id as usize].as_str();
+    let bins: Vec<_> = profile
+      .sample
+      .iter()
+      .map(|sample| {
+        let label = &sample.label[0];
+        (string(label.key), string(label.str), sample.value[0])
+      })
+      .collect();
+    assert_eq!(
+      bins,
+      [
+        ("top.addr", "0x0..0x3f", 2),
+        ("top.addr", "0xc0..0xff", 1),
+        ("top.addr", "0011xxxx", 1),
+        ("bus data", "01", 2),
+        ("bus data", "11", 1),
+        // in numeric order, 9 before 10
+        ("top.count", "9", 1),
+        ("top.count", "10", 1),
+      ]
+    );
+  }
+
+  #[test]
+  fn parse_bus_values() {
+    assert_eq!(parse_bus("0", 1), Some(0));
+    assert_eq!(parse_bus("1111", 4), Some(15));
+    assert_eq!(parse_bus("1xz0", 4), None);
+    assert_eq!(parse_bus(&"1".repeat(128), 128), None);
+  }
+
+  #[test]
+  fn scale_real_values() {
+    assert_eq!(scale_real(1.2345, 1000.0), Some(1235));
+    assert_eq!(scale_real(-0.5, 1.0), Some(-1));
+    assert_eq!(scale_real(f64::NAN, 1000.0), None);
+    assert_eq!(scale_real(1e300, 1000.0), None);
+  }
+
+  #[test]
+  fn name_buses_with_their_range() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    let data = wave.var("data", 8);
+    let queue = wave.var("queue [2:0]", 3);
+    wave.upscope();
+    wave
+      .change(0, valid, "0")
+      .change(0, data, "00000000")
+      .change(0, queue, "000");
+
+    let metadata = wave.collect(&["valid", "data", "queue*"]);
+    let options = ConvertOptions {
+      bus_ranges: true,
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    let keys: Vec<_> = profile
+      .sample
+      .iter()
+      .map(|sample| profile.string_table[sample.label[0].key as usize].as_str())
+      .collect();
+    assert_eq!(keys, ["top.valid", "top.data [7:0]", "top.queue [2:0]"]);
+  }
+
+  #[test]
+  fn stack_signals_under_their_scopes() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("TOP").scope("core").scope("lsu");
+    let valid = wave.var("valid", 1);
+    wave.upscope();
+    let data = wave.var("data", 4);
+    wave.upscope().upscope();
+    wave.change(0, valid, "0").change(0, data, "0000");
+
+    let metadata = wave.collect(&["valid", "data"]);
+    let options = ConvertOptions {
+      hierarchy_stacks: true,
+      bus_ranges: true,
+      ..Default::default()
+    };
+    let profile = convert(&mut wave, &metadata, &options).unwrap();
+    let name = |id: u64| {
+      let function =
+        &profile.function[profile.location[id as usize - 1].line[0].function_id as usize - 1];
+      profile.string_table[function.name as usize].as_str()
+    };
+    let stacks: Vec<Vec<_>> = profile
+      .sample
+      .iter()
+      .map(|sample| sample.location_id.iter().map(|id| name(*id)).collect())
+      .collect();
+    assert_eq!(
+      stacks,
+      [
+        vec!["valid", "lsu", "core", "TOP"],
+        vec!["data [3:0]", "core", "TOP"]
+      ]
+    );
+    // the labels still tell the signals apart by their full name
+    let key = profile.sample[0].label[0].key;
+    assert_eq!(profile.string_table[key as usize], "TOP.core.lsu.valid");
+  }
+
+  #[test]
+  fn label_values_with_enum_names() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.enum_table(1, &[("00", "IDLE"), ("01", "FETCH")]);
+    wave.scope("top");
+    wave.enum_ref(1);
+    let state = wave.var("state", 2);
+    let data = wave.var("data", 2);
+    wave.upscope();
+    wave
+      .change(0, state, "00")
+      .change(0, data, "00")
+      .change(3, state, "01")
+      .change(5, state, "11");
+
+    let metadata = wave.collect(&["state", "data"]);
+    assert!(metadata.enums[0].is_some() && metadata.enums[1].is_none());
+    let profile = convert(&mut wave, &metadata, &ConvertOptions::default()).unwrap();
+    let values: Vec<_> = profile
+      .sample
+      .iter()
+      .map(|sample| match &sample.label[0] {
+        label if label.str != 0 => profile.string_table[label.str as usize].clone(),
+        label => label.num.to_string(),
+      })
+      .collect();
+    // values missing from the table keep their number label
+    assert_eq!(values, ["IDLE", "0", "FETCH", "3"]);
+  }
+
+  #[test]
+  fn emit_changes_of_every_kept_alias() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    wave.scope("core");
+    wave.alias("valid", 1, valid);
+    wave.upscope();
+    wave.upscope();
+    wave.change(0, valid, "0").change(5, valid, "1");
+
+    let expected = vec!["valid".to_string()];
+    let options = CollectOptions {
+      keep_aliases: true,
+      ..Default::default()
+    };
+    let metadata = collect_signals(&mut wave, &expected, &options).unwrap();
+    assert_eq!(metadata.handle, [valid, valid]);
+
+    let profile = convert(&mut wave, &metadata, &ConvertOptions::default()).unwrap();
+    let samples: Vec<_> = profile
+      .sample
+      .iter()
+      .map(|sample| labels(&profile, sample)[0])
+      .collect();
+    assert_eq!(
+      samples,
+      [
+        ("top.valid", "0"),
+        ("top.core.valid", "0"),
+        ("top.valid", "1"),
+        ("top.core.valid", "1"),
+      ]
+    );
+  }
+
+  #[test]
+  fn resume_from_checkpoint() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    let data = wave.var("data", 4);
+    wave.upscope();
+    let changes = [
+      (0, valid, "0"),
+      (0, data, "0000"),
+      (2, valid, "1"),
+      (3, data, "0011"),
+      (5, valid, "0"),
+      (7, data, "0011"),
+      (8, valid, "1"),
+    ];
+    for (t, handle, value) in changes {
+      wave.change(t, handle, value);
+    }
+
+    let metadata = wave.collect(&["valid", "data"]);
+    let options = ConvertOptions {
+      final_state: true,
+      ..Default::default()
+    };
+    let whole = convert(&mut wave, &metadata, &options).unwrap();
+
+    // interrupted right before the changes at 5
+    let mut interrupted = PprofSink::new(&wave.header(), metadata.clone(), &options).unwrap();
+    for (t, handle, value) in changes.iter().filter(|(t, _, _)| *t < 5) {
+      interrupted.change(*t, *handle, &SignalValue::String(value.to_string()));
+    }
+    let saved = serde_json::to_vec(&interrupted.checkpoint(5)).unwrap();
+
+    let mut resumed = PprofSink::new(&wave.header(), metadata.clone(), &options).unwrap();
+    resumed
+      .resume(serde_json::from_slice(&saved).unwrap())
+      .unwrap();
+    sink::drive(
+      &mut wave,
+      std::slice::from_mut(&mut resumed),
+      sink::Feedback::default(),
+    )
+    .unwrap();
+    let resumed = resumed.finish().unwrap();
+    assert_eq!(pprof::to_text(&resumed), pprof::to_text(&whole));
+
+    let other = metadata.subset(|i| i == 0);
+    let mut sink = PprofSink::new(&wave.header(), other, &options).unwrap();
+    assert!(sink
+      .resume(serde_json::from_slice(&saved).unwrap())
+      .is_err());
+
+    let coalesced = ConvertOptions {
+      coalesce: true,
+      ..options
+    };
+    let mut sink = PprofSink::new(&wave.header(), metadata.clone(), &coalesced).unwrap();
+    let err = sink
+      .resume(serde_json::from_slice(&saved).unwrap())
+      .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "checkpoint is of a conversion with other options"
+    );
+  }
+}
@@ -0,0 +1,74 @@
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
+
+/// Formats tried, in order, on the FST `date` header when it is not RFC 3339. Simulators write
+/// the output of `asctime`, like `Tue Jan 23 10:42:01 2024`, which carries no time zone.
+const DATE_FORMATS: &[&str] = &[
+  "%a %b %e %H:%M:%S %Y",
+  "%Y-%m-%d %H:%M:%S",
+  "%Y-%m-%dT%H:%M:%S",
+];
+
+/// Parse the FST `date` header. Dates without time zone are taken in `tz`.
+pub fn parse_date(raw: &str, tz: FixedOffset) -> Option<DateTime<FixedOffset>> {
+  let raw = raw.trim();
+  if let Ok(date) = DateTime::parse_from_rfc3339(raw) {
+    return Some(date);
+  }
+  DATE_FORMATS
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(raw, format).ok())
+    .and_then(|naive| naive.and_local_timezone(tz).single())
+}
+
+/// Return the capture time of a waveform as an ISO 8601 string, or the raw trimmed header when it
+/// can't be parsed.
+pub fn capture_time(raw: &str, tz: FixedOffset) -> String {
+  parse_date(raw, tz)
+    .map(|date| date.to_rfc3339())
+    .unwrap_or_else(|| raw.trim().to_string())
+}
+
+/// Parse a time zone offset like `+08:00`, `-0530` or `Z`.
+pub fn parse_offset(raw: &str) -> Result<FixedOffset, String> {
+  let invalid = || format!("invalid time zone offset {raw:?}, expect something like +08:00");
+  if raw == "Z" || raw.eq_ignore_ascii_case("utc") {
+    return Ok(FixedOffset::east_opt(0).unwrap());
+  }
+
+  let (sign, rest) = match raw.split_at_checked(1) {
+    Some(("+", rest)) => (1, rest),
+    Some(("-", rest)) => (-1, rest),
+    _ => return Err(invalid()),
+  };
+  let digits = rest.replace(':', "");
+  if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+    return Err(invalid());
+  }
+  let hours: i32 = digits[..2].parse().map_err(|_| invalid())?;
+  let minutes: i32 = digits[2..].parse().map_err(|_| invalid())?;
+  FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
+
+#[test]
+fn parse_fst_dates() {
+  let utc = parse_offset("Z").unwrap();
+  let cst = parse_offset("+08:00").unwrap();
+
+  assert_eq!(
+    capture_time("Tue Jan 23 10:42:01 2024\n", utc),
+    "2024-01-23T10:42:01+00:00"
+  );
+  assert_eq!(
+    capture_time("Mon Jan  1 00:00:00 2024", cst),
+    "2024-01-01T00:00:00+08:00"
+  );
+  assert_eq!(
+    capture_time("2024-01-23T10:42:01-05:00", cst),
+    "2024-01-23T10:42:01-05:00"
+  );
+  assert_eq!(capture_time(" yesterday ", utc), "yesterday");
+
+  assert_eq!(parse_offset("-0530").unwrap().local_minus_utc(), -19800);
+  assert!(parse_offset("8").is_err());
+  assert!(parse_offset("+8:00").is_err());
+}
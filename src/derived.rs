@@ -0,0 +1,461 @@
+//! Virtual signals computed from the values of other signals, like `fifo_full && !grant`.
+//!
+//! They are added to the hierarchy of the waveform by [`DerivedWave`], so the rest of the crate
+//! selects, triggers on and aggregates them like any other signal.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::Context;
+use fst_native::FstSignalHandle;
+
+use crate::pattern::{BitSlice, SignalPattern};
+use crate::wave::{HierarchyEntry, SignalValue, WaveHeader, Waveform};
+
+/// A boolean expression over signals. A signal is true when one of its bits is set, or when its
+/// real value isn't zero.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  /// The i-th operand of the expression
+  Signal(usize),
+  Not(Box<Expr>),
+  And(Box<Expr>, Box<Expr>),
+  Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+  /// Parse an expression made of signal paths, `!`, `&&`, `||` and parentheses, `!` binding the
+  /// tightest and `||` the loosest. Return it with its operands, the paths as written.
+  pub fn parse(text: &str) -> anyhow::Result<(Expr, Vec<String>)> {
+    let mut parser = Parser {
+      tokens: tokenize(text)?,
+      next: 0,
+      operands: Vec::new(),
+    };
+    let expr = parser.or()?;
+    if let Some(token) = parser.tokens.get(parser.next) {
+      anyhow::bail!("unexpected `{token}`");
+    }
+    Ok((expr, parser.operands))
+  }
+
+  /// Evaluate the expression, `operand` giving the value of the i-th operand.
+  pub fn eval(&self, operand: &impl Fn(usize) -> bool) -> bool {
+    match self {
+      Expr::Signal(i) => operand(*i),
+      Expr::Not(expr) => !expr.eval(operand),
+      Expr::And(lhs, rhs) => lhs.eval(operand) && rhs.eval(operand),
+      Expr::Or(lhs, rhs) => lhs.eval(operand) || rhs.eval(operand),
+    }
+  }
+}
+
+fn tokenize(text: &str) -> anyhow::Result<Vec<String>> {
+  let mut tokens = Vec::new();
+  let mut chars = text.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      _ if c.is_whitespace() => (),
+      '!' | '(' | ')' => tokens.push(c.to_string()),
+      '&' | '|' => match chars.next() {
+        Some(next) if next == c => tokens.push(format!("{c}{c}")),
+        _ => anyhow::bail!("`{c}` should be doubled"),
+      },
+      _ => {
+        // a path runs up to the next operator or space
+        let mut path = c.to_string();
+        while let Some(&c) = chars.peek() {
+          if c.is_whitespace() || "!&|()".contains(c) {
+            break;
+          }
+          path.push(c);
+          chars.next();
+        }
+        tokens.push(path);
+      }
+    }
+  }
+  Ok(tokens)
+}
+
+struct Parser {
+  tokens: Vec<String>,
+  next: usize,
+  operands: Vec<String>,
+}
+
+impl Parser {
+  fn eat(&mut self, token: &str) -> bool {
+    let eaten = self.tokens.get(self.next).is_some_and(|t| t == token);
+    if eaten {
+      self.next += 1;
+    }
+    eaten
+  }
+
+  fn or(&mut self) -> anyhow::Result<Expr> {
+    let mut expr = self.and()?;
+    while self.eat("||") {
+      expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+    }
+    Ok(expr)
+  }
+
+  fn and(&mut self) -> anyhow::Result<Expr> {
+    let mut expr = self.unary()?;
+    while self.eat("&&") {
+      expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+    }
+    Ok(expr)
+  }
+
+  fn unary(&mut self) -> anyhow::Result<Expr> {
+    if self.eat("!") {
+      return Ok(Expr::Not(Box::new(self.unary()?)));
+    }
+    if self.eat("(") {
+      let expr = self.or()?;
+      anyhow::ensure!(self.eat(")"), "missing `)`");
+      return Ok(expr);
+    }
+    let token = self.tokens.get(self.next).context("missing operand")?;
+    anyhow::ensure!(
+      !matches!(token.as_str(), "&&" | "||" | ")"),
+      "unexpected `{token}`"
+    );
+    let i = match self.operands.iter().position(|path| path == token) {
+      Some(i) => i,
+      None => {
+        self.operands.push(token.clone());
+        self.operands.len() - 1
+      }
+    };
+    self.next += 1;
+    Ok(Expr::Signal(i))
+  }
+}
+
+/// A derived signal, declared at the dot separated path it is named with.
+#[derive(Debug)]
+struct Derived {
+  path: String,
+  expr: Expr,
+  operands: Vec<String>,
+  patterns: Vec<SignalPattern>,
+  /// Handle of the signal and of its operands, once the hierarchy is walked
+  handle: Option<FstSignalHandle>,
+  operand_handles: Vec<Option<FstSignalHandle>>,
+  /// Bits of each operand read, when its entry ends with a slice of the signal found
+  operand_slices: Vec<Option<BitSlice>>,
+}
+
+/// Wrap a waveform and add the derived signals to it, as 1-bit signals declared after the whole
+/// hierarchy. Their handles follow the ones of the waveform, and their values are computed once
+/// all the changes of a time are known, so glitches within a time aren't seen.
+pub struct DerivedWave<W> {
+  inner: W,
+  derived: Vec<Derived>,
+}
+
+impl<W: Waveform> DerivedWave<W> {
+  /// Wrap `inner` with the derived signals of the config, paths mapped to expressions.
+  pub fn new(inner: W, config: &BTreeMap<String, String>) -> anyhow::Result<Self> {
+    let derived = config
+      .iter()
+      .map(|(path, text)| -> anyhow::Result<Derived> {
+        let (expr, operands) = Expr::parse(text)
+          .with_context(|| format!("fail to parse the expression of derived signal {path}"))?;
+        Ok(Derived {
+          path: path.clone(),
+          expr,
+          operand_handles: vec![None; operands.len()],
+          operand_slices: vec![None; operands.len()],
+          patterns: operands.iter().map(|p| SignalPattern::new(p)).collect(),
+          operands,
+          handle: None,
+        })
+      })
+      .collect::<anyhow::Result<_>>()?;
+    Ok(Self { inner, derived })
+  }
+
+  /// Walk the hierarchy if it hasn't been yet, to know the handles of the derived signals.
+  fn resolve(&mut self) -> anyhow::Result<()> {
+    if self.derived.iter().all(|derived| derived.handle.is_some()) {
+      return Ok(());
+    }
+    self.for_each_hierarchy(|_| ())
+  }
+}
+
+impl<W: Waveform> Waveform for DerivedWave<W> {
+  fn header(&self) -> WaveHeader {
+    self.inner.header()
+  }
+
+  fn for_each_hierarchy(&mut self, mut callback: impl FnMut(HierarchyEntry)) -> anyhow::Result<()> {
+    if self.derived.is_empty() {
+      return self.inner.for_each_hierarchy(callback);
+    }
+
+    let derived = &mut self.derived;
+    let mut module_path: Vec<String> = Vec::new();
+    let mut next_handle = 0;
+    self.inner.for_each_hierarchy(|entry| {
+      match &entry {
+        HierarchyEntry::Scope { name } => module_path.push(name.clone()),
+        HierarchyEntry::UpScope => {
+          module_path.pop();
+        }
+        HierarchyEntry::Var { name, handle, .. } => {
+          next_handle = next_handle.max(handle.get_index() + 1);
+          for derived in derived.iter_mut() {
+            let operands = derived.operand_handles.iter_mut();
+            let operands = operands.zip(&mut derived.operand_slices);
+            for (pattern, (found, slice)) in derived.patterns.iter().zip(operands) {
+              // the first signal declared wins, like for handshakes
+              if found.is_some() {
+                continue;
+              }
+              if let Some(selection) = pattern.select(&module_path, name) {
+                *found = Some(*handle);
+                *slice = selection;
+              }
+            }
+          }
+        }
+        _ => (),
+      }
+      callback(entry);
+    })?;
+
+    for (i, derived) in derived.iter_mut().enumerate() {
+      let handle = FstSignalHandle::from_index(next_handle + i);
+      derived.handle = Some(handle);
+      let mut scopes: Vec<&str> = derived.path.split('.').collect();
+      let name = scopes.pop().unwrap_or_default().to_string();
+      for scope in &scopes {
+        callback(HierarchyEntry::Scope {
+          name: scope.to_string(),
+        });
+      }
+      callback(HierarchyEntry::Var {
+        name,
+        length: 1,
+        handle,
+      });
+      for _ in &scopes {
+        callback(HierarchyEntry::UpScope);
+      }
+    }
+    Ok(())
+  }
+
+  fn for_each_change(
+    &mut self,
+    handles: &[FstSignalHandle],
+    mut callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    if self.derived.is_empty() {
+      return self.inner.for_each_change(handles, callback);
+    }
+    self.resolve()?;
+
+    let wanted: Vec<&Derived> = self
+      .derived
+      .iter()
+      .filter(|derived| {
+        derived
+          .handle
+          .is_some_and(|handle| handles.contains(&handle))
+      })
+      .collect();
+    let derived_handles: HashSet<usize> = self
+      .derived
+      .iter()
+      .filter_map(|derived| derived.handle.map(|handle| handle.get_index()))
+      .collect();
+    let mut read: Vec<FstSignalHandle> = handles
+      .iter()
+      .filter(|handle| !derived_handles.contains(&handle.get_index()))
+      .copied()
+      .collect();
+    let forwarded: HashSet<usize> = read.iter().map(|handle| handle.get_index()).collect();
+    for derived in &wanted {
+      for (operand, handle) in derived.operands.iter().zip(&derived.operand_handles) {
+        let handle = handle.with_context(|| {
+          format!(
+            "operand {operand} of derived signal {} is not found",
+            derived.path
+          )
+        })?;
+        if !read.contains(&handle) {
+          read.push(handle);
+        }
+      }
+    }
+
+    let mut values: HashMap<usize, String> = HashMap::new();
+    let mut outputs: Vec<Option<bool>> = vec![None; wanted.len()];
+
+    let mut time = None;
+    self.inner.for_each_change(&read, |t, handle, value| {
+      if let Some(prev) = time.filter(|prev| *prev != t) {
+        evaluate(&wanted, &mut outputs, &values, prev, &mut callback);
+      }
+      time = Some(t);
+      values.insert(
+        handle.get_index(),
+        match &value {
+          SignalValue::String(bits) => bits.clone(),
+          SignalValue::Real(r) => if *r != 0.0 { "1" } else { "0" }.to_string(),
+        },
+      );
+      if forwarded.contains(&handle.get_index()) {
+        callback(t, handle, value);
+      }
+    })?;
+    if let Some(t) = time {
+      evaluate(&wanted, &mut outputs, &values, t, &mut callback);
+    }
+    Ok(())
+  }
+
+  fn for_each_change_in(
+    &mut self,
+    handles: &[FstSignalHandle],
+    range: std::ops::Range<u64>,
+    callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    // a derived value depends on the operand values from the start
+    if self.derived.is_empty() {
+      return self.inner.for_each_change_in(handles, range, callback);
+    }
+    self.for_each_change(handles, callback)
+  }
+}
+
+/// Emit the derived signals whose value changes with the operand values at time `t`.
+fn evaluate(
+  wanted: &[&Derived],
+  outputs: &mut [Option<bool>],
+  values: &HashMap<usize, String>,
+  t: u64,
+  callback: &mut impl FnMut(u64, FstSignalHandle, SignalValue),
+) {
+  for (derived, output) in wanted.iter().zip(outputs) {
+    let value = derived.expr.eval(&|i| {
+      let Some(bits) =
+        derived.operand_handles[i].and_then(|handle| values.get(&handle.get_index()))
+      else {
+        return false;
+      };
+      match derived.operand_slices[i] {
+        Some(slice) => slice.extract(bits).contains('1'),
+        None => bits.contains('1'),
+      }
+    });
+    if *output != Some(value) {
+      *output = Some(value);
+      let bit = if value { "1" } else { "0" };
+      callback(
+        t,
+        derived.handle.unwrap(),
+        SignalValue::String(bit.to_string()),
+      );
+    }
+  }
+}
+
+#[test]
+fn parse_expressions() {
+  let (expr, operands) = Expr::parse("!a || b && !(c || a)").unwrap();
+  assert_eq!(operands, ["a", "b", "c"]);
+  let signal = |i| Box::new(Expr::Signal(i));
+  let c_or_a = Expr::Or(signal(2), signal(0));
+  assert_eq!(
+    expr,
+    Expr::Or(
+      Box::new(Expr::Not(signal(0))),
+      Box::new(Expr::And(signal(1), Box::new(Expr::Not(Box::new(c_or_a)))))
+    )
+  );
+
+  for invalid in ["", "a &", "a && || b", "(a", "a b", "a )"] {
+    assert!(Expr::parse(invalid).is_err(), "{invalid}");
+  }
+}
+
+#[test]
+fn derive_signals() {
+  use crate::fixture::FixtureWave;
+  use crate::{collect_signals, CollectOptions};
+
+  let mut wave = FixtureWave::new(0, 10);
+  wave.scope("top");
+  let fifo_full = wave.var("fifo_full", 1);
+  let grant = wave.var("grant", 2);
+  wave.upscope();
+  wave
+    .change(0, fifo_full, "0")
+    .change(0, grant, "00")
+    .change(2, fifo_full, "1")
+    .change(4, grant, "x1")
+    .change(6, grant, "00")
+    .change(6, fifo_full, "1")
+    .change(8, fifo_full, "0");
+
+  let fixture = wave.clone();
+  let config = [("top.stall".to_string(), "fifo_full && !grant".to_string())].into();
+  let mut wave = DerivedWave::new(wave, &config).unwrap();
+  let signals = ["stall".to_string(), "grant".to_string()];
+  let metadata = collect_signals(&mut wave, &signals, &CollectOptions::default()).unwrap();
+  assert_eq!(metadata.full_name(1), "top.stall");
+  assert_eq!(metadata.widths[1], 1);
+
+  let mut changes = Vec::new();
+  wave
+    .for_each_change(&metadata.handle, |t, handle, value| {
+      let SignalValue::String(value) = value else {
+        unreachable!("no real signal")
+      };
+      changes.push((t, handle.get_index(), value));
+    })
+    .unwrap();
+  let stall = metadata.handle[1].get_index();
+  let expected = [
+    (0, grant.get_index(), "00"),
+    (0, stall, "0"),
+    (2, stall, "1"),
+    (4, grant.get_index(), "x1"),
+    (4, stall, "0"),
+    (6, grant.get_index(), "00"),
+    (6, stall, "1"),
+    (8, stall, "0"),
+  ];
+  let expected: Vec<_> = expected
+    .iter()
+    .map(|(t, handle, value)| (*t, *handle, value.to_string()))
+    .collect();
+  assert_eq!(changes, expected);
+
+  // a slice operand only reads its bits, not the whole bus
+  let config = [("top.low".to_string(), "grant[1]".to_string())].into();
+  let mut low = DerivedWave::new(fixture, &config).unwrap();
+  let metadata = collect_signals(&mut low, &["low".to_string()], &CollectOptions::default());
+  let mut changes = Vec::new();
+  low
+    .for_each_change(&metadata.unwrap().handle, |t, _, value| {
+      changes.push((t, value))
+    })
+    .unwrap();
+  let bit = |value: &str| SignalValue::String(value.to_string());
+  assert_eq!(changes, [(0, bit("0"))]);
+
+  let config = [("stall".to_string(), "full && !grant".to_string())].into();
+  let mut wave = DerivedWave::new(FixtureWave::new(0, 10), &config).unwrap();
+  let stall = [FstSignalHandle::from_index(0)];
+  let err = wave.for_each_change(&stall, |_, _, _| ()).unwrap_err();
+  assert!(err
+    .to_string()
+    .contains("operand full of derived signal stall"));
+}
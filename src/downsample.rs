@@ -0,0 +1,322 @@
+//! Decimation of dense signals, keeping one value change per signal and per time bucket.
+
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use fst_native::FstSignalHandle;
+
+use crate::edge::Edge;
+use crate::wave::{HierarchyEntry, SignalValue, WaveHeader, Waveform};
+
+/// Length of the buckets the value changes are grouped in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+  /// Raw FST time ticks
+  Ticks(u64),
+  /// Rising edges of a clock
+  Cycles(u64),
+}
+
+/// Parse an interval like `100` for ticks or `8cycles` for clock cycles.
+pub fn parse_interval(raw: &str) -> Result<Interval, String> {
+  let invalid = || format!("invalid interval {raw:?}, expect something like 100 or 8cycles");
+  let (digits, cycles) = match raw.trim().strip_suffix("cycles") {
+    Some(digits) => (digits.trim(), true),
+    None => (raw.trim(), false),
+  };
+  let n: u64 = digits.parse().map_err(|_| invalid())?;
+  if n == 0 {
+    return Err(format!("interval {raw:?} should not be zero"));
+  }
+  Ok(if cycles {
+    Interval::Cycles(n)
+  } else {
+    Interval::Ticks(n)
+  })
+}
+
+/// Which value change of a bucket is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Keep {
+  /// The first change
+  #[default]
+  First,
+  /// The last change, the value the signal settles at
+  Last,
+  /// The first change to the most frequent value, the earliest one winning ties
+  Mode,
+}
+
+impl Keep {
+  /// Return the kept change among the changes of one signal within one bucket.
+  fn pick<'c>(&self, changes: &'c [(u64, SignalValue)]) -> Option<&'c (u64, SignalValue)> {
+    match self {
+      Keep::First => changes.first(),
+      Keep::Last => changes.last(),
+      Keep::Mode => {
+        let count = |value: &SignalValue| changes.iter().filter(|(_, v)| v == value).count();
+        // max_by_key returns the last maximum, iterate backward to get the first one
+        changes.iter().rev().max_by_key(|(_, value)| count(value))
+      }
+    }
+  }
+}
+
+/// Wrap a waveform and only return one value change per signal and per bucket of `interval`. It
+/// returns every change as is without an interval.
+///
+/// The kept changes stay at their own time. A kept change to the value the signal already had is
+/// dropped, so a signal toggling within every bucket may not change at all. Every change of the
+/// control signals, like clocks and the operands sampled on their edges, is returned.
+pub struct Downsampled<W> {
+  inner: W,
+  interval: Option<Interval>,
+  keep: Keep,
+  /// Clock counting the cycles of [`Interval::Cycles`]
+  clock: Option<FstSignalHandle>,
+  control: Vec<FstSignalHandle>,
+}
+
+impl<W: Waveform> Downsampled<W> {
+  /// Wrap `inner`, `clock` being required by an interval in cycles. The changes of `control` are
+  /// all returned.
+  pub fn new(
+    inner: W,
+    interval: Option<Interval>,
+    keep: Keep,
+    clock: Option<FstSignalHandle>,
+    control: Vec<FstSignalHandle>,
+  ) -> anyhow::Result<Self> {
+    anyhow::ensure!(
+      clock.is_some() || !matches!(interval, Some(Interval::Cycles(_))),
+      "an interval in cycles needs a clock"
+    );
+    Ok(Self {
+      inner,
+      interval,
+      keep,
+      clock,
+      control,
+    })
+  }
+}
+
+impl<W: Waveform> Waveform for Downsampled<W> {
+  fn header(&self) -> WaveHeader {
+    self.inner.header()
+  }
+
+  fn for_each_hierarchy(&mut self, callback: impl FnMut(HierarchyEntry)) -> anyhow::Result<()> {
+    self.inner.for_each_hierarchy(callback)
+  }
+
+  fn for_each_change(
+    &mut self,
+    handles: &[FstSignalHandle],
+    mut callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    let Some(interval) = self.interval else {
+      return self.inner.for_each_change(handles, callback);
+    };
+    let mut read = handles.to_vec();
+    let clock = match interval {
+      Interval::Cycles(_) => self.clock,
+      Interval::Ticks(_) => None,
+    };
+    if let Some(clock) = clock.filter(|clock| !handles.contains(clock)) {
+      read.push(clock);
+    }
+
+    let mut bucket = Bucket {
+      keep: self.keep,
+      index: HashMap::new(),
+      pending: Vec::new(),
+      passed: Vec::new(),
+      emitted: HashMap::new(),
+    };
+    let mut current = None;
+    let (mut cycles, mut clock_value) = (0, String::new());
+    self.inner.for_each_change(&read, |t, handle, value| {
+      if Some(handle) == clock {
+        if let SignalValue::String(bits) = &value {
+          if Edge::classify(&clock_value, bits) == Some(Edge::Pos) {
+            cycles += 1;
+          }
+          clock_value.clone_from(bits);
+        }
+      }
+      let index = match interval {
+        Interval::Ticks(n) => t / n,
+        Interval::Cycles(n) => cycles / n,
+      };
+      if current.is_some_and(|current| current != index) {
+        bucket.flush(&mut callback);
+      }
+      current = Some(index);
+      if !handles.contains(&handle) {
+        return;
+      }
+      if self.control.contains(&handle) {
+        bucket.passed.push((t, handle, value));
+      } else {
+        bucket.push(t, handle, value);
+      }
+    })?;
+    bucket.flush(&mut callback);
+    Ok(())
+  }
+
+  fn for_each_change_in(
+    &mut self,
+    handles: &[FstSignalHandle],
+    range: std::ops::Range<u64>,
+    callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    // the intervals are counted from the start
+    match self.interval {
+      Some(_) => self.for_each_change(handles, callback),
+      None => self.inner.for_each_change_in(handles, range, callback),
+    }
+  }
+}
+
+/// The changes of the current bucket.
+struct Bucket {
+  keep: Keep,
+  /// Position in `pending` of each signal, by handle index
+  index: HashMap<usize, usize>,
+  pending: Vec<(FstSignalHandle, Vec<(u64, SignalValue)>)>,
+  /// Changes of the control signals, all returned
+  passed: Vec<(u64, FstSignalHandle, SignalValue)>,
+  /// Last value returned of every signal, by handle index
+  emitted: HashMap<usize, SignalValue>,
+}
+
+impl Bucket {
+  fn push(&mut self, t: u64, handle: FstSignalHandle, value: SignalValue) {
+    let i = *self.index.entry(handle.get_index()).or_insert_with(|| {
+      self.pending.push((handle, Vec::new()));
+      self.pending.len() - 1
+    });
+    let changes = &mut self.pending[i].1;
+    match self.keep {
+      Keep::First if !changes.is_empty() => (),
+      Keep::Last => *changes = vec![(t, value)],
+      _ => changes.push((t, value)),
+    }
+  }
+
+  /// Return the kept changes of the bucket in time order, and start a new bucket.
+  fn flush(&mut self, callback: &mut impl FnMut(u64, FstSignalHandle, SignalValue)) {
+    let mut kept: Vec<_> = self
+      .pending
+      .iter()
+      .filter_map(|(handle, changes)| {
+        let (t, value) = self.keep.pick(changes)?;
+        Some((*t, *handle, value))
+      })
+      .filter(|(_, handle, value)| self.emitted.get(&handle.get_index()) != Some(*value))
+      .map(|(t, handle, value)| (t, handle, value.clone()))
+      .collect();
+    kept.append(&mut self.passed);
+    // stable, so the changes at the same time stay in file order
+    kept.sort_by_key(|(t, _, _)| *t);
+    for (t, handle, value) in kept {
+      self.emitted.insert(handle.get_index(), value.clone());
+      callback(t, handle, value);
+    }
+    self.index.clear();
+    self.pending.clear();
+  }
+}
+
+#[test]
+fn downsample_value_changes() {
+  use crate::fixture::FixtureWave;
+
+  let mut wave = FixtureWave::new(0, 40);
+  let clock = wave.var("clock", 1);
+  let data = wave.var("data", 2);
+  let mut changes: Vec<_> = (0..40)
+    .step_by(5)
+    .map(|t| (t, clock, if t % 10 == 0 { "0" } else { "1" }))
+    .collect();
+  changes.extend(
+    [
+      (0, "00"),
+      (3, "01"),
+      (6, "10"),
+      (8, "01"),
+      (9, "10"),
+      (12, "10"),
+      (23, "11"),
+      (31, "11"),
+    ]
+    .map(|(t, value)| (t, data, value)),
+  );
+  // the fixture replays the changes as given, like a file they should be in time order
+  changes.sort_by_key(|(t, _, _)| *t);
+  for (t, handle, value) in changes {
+    wave.change(t, handle, value);
+  }
+
+  let changes = |interval, keep| {
+    let mut wave =
+      Downsampled::new(wave.clone(), Some(interval), keep, Some(clock), vec![clock]).unwrap();
+    let mut changes = Vec::new();
+    wave
+      .for_each_change(&[data], |t, _, value| {
+        let SignalValue::String(value) = value else {
+          unreachable!("no real signal")
+        };
+        changes.push((t, value));
+      })
+      .unwrap();
+    changes
+  };
+  let expected = |changes: &[(u64, &str)]| -> Vec<(u64, String)> {
+    changes.iter().map(|(t, v)| (*t, v.to_string())).collect()
+  };
+
+  assert_eq!(
+    changes(Interval::Ticks(10), Keep::First),
+    expected(&[(0, "00"), (12, "10"), (23, "11")])
+  );
+  assert_eq!(
+    changes(Interval::Ticks(10), Keep::Last),
+    expected(&[(9, "10"), (23, "11")])
+  );
+  assert_eq!(
+    changes(Interval::Ticks(10), Keep::Mode),
+    expected(&[(3, "01"), (12, "10"), (23, "11")])
+  );
+  // rising edges at 5, 15, 25 and 35
+  assert_eq!(
+    changes(Interval::Cycles(2), Keep::Last),
+    expected(&[(12, "10"), (31, "11")])
+  );
+
+  // the clock is a control signal, so every one of its changes is returned
+  let mut wave = Downsampled::new(
+    wave.clone(),
+    Some(Interval::Ticks(10)),
+    Keep::Last,
+    None,
+    vec![clock],
+  )
+  .unwrap();
+  let mut edges = Vec::new();
+  wave
+    .for_each_change(&[clock, data], |t, handle, _| {
+      if handle == clock {
+        edges.push(t);
+      }
+    })
+    .unwrap();
+  assert_eq!(edges, [0, 5, 10, 15, 20, 25, 30, 35]);
+
+  assert_eq!(parse_interval("100"), Ok(Interval::Ticks(100)));
+  assert_eq!(parse_interval("8cycles"), Ok(Interval::Cycles(8)));
+  assert!(parse_interval("0").is_err());
+  assert!(parse_interval("fast").is_err());
+}
@@ -0,0 +1,260 @@
+//! Raw dump of the value changes, to check which signals a config matches without a pprof viewer.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use fst_native::FstSignalHandle;
+use serde::Serialize;
+
+use crate::sink::OutputSink;
+use crate::wave::SignalValue;
+use crate::window::TimeWindow;
+use crate::SignalMetadata;
+
+/// One value change of a matched signal.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DumpRecord {
+  /// Raw FST time ticks
+  pub time: u64,
+  /// Scopes of the signal joined with `.`
+  pub module_path: String,
+  pub name: String,
+  pub value: String,
+  /// Position of the signal in the metadata, telling apart signals of the same path
+  #[serde(skip)]
+  pub signal: usize,
+}
+
+/// Records every value change it is fed as is, including the initial values.
+pub struct DumpSink {
+  metadata: SignalMetadata,
+  window: TimeWindow,
+  /// Positions in `metadata` of each signal, by handle index
+  index: HashMap<usize, Vec<usize>>,
+  records: Vec<DumpRecord>,
+}
+
+impl DumpSink {
+  pub fn new(metadata: SignalMetadata, window: TimeWindow) -> Self {
+    Self {
+      index: metadata.positions(),
+      metadata,
+      window,
+      records: Vec::new(),
+    }
+  }
+
+  /// Return the records in file order.
+  pub fn finish(self) -> Vec<DumpRecord> {
+    self.records
+  }
+
+  /// Return the records as a wide table, with a column per signal of the metadata.
+  pub fn finish_wide(self) -> WideTable {
+    let signals = (0..self.metadata.names.len())
+      .map(|i| self.metadata.full_name(i))
+      .collect();
+    WideTable::new(&self.records, signals)
+  }
+}
+
+/// The value changes as a table of one row per time a signal changes, with a column per signal
+/// holding its value at that time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WideTable {
+  /// Full name of the signal of each column
+  pub signals: Vec<String>,
+  /// Time of each row and the value of every signal then, `None` before its first change
+  pub rows: Vec<(u64, Vec<Option<String>>)>,
+}
+
+impl WideTable {
+  /// Gather the `records` of the `signals` by time. A signal changing several times at once
+  /// keeps its last value of that time.
+  pub fn new(records: &[DumpRecord], signals: Vec<String>) -> Self {
+    let mut values = vec![None; signals.len()];
+    let mut rows: Vec<(u64, Vec<Option<String>>)> = Vec::new();
+    for (i, record) in records.iter().enumerate() {
+      values[record.signal] = Some(record.value.clone());
+      // a row is complete once the records of its time are over
+      if records
+        .get(i + 1)
+        .map_or(true, |next| next.time != record.time)
+      {
+        rows.push((record.time, values.clone()));
+      }
+    }
+    Self { signals, rows }
+  }
+}
+
+/// Write the records as CSV with a `time,signal,value` header, one row per record, the signal
+/// being its full dot separated path.
+pub fn write_csv(records: &[DumpRecord], mut out: impl Write) -> std::io::Result<()> {
+  writeln!(out, "time,signal,value")?;
+  for record in records {
+    let signal = match record.module_path.as_str() {
+      "" => record.name.clone(),
+      path => format!("{path}.{}", record.name),
+    };
+    writeln!(
+      out,
+      "{},{},{}",
+      record.time,
+      csv_field(&signal),
+      csv_field(&record.value)
+    )?;
+  }
+  Ok(())
+}
+
+/// Write the wide table as CSV with a `time` column then a column named after each signal. The
+/// cells of the signals without a value yet are empty.
+pub fn write_wide_csv(table: &WideTable, mut out: impl Write) -> std::io::Result<()> {
+  write!(out, "time")?;
+  for signal in &table.signals {
+    write!(out, ",{}", csv_field(signal))?;
+  }
+  writeln!(out)?;
+  for (time, values) in &table.rows {
+    write!(out, "{time}")?;
+    for value in values {
+      write!(out, ",{}", csv_field(value.as_deref().unwrap_or_default()))?;
+    }
+    writeln!(out)?;
+  }
+  Ok(())
+}
+
+/// Quote `text` as a CSV field if it needs it, as RFC 4180 says.
+fn csv_field(text: &str) -> std::borrow::Cow<'_, str> {
+  if text.contains([',', '"', '\n', '\r']) {
+    format!("\"{}\"", text.replace('"', "\"\"")).into()
+  } else {
+    text.into()
+  }
+}
+
+impl OutputSink for DumpSink {
+  fn handles(&self) -> Vec<FstSignalHandle> {
+    self.metadata.handle.clone()
+  }
+
+  fn change(&mut self, t: u64, handle: FstSignalHandle, value: &SignalValue) {
+    let Some(positions) = self.index.get(&handle.get_index()) else {
+      return;
+    };
+    if !self.window.contains(t) {
+      return;
+    }
+    for &i in positions {
+      let value = self.metadata.value_of(i, value);
+      self.records.push(DumpRecord {
+        time: t,
+        module_path: self.metadata.module_paths[i].join("."),
+        name: self.metadata.names[i].clone(),
+        value: match &*value {
+          SignalValue::String(s) => self.metadata.enum_name(i, s).unwrap_or(s).to_string(),
+          SignalValue::Real(r) => r.to_string(),
+        },
+        signal: i,
+      });
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixture::FixtureWave;
+  use crate::sink::{drive, Feedback};
+
+  #[test]
+  fn dump_value_changes() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    wave.scope("core");
+    let valid = wave.var("valid", 1);
+    wave.upscope();
+    let data = wave.var("data", 4);
+    wave.upscope();
+    wave
+      .change(0, valid, "0")
+      .change(0, data, "0000")
+      .change(3, valid, "1")
+      .change(3, valid, "1");
+
+    let metadata = wave.collect(&["valid"]);
+    let mut sink = DumpSink::new(metadata, TimeWindow::default());
+    drive(
+      &mut wave,
+      std::slice::from_mut(&mut sink),
+      Feedback::default(),
+    )
+    .unwrap();
+    let records = sink.finish();
+
+    let record = |time, value: &str| DumpRecord {
+      time,
+      module_path: "top.core".to_string(),
+      name: "valid".to_string(),
+      value: value.to_string(),
+      signal: 0,
+    };
+    assert_eq!(records, [record(0, "0"), record(3, "1"), record(3, "1")]);
+    assert_eq!(
+      serde_json::to_string(&records[0]).unwrap(),
+      r#"{"time":0,"module_path":"top.core","name":"valid","value":"0"}"#
+    );
+  }
+
+  #[test]
+  fn write_records_as_csv() {
+    let record = |module_path: &str, name: &str, value: &str| DumpRecord {
+      time: 5,
+      module_path: module_path.to_string(),
+      name: name.to_string(),
+      value: value.to_string(),
+      signal: 0,
+    };
+    let records = [
+      record("top.core", "valid", "1"),
+      record("", "data, \"low\"", "0x"),
+    ];
+    let mut out = Vec::new();
+    write_csv(&records, &mut out).unwrap();
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      "time,signal,value\n5,top.core.valid,1\n5,\"data, \"\"low\"\"\",0x\n"
+    );
+  }
+
+  #[test]
+  fn write_records_as_wide_csv() {
+    let record = |time, signal: usize, value: &str| DumpRecord {
+      time,
+      module_path: "top".to_string(),
+      name: ["valid", "data"][signal].to_string(),
+      value: value.to_string(),
+      signal,
+    };
+    let records = [
+      record(0, 0, "0"),
+      record(3, 1, "1010"),
+      record(3, 0, "1"),
+      record(5, 0, "0"),
+      record(5, 0, "1"),
+    ];
+    let table = WideTable::new(
+      &records,
+      vec!["top.valid".to_string(), "top.data".to_string()],
+    );
+    let mut out = Vec::new();
+    write_wide_csv(&table, &mut out).unwrap();
+    // the data is empty until it changes, the valid keeps its last value of time 5
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      "time,top.valid,top.data\n0,0,\n3,1,1010\n5,1,1010\n"
+    );
+  }
+}
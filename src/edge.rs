@@ -0,0 +1,62 @@
+use clap::ValueEnum;
+
+/// Selects which transitions of a 1-bit signal are kept when running in edge mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EdgeFilter {
+  /// Only keep `0 -> 1` transitions
+  Pos,
+  /// Only keep `1 -> 0` transitions
+  Neg,
+  /// Keep both rising and falling transitions
+  Both,
+}
+
+impl EdgeFilter {
+  /// Return true if the given edge should be emitted.
+  pub fn accept(&self, edge: Edge) -> bool {
+    matches!(
+      (self, edge),
+      (Self::Both, _) | (Self::Pos, Edge::Pos) | (Self::Neg, Edge::Neg)
+    )
+  }
+}
+
+/// The direction of a transition of a 1-bit signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+  Pos,
+  Neg,
+}
+
+impl Edge {
+  /// Classify the transition from `prev` to `next`. Only `0 -> 1` and `1 -> 0` are considered as
+  /// edges, transitions from or into unknown values are not.
+  pub fn classify(prev: &str, next: &str) -> Option<Self> {
+    match (prev, next) {
+      ("0", "1") => Some(Self::Pos),
+      ("1", "0") => Some(Self::Neg),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Pos => "posedge",
+      Self::Neg => "negedge",
+    }
+  }
+}
+
+#[test]
+fn classify_edges() {
+  assert_eq!(Edge::classify("0", "1"), Some(Edge::Pos));
+  assert_eq!(Edge::classify("1", "0"), Some(Edge::Neg));
+  assert_eq!(Edge::classify("1", "1"), None);
+  assert_eq!(Edge::classify("x", "1"), None);
+  assert_eq!(Edge::classify("0", "z"), None);
+
+  assert!(EdgeFilter::Pos.accept(Edge::Pos));
+  assert!(!EdgeFilter::Pos.accept(Edge::Neg));
+  assert!(EdgeFilter::Neg.accept(Edge::Neg));
+  assert!(EdgeFilter::Both.accept(Edge::Pos) && EdgeFilter::Both.accept(Edge::Neg));
+}
@@ -0,0 +1,106 @@
+//! Kinds of failure told apart by the exit code of the command line tool, so a CI job can react
+//! to an unreadable waveform differently than to a misspelled signal.
+
+use serde::Serialize;
+
+/// What went wrong in a failed run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+  /// Any failure not told apart below
+  Other,
+  /// The config can't be read or parsed, or is invalid
+  Config,
+  /// The waveform can't be opened, or its value changes can't be read
+  Waveform,
+  /// The signals the config selects aren't in the hierarchy
+  NoSignals,
+  /// An output can't be created or written
+  Output,
+}
+
+impl FailureKind {
+  /// Return the exit code of a run failing this way. Code 2 is left to the argument errors.
+  pub fn exit_code(self) -> u8 {
+    match self {
+      FailureKind::Other => 1,
+      FailureKind::Config => 3,
+      FailureKind::Waveform => 4,
+      FailureKind::NoSignals => 5,
+      FailureKind::Output => 6,
+    }
+  }
+
+  /// Return an error of this kind, to raise or to add as context of the error causing it.
+  pub fn with(self, message: impl Into<String>) -> Failure {
+    Failure {
+      kind: self,
+      message: message.into(),
+    }
+  }
+}
+
+/// An error of a known kind.
+#[derive(Debug)]
+pub struct Failure {
+  pub kind: FailureKind,
+  message: String,
+}
+
+impl std::fmt::Display for Failure {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(&self.message)
+  }
+}
+
+impl std::error::Error for Failure {}
+
+/// Return the kind of the outermost [`Failure`] in the chain of `err`.
+pub fn kind(err: &anyhow::Error) -> FailureKind {
+  err
+    .downcast_ref::<Failure>()
+    .map_or(FailureKind::Other, |failure| failure.kind)
+}
+
+/// A failed run, as written by `--errors-json`.
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+  pub kind: FailureKind,
+  pub exit_code: u8,
+  pub message: String,
+  /// Messages of the errors causing it, outermost first
+  pub causes: Vec<String>,
+}
+
+impl ErrorReport {
+  pub fn new(err: &anyhow::Error) -> Self {
+    let kind = kind(err);
+    Self {
+      kind,
+      exit_code: kind.exit_code(),
+      message: err.to_string(),
+      causes: err.chain().skip(1).map(ToString::to_string).collect(),
+    }
+  }
+}
+
+#[test]
+fn report_failure_kinds() {
+  use anyhow::Context;
+
+  let read: anyhow::Result<()> = Err(std::io::Error::other("permission denied"))
+    .context(FailureKind::Waveform.with("fail to open wave.fst"));
+  let err = read.context("fail to extract").unwrap_err();
+  let report = ErrorReport::new(&err);
+  assert_eq!(report.kind, FailureKind::Waveform);
+  assert_eq!(report.exit_code, 4);
+  assert_eq!(report.message, "fail to extract");
+  assert_eq!(
+    report.causes,
+    ["fail to open wave.fst", "permission denied"]
+  );
+
+  let err = anyhow::Error::new(FailureKind::NoSignals.with("config signals don't match"));
+  assert_eq!(kind(&err), FailureKind::NoSignals);
+  assert_eq!(kind(&anyhow::anyhow!("bad slice")), FailureKind::Other);
+}
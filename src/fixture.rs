@@ -0,0 +1,174 @@
+//! Synthetic waveforms for tests.
+//!
+//! `fst_native` only ships a reader, so instead of writing real FST files the tests build an
+//! in-memory waveform that replays a hand written hierarchy and value change list through the
+//! [`Waveform`] trait, exactly like an FST file would. The FST backends themselves are tested on
+//! `assets/wave.fst`.
+
+use fst_native::FstSignalHandle;
+
+use crate::pprof::{Profile, Sample};
+use crate::wave::{HierarchyEntry, SignalValue, WaveHeader, Waveform};
+use crate::{collect_signals, CollectOptions, SignalMetadata};
+
+#[derive(Debug, Clone, Default)]
+pub struct FixtureWave {
+  header: WaveHeader,
+  hierarchy: Vec<HierarchyEntry>,
+  changes: Vec<(u64, FstSignalHandle, SignalValue)>,
+  next_handle: usize,
+}
+
+impl FixtureWave {
+  /// Create an empty waveform covering `start_time..=end_time`.
+  pub fn new(start_time: u64, end_time: u64) -> Self {
+    Self {
+      header: WaveHeader {
+        start_time,
+        end_time,
+        version: "fixture".to_string(),
+        date: "Thu Jan  1 00:00:00 1970".to_string(),
+        timescale: None,
+      },
+      ..Default::default()
+    }
+  }
+
+  /// Give the time ticks a length of 10 to the `exponent` seconds.
+  pub fn timescale(&mut self, exponent: i8) -> &mut Self {
+    self.header.timescale = Some(exponent);
+    self
+  }
+
+  /// Enter a new scope.
+  pub fn scope(&mut self, name: &str) -> &mut Self {
+    self.hierarchy.push(HierarchyEntry::Scope {
+      name: name.to_string(),
+    });
+    self
+  }
+
+  /// Leave the current scope.
+  pub fn upscope(&mut self) -> &mut Self {
+    self.hierarchy.push(HierarchyEntry::UpScope);
+    self
+  }
+
+  /// Declare a new `length` bits wide signal in the current scope and return its handle.
+  pub fn var(&mut self, name: &str, length: u32) -> FstSignalHandle {
+    let handle = FstSignalHandle::from_index(self.next_handle);
+    self.next_handle += 1;
+    self.hierarchy.push(HierarchyEntry::Var {
+      name: name.to_string(),
+      length,
+      handle,
+    });
+    handle
+  }
+
+  /// Declare `name` in the current scope as an alias of an existing signal.
+  pub fn alias(&mut self, name: &str, length: u32, of: FstSignalHandle) -> &mut Self {
+    self.hierarchy.push(HierarchyEntry::Var {
+      name: name.to_string(),
+      length,
+      handle: of,
+    });
+    self
+  }
+
+  /// Define the enum table `handle` from `(value, name)` pairs.
+  pub fn enum_table(&mut self, handle: u64, mapping: &[(&str, &str)]) -> &mut Self {
+    let mapping = mapping
+      .iter()
+      .map(|(value, name)| (value.to_string(), name.to_string()))
+      .collect();
+    self
+      .hierarchy
+      .push(HierarchyEntry::EnumTable { handle, mapping });
+    self
+  }
+
+  /// Make the next declared signal refer to the enum table `handle`.
+  pub fn enum_ref(&mut self, handle: u64) -> &mut Self {
+    self.hierarchy.push(HierarchyEntry::EnumTableRef { handle });
+    self
+  }
+
+  /// Record a value change. Changes are replayed in insertion order.
+  pub fn change(&mut self, t: u64, handle: FstSignalHandle, value: &str) -> &mut Self {
+    self
+      .changes
+      .push((t, handle, SignalValue::String(value.to_string())));
+    self
+  }
+
+  /// Record a change of a real signal.
+  pub fn change_real(&mut self, t: u64, handle: FstSignalHandle, value: f64) -> &mut Self {
+    self.changes.push((t, handle, SignalValue::Real(value)));
+    self
+  }
+
+  /// Collect the signals matching `patterns` with the default options.
+  pub fn collect(&mut self, patterns: &[&str]) -> SignalMetadata {
+    let expected: Vec<_> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+    collect_signals(self, &expected, &CollectOptions::default()).unwrap()
+  }
+}
+
+/// Return the key and the string of every label of `sample`.
+pub fn labels<'p>(profile: &'p Profile, sample: &Sample) -> Vec<(&'p str, &'p str)> {
+  let string = |id: i64| profile.string_table[id as usize].as_str();
+  sample
+    .label
+    .iter()
+    .map(|label| (string(label.key), string(label.str)))
+    .collect()
+}
+
+impl Waveform for FixtureWave {
+  fn header(&self) -> WaveHeader {
+    self.header.clone()
+  }
+
+  fn for_each_hierarchy(&mut self, mut callback: impl FnMut(HierarchyEntry)) -> anyhow::Result<()> {
+    self.hierarchy.iter().cloned().for_each(&mut callback);
+    Ok(())
+  }
+
+  fn for_each_change(
+    &mut self,
+    handles: &[FstSignalHandle],
+    mut callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    self
+      .changes
+      .iter()
+      .filter(|(_, handle, _)| handles.contains(handle))
+      .for_each(|(t, handle, value)| callback(*t, *handle, value.clone()));
+    Ok(())
+  }
+}
+
+#[test]
+fn replay_only_requested_signals() {
+  let mut wave = FixtureWave::new(0, 10);
+  wave.scope("top");
+  let clock = wave.var("clock", 1);
+  let reset = wave.var("reset", 1);
+  wave.upscope();
+  wave
+    .change(0, clock, "0")
+    .change(0, reset, "1")
+    .change(5, clock, "1")
+    .change(5, reset, "0");
+
+  let mut seen = Vec::new();
+  wave
+    .for_each_change(&[clock], |t, handle, value| seen.push((t, handle, value)))
+    .unwrap();
+
+  assert_eq!(seen.len(), 2);
+  assert!(seen.iter().all(|(_, handle, _)| *handle == clock));
+  assert_eq!(seen[0].0, 0);
+  assert_eq!(seen[1].0, 5);
+}
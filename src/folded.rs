@@ -0,0 +1,139 @@
+//! Folded stack text, the input of `flamegraph.pl` and speedscope, built from the hierarchy.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use fst_native::FstSignalHandle;
+
+use crate::sink::OutputSink;
+use crate::wave::{SignalValue, WaveHeader};
+use crate::window::TimeWindow;
+use crate::SignalMetadata;
+
+/// Sums how long every signal is active, meaning it has a bit set or a non-zero real value, and
+/// writes one `scope;scope;signal ticks` line per active signal.
+pub struct FoldedSink {
+  metadata: SignalMetadata,
+  /// Bounds of the window, within the waveform time range
+  start: u64,
+  end: u64,
+  /// Positions in `metadata` of each signal, by handle index
+  index: HashMap<usize, Vec<usize>>,
+  /// Time every active signal became active
+  active_since: Vec<Option<u64>>,
+  /// Ticks every signal has been active inside the window
+  weights: Vec<u64>,
+}
+
+impl FoldedSink {
+  pub fn new(header: &WaveHeader, metadata: SignalMetadata, window: TimeWindow) -> Self {
+    let (start, end) = window.clamp(header.start_time, header.end_time);
+    Self {
+      index: metadata.positions(),
+      active_since: vec![None; metadata.handle.len()],
+      weights: vec![0; metadata.handle.len()],
+      metadata,
+      start,
+      end,
+    }
+  }
+
+  /// Write the folded stacks to `out`, in declaration order. Signals never active are left out.
+  pub fn finish(mut self, mut out: impl Write) -> std::io::Result<()> {
+    for i in 0..self.metadata.handle.len() {
+      self.deactivate(i, self.end);
+      if self.weights[i] == 0 {
+        continue;
+      }
+      let stack: Vec<_> = self.metadata.module_paths[i]
+        .iter()
+        .chain(std::iter::once(&self.metadata.names[i]))
+        // `;` separates the frames and the space the weight
+        .map(|frame| frame.replace([';', ' '], "_"))
+        .collect();
+      writeln!(out, "{} {}", stack.join(";"), self.weights[i])?;
+    }
+    Ok(())
+  }
+
+  /// Add the time the i-th signal has been active until `t` to its weight.
+  fn deactivate(&mut self, i: usize, t: u64) {
+    if let Some(since) = self.active_since[i].take() {
+      let active = t.min(self.end).saturating_sub(since.max(self.start));
+      self.weights[i] += active;
+    }
+  }
+}
+
+impl OutputSink for FoldedSink {
+  fn handles(&self) -> Vec<FstSignalHandle> {
+    self.metadata.handle.clone()
+  }
+
+  fn change(&mut self, t: u64, handle: FstSignalHandle, value: &SignalValue) {
+    let Some(aliases) = self.index.get(&handle.get_index()).map(Vec::len) else {
+      return;
+    };
+    for alias in 0..aliases {
+      let i = self.index[&handle.get_index()][alias];
+      let active = match &*self.metadata.value_of(i, value) {
+        SignalValue::String(v) => v.bytes().any(|b| b == b'1'),
+        SignalValue::Real(r) => *r != 0.0,
+      };
+      match (active, self.active_since[i]) {
+        (true, None) => self.active_since[i] = Some(t),
+        (false, Some(_)) => self.deactivate(i, t),
+        _ => (),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixture::FixtureWave;
+  use crate::sink::{drive, Feedback};
+  use crate::wave::Waveform;
+
+  #[test]
+  fn fold_active_time() {
+    let mut wave = FixtureWave::new(0, 100);
+    wave.scope("TOP");
+    wave.scope("lsu");
+    let busy = wave.var("busy", 1);
+    let idle = wave.var("idle", 1);
+    wave.upscope();
+    let state = wave.var("state", 2);
+    wave.upscope();
+    wave
+      .change(0, busy, "0")
+      .change(0, idle, "0")
+      .change(0, state, "00")
+      .change(10, busy, "1")
+      .change(20, busy, "1")
+      .change(30, busy, "0")
+      .change(40, state, "10")
+      .change(50, busy, "x");
+
+    let metadata = wave.collect(&["busy", "idle", "state"]);
+    let window = TimeWindow {
+      end: Some(90),
+      ..Default::default()
+    };
+    let mut sink = FoldedSink::new(&wave.header(), metadata, window);
+    drive(
+      &mut wave,
+      std::slice::from_mut(&mut sink),
+      Feedback::default(),
+    )
+    .unwrap();
+    let mut out = Vec::new();
+    sink.finish(&mut out).unwrap();
+
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      "TOP;lsu;busy 20\nTOP;state 50\n"
+    );
+  }
+}
@@ -0,0 +1,81 @@
+//! Reads of a waveform still being written by a running simulation, each one only giving the
+//! value changes appended since the previous one.
+
+use fst_native::FstSignalHandle;
+
+use crate::wave::{HierarchyEntry, SignalValue, WaveHeader, Waveform};
+
+/// Wrap a waveform whose changes up to time `converted` were already given by an earlier read of
+/// the same file, and only return the later ones.
+///
+/// The changes at `converted` itself are taken as complete, the writer only flushing the value
+/// changes of a time once it moves past it.
+pub struct Since<W> {
+  inner: W,
+  converted: Option<u64>,
+}
+
+impl<W: Waveform> Since<W> {
+  /// Wrap `inner`, returning every change without a `converted` time.
+  pub fn new(inner: W, converted: Option<u64>) -> Self {
+    Self { inner, converted }
+  }
+}
+
+impl<W: Waveform> Waveform for Since<W> {
+  fn header(&self) -> WaveHeader {
+    self.inner.header()
+  }
+
+  fn for_each_hierarchy(&mut self, callback: impl FnMut(HierarchyEntry)) -> anyhow::Result<()> {
+    self.inner.for_each_hierarchy(callback)
+  }
+
+  fn for_each_change(
+    &mut self,
+    handles: &[FstSignalHandle],
+    mut callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    let Some(converted) = self.converted else {
+      return self.inner.for_each_change(handles, callback);
+    };
+    // the backend may still give the earlier changes of a block it can't skip
+    self
+      .inner
+      .for_each_change_in(handles, converted + 1..u64::MAX, |t, handle, value| {
+        if t > converted {
+          callback(t, handle, value);
+        }
+      })
+  }
+}
+
+#[test]
+fn read_changes_since_converted() {
+  use crate::fixture::FixtureWave;
+
+  let mut wave = FixtureWave::new(0, 20);
+  wave.scope("top");
+  let valid = wave.var("valid", 1);
+  let data = wave.var("data", 4);
+  wave.upscope();
+  wave.change(0, valid, "0");
+  wave.change(5, valid, "1");
+  wave.change(5, data, "0011");
+  wave.change(10, data, "0100");
+  wave.change(20, valid, "0");
+
+  let mut read = |converted| {
+    let mut changes = Vec::new();
+    let mut since = Since::new(&mut wave, converted);
+    since
+      .for_each_change(&[valid, data], |t, handle, _| {
+        changes.push((t, handle.get_index()))
+      })
+      .unwrap();
+    changes
+  };
+  assert_eq!(read(None).len(), 5);
+  assert_eq!(read(Some(5)), [(10, 1), (20, 0)]);
+  assert!(read(Some(20)).is_empty());
+}
@@ -0,0 +1,291 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Context;
+use fst_native::FstSignalHandle;
+use serde::{Deserialize, Serialize};
+
+use crate::SignalLookup;
+
+/// How the state of a state machine is encoded over its member signals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+  /// One 1-bit member per state, the i-th state is the one where only the i-th member is high
+  OneHot,
+  /// The members are concatenated into a number, the i-th state is the one encoded as i
+  Binary,
+  /// The value of a single member names the state, see [`StateRegisterConfig`]
+  #[serde(skip)]
+  Named,
+}
+
+/// A state machine decoded from several signals into a single derived signal holding the state
+/// name. Members are given by their full dot separated path, the first one being the most
+/// significant.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateMachineConfig {
+  pub name: String,
+  pub encoding: Encoding,
+  pub members: Vec<String>,
+  /// Name of every state, indexed as described by the encoding
+  pub states: Vec<String>,
+}
+
+/// A state register whose values are named, like
+/// `{ fsm = "top.ctrl.state", states = { "00" = "IDLE", "01" = "BUSY" } }`. The values are written
+/// in raw bits as wide as the register, and the state machine is named after the register path.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateRegisterConfig {
+  pub fsm: String,
+  pub states: BTreeMap<String, String>,
+}
+
+/// A state machine whose members are resolved to handles.
+#[derive(Debug, Clone)]
+pub struct StateMachine {
+  pub name: String,
+  pub encoding: Encoding,
+  pub members: Vec<FstSignalHandle>,
+  pub states: Vec<String>,
+  /// Index of the state of every value of a named register
+  values: BTreeMap<String, usize>,
+}
+
+/// Find the members of every state machine and state register in the hierarchy and check their
+/// widths fit the encoding. The rest of the config is checked by
+/// [`crate::config::Config::validate`].
+pub fn resolve(
+  signals: &SignalLookup,
+  configs: &[StateMachineConfig],
+  registers: &[StateRegisterConfig],
+) -> anyhow::Result<Vec<StateMachine>> {
+  let find = |path: &str| -> anyhow::Result<(FstSignalHandle, u32)> {
+    signals
+      .find(path)
+      .with_context(|| format!("state machine member {path} is not found"))
+  };
+
+  configs
+    .iter()
+    .map(|c| -> anyhow::Result<StateMachine> {
+      let mut members = Vec::with_capacity(c.members.len());
+      let mut width = 0;
+      for path in &c.members {
+        let (handle, bits) = find(path)?;
+        anyhow::ensure!(
+          c.encoding == Encoding::Binary || bits == 1,
+          "one-hot state machine {} member {path} should be 1-bit, got {bits} bits",
+          c.name
+        );
+        members.push(handle);
+        width += bits;
+      }
+      anyhow::ensure!(
+        c.encoding == Encoding::OneHot
+          || width <= 64 && (width == 64 || c.states.len() as u64 <= 1 << width),
+        "binary state machine {} is {width} bits wide and can't encode {} states",
+        c.name,
+        c.states.len()
+      );
+      Ok(StateMachine {
+        name: c.name.clone(),
+        encoding: c.encoding,
+        members,
+        states: c.states.clone(),
+        values: BTreeMap::new(),
+      })
+    })
+    .chain(registers.iter().map(|r| -> anyhow::Result<StateMachine> {
+      let (handle, bits) = find(&r.fsm)?;
+      let wrong: Vec<_> = r
+        .states
+        .keys()
+        .filter(|value| value.len() != bits as usize || !value.bytes().all(|b| b"01".contains(&b)))
+        .map(String::as_str)
+        .collect();
+      anyhow::ensure!(
+        wrong.is_empty(),
+        "state register {} is {bits} bits wide, its values should be as many 0 and 1, got {}",
+        r.fsm,
+        wrong.join(", ")
+      );
+      Ok(StateMachine {
+        name: r.fsm.clone(),
+        encoding: Encoding::Named,
+        members: vec![handle],
+        states: r.states.values().cloned().collect(),
+        values: r
+          .states
+          .keys()
+          .enumerate()
+          .map(|(i, v)| (v.clone(), i))
+          .collect(),
+      })
+    }))
+    .collect()
+}
+
+impl StateMachine {
+  /// Return the name of the state encoded by the member values, most significant first. Values
+  /// not matching any state decode to `invalid(<bits>)`.
+  fn decode(&self, values: &[&str]) -> String {
+    let bits = values.concat();
+    let state = match self.encoding {
+      Encoding::OneHot => {
+        let hot: Vec<_> = (0..values.len()).filter(|i| values[*i] == "1").collect();
+        match hot[..] {
+          [i] if values.iter().all(|v| *v == "0" || *v == "1") => Some(i),
+          _ => None,
+        }
+      }
+      Encoding::Binary => u64::from_str_radix(&bits, 2)
+        .ok()
+        .and_then(|i| usize::try_from(i).ok()),
+      Encoding::Named => self.values.get(&bits).copied(),
+    };
+    match state.and_then(|i| self.states.get(i)) {
+      Some(state) => state.clone(),
+      None => format!("invalid({bits})"),
+    }
+  }
+}
+
+/// Decode the state of state machines from the value changes of their members.
+#[derive(Debug, Clone)]
+pub struct StateDecoder<'a> {
+  machines: &'a [StateMachine],
+  values: HashMap<usize, String>,
+  states: Vec<Option<String>>,
+}
+
+impl<'a> StateDecoder<'a> {
+  pub fn new(machines: &'a [StateMachine]) -> Self {
+    Self {
+      machines,
+      values: HashMap::new(),
+      states: vec![None; machines.len()],
+    }
+  }
+
+  /// Return the handles of all the members of the state machines.
+  pub fn handles(&self) -> Vec<FstSignalHandle> {
+    self
+      .machines
+      .iter()
+      .flat_map(|m| m.members.iter().copied())
+      .collect()
+  }
+
+  /// Feed a value change and return the state machines entering a new state, with that state.
+  /// A state machine has no state until all its members have a value.
+  pub fn update(
+    &mut self,
+    handle: FstSignalHandle,
+    value: &str,
+  ) -> Vec<(&'a StateMachine, String)> {
+    self.values.insert(handle.get_index(), value.to_string());
+
+    let mut entered = Vec::new();
+    for (i, machine) in self.machines.iter().enumerate() {
+      if !machine.members.contains(&handle) {
+        continue;
+      }
+      let Some(values) = machine
+        .members
+        .iter()
+        .map(|member| self.values.get(&member.get_index()).map(String::as_str))
+        .collect::<Option<Vec<_>>>()
+      else {
+        continue;
+      };
+      let state = machine.decode(&values);
+      if self.states[i].as_ref() != Some(&state) {
+        self.states[i] = Some(state.clone());
+        entered.push((machine, state));
+      }
+    }
+    entered
+  }
+}
+
+#[test]
+fn decode_state_machines() {
+  use crate::fixture::FixtureWave;
+
+  let mut wave = FixtureWave::new(0, 100);
+  wave.scope("top");
+  let idle = wave.var("idle", 1);
+  let busy = wave.var("busy", 1);
+  let state = wave.var("state", 2);
+  wave.upscope();
+
+  let configs = vec![
+    StateMachineConfig {
+      name: "onehot".to_string(),
+      encoding: Encoding::OneHot,
+      members: vec!["top.idle".to_string(), "top.busy".to_string()],
+      states: vec!["IDLE".to_string(), "BUSY".to_string()],
+    },
+    StateMachineConfig {
+      name: "encoded".to_string(),
+      encoding: Encoding::Binary,
+      members: vec!["top.state".to_string()],
+      states: vec!["A".to_string(), "B".to_string(), "C".to_string()],
+    },
+  ];
+  let registers = vec![StateRegisterConfig {
+    fsm: "top.state".to_string(),
+    states: [("01", "BUSY"), ("00", "IDLE")]
+      .map(|(value, state)| (value.to_string(), state.to_string()))
+      .into(),
+  }];
+  let machines = resolve(&SignalLookup::new(&mut wave).unwrap(), &configs, &registers).unwrap();
+  let mut decoder = StateDecoder::new(&machines);
+
+  let changes = [
+    (idle, "1"),
+    (busy, "0"),
+    (state, "00"),
+    (state, "01"),
+    (idle, "0"),
+    (busy, "1"),
+    (state, "11"),
+  ];
+  let entered: Vec<_> = changes
+    .into_iter()
+    .flat_map(|(handle, value)| decoder.update(handle, value))
+    .map(|(machine, state)| format!("{}={state}", machine.name))
+    .collect();
+  assert_eq!(
+    entered,
+    [
+      "onehot=IDLE",
+      "encoded=A",
+      "top.state=IDLE",
+      "encoded=B",
+      "top.state=BUSY",
+      "onehot=invalid(00)",
+      "onehot=BUSY",
+      "encoded=invalid(11)",
+      "top.state=invalid(11)",
+    ]
+  );
+
+  let bus = vec![StateMachineConfig {
+    members: vec!["top.idle".to_string(), "top.state".to_string()],
+    ..configs[0].clone()
+  }];
+  assert!(resolve(&SignalLookup::new(&mut wave).unwrap(), &bus, &[]).is_err());
+  let too_many = vec![StateMachineConfig {
+    states: vec!["A".to_string(); 5],
+    ..configs[1].clone()
+  }];
+  assert!(resolve(&SignalLookup::new(&mut wave).unwrap(), &too_many, &[]).is_err());
+  let narrow = vec![StateRegisterConfig {
+    states: [("0".to_string(), "IDLE".to_string())].into(),
+    ..registers[0].clone()
+  }];
+  assert!(resolve(&SignalLookup::new(&mut wave).unwrap(), &[], &narrow).is_err());
+}
@@ -0,0 +1,145 @@
+//! GTKWave save files (`.gtkw`) as the source of the selected signals.
+
+use anyhow::Context;
+
+use crate::properties::{Properties, Radix, SignalProperties};
+
+/// Flags of the `@` lines, see `analyzer.h` of GTKWave
+const HEX: u32 = 0x2;
+const DEC: u32 = 0x4;
+const BIN: u32 = 0x8;
+const OCT: u32 = 0x10;
+const GROUP_BEGIN: u32 = 0x80_0000;
+const GROUP_END: u32 = 0x100_0000;
+
+/// The traces of a GTKWave save file.
+///
+/// Every trace is selected like a config entry by the path it is saved with, so a bus range like
+/// `top.data[7:0]` selects those bits. The traces of a `#{name} a b` concatenation are selected
+/// one by one. The save file also gives display properties: the innermost group a trace is shown
+/// in, its radix, and the alias of a `+{alias} path` trace as its label.
+#[derive(Debug, Default)]
+pub struct SaveFile {
+  pub signals: Vec<String>,
+  pub properties: Properties,
+}
+
+impl SaveFile {
+  pub fn from_path(path: &str) -> anyhow::Result<Self> {
+    let text =
+      std::fs::read_to_string(path).with_context(|| format!("fail to read save file {path}"))?;
+    Self::parse(&text).with_context(|| format!("fail to parse save file {path}"))
+  }
+
+  pub fn parse(text: &str) -> anyhow::Result<Self> {
+    let mut save = SaveFile::default();
+    let mut flags = 0;
+    let mut groups: Vec<String> = Vec::new();
+    for line in text.lines().map(str::trim) {
+      let Some(first) = line.chars().next() else {
+        continue;
+      };
+      match first {
+        // `[dumpfile]` and other directives, and the markers
+        '[' | '*' => (),
+        '@' => {
+          flags = u32::from_str_radix(&line[1..], 16)
+            .with_context(|| format!("malformed trace flags {line}"))?;
+        }
+        // a blank trace or a comment, unless it opens or closes a group
+        '-' if flags & GROUP_BEGIN != 0 => groups.push(line[1..].to_string()),
+        '-' if flags & GROUP_END != 0 => {
+          groups.pop();
+        }
+        '-' => (),
+        '+' => {
+          let (alias, path) =
+            split_name(&line[1..]).with_context(|| format!("malformed {line}"))?;
+          save.add(path, Some(alias), flags, groups.last());
+        }
+        '#' => {
+          let (_, paths) = split_name(&line[1..]).with_context(|| format!("malformed {line}"))?;
+          for path in paths.split_whitespace() {
+            save.add(path, None, flags, groups.last());
+          }
+        }
+        _ => save.add(line, None, flags, groups.last()),
+      }
+    }
+    Ok(save)
+  }
+
+  fn add(&mut self, path: &str, label: Option<&str>, flags: u32, group: Option<&String>) {
+    let radix = match flags {
+      _ if flags & HEX != 0 => Some(Radix::Hex),
+      _ if flags & DEC != 0 => Some(Radix::Dec),
+      _ if flags & OCT != 0 => Some(Radix::Oct),
+      _ if flags & BIN != 0 => Some(Radix::Bin),
+      _ => None,
+    };
+    self.signals.push(path.to_string());
+    self.properties.signals.insert(
+      path.to_string(),
+      SignalProperties {
+        label: label.map(str::to_string),
+        group: group.cloned(),
+        radix,
+        unit: None,
+        histogram: None,
+      },
+    );
+  }
+}
+
+/// Split `{name} rest` into the name and the rest.
+fn split_name(text: &str) -> Option<(&str, &str)> {
+  let (name, rest) = text.strip_prefix('{')?.split_once('}')?;
+  Some((name, rest.trim()))
+}
+
+#[test]
+fn parse_save_file() {
+  let save = SaveFile::parse(
+    r#"
+[*] GTKWave Analyzer v3.3.104 (w)1999-2020 BSI
+[dumpfile] "/tmp/top.fst"
+[timestart] 0
+*-24.000000 1000 -1
+[treeopen] top.
+@28
+top.clock
+@800200
+-core
+@22
+top.core.data[7:0]
++{ready alias} top.core.ready
+@1000200
+-core
+@28
+#{lanes} top.lane0 top.lane1
+-
+"#,
+  )
+  .unwrap();
+
+  assert_eq!(
+    save.signals,
+    [
+      "top.clock",
+      "top.core.data[7:0]",
+      "top.core.ready",
+      "top.lane0",
+      "top.lane1"
+    ]
+  );
+  let data = save.properties.get("top.core.data[7:0]", "data").unwrap();
+  assert_eq!(data.group.as_deref(), Some("core"));
+  assert_eq!(data.radix, Some(Radix::Hex));
+  let ready = save.properties.get("top.core.ready", "ready").unwrap();
+  assert_eq!(ready.label.as_deref(), Some("ready alias"));
+  let lane = save.properties.get("top.lane1", "lane1").unwrap();
+  assert!(lane.group.is_none());
+  assert_eq!(lane.radix, Some(Radix::Bin));
+
+  assert!(SaveFile::parse("@zz\ntop.clock").is_err());
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use fst_native::FstSignalHandle;
+use serde::{Deserialize, Serialize};
+
+use crate::edge::Edge;
+use crate::SignalLookup;
+
+/// A valid/ready handshake, counted on every rising edge of `clock` where both `valid` and
+/// `ready` are high. Signals are given by their full dot separated path.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HandshakeConfig {
+  pub name: String,
+  pub clock: String,
+  pub valid: String,
+  pub ready: String,
+}
+
+/// A handshake whose signals are resolved to handles.
+#[derive(Debug, Clone)]
+pub struct Handshake {
+  pub name: String,
+  pub clock: FstSignalHandle,
+  pub valid: FstSignalHandle,
+  pub ready: FstSignalHandle,
+}
+
+/// Find the signals of every handshake in the hierarchy. Every signal must exist and be 1-bit.
+pub fn resolve(
+  signals: &SignalLookup,
+  configs: &[HandshakeConfig],
+) -> anyhow::Result<Vec<Handshake>> {
+  let find = |path: &str| -> anyhow::Result<FstSignalHandle> {
+    let (handle, width) = signals
+      .find(path)
+      .with_context(|| format!("handshake signal {path} is not found"))?;
+    anyhow::ensure!(
+      width == 1,
+      "handshake signal {path} should be 1-bit, got {width} bits"
+    );
+    Ok(handle)
+  };
+
+  configs
+    .iter()
+    .map(|c| -> anyhow::Result<Handshake> {
+      Ok(Handshake {
+        name: c.name.clone(),
+        clock: find(&c.clock)?,
+        valid: find(&c.valid)?,
+        ready: find(&c.ready)?,
+      })
+    })
+    .collect()
+}
+
+/// The value of a signal, remembering the value it had before the latest change time.
+#[derive(Debug, Clone)]
+pub struct Sampled {
+  before: Option<String>,
+  current: String,
+  since: u64,
+}
+
+impl Sampled {
+  /// Return a signal getting its first value at time `t`.
+  pub fn new(t: u64, value: &str) -> Self {
+    Self {
+      before: None,
+      current: value.to_string(),
+      since: t,
+    }
+  }
+
+  /// Return the value the signal had right before time `t`.
+  pub fn before(&self, t: u64) -> Option<&str> {
+    if self.since == t {
+      self.before.as_deref()
+    } else {
+      Some(&self.current)
+    }
+  }
+
+  /// Record a change to `value` at time `t`.
+  pub fn set(&mut self, t: u64, value: &str) {
+    if self.since != t {
+      self.before = Some(std::mem::replace(&mut self.current, value.to_string()));
+      self.since = t;
+    } else {
+      self.current = value.to_string();
+    }
+  }
+}
+
+/// Count handshakes from the value changes of their signals.
+///
+/// Like a flip-flop, the operands are sampled with the value they had right before the clock
+/// edge, so changes landing at the same time as the edge only count for the next cycle.
+#[derive(Debug, Clone)]
+pub struct HandshakeCounter<'a> {
+  handshakes: &'a [Handshake],
+  values: HashMap<usize, Sampled>,
+  counts: Vec<i64>,
+}
+
+impl<'a> HandshakeCounter<'a> {
+  pub fn new(handshakes: &'a [Handshake]) -> Self {
+    Self {
+      handshakes,
+      values: HashMap::new(),
+      counts: vec![0; handshakes.len()],
+    }
+  }
+
+  /// Return the handles of all the signals needed by the handshakes.
+  pub fn handles(&self) -> Vec<FstSignalHandle> {
+    self
+      .handshakes
+      .iter()
+      .flat_map(|hs| [hs.clock, hs.valid, hs.ready])
+      .collect()
+  }
+
+  fn before(&self, handle: FstSignalHandle, t: u64) -> Option<&str> {
+    self
+      .values
+      .get(&handle.get_index())
+      .and_then(|sampled| sampled.before(t))
+  }
+
+  /// Feed a value change at time `t`.
+  pub fn update(&mut self, t: u64, handle: FstSignalHandle, value: &str) {
+    let prev = self.before(handle, t);
+    if prev.and_then(|prev| Edge::classify(prev, value)) == Some(Edge::Pos) {
+      let handshakes = self.handshakes;
+      for (i, hs) in handshakes.iter().enumerate() {
+        if hs.clock == handle
+          && self.before(hs.valid, t) == Some("1")
+          && self.before(hs.ready, t) == Some("1")
+        {
+          self.counts[i] += 1;
+        }
+      }
+    }
+
+    match self.values.get_mut(&handle.get_index()) {
+      Some(sampled) => sampled.set(t, value),
+      None => {
+        self
+          .values
+          .insert(handle.get_index(), Sampled::new(t, value));
+      }
+    }
+  }
+
+  /// Return every handshake with the number of times it happened.
+  pub fn counts(&self) -> impl Iterator<Item = (&Handshake, i64)> {
+    self.handshakes.iter().zip(self.counts.iter().copied())
+  }
+}
+
+#[test]
+fn count_handshakes_on_rising_edges() {
+  use crate::fixture::FixtureWave;
+
+  let mut wave = FixtureWave::new(0, 100);
+  wave.scope("top");
+  let clock = wave.var("clock", 1);
+  let valid = wave.var("valid", 1);
+  let ready = wave.var("ready", 1);
+  wave.var("data", 8);
+  wave.upscope();
+
+  let configs = vec![HandshakeConfig {
+    name: "req".to_string(),
+    clock: "top.clock".to_string(),
+    valid: "top.valid".to_string(),
+    ready: "top.ready".to_string(),
+  }];
+  let handshakes = resolve(&SignalLookup::new(&mut wave).unwrap(), &configs).unwrap();
+  let mut counter = HandshakeCounter::new(&handshakes);
+
+  let changes = [
+    (0, clock, "0"),
+    (0, valid, "0"),
+    (0, ready, "1"),
+    // valid rises together with the clock, it is only seen by the next edge
+    (10, clock, "1"),
+    (10, valid, "1"),
+    (15, clock, "0"),
+    // counted
+    (20, clock, "1"),
+    (25, clock, "0"),
+    (28, ready, "0"),
+    // not counted, ready is low
+    (30, clock, "1"),
+  ];
+  for (t, handle, value) in changes {
+    counter.update(t, handle, value);
+  }
+  let counts: Vec<_> = counter
+    .counts()
+    .map(|(hs, n)| (hs.name.as_str(), n))
+    .collect();
+  assert_eq!(counts, [("req", 1)]);
+
+  let bus = vec![HandshakeConfig {
+    valid: "top.data".to_string(),
+    ..configs[0].clone()
+  }];
+  assert!(resolve(&SignalLookup::new(&mut wave).unwrap(), &bus).is_err());
+}
@@ -0,0 +1,472 @@
+//! Instruction retirement profiles: every retired instruction is decoded with an opcode map, and
+//! the cycles it took are accumulated in the function of its mnemonic.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Context;
+use fst_native::FstSignalHandle;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigFormat;
+use crate::edge::Edge;
+use crate::handshake::Sampled;
+use crate::pprof::{self, ProfileBuilder};
+use crate::sink::OutputSink;
+use crate::wave::{SignalValue, WaveHeader};
+use crate::window::TimeWindow;
+use crate::SignalLookup;
+
+/// Mnemonic of the instructions no opcode matches
+const UNKNOWN: &str = "unknown";
+
+/// The signals of the retired instructions, given by their full dot separated path.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstretConfig {
+  /// Encoding of the retired instruction, every change of it retires one instruction unless
+  /// `valid` is given
+  pub instruction: String,
+  /// PC of the retired instruction, the samples are then split by PC
+  #[serde(default)]
+  pub pc: Option<String>,
+  /// 1-bit clock the cycles are counted in. Nanoseconds are counted without it
+  #[serde(default)]
+  pub clock: Option<String>,
+  /// 1-bit retire valid signal, which needs `clock`. One instruction then retires on every rising
+  /// edge of the clock where it is high, so the same instruction retired twice in a row counts
+  /// twice
+  #[serde(default)]
+  pub valid: Option<String>,
+  /// Path of the opcode map, a JSON, TOML or YAML file mapping every mnemonic to its `match`
+  /// and `mask`, like `"vadd.vv": {"match": "0x57", "mask": "0xfc00707f"}`
+  pub opcodes: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Opcode {
+  #[serde(rename = "match")]
+  matches: String,
+  mask: String,
+}
+
+/// Decodes instruction encodings into mnemonics.
+#[derive(Debug, Clone, Default)]
+pub struct OpcodeMap {
+  /// `(mnemonic, match, mask)`, the most specific masks first
+  opcodes: Vec<(String, u64, u64)>,
+}
+
+impl OpcodeMap {
+  /// Load the opcode map from a file path, its format is given by the extension.
+  pub fn from_path(path: &str) -> anyhow::Result<Self> {
+    let format = ConfigFormat::from_path(path)?;
+    let raw = std::fs::read(path).with_context(|| format!("fail to read opcode map {path}"))?;
+    Self::parse(&raw, format).with_context(|| format!("fail to parse opcode map {path}"))
+  }
+
+  /// Parse an opcode map written in the given format. The match and the mask are integers
+  /// written in hexadecimal with `0x`, in binary with `0b`, or in decimal.
+  pub fn parse(raw: &[u8], format: ConfigFormat) -> anyhow::Result<Self> {
+    let map: BTreeMap<String, Opcode> = match format {
+      ConfigFormat::Json => serde_json::from_slice(raw)?,
+      ConfigFormat::Toml => toml::from_str(std::str::from_utf8(raw)?)?,
+      ConfigFormat::Yaml => serde_yaml::from_slice(raw)?,
+    };
+    let mut opcodes = map
+      .into_iter()
+      .map(|(mnemonic, opcode)| {
+        let matches = parse_int(&opcode.matches)
+          .with_context(|| format!("invalid match {} of {mnemonic}", opcode.matches))?;
+        let mask = parse_int(&opcode.mask)
+          .with_context(|| format!("invalid mask {} of {mnemonic}", opcode.mask))?;
+        Ok((mnemonic, matches, mask))
+      })
+      .collect::<anyhow::Result<Vec<_>>>()?;
+    // stable, so mnemonics as specific as each other stay in name order
+    opcodes.sort_by_key(|(_, _, mask)| std::cmp::Reverse(mask.count_ones()));
+    Ok(Self { opcodes })
+  }
+
+  /// Return the mnemonic of the most specific opcode matching `encoding`.
+  pub fn decode(&self, encoding: u64) -> Option<&str> {
+    self
+      .opcodes
+      .iter()
+      .find(|(_, matches, mask)| encoding & mask == *matches)
+      .map(|(mnemonic, _, _)| mnemonic.as_str())
+  }
+}
+
+fn parse_int(raw: &str) -> Option<u64> {
+  let raw = raw.replace('_', "");
+  if let Some(hex) = raw.strip_prefix("0x") {
+    u64::from_str_radix(hex, 16).ok()
+  } else if let Some(bin) = raw.strip_prefix("0b") {
+    u64::from_str_radix(bin, 2).ok()
+  } else {
+    raw.parse().ok()
+  }
+}
+
+/// An instruction retirement config whose signals are resolved to handles.
+#[derive(Debug, Clone)]
+pub struct Instret {
+  pub instruction: FstSignalHandle,
+  pub pc: Option<FstSignalHandle>,
+  pub clock: Option<FstSignalHandle>,
+  pub valid: Option<FstSignalHandle>,
+  pub opcodes: OpcodeMap,
+}
+
+/// Find the signals of the config in the hierarchy and load its opcode map. The clock and the
+/// retire valid signal must be 1-bit.
+pub fn resolve(signals: &SignalLookup, config: &InstretConfig) -> anyhow::Result<Instret> {
+  let find = |what: &str, path: &str| {
+    signals
+      .find(path)
+      .with_context(|| format!("{what} signal {path} is not found"))
+  };
+
+  let (instruction, _) = find("instruction", &config.instruction)?;
+  let pc = match &config.pc {
+    Some(path) => Some(find("pc", path)?.0),
+    None => None,
+  };
+  let bit = |what: &str, path: &Option<String>| -> anyhow::Result<_> {
+    let Some(path) = path else {
+      return Ok(None);
+    };
+    let (handle, width) = find(what, path)?;
+    anyhow::ensure!(
+      width == 1,
+      "{what} signal {path} should be 1-bit, got {width} bits"
+    );
+    Ok(Some(handle))
+  };
+  let clock = bit("clock", &config.clock)?;
+  let valid = bit("valid", &config.valid)?;
+  anyhow::ensure!(
+    valid.is_none() || clock.is_some(),
+    "the instret valid signal is sampled on the edges of the instret clock, which is missing"
+  );
+  Ok(Instret {
+    instruction,
+    pc,
+    clock,
+    valid,
+    opcodes: OpcodeMap::from_path(&config.opcodes)?,
+  })
+}
+
+/// Builds a profile with one sample per mnemonic, and per PC if the config gives one, counting
+/// the retired instructions and the cycles they took. An instruction takes the cycles since the
+/// previous retirement, or since the start of the window for the first one.
+///
+/// With a retire valid signal, the instruction, PC and valid values are the ones right before the
+/// rising edge of the clock, like a flip-flop would sample them.
+pub struct InstretSink {
+  instret: Instret,
+  window: TimeWindow,
+  /// Bounds of the window, within the waveform time range
+  start: u64,
+  /// Header of the waveform, for the length of its time ticks
+  header: WaveHeader,
+  /// Clock value before the current change and rising edges of the clock so far
+  clock_value: String,
+  cycles: u64,
+  /// Latest value of the PC
+  pc: Option<String>,
+  /// Values of the instruction, PC and valid signals, by handle index, with a valid signal
+  sampled: HashMap<usize, Sampled>,
+  /// Retirement waiting for the other changes of its time, to get the PC it retires at
+  pending: Option<(u64, String)>,
+  /// Cycle or time of the previous retirement
+  retired: Option<u64>,
+  /// Retired instructions and their cycles, by mnemonic and PC
+  counts: BTreeMap<(String, Option<String>), (i64, i64)>,
+  builder: ProfileBuilder,
+}
+
+impl InstretSink {
+  pub fn new(header: &WaveHeader, instret: Instret, window: TimeWindow) -> anyhow::Result<Self> {
+    anyhow::ensure!(
+      header.end_time >= header.start_time,
+      "header end time {} is before start time {}",
+      header.end_time,
+      header.start_time
+    );
+    let mut builder = ProfileBuilder::new();
+    builder.profile.period_type = Some(pprof::ValueType {
+      r#type: builder.str_tbl.id("instructions"),
+      unit: builder.str_tbl.id("count"),
+    });
+    builder.profile.period = 1;
+    let (start, end) = window.clamp(header.start_time, header.end_time);
+    builder.profile.duration_nanos = header.ticks_to_nanos(end - start).try_into()?;
+    let unit = if instret.clock.is_some() {
+      "cycles"
+    } else {
+      "nanoseconds"
+    };
+    builder.profile.sample_type = vec![
+      pprof::ValueType {
+        r#type: builder.str_tbl.id("instructions"),
+        unit: builder.str_tbl.id("count"),
+      },
+      pprof::ValueType {
+        r#type: builder.str_tbl.id("cycles"),
+        unit: builder.str_tbl.id(unit),
+      },
+    ];
+    builder.profile.default_sample_type = builder.str_tbl.id("cycles");
+
+    Ok(Self {
+      instret,
+      window,
+      start,
+      header: header.clone(),
+      clock_value: String::new(),
+      cycles: 0,
+      pc: None,
+      sampled: HashMap::new(),
+      pending: None,
+      retired: None,
+      counts: BTreeMap::new(),
+      builder,
+    })
+  }
+
+  /// Emit one sample per mnemonic and PC, in name order, and return the profile.
+  pub fn finish(mut self) -> pprof::Profile {
+    self.retire();
+    let Self {
+      counts,
+      mut builder,
+      ..
+    } = self;
+    for ((mnemonic, pc), (count, cycles)) in counts {
+      let location_id = builder.stack(&[&mnemonic]);
+      let label = pc.map(|pc| builder.label("pc", &pc)).into_iter().collect();
+      builder.profile.sample.push(pprof::Sample {
+        location_id,
+        value: vec![count, cycles],
+        label,
+      });
+    }
+    builder.finish()
+  }
+
+  /// Account the pending retirement, if any.
+  fn retire(&mut self) {
+    let Some((t, encoding)) = self.pending.take() else {
+      return;
+    };
+    let pc = self.pc.clone();
+    self.account(t, &encoding, pc.as_deref());
+  }
+
+  /// Account the instruction `encoding` retired at time `t` and `pc`.
+  fn account(&mut self, t: u64, encoding: &str, pc: Option<&str>) {
+    let now = match self.instret.clock {
+      Some(_) => self.cycles,
+      None => self.header.ticks_to_nanos(t),
+    };
+    let since = self.retired.unwrap_or(match self.instret.clock {
+      Some(_) => 0,
+      None => self.header.ticks_to_nanos(self.start),
+    });
+    self.retired = Some(now);
+
+    let mnemonic = u64::from_str_radix(encoding, 2)
+      .ok()
+      .and_then(|encoding| self.instret.opcodes.decode(encoding))
+      .unwrap_or(UNKNOWN);
+    let pc = pc.map(|pc| match u64::from_str_radix(pc, 2) {
+      Ok(pc) => format!("{pc:#x}"),
+      Err(_) => pc.to_string(),
+    });
+    let (count, cycles) = self.counts.entry((mnemonic.to_string(), pc)).or_default();
+    *count += 1;
+    *cycles += now.saturating_sub(since) as i64;
+  }
+
+  fn before(&self, handle: Option<FstSignalHandle>, t: u64) -> Option<String> {
+    let sampled = self.sampled.get(&handle?.get_index())?;
+    sampled.before(t).map(str::to_string)
+  }
+}
+
+impl OutputSink for InstretSink {
+  fn handles(&self) -> Vec<FstSignalHandle> {
+    let Instret {
+      instruction,
+      pc,
+      clock,
+      valid,
+      ..
+    } = &self.instret;
+    std::iter::once(*instruction)
+      .chain(*pc)
+      .chain(*clock)
+      .chain(*valid)
+      .collect()
+  }
+
+  fn change(&mut self, t: u64, handle: FstSignalHandle, value: &SignalValue) {
+    if self.pending.as_ref().is_some_and(|(since, _)| *since < t) {
+      self.retire();
+    }
+    let SignalValue::String(bits) = value else {
+      return;
+    };
+    if Some(handle) == self.instret.clock {
+      let rising = Edge::classify(&self.clock_value, bits) == Some(Edge::Pos);
+      if rising && self.window.contains(t) {
+        self.cycles += 1;
+        if self.before(self.instret.valid, t).as_deref() == Some("1") {
+          if let Some(encoding) = self.before(Some(self.instret.instruction), t) {
+            let pc = self.before(self.instret.pc, t);
+            self.account(t, &encoding, pc.as_deref());
+          }
+        }
+      }
+      self.clock_value.clone_from(bits);
+    }
+    if self.instret.valid.is_some() {
+      // retired on the clock edges only
+      match self.sampled.get_mut(&handle.get_index()) {
+        Some(sampled) => sampled.set(t, bits),
+        None => {
+          self
+            .sampled
+            .insert(handle.get_index(), Sampled::new(t, bits));
+        }
+      }
+      return;
+    }
+    if Some(handle) == self.instret.pc {
+      self.pc = Some(bits.clone());
+    }
+    if handle == self.instret.instruction && self.window.contains(t) {
+      // two retirements at the same time only count the last one
+      self.pending = Some((t, bits.clone()));
+    }
+  }
+}
+
+#[test]
+fn profile_retired_instructions() {
+  use crate::fixture::FixtureWave;
+  use crate::sink::{drive, Feedback};
+  use crate::wave::Waveform;
+
+  let opcodes = br#"
+    "vadd.vv" = { match = "0x57", mask = "0xfc00707f" }
+    "vdiv.vv" = { match = "0x84000057", mask = "0xfc00707f" }
+    "vector" = { match = "0x57", mask = "0x7f" }
+  "#;
+  let opcodes = OpcodeMap::parse(opcodes, ConfigFormat::Toml).unwrap();
+  assert_eq!(opcodes.decode(0x0000_0057), Some("vadd.vv"));
+  assert_eq!(opcodes.decode(0x8400_0057), Some("vdiv.vv"));
+  assert_eq!(opcodes.decode(0x0000_1057), Some("vector"));
+  assert_eq!(opcodes.decode(0x13), None);
+
+  let mut wave = FixtureWave::new(0, 100);
+  wave.scope("core");
+  let clock = wave.var("clock", 1);
+  let instruction = wave.var("instruction", 32);
+  let pc = wave.var("pc", 8);
+  wave.upscope();
+  let encoding = |encoding: u32| format!("{encoding:032b}");
+  wave.change(0, clock, "0");
+  for t in 1..=10 {
+    wave
+      .change(t * 10 - 5, clock, "1")
+      .change(t * 10, clock, "0");
+    let retired = match t {
+      2 => Some((0x0000_0057, "00010000")),
+      3 => Some((0x8400_0057, "00010100")),
+      7 => Some((0x0000_0057, "00010000")),
+      8 => Some((0x13, "00011000")),
+      _ => None,
+    };
+    if let Some((retired, at)) = retired {
+      // the PC is dumped after the instruction, it still labels it
+      wave
+        .change(t * 10, instruction, &encoding(retired))
+        .change(t * 10, pc, at);
+    }
+  }
+
+  let instret = Instret {
+    instruction,
+    pc: Some(pc),
+    clock: Some(clock),
+    valid: None,
+    opcodes,
+  };
+  let run = |wave: &mut FixtureWave, instret: Instret| {
+    let mut sink = InstretSink::new(&wave.header(), instret, TimeWindow::default()).unwrap();
+    drive(wave, std::slice::from_mut(&mut sink), Feedback::default()).unwrap();
+    let profile = sink.finish();
+    profile
+      .sample
+      .iter()
+      .map(|sample| {
+        let function = &profile.function[sample.location_id[0] as usize - 1];
+        let pc = &profile.string_table[sample.label[0].str as usize];
+        (
+          profile.string_table[function.name as usize].clone(),
+          pc.clone(),
+          sample.value.clone(),
+        )
+      })
+      .collect::<Vec<_>>()
+  };
+  let expected = |samples: &[(&str, &str, Vec<i64>)]| -> Vec<(String, String, Vec<i64>)> {
+    samples
+      .iter()
+      .map(|(mnemonic, pc, value)| (mnemonic.to_string(), pc.to_string(), value.clone()))
+      .collect()
+  };
+  assert_eq!(
+    run(&mut wave, instret.clone()),
+    expected(&[
+      ("unknown", "0x18", vec![1, 1]),
+      ("vadd.vv", "0x10", vec![2, 6]),
+      ("vdiv.vv", "0x14", vec![1, 1]),
+    ])
+  );
+
+  // the same instruction retired on two edges in a row counts twice with a valid signal
+  let mut wave = FixtureWave::new(0, 60);
+  let clock = wave.var("clock", 1);
+  let valid = wave.var("valid", 1);
+  let instruction = wave.var("instruction", 32);
+  let pc = wave.var("pc", 8);
+  wave
+    .change(0, clock, "0")
+    .change(0, valid, "0")
+    .change(0, instruction, &encoding(0x57))
+    .change(0, pc, "00010000");
+  for t in 1..=5 {
+    wave.change(t * 10 - 5, clock, "1");
+    if t == 1 || t == 3 {
+      wave.change(t * 10 - 5, valid, if t == 1 { "1" } else { "0" });
+    }
+    wave.change(t * 10, clock, "0");
+  }
+  let instret = Instret {
+    instruction,
+    pc: Some(pc),
+    clock: Some(clock),
+    valid: Some(valid),
+    ..instret
+  };
+  // valid rises with the first edge, so it is only seen by the second and third ones
+  assert_eq!(
+    run(&mut wave, instret),
+    expected(&[("vadd.vv", "0x10", vec![2, 3])])
+  );
+}
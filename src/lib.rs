@@ -0,0 +1,1164 @@
+//! Extract signal value changes from FST waveforms into pprof profiles.
+//!
+//! The binary is a thin CLI over this crate. Library users can collect signals with
+//! [`collect_signals`] and convert them with [`convert::convert`], or go from a waveform and a
+//! [`Config`] straight to a profile with [`fst_to_profile`], or to the value changes and
+//! statistics of every signal with [`fst_to_series`]. The latter is exposed to Python by the
+//! `python` feature.
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use fst_native::FstSignalHandle;
+use tracing::{info, warn};
+
+use crate::failure::FailureKind;
+
+pub mod aggregate;
+pub mod cache;
+pub mod checkpoint;
+pub mod clock;
+pub mod compress;
+pub mod config;
+pub mod convert;
+pub mod date;
+pub mod derived;
+pub mod downsample;
+pub mod dump;
+pub mod edge;
+pub mod failure;
+#[cfg(test)]
+mod fixture;
+pub mod folded;
+pub mod follow;
+pub mod fsm;
+pub mod gtkw;
+pub mod handshake;
+pub mod instret;
+pub mod parquet;
+pub mod pattern;
+pub mod pprof;
+pub mod progress;
+pub mod properties;
+#[cfg(feature = "python")]
+mod python;
+pub mod report;
+pub mod sink;
+pub mod transaction;
+pub mod transitions;
+pub mod trigger;
+pub mod vcd;
+pub mod verify;
+pub mod wave;
+pub mod window;
+pub mod xz;
+
+use config::Config;
+use convert::ConvertOptions;
+use pattern::{BitSlice, SignalPattern};
+use wave::{EnumTable, HierarchyEntry, SignalValue, Waveform};
+
+/// Convert every signal selected by `config` inside its time window with the default options,
+/// without touching the filesystem.
+pub fn fst_to_profile(
+  reader: &mut impl Waveform,
+  config: &Config,
+) -> anyhow::Result<pprof::Profile> {
+  let reader = &mut derived::DerivedWave::new(reader, &config.derived)?;
+  let collect = CollectOptions {
+    regexes: config.regexes()?,
+    scopes: config.scopes.clone(),
+    ..Default::default()
+  };
+  let metadata = collect_signals(reader, &config.signals, &collect)?;
+  let signals = SignalLookup::new(reader)?;
+  let options = ConvertOptions::from_config(&signals, config)?;
+  let header = reader.header();
+  options
+    .window
+    .validate(header.start_time, header.end_time)?;
+  convert::convert(reader, &metadata, &options)
+}
+
+/// The value changes of one signal, as parallel lists.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Series {
+  /// Full dot separated path of the signal
+  pub signal: String,
+  pub width: u32,
+  /// Raw time ticks of every change, the initial value included
+  pub times: Vec<u64>,
+  /// Value after every change: its bits, its enum name, or the real number printed
+  pub values: Vec<String>,
+}
+
+/// The aggregations of the config of one signal over the time window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregates {
+  /// Full dot separated path of the signal
+  pub signal: String,
+  /// In config order, the high time in nanoseconds like in the profiles
+  pub values: Vec<(aggregate::Aggregation, i64)>,
+}
+
+/// The value changes and statistics of every signal a config selects.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Extraction {
+  /// In the order of [`collect_signals`]
+  pub series: Vec<Series>,
+  pub report: report::Report,
+  /// One entry per aggregated signal, in config order
+  pub aggregates: Vec<Aggregates>,
+}
+
+/// Read the value changes of every signal selected by `config` inside its time window, with the
+/// statistics `--report` writes and the aggregations of the config, without building a profile.
+/// With a window start, every signal starts with the value it has there.
+pub fn fst_to_series(reader: &mut impl Waveform, config: &Config) -> anyhow::Result<Extraction> {
+  let reader = &mut derived::DerivedWave::new(reader, &config.derived)?;
+  let collect = CollectOptions {
+    regexes: config.regexes()?,
+    scopes: config.scopes.clone(),
+    ..Default::default()
+  };
+  let metadata = collect_signals(reader, &config.signals, &collect)?;
+  let header = reader.header();
+  let window = window::TimeWindow {
+    start: config.time_start,
+    end: config.time_end,
+    ..Default::default()
+  };
+  window.validate(header.start_time, header.end_time)?;
+  let signals = SignalLookup::new(reader)?;
+  let machines = fsm::resolve(&signals, &config.state_machines, &config.state_registers)?;
+  let aggregations = aggregate::resolve(&signals, &config.aggregations)?;
+  let properties = properties::Properties::default();
+
+  let mut series: Vec<_> = (0..metadata.handle.len())
+    .map(|i| Series {
+      signal: metadata.full_name(i),
+      width: metadata.widths[i],
+      ..Default::default()
+    })
+    .collect();
+  let (start, end) = window.clamp(header.start_time, header.end_time);
+  let mut sinks = [
+    // the changes before the window give the values at its start
+    sink::Sink::Json(dump::DumpSink::new(
+      metadata.clone(),
+      window::TimeWindow {
+        start: None,
+        ..window
+      },
+    )),
+    sink::Sink::Report(report::ReportSink::new(
+      &header,
+      metadata,
+      window,
+      &properties,
+      &machines,
+    )),
+    sink::Sink::Aggregate(aggregate::Aggregator::new(
+      &aggregations,
+      window,
+      (start, end),
+    )),
+  ];
+  sink::drive(reader, &mut sinks, sink::Feedback::default())?;
+  let [sink::Sink::Json(dump), sink::Sink::Report(report), sink::Sink::Aggregate(aggregator)] =
+    sinks
+  else {
+    unreachable!("the sinks are built above")
+  };
+
+  // value of every signal at the window start, until its first change inside the window
+  let mut before = vec![None; series.len()];
+  for record in dump.finish() {
+    let series = &mut series[record.signal];
+    if !window.contains(record.time) {
+      before[record.signal] = Some(record.value);
+      continue;
+    }
+    if let Some(value) = before[record.signal].take() {
+      if record.time != start {
+        series.times.push(start);
+        series.values.push(value);
+      }
+    }
+    series.times.push(record.time);
+    series.values.push(record.value);
+  }
+  for (series, value) in series.iter_mut().zip(before) {
+    if let Some(value) = value {
+      series.times.push(start);
+      series.values.push(value);
+    }
+  }
+
+  let aggregates = aggregator
+    .finish()
+    .into_iter()
+    .map(|(aggregate, values)| Aggregates {
+      signal: aggregate.signal.clone(),
+      values: values
+        .into_iter()
+        .map(|(aggregation, value)| {
+          let value = match aggregation {
+            aggregate::Aggregation::Duration => header.ticks_to_nanos(value as u64) as i64,
+            _ => value,
+          };
+          (aggregation, value)
+        })
+        .collect(),
+    })
+    .collect();
+  Ok(Extraction {
+    series,
+    report: report.finish(),
+    aggregates,
+  })
+}
+
+/// The signals selected from the hierarchy, one entry per signal in every field.
+#[derive(Default, Debug, Clone)]
+pub struct SignalMetadata {
+  pub module_paths: Vec<Vec<String>>,
+  pub names: Vec<String>,
+  pub widths: Vec<u32>,
+  pub handle: Vec<FstSignalHandle>,
+  /// Enum table naming the values of the signal, if the hierarchy gives one
+  pub enums: Vec<Option<Arc<EnumTable>>>,
+  /// Bits of the FST signal this signal is made of, if it is only a slice of it. The name and the
+  /// width are the ones of the slice
+  pub slices: Vec<Option<BitSlice>>,
+  /// Number of matching signals skipped because they are nested deeper than the max depth
+  pub too_deep: usize,
+}
+
+impl SignalMetadata {
+  /// Return the hierarchical name of the i-th signal, scopes are joined with `.`.
+  pub fn full_name(&self, i: usize) -> String {
+    self.module_paths[i]
+      .iter()
+      .chain(std::iter::once(&self.names[i]))
+      .map(String::as_str)
+      .collect::<Vec<_>>()
+      .join(".")
+  }
+
+  /// Return the full name of the i-th signal with its bit range, like `top.data [7:0]`. The FST
+  /// hierarchy only gives the width, so the range is assumed to end at bit 0. Scalars and names
+  /// already carrying a range are returned as is.
+  pub fn ranged_name(&self, i: usize) -> String {
+    let name = self.full_name(i);
+    match self.widths[i] {
+      width if width > 1 && !self.names[i].ends_with(']') => {
+        format!("{name} [{}:0]", width - 1)
+      }
+      _ => name,
+    }
+  }
+
+  /// Return the name the enum table of the i-th signal gives to `value`, if any.
+  pub fn enum_name(&self, i: usize, value: &str) -> Option<&str> {
+    self.enums[i].as_ref()?.name(value)
+  }
+
+  /// Return the value of the i-th signal out of a change of its FST signal, which is only the bits
+  /// of its slice if it has one.
+  pub fn value_of<'v>(&self, i: usize, value: &'v SignalValue) -> Cow<'v, SignalValue> {
+    match (self.slices[i], value) {
+      (Some(slice), SignalValue::String(bits)) => {
+        Cow::Owned(SignalValue::String(slice.extract(bits)))
+      }
+      _ => Cow::Borrowed(value),
+    }
+  }
+
+  /// Return the positions of the signals of every handle, by handle index. A handle has several
+  /// positions when aliases are kept.
+  pub fn positions(&self) -> HashMap<usize, Vec<usize>> {
+    let mut positions: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (i, handle) in self.handle.iter().enumerate() {
+      positions.entry(handle.get_index()).or_default().push(i);
+    }
+    positions
+  }
+
+  /// Return the signals whose index is accepted by `keep`.
+  pub fn subset(&self, keep: impl Fn(usize) -> bool) -> SignalMetadata {
+    let mut subset = SignalMetadata {
+      too_deep: self.too_deep,
+      ..Default::default()
+    };
+    for i in (0..self.handle.len()).filter(|i| keep(*i)) {
+      subset.push(
+        self.module_paths[i].clone(),
+        self.names[i].clone(),
+        self.widths[i],
+        self.handle[i],
+        self.enums[i].clone(),
+      );
+      *subset.slices.last_mut().unwrap() = self.slices[i];
+    }
+    subset
+  }
+
+  fn push(
+    &mut self,
+    module_path: Vec<String>,
+    name: String,
+    width: u32,
+    handle_id: FstSignalHandle,
+    enum_table: Option<Arc<EnumTable>>,
+  ) {
+    self.module_paths.push(module_path);
+    self.names.push(name);
+    self.widths.push(width);
+    self.handle.push(handle_id);
+    self.enums.push(enum_table);
+    self.slices.push(None);
+  }
+}
+
+/// The enum tables met so far in the hierarchy, and the one the next var refers to.
+#[derive(Default)]
+struct EnumTables {
+  tables: HashMap<u64, Arc<EnumTable>>,
+  next: Option<u64>,
+}
+
+impl EnumTables {
+  /// Record the enum table `handle`.
+  fn define(&mut self, handle: u64, mapping: Vec<(String, String)>) {
+    self
+      .tables
+      .insert(handle, Arc::new(EnumTable::new(mapping)));
+  }
+
+  /// Return the enum table of the var being declared.
+  fn take(&mut self) -> Option<Arc<EnumTable>> {
+    let handle = self.next.take()?;
+    self.tables.get(&handle).cloned()
+  }
+}
+
+/// Options restricting which part of the hierarchy is considered by [`collect_signals`].
+#[derive(Debug, Default)]
+pub struct CollectOptions {
+  /// Skip signals nested deeper than this many scopes below the root
+  pub max_depth: Option<usize>,
+  /// Dot separated path of the only scope whose signals are collected
+  pub root_scope: Option<String>,
+  /// Fail if a config entry doesn't match any signal, instead of warning
+  pub strict: bool,
+  /// Also select the signals whose full dot separated path or bare name matches one of these
+  pub regexes: Vec<regex::Regex>,
+  /// Also select every signal declared in one of these scopes
+  pub scopes: Vec<config::ScopeConfig>,
+  /// Collect every selected alias of a handle under its own path, instead of only the first one
+  pub keep_aliases: bool,
+  /// Log the number of hierarchy entries scanned so far every this many entries
+  pub progress: Option<u64>,
+}
+
+/// Walk the hierarchy and return every signal matching `filter`, in declaration order. Aliases
+/// are all listed, each with the scope it is declared in.
+pub fn list_signals(
+  reader: &mut impl Waveform,
+  filter: &SignalPattern,
+) -> anyhow::Result<SignalMetadata> {
+  let mut metadata = SignalMetadata::default();
+  let mut module_path: Vec<String> = Vec::new();
+  let mut enums = EnumTables::default();
+  reader.for_each_hierarchy(|hier| match hier {
+    HierarchyEntry::Var {
+      name,
+      length,
+      handle,
+    } => {
+      let enum_table = enums.take();
+      if filter.matches(&module_path, &name) {
+        metadata.push(module_path.clone(), name, length, handle, enum_table);
+      }
+    }
+    HierarchyEntry::Scope { name } => module_path.push(name),
+    HierarchyEntry::UpScope => {
+      module_path.pop();
+    }
+    HierarchyEntry::EnumTable { handle, mapping } => enums.define(handle, mapping),
+    HierarchyEntry::EnumTableRef { handle } => enums.next = Some(handle),
+  })?;
+  Ok(metadata)
+}
+
+/// Walk the hierarchy and return every signal selected by one of the `expected` config entries
+/// or by one of the regexes or scopes of `options`. Aliases sharing a handle are only collected
+/// once, even when selected by several entries, unless `options` keeps them. The first declared
+/// alias is the one collected, unless an entry names another one by its full path.
+pub fn collect_signals(
+  reader: &mut impl Waveform,
+  expected: &[String],
+  options: &CollectOptions,
+) -> anyhow::Result<SignalMetadata> {
+  let mut metadata = SignalMetadata::default();
+  let mut module_path: Vec<String> = Vec::new();
+  // position in `metadata` of every collected handle and selection, and whether an entry names
+  // the alias collected there by its full path
+  let mut dedup_pool: HashMap<_, (usize, bool)> = HashMap::new();
+  let patterns: Vec<_> = expected.iter().map(|p| SignalPattern::new(p)).collect();
+  // number of signals selected by each pattern
+  let mut matched = vec![0; patterns.len()];
+  let mut regex_matched = vec![0; options.regexes.len()];
+  let mut scope_matched = vec![0; options.scopes.len()];
+
+  let root: Vec<&str> = match &options.root_scope {
+    Some(scope) => scope.split('.').collect(),
+    None => Vec::new(),
+  };
+  // whether the root scope has been entered, scopes declared again later like the ones of the
+  // derived signals being entered again
+  let mut entered = root.is_empty();
+
+  let mut enums = EnumTables::default();
+  let mut out_of_range = Vec::new();
+  let mut scanned: u64 = 0;
+  reader.for_each_hierarchy(|hier| {
+    scanned += 1;
+    if options.progress.is_some_and(|every| scanned % every == 0) {
+      info!(
+        "scanned {scanned} hierarchy entries, {} signals selected so far",
+        metadata.handle.len()
+      );
+    }
+    match hier {
+      HierarchyEntry::Var {
+        name,
+        length,
+        handle,
+      } => {
+        let enum_table = enums.take();
+        let in_root = module_path.len() >= root.len()
+          && module_path
+            .iter()
+            .zip(&root)
+            .all(|(have, want)| have == want);
+        if !in_root {
+          return;
+        }
+        // the signal is selected whole, as `None`, and by the slices entries ask for
+        let mut selections: Vec<Option<BitSlice>> = Vec::new();
+        let mut by_path = HashSet::new();
+        for (pattern, count) in patterns.iter().zip(&mut matched) {
+          if let Some(selection) = pattern.select(&module_path, &name) {
+            *count += 1;
+            if !selections.contains(&selection) {
+              selections.push(selection);
+            }
+            if pattern.is_full_path() {
+              by_path.insert(selection);
+            }
+          }
+        }
+        if !options.regexes.is_empty() {
+          let full_name = module_path
+            .iter()
+            .chain(std::iter::once(&name))
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(".");
+          for (regex, count) in options.regexes.iter().zip(&mut regex_matched) {
+            if regex.is_match(&full_name) || regex.is_match(&name) {
+              *count += 1;
+              if !selections.contains(&None) {
+                selections.push(None);
+              }
+            }
+          }
+        }
+        for (scope, count) in options.scopes.iter().zip(&mut scope_matched) {
+          if scope.contains(&module_path) {
+            *count += 1;
+            if !selections.contains(&None) {
+              selections.push(None);
+            }
+          }
+        }
+        if selections.is_empty() {
+          return;
+        }
+        let depth = module_path.len() - root.len();
+        if options.max_depth.is_some_and(|max| depth > max) {
+          metadata.too_deep += 1;
+          return;
+        }
+        for selection in selections {
+          let key = (handle.get_index(), selection);
+          let named = by_path.contains(&selection);
+          if !options.keep_aliases {
+            if let Some((i, kept_named)) = dedup_pool.get_mut(&key) {
+              // the entry asked for this alias, report the signal under its path instead
+              if named && !*kept_named {
+                *kept_named = true;
+                metadata.module_paths[*i].clone_from(&module_path);
+                metadata.names[*i] = match selection {
+                  Some(slice) => format!("{name}{slice}"),
+                  None => name.clone(),
+                };
+              }
+              continue;
+            }
+          }
+          dedup_pool.insert(key, (metadata.handle.len(), named));
+          match selection {
+            None => metadata.push(
+              module_path.clone(),
+              name.clone(),
+              length,
+              handle,
+              enum_table.clone(),
+            ),
+            Some(slice) if slice.msb >= length => out_of_range.push(format!(
+              "{}{slice} is out of the {length} bits of the signal",
+              module_path
+                .iter()
+                .chain(std::iter::once(&name))
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(".")
+            )),
+            // an enum table names values of the whole signal
+            Some(slice) => {
+              metadata.push(
+                module_path.clone(),
+                format!("{name}{slice}"),
+                slice.width(),
+                handle,
+                None,
+              );
+              *metadata.slices.last_mut().unwrap() = Some(slice);
+            }
+          }
+        }
+      }
+      HierarchyEntry::Scope { name } => {
+        module_path.push(name);
+        if !entered && module_path.iter().eq(&root) {
+          entered = true;
+        }
+      }
+      HierarchyEntry::UpScope => {
+        module_path.pop();
+      }
+      HierarchyEntry::EnumTable { handle, mapping } => enums.define(handle, mapping),
+      HierarchyEntry::EnumTableRef { handle } => enums.next = Some(handle),
+    }
+  })?;
+
+  if let Some(scope) = &options.root_scope {
+    if !entered {
+      let message = format!("root scope {scope} is not found in the hierarchy");
+      return Err(FailureKind::NoSignals.with(message).into());
+    }
+  }
+  anyhow::ensure!(
+    out_of_range.is_empty(),
+    "bit slices out of range: {}",
+    out_of_range.join(", ")
+  );
+  let missing: Vec<_> = expected
+    .iter()
+    .map(String::as_str)
+    .zip(matched)
+    .chain(
+      options
+        .regexes
+        .iter()
+        .map(regex::Regex::as_str)
+        .zip(regex_matched),
+    )
+    .chain(
+      options
+        .scopes
+        .iter()
+        .map(|scope| scope.scope.as_str())
+        .zip(scope_matched),
+    )
+    .filter(|(_, n)| *n == 0)
+    .map(|(pattern, _)| pattern)
+    .collect();
+  if missing.is_empty() {
+    return Ok(metadata);
+  }
+
+  // regexes and scopes can't be misspelled into a signal name, only suggest names for the entries
+  let literal: Vec<_> = missing
+    .iter()
+    .filter(|pattern| expected.iter().any(|entry| entry == *pattern))
+    .copied()
+    .collect();
+  let suggestions = suggest(reader, &literal)?;
+  let missing: Vec<_> = missing
+    .iter()
+    .map(|pattern| match literal.iter().position(|p| p == pattern) {
+      Some(i) if suggestions[i].is_some() => {
+        format!(
+          "{pattern} (did you mean {}?)",
+          suggestions[i].as_ref().unwrap()
+        )
+      }
+      _ => pattern.to_string(),
+    })
+    .collect();
+  if options.strict {
+    let message = format!(
+      "config signals don't match any signal: {}",
+      missing.join(", ")
+    );
+    return Err(FailureKind::NoSignals.with(message).into());
+  }
+  for pattern in missing {
+    warn!("{pattern} doesn't match any signal");
+  }
+
+  Ok(metadata)
+}
+
+/// Return the signal of the hierarchy closest to every config entry, if one is close enough to
+/// be a typo of it. Dotted entries are compared to full paths and the others to bare names.
+/// Entries with wildcards get no suggestion.
+fn suggest(reader: &mut impl Waveform, entries: &[&str]) -> anyhow::Result<Vec<Option<String>>> {
+  if entries.is_empty() {
+    return Ok(Vec::new());
+  }
+  // (distance, suggestion) of every entry
+  let mut best: Vec<Option<(usize, String)>> = vec![None; entries.len()];
+  let mut module_path = Vec::new();
+  reader.for_each_hierarchy(|entry| match entry {
+    HierarchyEntry::Scope { name } => module_path.push(name),
+    HierarchyEntry::UpScope => {
+      module_path.pop();
+    }
+    HierarchyEntry::Var { name, .. } => {
+      for (entry, best) in entries.iter().zip(&mut best) {
+        if entry.contains(['*', '?']) {
+          continue;
+        }
+        let candidate = match entry.contains('.') {
+          true => module_path
+            .iter()
+            .chain(std::iter::once(&name))
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join("."),
+          false => name.clone(),
+        };
+        let distance = pattern::edit_distance(entry, &candidate);
+        // a third of the entry is a lot of typos already
+        let close = distance <= (entry.chars().count() / 3).max(1);
+        if close && best.as_ref().map_or(true, |(d, _)| distance < *d) {
+          *best = Some((distance, candidate));
+        }
+      }
+    }
+    _ => (),
+  })?;
+  Ok(best.into_iter().map(|best| best.map(|(_, s)| s)).collect())
+}
+
+/// Every signal of the hierarchy by its full dot separated path and by its bare name, for the
+/// signals the config names outside of the selection, like the clocks and operands of handshakes
+/// and transactions. All of them are resolved from the one hierarchy walk building it, and unlike
+/// [`collect_signals`] every alias of a handle can be found under its own path.
+#[derive(Debug, Clone, Default)]
+pub struct SignalLookup {
+  /// Handle and width of every signal
+  by_path: HashMap<String, (FstSignalHandle, u32)>,
+  /// Handle and width of every signal of each bare name, in declaration order
+  by_name: HashMap<String, Vec<(FstSignalHandle, u32)>>,
+}
+
+impl SignalLookup {
+  /// Walk the hierarchy of `reader` once to index all of its signals.
+  pub fn new(reader: &mut impl Waveform) -> anyhow::Result<Self> {
+    let mut lookup = Self::default();
+    let mut module_path: Vec<String> = Vec::new();
+    reader.for_each_hierarchy(|entry| match entry {
+      HierarchyEntry::Scope { name } => module_path.push(name),
+      HierarchyEntry::UpScope => {
+        module_path.pop();
+      }
+      HierarchyEntry::Var {
+        name,
+        length,
+        handle,
+      } => {
+        let path = module_path
+          .iter()
+          .chain(std::iter::once(&name))
+          .map(String::as_str)
+          .collect::<Vec<_>>()
+          .join(".");
+        lookup.by_path.entry(path).or_insert((handle, length));
+        lookup
+          .by_name
+          .entry(name)
+          .or_default()
+          .push((handle, length));
+      }
+      _ => (),
+    })?;
+    Ok(lookup)
+  }
+
+  /// Return the handle and the width of the signal at the full path `path`, or else of the first
+  /// declared signal whose bare name is `path`.
+  pub fn find(&self, path: &str) -> Option<(FstSignalHandle, u32)> {
+    self.find_all(path).first().copied()
+  }
+
+  /// Return the handle and the width of the signal at the full path `path`, or else of every
+  /// signal whose bare name is `path`.
+  pub fn find_all(&self, path: &str) -> &[(FstSignalHandle, u32)] {
+    match self.by_path.get(path) {
+      Some(signal) => std::slice::from_ref(signal),
+      None => self.by_name.get(path).map_or(&[], Vec::as_slice),
+    }
+  }
+}
+
+#[test]
+fn collect_signals_from_fixture() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  let valid = wave.var("valid", 1);
+  wave.scope("core");
+  let ready = wave.var("ready", 1);
+  wave.alias("valid", 1, valid);
+  wave.upscope();
+  wave.var("data", 8);
+  wave.upscope();
+
+  let expected = vec!["valid".to_string(), "ready".to_string()];
+  let metadata = collect_signals(&mut wave, &expected, &CollectOptions::default()).unwrap();
+
+  // the alias of `valid` inside `core` shares the handle and must be deduplicated
+  assert_eq!(metadata.names, ["valid", "ready"]);
+  assert_eq!(metadata.handle, [valid, ready]);
+  assert_eq!(metadata.module_paths, [vec!["top"], vec!["top", "core"]]);
+
+  // naming the alias by its full path reports the signal under it, at the same position
+  let expected = vec![
+    "valid".to_string(),
+    "ready".to_string(),
+    "top.core.valid".to_string(),
+  ];
+  let metadata = collect_signals(&mut wave, &expected, &CollectOptions::default()).unwrap();
+  assert_eq!(metadata.handle, [valid, ready]);
+  assert_eq!(
+    metadata.module_paths,
+    [vec!["top", "core"], vec!["top", "core"]]
+  );
+}
+
+#[test]
+fn look_up_every_alias() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  let clock = wave.var("clock", 1);
+  wave.scope("core");
+  // the clock of the core is the top clock
+  wave.alias("clk", 1, clock);
+  let valid = wave.var("valid", 1);
+  wave.upscope();
+  wave.scope("lsu");
+  let other = wave.var("valid", 1);
+  wave.upscope();
+  wave.upscope();
+
+  let signals = SignalLookup::new(&mut wave).unwrap();
+  assert_eq!(signals.find("top.clock"), Some((clock, 1)));
+  assert_eq!(signals.find("top.core.clk"), Some((clock, 1)));
+  assert_eq!(signals.find("valid"), Some((valid, 1)));
+  assert_eq!(signals.find_all("valid"), [(valid, 1), (other, 1)]);
+  assert_eq!(signals.find("top.core.ready"), None);
+}
+
+#[test]
+fn collect_signals_with_max_depth() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  let shallow = wave.var("valid", 1);
+  wave.scope("core");
+  wave.scope("lsu");
+  wave.var("valid", 1);
+  wave.upscope();
+  wave.upscope();
+  wave.upscope();
+
+  let expected = vec!["valid".to_string()];
+  let options = CollectOptions {
+    max_depth: Some(2),
+    ..Default::default()
+  };
+  let metadata = collect_signals(&mut wave, &expected, &options).unwrap();
+
+  assert_eq!(metadata.handle, [shallow]);
+  assert_eq!(metadata.too_deep, 1);
+}
+
+#[test]
+fn collect_signals_under_root_scope() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  wave.var("valid", 1);
+  wave.scope("cpu");
+  let inner = wave.var("valid", 1);
+  wave.upscope();
+  wave.scope("gpu");
+  wave.var("valid", 1);
+  wave.upscope();
+  wave.upscope();
+  // declared again after leaving it, like the derived signals are
+  wave.scope("top");
+  wave.scope("cpu");
+  let derived = wave.var("stall", 1);
+  wave.upscope();
+  wave.upscope();
+
+  let expected = vec!["valid".to_string(), "stall".to_string()];
+  let options = CollectOptions {
+    root_scope: Some("top.cpu".to_string()),
+    ..Default::default()
+  };
+  let metadata = collect_signals(&mut wave, &expected, &options).unwrap();
+  assert_eq!(metadata.handle, [inner, derived]);
+
+  let options = CollectOptions {
+    root_scope: Some("top.fpu".to_string()),
+    ..Default::default()
+  };
+  assert!(collect_signals(&mut wave, &expected, &options).is_err());
+}
+
+#[test]
+fn collect_signals_by_pattern() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  wave.scope("core0");
+  let first = wave.var("valid", 1);
+  wave.upscope();
+  wave.scope("core1");
+  let second = wave.var("valid", 1);
+  wave.upscope();
+  wave.var("valid", 1);
+  wave.upscope();
+
+  // both patterns select the signals of core0, which is only collected once
+  let expected = vec!["top.core*.valid".to_string(), "top.core0.*".to_string()];
+  let metadata = collect_signals(&mut wave, &expected, &CollectOptions::default()).unwrap();
+  assert_eq!(metadata.handle, [first, second]);
+
+  // a typo only fails when strict
+  let expected = vec!["top.core*.valid".to_string(), "top.core0.vaild".to_string()];
+  assert!(collect_signals(&mut wave, &expected, &CollectOptions::default()).is_ok());
+  let strict = CollectOptions {
+    strict: true,
+    ..Default::default()
+  };
+  let err = collect_signals(&mut wave, &expected, &strict).unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "config signals don't match any signal: top.core0.vaild (did you mean top.core0.valid?)"
+  );
+
+  // bare entries are compared to bare names, and the distant ones get no suggestion
+  let expected = vec![
+    "vlid".to_string(),
+    "retire".to_string(),
+    "vaild*".to_string(),
+  ];
+  let err = collect_signals(&mut wave, &expected, &strict).unwrap_err();
+  assert_eq!(
+    err.to_string(),
+    "config signals don't match any signal: vlid (did you mean valid?), retire, vaild*"
+  );
+}
+
+#[test]
+fn collect_bit_slices() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  let instr = wave.var("instr", 8);
+  wave.upscope();
+
+  // the whole signal and its slice are told apart even though they share a handle
+  let expected = vec!["top.instr[2:0]".to_string(), "instr".to_string()];
+  let metadata = collect_signals(&mut wave, &expected, &CollectOptions::default()).unwrap();
+  assert_eq!(metadata.names, ["instr[2:0]", "instr"]);
+  assert_eq!(metadata.widths, [3, 8]);
+  assert_eq!(metadata.handle, [instr, instr]);
+  let value = SignalValue::String("10110110".to_string());
+  match &*metadata.value_of(0, &value) {
+    SignalValue::String(bits) => assert_eq!(bits, "110"),
+    SignalValue::Real(_) => unreachable!(),
+  }
+  assert!(matches!(metadata.value_of(1, &value), Cow::Borrowed(_)));
+
+  let expected = vec!["instr[8:6]".to_string()];
+  let err = collect_signals(&mut wave, &expected, &CollectOptions::default()).unwrap_err();
+  assert!(err
+    .to_string()
+    .contains("top.instr[8:6] is out of the 8 bits"));
+}
+
+#[test]
+fn collect_signals_by_regex() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  let lanes: Vec<_> = (0..3)
+    .map(|i| {
+      wave.scope(&format!("lane[{i}]"));
+      let valid = wave.var("valid", 1);
+      wave.var("ready", 1);
+      wave.upscope();
+      valid
+    })
+    .collect();
+  wave.var("valid", 1);
+  wave.upscope();
+
+  let options = CollectOptions {
+    regexes: vec![regex::Regex::new(r"^top\.lane\[\d+\]\.valid$").unwrap()],
+    ..Default::default()
+  };
+  let metadata = collect_signals(&mut wave, &[], &options).unwrap();
+  assert_eq!(metadata.handle, lanes);
+
+  // a signal selected by both a literal and a regex is only collected once
+  let expected = vec!["top.lane[0].valid".to_string()];
+  let metadata = collect_signals(&mut wave, &expected, &options).unwrap();
+  assert_eq!(metadata.handle, lanes);
+
+  // an anchored regex can also match the bare name
+  let options = CollectOptions {
+    regexes: vec![regex::Regex::new(r"^val.d$").unwrap()],
+    ..Default::default()
+  };
+  let metadata = collect_signals(&mut wave, &[], &options).unwrap();
+  assert_eq!(metadata.handle.len(), 4);
+
+  let strict = CollectOptions {
+    regexes: vec![regex::Regex::new(r"^top\.lane\[\d+\]\.vaild$").unwrap()],
+    strict: true,
+    ..Default::default()
+  };
+  assert!(collect_signals(&mut wave, &[], &strict).is_err());
+}
+
+#[test]
+fn collect_signals_by_scope() {
+  use config::ScopeConfig;
+
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  wave.var("clock", 1);
+  wave.scope("fpu");
+  let valid = wave.var("valid", 1);
+  wave.scope("adder");
+  let sum = wave.var("sum", 32);
+  wave.upscope();
+  wave.upscope();
+  wave.upscope();
+
+  let scope = |recursive| CollectOptions {
+    scopes: vec![ScopeConfig {
+      scope: "top.fpu".to_string(),
+      recursive,
+    }],
+    ..Default::default()
+  };
+  let metadata = collect_signals(&mut wave, &[], &scope(false)).unwrap();
+  assert_eq!(metadata.handle, [valid]);
+  let metadata = collect_signals(&mut wave, &[], &scope(true)).unwrap();
+  assert_eq!(metadata.handle, [valid, sum]);
+
+  let strict = CollectOptions {
+    scopes: vec![ScopeConfig {
+      scope: "top.fpv".to_string(),
+      recursive: true,
+    }],
+    strict: true,
+    ..Default::default()
+  };
+  assert!(collect_signals(&mut wave, &[], &strict).is_err());
+}
+
+#[test]
+fn list_signals_with_aliases() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  let valid = wave.var("valid", 1);
+  wave.scope("core");
+  wave.var("data", 8);
+  wave.alias("valid", 1, valid);
+  wave.upscope();
+  wave.upscope();
+
+  let all = list_signals(&mut wave, &SignalPattern::new("*")).unwrap();
+  let names: Vec<_> = (0..all.handle.len()).map(|i| all.full_name(i)).collect();
+  assert_eq!(names, ["top.valid", "top.core.data", "top.core.valid"]);
+  assert_eq!(all.widths, [1, 8, 1]);
+  assert_eq!(all.handle[2], valid);
+
+  let filtered = list_signals(&mut wave, &SignalPattern::new("top.core.*")).unwrap();
+  assert_eq!(filtered.names, ["data", "valid"]);
+}
+
+#[test]
+fn collect_signals_by_full_path() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  wave.scope("alu");
+  let alu = wave.var("state", 2);
+  wave.upscope();
+  wave.scope("lsu");
+  wave.var("state", 2);
+  // an alias of the alu state under another scope
+  wave.alias("alu_state", 2, alu);
+  wave.upscope();
+  wave.upscope();
+
+  let expected = vec!["top.alu.state".to_string(), "top.lsu.alu_state".to_string()];
+  let metadata = collect_signals(&mut wave, &expected, &CollectOptions::default()).unwrap();
+  assert_eq!(metadata.handle, [alu]);
+  assert_eq!(metadata.full_name(0), "top.alu.state");
+}
+
+#[test]
+fn convert_fixture_to_profile() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  let valid = wave.var("valid", 1);
+  wave.upscope();
+  wave.change(0, valid, "0").change(5, valid, "1");
+
+  let config = Config::parse(br#"{"signals": ["top.valid"]}"#, config::ConfigFormat::Json).unwrap();
+  let profile = fst_to_profile(&mut wave, &config).unwrap();
+  assert_eq!(profile.sample.len(), 2);
+  assert_eq!(profile.function.len(), 1);
+
+  // the time window of the config applies
+  let config = br#"{"signals": ["top.valid"], "time_start": 5}"#;
+  let config = Config::parse(config, config::ConfigFormat::Json).unwrap();
+  let profile = fst_to_profile(&mut wave, &config).unwrap();
+  assert_eq!(profile.sample.len(), 1);
+}
+
+#[test]
+fn convert_values_of_another_width() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  let data = wave.var("data", 4);
+  wave.upscope();
+  // narrower and wider values than the declared width, as some writers give
+  wave
+    .change(0, data, "10")
+    .change(2, data, "110011")
+    .change(4, data, "0101");
+
+  let config = br#"{"signals": ["top.data", "top.data[1:0]"]}"#;
+  let config = Config::parse(config, config::ConfigFormat::Json).unwrap();
+  let profile = fst_to_profile(&mut wave, &config).unwrap();
+  let labels: Vec<_> = profile
+    .sample
+    .iter()
+    .map(|sample| {
+      let label = &sample.label[0];
+      (profile.string_table[label.key as usize].as_str(), label.num)
+    })
+    .collect();
+  // the values are taken as given, their slices count from their low bit
+  assert_eq!(
+    labels,
+    [
+      ("top.data", 2),
+      ("top.data[1:0]", 2),
+      ("top.data", 51),
+      ("top.data[1:0]", 3),
+      ("top.data", 5),
+      ("top.data[1:0]", 1),
+    ]
+  );
+}
+
+#[test]
+fn convert_empty_hierarchy() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  let config = Config::parse(br#"{"signals": ["top.valid"]}"#, config::ConfigFormat::Json).unwrap();
+  assert!(fst_to_profile(&mut wave, &config)
+    .unwrap()
+    .sample
+    .is_empty());
+
+  let strict = CollectOptions {
+    strict: true,
+    ..Default::default()
+  };
+  let err = collect_signals(&mut wave, &["top.valid".to_string()], &strict).unwrap_err();
+  assert_eq!(failure::kind(&err), FailureKind::NoSignals);
+}
+
+#[test]
+fn extract_fixture_series() {
+  let mut wave = fixture::FixtureWave::new(0, 10);
+  wave.scope("top");
+  let valid = wave.var("valid", 1);
+  let data = wave.var("data", 4);
+  wave.upscope();
+  wave
+    .change(0, valid, "0")
+    .change(0, data, "0000")
+    .change(5, valid, "1")
+    .change(5, data, "1010")
+    .change(8, valid, "0");
+
+  let config = Config::parse(
+    br#"{"signals": ["top.valid", "top.data"], "time_start": 2,
+      "aggregations": [{"signal": "top.valid", "aggregation": "duration"}]}"#,
+    config::ConfigFormat::Json,
+  )
+  .unwrap();
+  let extraction = fst_to_series(&mut wave, &config).unwrap();
+  let series: Vec<_> = extraction
+    .series
+    .iter()
+    .map(|s| (s.signal.as_str(), s.times.clone(), s.values.clone()))
+    .collect();
+  assert_eq!(
+    series,
+    [
+      (
+        "top.valid",
+        vec![2, 5, 8],
+        vec!["0".to_string(), "1".to_string(), "0".to_string()]
+      ),
+      (
+        "top.data",
+        vec![2, 5],
+        vec!["0000".to_string(), "1010".to_string()]
+      ),
+    ]
+  );
+  let valid = &extraction.report.signals[0];
+  assert_eq!((valid.time_high, valid.time_low), (Some(3), Some(5)));
+  let duration = (aggregate::Aggregation::Duration, 3);
+  assert_eq!(
+    extraction.aggregates,
+    [Aggregates {
+      signal: "top.valid".to_string(),
+      values: vec![duration],
+    }]
+  );
+}
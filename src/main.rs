@@ -1,16 +1,25 @@
-use std::collections::HashSet;
-use std::io::Write;
-
-use clap::Parser;
-use flate2::write::GzEncoder;
-use flate2::Compression;
-use fst_native::*;
-use prost::Message;
-use serde::Deserialize;
-use tracing::{info, trace, Level};
+use std::io::{IsTerminal, Write};
+
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
+use fst_native::FstSignalHandle;
+use tracing::{info, trace, warn, Level};
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
-mod pprof;
+use toyfst::compress::Codec;
+use toyfst::config::{Config, ScopeConfig};
+use toyfst::convert::{self, ConvertOptions, SampleOrder};
+use toyfst::edge::{Edge, EdgeFilter};
+use toyfst::failure::{ErrorReport, FailureKind};
+use toyfst::pattern::SignalPattern;
+use toyfst::sink::{self, FormatSpec, OutputFormat, SignalFilter, Sink};
+use toyfst::wave::{self, InputFormat, InputWave, SignalValue, Spool, WaveHeader, Waveform};
+use toyfst::window::{Boundary, TimeWindow};
+use toyfst::{
+  cache, checkpoint, clock, collect_signals, date, derived, downsample, dump, folded, follow, gtkw,
+  instret, list_signals, parquet, pprof, properties, report, transitions, vcd, verify,
+  CollectOptions, SignalLookup, SignalMetadata,
+};
 
 #[derive(Parser, Debug)]
 #[command(
@@ -18,154 +27,1620 @@ mod pprof;
   version = "v0.1.0",
   about = "Extract signals from FST file"
 )]
-struct CliArgs {
-  /// File path to the fst file
-  #[arg(short, long)]
-  fst: String,
-  /// File path to the properties file
+struct Cli {
+  #[command(subcommand)]
+  command: Command,
+  /// File path to write the failure of the run to as JSON, with its kind, exit code and the chain
+  /// of its causes. Nothing is written if the run succeeds. The exit code tells the kind too: 1
+  /// for any failure, 3 for an invalid config, 4 for an unreadable waveform, 5 when the config
+  /// selects no signal of the hierarchy and 6 when an output can't be written
+  #[arg(long, global = true, value_name = "FILE")]
+  errors_json: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Extract signals from an FST file into a pprof profile
+  Extract(Box<ExtractArgs>),
+  /// List the signals of a waveform with their bit width and handle index
+  List(ListArgs),
+  /// Diff the activity of the signals between two runs into a differential pprof profile
+  Diff(DiffArgs),
+  /// Resolve every entry of the config against the hierarchy of a waveform and print what would
+  /// be extracted, without reading any value change
+  Validate(ValidateArgs),
+  /// Decode a produced profile and check its invariants
+  Verify {
+    /// File path to the profile, gzip or zstd compressed or raw protobuf
+    profile: String,
+  },
+}
+
+#[derive(Args, Debug)]
+struct ExtractArgs {
+  /// File path to the waveform, an FST file or a VCD file as told by its extension, `-` to read an
+  /// FST from stdin, spooled to a temporary file. Repeat it to merge the waveforms of one design,
+  /// like the shards of a simulation, into a single profile. Each file is then read on its own
+  /// thread, only pprof and prototext outputs are allowed, and the outputs are named after the
+  /// first file
+  #[arg(short, long, required_unless_present = "dry_parse_config")]
+  fst: Vec<String>,
+  /// Format of the waveform file, overriding the one told by its extension
+  #[arg(long, value_name = "FORMAT")]
+  input_format: Option<InputFormat>,
+  /// File path to the properties file, giving display labels, groups and radixes of signals. See
+  /// the `Properties` doc for the schema
   #[arg(short, long)]
   properties: Option<String>,
-  /// File path to the runtime configuration
+  /// File path to the runtime configuration, in JSON, TOML or YAML as told by its extension. An
+  /// HTTP(S) URL can be given instead when built with the `remote-config` feature
+  #[arg(short, long, required_unless_present_any = ["list_signals", "gtkw"])]
+  config: Option<String>,
+  /// File path to a GTKWave save file, selecting its traces instead of a config. Bus ranges select
+  /// those bits, and the groups, radixes and aliases of the traces are taken as properties, the
+  /// --properties file winning over them
+  #[arg(long, value_name = "FILE", conflicts_with = "config")]
+  gtkw: Option<String>,
+  /// File path to the output, `-` writes it to stdout. Only allowed with a single --format,
+  /// otherwise each output is written to the current directory, named after the .fst file. The
+  /// outputs of the profiles of the config are named by their own template
+  #[arg(short, long)]
+  output: Option<String>,
+  /// What the pprof and prototext outputs profile: the value changes of the signals, or with
+  /// `instret` the instructions retired as the `instret` section of the config says, one function
+  /// per mnemonic weighted by the cycles the instructions took
+  #[arg(long, value_enum, default_value_t = Mode::Signals)]
+  mode: Mode,
+  /// Format of an output file, `pprof`, `prototext`, `transitions`, `json`, `csv`, `parquet`,
+  /// `vcd` or `folded`.
+  /// Repeat it to write several outputs from a single read of the FST file. A format can be
+  /// qualified with the signals it receives as `FORMAT:FILTER`, where the filter is `all` (the
+  /// default), `scalars` for 1-bit signals or `buses` for multi-bit ones. For example
+  /// `--format pprof:scalars --format transitions:buses`. Qualified outputs get the filter in
+  /// their default file name, like `top.buses.transitions.json`
+  #[arg(
+    long,
+    visible_alias = "output-format",
+    value_name = "FORMAT[:FILTER]",
+    value_parser = FormatSpec::parse,
+    default_value = "pprof"
+  )]
+  format: Vec<FormatSpec>,
+  /// Write the csv and parquet outputs as a wide table: one row per time a signal changes, with
+  /// a column per signal holding its value then, empty until its first change
+  #[arg(long)]
+  wide: bool,
+  #[command(flatten)]
+  scope: ScopeArgs,
+  /// Only emit the selected edges of 1-bit signals, each labeled with its direction. Multi-bit
+  /// signals are rejected in this mode
+  #[arg(long, value_enum)]
+  edges: Option<EdgeFilter>,
+  /// Add a `name=value` frame under each signal so pprof groups identical states together. Every
+  /// distinct value of a signal becomes a new function and location, so this grows the string
+  /// table with the number of distinct values
+  #[arg(long)]
+  group_by_value: bool,
+  /// Merge adjacent samples with identical locations and labels by summing their values
+  #[arg(long)]
+  coalesce: bool,
+  /// Order of the value change samples in the profile. Samples are sorted before --coalesce
+  /// runs, so with `signal` it also merges repeated values of a signal that other signals
+  /// interleaved in time
+  #[arg(long, value_enum, default_value_t = SampleOrder::Time)]
+  sample_order: SampleOrder,
+  /// Fail on malformed input, like value changes going back in time or config signals matching
+  /// nothing, instead of warning
+  #[arg(long)]
+  strict: bool,
+  /// Emit one extra sample holding the last value of every signal at the end of simulation
+  #[arg(long)]
+  final_state: bool,
+  /// Don't populate the function table. pprof then shows frames as bare addresses
+  #[arg(long)]
+  no_functions: bool,
+  /// Don't populate the location table, implies --no-functions. Samples then have no stack and
+  /// can only be told apart by their labels, so graph and flame graph views are empty
+  #[arg(long)]
+  no_locations: bool,
+  /// Stop emitting the changes of a signal once it has emitted N of them, protecting the output
+  /// from glitching signals
+  #[arg(long, value_name = "N")]
+  max_changes_per_signal: Option<usize>,
+  /// Label the values of the pprof outputs past the first N distinct ones as `<other>`, so a wide
+  /// bus changing to millions of values doesn't blow up the string table
+  #[arg(long, value_name = "N")]
+  max_label_values: Option<usize>,
+  /// Keep one value change per signal in every bucket of this length, in raw FST ticks like `100`
+  /// or in rising edges of --clock-signal like `8cycles`. Dense signals then bloat the output less
+  #[arg(long, value_name = "INTERVAL", value_parser = downsample::parse_interval)]
+  sample_every: Option<downsample::Interval>,
+  /// Which value change of a --sample-every bucket is kept
+  #[arg(long, value_enum, default_value_t = downsample::Keep::First)]
+  sample_keep: downsample::Keep,
+  /// Drop value changes before this time, in raw FST ticks. The profile duration then starts
+  /// there. Overrides the `time_start` of the config
+  #[arg(long, visible_alias = "start-time", value_name = "TIME")]
+  time_start: Option<u64>,
+  /// Drop value changes after this time, in raw FST ticks. The profile duration then ends there.
+  /// Overrides the `time_end` of the config
+  #[arg(long, visible_alias = "end-time", value_name = "TIME")]
+  time_end: Option<u64>,
+  /// Whether changes landing exactly on --time-start or --time-end are kept. By default the
+  /// window is half-open: a change at the start is kept and a change at the end is dropped
+  #[arg(long, value_enum, default_value_t = Boundary::HalfOpen)]
+  boundary: Boundary,
+  /// Cache the hierarchy of the FST file at this path and reuse it on later runs, skipping the
+  /// hierarchy walk. The cache is rebuilt whenever the FST file size or modification time changes
+  #[arg(long, value_name = "PATH")]
+  cache_hierarchy: Option<String>,
+  /// Time zone offset, like `+08:00`, of the FST `date` header when the header doesn't carry
+  /// one. Defaults to UTC
+  #[arg(long, value_name = "OFFSET", value_parser = date::parse_offset)]
+  tz: Option<chrono::FixedOffset>,
+  /// Drop the signals that never change inside the time window. This takes an extra read of the
+  /// value changes to find them
+  #[arg(long)]
+  drop_constant: bool,
+  /// Log the progress of the value change read every N seconds, so supervisors watching the log
+  /// see the process is alive. Off by default
+  #[arg(long, value_name = "SECS")]
+  heartbeat: Option<u64>,
+  /// Log the progress of the hierarchy walk every 100000 entries, and of the value change read as
+  /// with `--heartbeat 5` unless another interval is given
+  #[arg(long)]
+  progress: bool,
+  /// Don't draw the progress bar of the value change read nor log its summary. The bar is only
+  /// drawn when stderr is a terminal
+  #[arg(long)]
+  quiet: bool,
+  /// Number of threads reading the value changes of an FST file. Defaults to the available
+  /// parallelism. Every thread decodes its own blocks of the file, or every block for its own
+  /// chunk of the signals when the config has derived signals, with --sample-every, or when the
+  /// file is a single block. The changes are converted in order of time while they are read
+  #[arg(long, value_name = "N")]
+  jobs: Option<std::num::NonZeroUsize>,
+  /// Map the FST file in memory instead of reading it through a buffer, much faster on large
+  /// files on network file systems. VCD files are read whole anyway. The file must not change
+  /// while it is mapped, so it can't be used with --follow
+  #[arg(long)]
+  mmap: bool,
+  /// Print the full path, bit width and handle index of every signal in the FST file, then exit.
+  /// The config isn't needed. An optional pattern, written like a config entry, only lists the
+  /// signals it matches, like `--list-signals 'top.core.*'`
+  #[arg(long, value_name = "PATTERN", num_args = 0..=1, default_missing_value = "*")]
+  list_signals: Option<String>,
+  /// Only load and validate the config, then exit. The FST file isn't needed nor read
+  #[arg(long)]
+  dry_parse_config: bool,
+  /// How the pprof output is compressed. It is named `.pprof.gz`, `.pprof.zst` or `.pprof` by
+  /// default accordingly. go's pprof doesn't read zstd, run `zstd -d` on the output first
+  #[arg(long, value_enum, value_name = "CODEC", default_value_t = Codec::Gzip)]
+  compress: Codec,
+  /// Compression level of the pprof output, from 0 (none) to 9 (smallest) with gzip and from 1
+  /// to 22 with zstd
+  #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(0..=22))]
+  compression_level: Option<u32>,
+  /// Write the pprof output as raw protobuf, like `--compress none`
+  #[arg(long, conflicts_with_all = ["compression_level", "compress"])]
+  no_compress: bool,
+  /// Name multi-bit signals with their bit range in the labels and the stacks, like
+  /// `top.data [7:0]`, so buses and scalars with similar names are told apart
+  #[arg(long)]
+  bus_ranges: bool,
+  /// Stack every signal under its scopes, like `TOP -> core -> lsu -> valid`, so the graph and
+  /// flame graph views follow the module hierarchy. The leaf frame is then the bare signal name
+  #[arg(long)]
+  hierarchy_stacks: bool,
+  /// Also label the samples of multi-bit signals with each of their bits, like `top.state[0]=1`,
+  /// to filter profiles by single control bits in the pprof UI
+  #[arg(long)]
+  bit_labels: bool,
+  /// Split the run in buckets of this many ticks and label every value change sample with the
+  /// start of its bucket, like `bucket=2000`, to follow the activity over time with
+  /// `pprof -tagfocus` or `-tagshow`. A value held across buckets goes on in a sample of each
+  #[arg(long, value_name = "TICKS")]
+  bucket: Option<std::num::NonZeroU64>,
+  /// Label the values of real signals with a number, the value multiplied by this factor and
+  /// rounded, in the `1/<factor>` unit. pprof label numbers are integers, so the precision below
+  /// 1/factor is lost. Without it, or when the scaled value overflows, reals keep a string label
+  #[arg(long, value_name = "FACTOR")]
+  real_scale: Option<f64>,
+  /// Sample every signal on each rising edge of this 1-bit clock, given by its dot separated path.
+  /// The pprof output then has one sample per cycle weighted by the cycle number and labeled with
+  /// the signal values, and the value change options don't apply. To keep one sample per value
+  /// change but count its held time in cycles, use --clock-signal instead
+  #[arg(long, value_name = "SIGNAL")]
+  sample_clock: Option<String>,
+  /// Measure how long each value is held in rising edges of this 1-bit clock, given by its dot
+  /// separated path, instead of nanoseconds. Samples still follow the value changes, to sample
+  /// on the clock edges instead use --sample-clock
+  #[arg(long, value_name = "SIGNAL")]
+  clock_signal: Option<String>,
+  /// Store the resolved config as JSON in a comment of the profile, for reproducibility
+  #[arg(long)]
+  embed_config: bool,
+  /// Store the number of samples each signal contributed in a comment of the profile
+  #[arg(long)]
+  embed_counts: bool,
+  /// Also write statistics of every selected signal as JSON to this path, `-` for stdout: its
+  /// number of changes, its first and last change times, the time 1-bit signals spend high and
+  /// low, and the extremes of real signals, then the time every state machine spends in each
+  /// state and its transition counts. It is gathered from the same read as the outputs
+  #[arg(long, value_name = "PATH")]
+  report: Option<String>,
+  /// Periodically save the state of the pprof conversion to this path, so an interrupted
+  /// extraction can go on with --resume. The checkpoint is removed once the outputs are written
+  #[arg(long, value_name = "PATH")]
+  checkpoint: Option<String>,
+  /// Wall clock seconds between two checkpoints
+  #[arg(
+    long,
+    value_name = "SECONDS",
+    default_value_t = 600,
+    requires = "checkpoint"
+  )]
+  checkpoint_every: u64,
+  /// Go on from the --checkpoint of an interrupted extraction of the same waveform and config.
+  /// The waveform is read again from the start, but the changes before the checkpoint are only
+  /// replayed to the handshakes, transactions and other trackers
+  #[arg(long, requires = "checkpoint")]
+  resume: bool,
+  /// Follow a waveform still being written by a running simulation: read it again every
+  /// --follow-every seconds, convert only the value changes appended since the previous read and
+  /// write the pprof and prototext outputs again with them. It stops once the waveform hasn't
+  /// grown for --follow-idle seconds. The FST writer has to leave the file readable as it goes,
+  /// which a writer only putting the hierarchy at the end does not
+  #[arg(long)]
+  follow: bool,
+  /// Wall clock seconds between two reads of the followed waveform
+  #[arg(
+    long,
+    value_name = "SECONDS",
+    default_value_t = 10,
+    requires = "follow"
+  )]
+  follow_every: u64,
+  /// Wall clock seconds without the followed waveform growing after which the simulation is taken
+  /// as ended
+  #[arg(
+    long,
+    value_name = "SECONDS",
+    default_value_t = 300,
+    requires = "follow"
+  )]
+  follow_idle: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Mode {
+  /// Sample the value changes of the signals
+  Signals,
+  /// Count the cycles of the retired instructions
+  Instret,
+}
+
+/// Which part of the hierarchy the signals of the config are selected in.
+#[derive(Args, Debug)]
+struct ScopeArgs {
+  /// Only consider signals declared within N scope levels, signals declared deeper than that are
+  /// silently excluded from the output. Levels are counted from the root scope if given.
+  #[arg(long, value_name = "N")]
+  max_depth: Option<usize>,
+  /// Output every selected alias of a signal under its own path. By default aliases sharing the
+  /// value data of a signal are only output once, under the first path declaring it
+  #[arg(long)]
+  keep_aliases: bool,
+  /// Only consider signals under the given scope, which is a dot separated path like `top.cpu`
+  #[arg(long, value_name = "SCOPE")]
+  root_scope: Option<String>,
+}
+
+impl ScopeArgs {
+  /// Return the options collecting the signals of a config entry in this part of the hierarchy.
+  fn collect(&self, regexes: Vec<regex::Regex>, scopes: Vec<ScopeConfig>) -> CollectOptions {
+    CollectOptions {
+      max_depth: self.max_depth,
+      root_scope: self.root_scope.clone(),
+      regexes,
+      scopes,
+      keep_aliases: self.keep_aliases,
+      ..Default::default()
+    }
+  }
+}
+
+#[derive(Args, Debug)]
+struct DiffArgs {
+  /// File path to the waveform of the baseline run, an FST file or a VCD file as told by its
+  /// extension
+  base: String,
+  /// File path to the waveform of the run compared to the baseline
+  new: String,
+  /// File path to the runtime configuration selecting the signals of both runs
   #[arg(short, long)]
   config: String,
+  #[command(flatten)]
+  scope: ScopeArgs,
+  /// File path to the output, `-` writes it to stdout. Named after the new run by default, like
+  /// `new.diff.pprof.gz`
   #[arg(short, long)]
   output: Option<String>,
+  /// Count the held time in rising edges of this 1-bit clock instead of nanoseconds, so runs
+  /// with different clock periods line up cycle by cycle
+  #[arg(long, value_name = "SIGNAL")]
+  clock_signal: Option<String>,
+  /// Start each run at the first rising edge of this 1-bit signal, like a reset release or a
+  /// start of test marker, so runs spending different times before it line up
+  #[arg(long, value_name = "SIGNAL")]
+  sync: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-struct Config {
-  signals: Vec<String>,
+#[derive(Args, Debug)]
+struct ListArgs {
+  /// File path to the waveform, an FST file or a VCD file as told by its extension, `-` to read
+  /// an FST from stdin
+  fst: String,
+  /// Only list the signals matching this pattern, written like a config entry, like `top.core.*`
+  pattern: Option<String>,
+  /// Format of the waveform file, overriding the one told by its extension
+  #[arg(long, value_name = "FORMAT")]
+  input_format: Option<InputFormat>,
+  /// Print the signals indented under their scopes instead of one full path per line
+  #[arg(long)]
+  tree: bool,
 }
 
-type MyFstReader = FstReader<std::io::BufReader<std::fs::File>>;
+#[derive(Args, Debug)]
+struct ValidateArgs {
+  /// File path to the waveform, an FST file or a VCD file as told by its extension
+  fst: String,
+  /// File path to the runtime configuration, in JSON, TOML or YAML as told by its extension
+  #[arg(short, long)]
+  config: String,
+  /// Format of the waveform file, overriding the one told by its extension
+  #[arg(long, value_name = "FORMAT")]
+  input_format: Option<InputFormat>,
+  #[command(flatten)]
+  scope: ScopeArgs,
+  /// Also resolve this 1-bit clock, as given to --sample-clock or --clock-signal of extract
+  #[arg(long, value_name = "SIGNAL")]
+  clock: Option<String>,
+}
 
-fn main() -> anyhow::Result<()> {
+fn main() -> std::process::ExitCode {
   let global_logger = FmtSubscriber::builder()
     .with_env_filter(EnvFilter::from_default_env())
     .with_max_level(Level::TRACE)
     .without_time()
     .with_target(false)
+    // keep stdout clean for `--output -`
+    .with_writer(std::io::stderr)
     .compact()
     .finish();
   tracing::subscriber::set_global_default(global_logger)
     .expect("internal error: fail to setup log subscriber");
 
-  let args = CliArgs::parse();
-  info!("Reading FST from file: {}", args.fst);
+  let cli = Cli::parse();
+  let result = match cli.command {
+    Command::Extract(args) => extract(*args),
+    Command::List(args) => list(args),
+    Command::Diff(args) => diff(args),
+    Command::Validate(args) => validate(args),
+    Command::Verify { profile } => verify::verify(&profile),
+  };
+  let Err(err) = result else {
+    return std::process::ExitCode::SUCCESS;
+  };
+  eprintln!("Error: {err:?}");
+  let report = ErrorReport::new(&err);
+  if let Some(path) = &cli.errors_json {
+    let written = serde_json::to_vec_pretty(&report)
+      .map_err(anyhow::Error::from)
+      .and_then(|raw| Ok(std::fs::write(path, raw)?));
+    if let Err(err) = written {
+      eprintln!("fail to write {path}: {err}");
+    }
+  }
+  std::process::ExitCode::from(report.exit_code)
+}
+
+fn extract(mut args: ExtractArgs) -> anyhow::Result<()> {
+  if args.dry_parse_config {
+    let config = args.config.as_deref().context("--config is required")?;
+    info!("Checking config {config}");
+    Config::from_path(config)?;
+    info!("Config is valid");
+    return Ok(());
+  }
+
+  // kept until the end, the waveform is read again by the parallel jobs
+  let stdin = spool_stdin(&mut args.fst)?;
+  anyhow::ensure!(
+    stdin.is_none() || args.cache_hierarchy.is_none(),
+    "--cache-hierarchy can't be used with --fst -, the piped waveform has no stable file"
+  );
+  // clap requires it when not dry parsing the config
+  let fst = args.fst.first().context("--fst is required")?;
+
+  if let Some(filter) = &args.list_signals {
+    let (mut reader, _) = open(fst, &args)?;
+    let signals = list_signals(&mut reader, &SignalPattern::new(filter))?;
+    print_signals(&signals, false, std::io::stdout().lock())?;
+    return Ok(());
+  }
+
+  let config = load_config(&args)?;
+
+  anyhow::ensure!(
+    args.output.is_none() || args.format.len() == 1,
+    "--output can only be used with a single --format"
+  );
+  anyhow::ensure!(
+    !args.wide
+      || args
+        .format
+        .iter()
+        .any(|spec| matches!(spec.format, OutputFormat::Csv | OutputFormat::Parquet)),
+    "--wide needs a csv or parquet --format"
+  );
+  let pprof = args
+    .format
+    .iter()
+    .any(|spec| spec.format == OutputFormat::Pprof);
+  if pprof && codec(&args) == Codec::Zstd {
+    warn!("go's pprof can't read a zstd compressed profile, decompress it with `zstd -d` first");
+  }
+  // the level is only given to the codec once the outputs are written, after the whole read
+  if let Some(level) = args.compression_level {
+    codec(&args).check_level(level)?;
+  }
+  if args.follow {
+    anyhow::ensure!(
+      args.fst.len() == 1 && stdin.is_none(),
+      "--follow needs a single --fst file, not a piped or merged waveform"
+    );
+    return follow(fst, &args, &config);
+  }
+  if args.fst.len() > 1 {
+    return extract_merged(&args, &config);
+  }
+  if args.checkpoint.is_some() {
+    let pprof = args
+      .format
+      .iter()
+      .filter(|spec| matches!(spec.format, OutputFormat::Pprof | OutputFormat::Prototext))
+      .count();
+    anyhow::ensure!(
+      pprof == 1 && args.mode == Mode::Signals && args.sample_clock.is_none(),
+      "--checkpoint needs a single pprof or prototext output of the signals mode, without \
+       --sample-clock"
+    );
+    anyhow::ensure!(
+      config.profiles.is_empty(),
+      "--checkpoint can't be used with the profiles of the config"
+    );
+  }
+
+  // named and checked before reading the changes, so a clash or a read-only directory doesn't
+  // waste the whole read
+  let outputs = output_paths(fst, &args, &config)?;
+  let checked = outputs.iter().map(|(_, output)| output);
+  for path in checked.chain(&args.report).chain(&args.checkpoint) {
+    check_output(path)?;
+  }
+
+  let (reader, input_format) = open(fst, &args)?;
+  let mut reader = derived::DerivedWave::new(reader, &config.derived)?;
+  let input = Input::resolve(&mut reader, &args, &config)?;
+  let mut sinks = input.sinks(&args)?;
+
+  info!("Fetching signals value");
+  let started = std::time::Instant::now();
+  let jobs = args
+    .jobs
+    .or_else(|| std::thread::available_parallelism().ok())
+    .map_or(1, std::num::NonZeroUsize::get);
+  // a VCD file is parsed whole when opened, reading it again on each thread gains nothing
+  let read_failure = || FailureKind::Waveform.with(format!("fail to read the changes of {fst}"));
+  let changes = if jobs > 1 && input_format == InputFormat::Fst {
+    // derived signals and downsampling need the changes from the start of the waveform
+    let cuts = if config.derived.is_empty() && args.sample_every.is_none() {
+      wave::fst_block_cuts(fst).with_context(read_failure)?
+    } else {
+      Vec::new()
+    };
+    // a file of a single block can only be split by signals
+    let split = if cuts.is_empty() {
+      sink::Split::Signals
+    } else {
+      sink::Split::Time(cuts)
+    };
+    info!("Reading value changes with {jobs} jobs");
+    let open = || {
+      let reader = open_wave(fst, input_format, &args)?;
+      input.downsample(derived::DerivedWave::new(reader, &config.derived)?, &args)
+    };
+    sink::drive_parallel(open, &mut sinks, jobs, split, input.feedback)
+      .with_context(read_failure)?
+  } else {
+    let mut reader = input.downsample(&mut reader, &args)?;
+    sink::drive(&mut reader, &mut sinks, input.feedback).with_context(read_failure)?
+  };
+  if !args.quiet {
+    info!(
+      "Read {changes} value changes of {} signals in {:.1?}",
+      input.metadata.handle.len(),
+      started.elapsed()
+    );
+  }
+
+  if let Some(path) = &args.report {
+    let Some(Sink::Report(sink)) = sinks.pop() else {
+      unreachable!("the report sink is the last one")
+    };
+    info!("Writing report {path}");
+    let mut out = create_output(path)?;
+    let context = || FailureKind::Output.with(format!("fail to write {path}"));
+    serde_json::to_writer_pretty(&mut out, &sink.finish()).with_context(context)?;
+    out.flush().with_context(context)?;
+  }
+
+  for ((spec, output), sink) in outputs.into_iter().zip(sinks) {
+    info!("Writing {output}");
+    let mut out = create_output(&output)?;
+    let context = || FailureKind::Output.with(format!("fail to write {output}"));
+    let p = match sink {
+      Sink::Pprof(sink) => sink.finish()?,
+      Sink::Clock(sink) => sink.finish(),
+      Sink::Instret(sink) => sink.finish(),
+      Sink::Report(_) => unreachable!("the report sink is written above"),
+      Sink::Aggregate(_) => unreachable!("only the series build an aggregate sink"),
+      Sink::Transitions(sink) => {
+        serde_json::to_writer_pretty(&mut out, &sink.finish()).with_context(context)?;
+        out.flush().with_context(context)?;
+        continue;
+      }
+      Sink::Csv(sink) => {
+        if args.wide {
+          dump::write_wide_csv(&sink.finish_wide(), &mut out).with_context(context)?;
+        } else {
+          dump::write_csv(&sink.finish(), &mut out).with_context(context)?;
+        }
+        out.flush().with_context(context)?;
+        continue;
+      }
+      Sink::Parquet(sink) => {
+        if args.wide {
+          parquet::write_wide(&sink.finish_wide(), &mut out).with_context(context)?;
+        } else {
+          parquet::write_records(&sink.finish(), &mut out).with_context(context)?;
+        }
+        out.flush().with_context(context)?;
+        continue;
+      }
+      Sink::Folded(sink) => {
+        sink.finish(&mut out).with_context(context)?;
+        out.flush().with_context(context)?;
+        continue;
+      }
+      Sink::Vcd(sink) => {
+        sink.finish(&mut out).with_context(context)?;
+        out.flush().with_context(context)?;
+        continue;
+      }
+      Sink::Json(sink) => {
+        serde_json::to_writer_pretty(&mut out, &sink.finish()).with_context(context)?;
+        out.flush().with_context(context)?;
+        continue;
+      }
+    };
+    write_pprof(p, spec, &input.header, &args, &config, &mut out).with_context(context)?;
+  }
+  if let Some(path) = &args.checkpoint {
+    if let Err(err) = std::fs::remove_file(path) {
+      // no checkpoint is saved by a short extraction
+      if err.kind() != std::io::ErrorKind::NotFound {
+        warn!("fail to remove checkpoint {path}: {err}");
+      }
+    }
+  }
+  Ok(())
+}
+
+/// Convert the waveform at `fst` while a running simulation appends to it, see --follow.
+fn follow(fst: &str, args: &ExtractArgs, config: &Config) -> anyhow::Result<()> {
+  anyhow::ensure!(
+    args
+      .format
+      .iter()
+      .all(|spec| matches!(spec.format, OutputFormat::Pprof | OutputFormat::Prototext)),
+    "--follow only writes pprof and prototext outputs"
+  );
+  anyhow::ensure!(
+    args.mode == Mode::Signals
+      && args.sample_clock.is_none()
+      && args.sample_every.is_none()
+      && !args.drop_constant,
+    "--follow can't be used with --mode instret, --sample-clock, --sample-every or \
+     --drop-constant, which need the whole waveform"
+  );
+  anyhow::ensure!(
+    args.report.is_none() && args.checkpoint.is_none(),
+    "--follow can't be used with --report or --checkpoint"
+  );
+  // the simulation appending to a mapped file would change the bytes under the reader
+  anyhow::ensure!(
+    !args.mmap,
+    "--follow can't be used with --mmap, the simulation writes to the file"
+  );
+  let outputs = output_paths(fst, args, config)?;
+  for (_, output) in &outputs {
+    anyhow::ensure!(
+      output != "-",
+      "--follow writes its outputs again on each read, which stdout can't take"
+    );
+    check_output(output)?;
+  }
+
+  let every = std::time::Duration::from_secs(args.follow_every);
+  let idle = std::time::Duration::from_secs(args.follow_idle);
+  let reopen = || -> anyhow::Result<_> {
+    let (reader, _) = open(fst, args)?;
+    derived::DerivedWave::new(reader, &config.derived)
+  };
+  // the simulation may not have written a readable waveform yet
+  let mut grown = std::time::Instant::now();
+  let mut reader = loop {
+    match reopen() {
+      Ok(reader) => break reader,
+      Err(err) if grown.elapsed() < idle => {
+        warn!("{err:#}, trying again in {}s", args.follow_every);
+        std::thread::sleep(every);
+      }
+      Err(err) => return Err(err),
+    }
+  };
+  let input = Input::resolve(&mut reader, args, config)?;
+  let mut sinks = input.sinks(args)?;
+
+  let read_failure = || FailureKind::Waveform.with(format!("fail to read the changes of {fst}"));
+  let mut converted = None;
+  grown = std::time::Instant::now();
+  loop {
+    let header = reader.header();
+    if converted.map_or(true, |t| header.end_time > t) {
+      for sink in &mut sinks {
+        let Sink::Pprof(sink) = sink else {
+          unreachable!("--follow only builds pprof sinks")
+        };
+        sink.follow(&header)?;
+      }
+      let mut since = follow::Since::new(&mut reader, converted);
+      let changes =
+        sink::drive(&mut since, &mut sinks, input.feedback).with_context(read_failure)?;
+      info!(
+        "Read {changes} new value changes up to time {}",
+        header.end_time
+      );
+      converted = Some(header.end_time);
+      grown = std::time::Instant::now();
+      write_snapshots(&sinks, &outputs, &header, args, config)?;
+    } else if grown.elapsed() >= idle {
+      info!(
+        "{fst} hasn't grown for {}s, the outputs are up to date",
+        args.follow_idle
+      );
+      return Ok(());
+    }
+
+    std::thread::sleep(every);
+    // a read racing the writer may fail, the next one sees the file once the block is flushed
+    match reopen() {
+      Ok(reopened) => reader = reopened,
+      Err(err) => warn!("{err:#}, trying again in {}s", args.follow_every),
+    }
+  }
+}
+
+/// Write the profile of the changes converted so far by every sink of --follow to its output.
+/// Each one is written next to its output then moved over it, so whoever watches the output never
+/// reads it half written.
+fn write_snapshots(
+  sinks: &[Sink<'_>],
+  outputs: &[(&FormatSpec, String)],
+  header: &WaveHeader,
+  args: &ExtractArgs,
+  config: &Config,
+) -> anyhow::Result<()> {
+  for ((spec, output), sink) in outputs.iter().zip(sinks) {
+    let Sink::Pprof(sink) = sink else {
+      unreachable!("--follow only builds pprof sinks")
+    };
+    let partial = format!("{output}.partial");
+    let context = || FailureKind::Output.with(format!("fail to write {output}"));
+    let mut out = create_output(&partial)?;
+    write_pprof(sink.snapshot()?, spec, header, args, config, &mut out).with_context(context)?;
+    drop(out);
+    std::fs::rename(&partial, output).with_context(context)?;
+  }
+  Ok(())
+}
+
+fn list(mut args: ListArgs) -> anyhow::Result<()> {
+  let _stdin = spool_stdin(std::slice::from_mut(&mut args.fst))?;
+  let input_format = args
+    .input_format
+    .unwrap_or_else(|| InputFormat::from_path(&args.fst));
+  let fst = &args.fst;
+  let mut reader = InputWave::open(fst, input_format)
+    .with_context(|| FailureKind::Waveform.with(format!("fail to open {fst}")))?;
+  let pattern = SignalPattern::new(args.pattern.as_deref().unwrap_or("*"));
+  let signals = list_signals(&mut reader, &pattern)?;
+  print_signals(&signals, args.tree, std::io::stdout().lock())?;
+  Ok(())
+}
+
+/// Print the signals selected by the config with their width and handle, then every other entry
+/// of the config resolved to handles. Config entries not matching any signal are errors.
+fn validate(args: ValidateArgs) -> anyhow::Result<()> {
+  info!("Reading config from {}", args.config);
+  let config = Config::from_path(&args.config)?;
+  let input_format = args
+    .input_format
+    .unwrap_or_else(|| InputFormat::from_path(&args.fst));
+  let fst = &args.fst;
+  let reader = InputWave::open(fst, input_format)
+    .with_context(|| FailureKind::Waveform.with(format!("fail to open {fst}")))?;
+  let mut reader = derived::DerivedWave::new(reader, &config.derived)?;
+
+  let collect = CollectOptions {
+    strict: true,
+    ..args.scope.collect(config.regexes()?, config.scopes.clone())
+  };
+  let metadata = collect_signals(&mut reader, &config.signals, &collect)?;
+  let mut out = std::io::stdout().lock();
+  print_signals(&metadata, false, &mut out)?;
+  let handles: std::collections::HashSet<_> =
+    metadata.handle.iter().map(|h| h.get_index()).collect();
+  writeln!(
+    out,
+    "{} signals on {} handles",
+    metadata.handle.len(),
+    handles.len()
+  )?;
+  for profile in &config.profiles {
+    let collect = CollectOptions {
+      strict: true,
+      ..args
+        .scope
+        .collect(profile.regexes()?, profile.scopes.clone())
+    };
+    let metadata = collect_signals(&mut reader, &profile.signals, &collect)?;
+    writeln!(
+      out,
+      "profile {}: {} signals",
+      profile.name,
+      metadata.handle.len()
+    )?;
+  }
+
+  let signals = SignalLookup::new(&mut reader)?;
+  let options = ConvertOptions::from_config(&signals, &config)?;
+  for hs in &options.handshakes {
+    writeln!(
+      out,
+      "handshake {}: clock {}, valid {}, ready {}",
+      hs.name,
+      hs.clock.get_index(),
+      hs.valid.get_index(),
+      hs.ready.get_index()
+    )?;
+  }
+  for tx in &options.transactions {
+    writeln!(
+      out,
+      "transaction {}: clock {}",
+      tx.name,
+      tx.clock.get_index()
+    )?;
+  }
+  for machine in &options.state_machines {
+    writeln!(
+      out,
+      "state machine {}: {} members, {} states",
+      machine.name,
+      machine.members.len(),
+      machine.states.len()
+    )?;
+  }
+  for aggregate in &options.aggregations {
+    writeln!(
+      out,
+      "aggregation {} of {}: handle {}",
+      aggregate.aggregation.as_str(),
+      aggregate.signal,
+      aggregate.handle.get_index()
+    )?;
+  }
+  if let Some(trigger) = &options.trigger {
+    writeln!(out, "trigger: handle {}", trigger.handle.get_index())?;
+  }
+  if let Some(instret) = &config.instret {
+    let instret = instret::resolve(&signals, instret)?;
+    writeln!(
+      out,
+      "instret: instruction {}",
+      instret.instruction.get_index()
+    )?;
+  }
+  if let Some(path) = &args.clock {
+    let clock = clock::resolve(&signals, path)?;
+    writeln!(out, "clock {path}: handle {}", clock.get_index())?;
+  }
+  Ok(())
+}
+
+/// Profile both runs the same way and write the activity of the new run minus the one of the
+/// baseline. Samples are per signal, the values they held add up.
+fn diff(args: DiffArgs) -> anyhow::Result<()> {
+  info!("Reading config from {}", args.config);
+  let config = Config::from_path(&args.config)?;
+  let output = match &args.output {
+    Some(output) => output.clone(),
+    None => {
+      let stem = std::path::Path::new(&args.new).file_stem();
+      let stem = stem.and_then(|stem| stem.to_str());
+      let stem = stem.with_context(|| format!("can't name the output after {}", args.new))?;
+      format!("{stem}.diff.pprof.gz")
+    }
+  };
+  check_output(&output)?;
+
+  let base = diff_run(&args.base, &args, &config)?;
+  let new = diff_run(&args.new, &args, &config)?;
+  let profile = pprof::diff(&base, &new)?;
+
+  info!("Writing {output}");
+  let mut out = create_output(&output)?;
+  let context = || FailureKind::Output.with(format!("fail to write {output}"));
+  write_profile(&profile, &mut out, Codec::Gzip, None).with_context(context)?;
+  out.flush().with_context(context)
+}
+
+/// Profile the run at `fst` with the options of `args`.
+fn diff_run(fst: &str, args: &DiffArgs, config: &Config) -> anyhow::Result<pprof::Profile> {
+  let input_format = InputFormat::from_path(fst);
+  info!("Reading {input_format:?} from file: {fst}");
+  let reader = InputWave::open(fst, input_format)
+    .with_context(|| FailureKind::Waveform.with(format!("fail to open {fst}")))?;
+  let mut reader = derived::DerivedWave::new(reader, &config.derived)?;
+  let collect = args.scope.collect(config.regexes()?, config.scopes.clone());
+  let metadata = collect_signals(&mut reader, &config.signals, &collect)?;
+  let signals = SignalLookup::new(&mut reader)?;
+  let cycle_clock = match &args.clock_signal {
+    Some(path) => Some(clock::resolve(&signals, path)?),
+    None => None,
+  };
+  let sync = match &args.sync {
+    Some(path) => {
+      let handle = clock::resolve(&signals, path)?;
+      let rise = first_rising_edge(&mut reader, handle)?;
+      Some(rise.with_context(|| format!("{path} never rises in {fst}"))?)
+    }
+    None => None,
+  };
+  let mut options = ConvertOptions {
+    cycle_clock,
+    ..ConvertOptions::from_config(&signals, config)?
+  };
+  // `None` is below any time
+  options.window.start = sync.max(options.window.start);
+  convert::convert(&mut reader, &metadata, &options)
+    .with_context(|| FailureKind::Waveform.with(format!("fail to convert {fst}")))
+}
 
-  let file = std::fs::File::open(&args.fst)?;
-  let mut reader = FstReader::open(std::io::BufReader::new(file))?;
+/// Return the time of the first rising edge of the 1-bit signal `handle`, if it ever rises.
+fn first_rising_edge(
+  reader: &mut impl Waveform,
+  handle: FstSignalHandle,
+) -> anyhow::Result<Option<u64>> {
+  let (mut prev, mut rise) = (None::<String>, None);
+  reader.for_each_change(&[handle], |t, _, value| {
+    if let SignalValue::String(bits) = value {
+      let edge = prev.as_deref().and_then(|prev| Edge::classify(prev, &bits));
+      if edge == Some(Edge::Pos) && rise.is_none() {
+        rise = Some(t);
+      }
+      prev = Some(bits);
+    }
+  })?;
+  Ok(rise)
+}
 
-  let header = reader.get_header();
+/// Replace the `-` path with a copy of stdin, and return the copy to keep until it is read.
+fn spool_stdin(paths: &mut [String]) -> anyhow::Result<Option<Spool>> {
+  let mut piped = paths.iter_mut().filter(|path| path.as_str() == "-");
+  let Some(path) = piped.next() else {
+    return Ok(None);
+  };
+  anyhow::ensure!(piped.next().is_none(), "stdin can only be read once");
+  info!("Spooling the waveform from stdin");
+  let spool = Spool::stdin()?;
+  *path = spool.path().to_string();
+  Ok(Some(spool))
+}
+
+/// Print one `path\twidth\thandle` line per signal, or with `tree` the scopes on their own
+/// lines and every signal indented under its scope by its bare name.
+fn print_signals(signals: &SignalMetadata, tree: bool, mut out: impl Write) -> std::io::Result<()> {
+  let mut scope: &[String] = &[];
+  for i in 0..signals.handle.len() {
+    let (width, handle) = (signals.widths[i], signals.handle[i].get_index());
+    if !tree {
+      writeln!(out, "{}\t{width}\t{handle}", signals.full_name(i))?;
+      continue;
+    }
+    let path = &signals.module_paths[i];
+    let common = scope.iter().zip(path).take_while(|(a, b)| a == b).count();
+    for (depth, name) in path.iter().enumerate().skip(common) {
+      writeln!(out, "{:indent$}{name}", "", indent = depth * 2)?;
+    }
+    scope = path;
+    writeln!(
+      out,
+      "{:indent$}{}\t{width}\t{handle}",
+      "",
+      signals.names[i],
+      indent = path.len() * 2
+    )?;
+  }
+  Ok(())
+}
+
+/// Load the config, or make one selecting the traces of the --gtkw save file.
+fn load_config(args: &ExtractArgs) -> anyhow::Result<Config> {
+  if let Some(path) = &args.gtkw {
+    info!("Reading signals from {path}");
+    let save = gtkw::SaveFile::from_path(path)?;
+    return Ok(Config {
+      signals: save.signals,
+      ..Default::default()
+    });
+  }
+  // clap requires one of them when not listing signals
+  let path = args
+    .config
+    .as_deref()
+    .context("--config or --gtkw is required")?;
+  info!("Reading config from {path}");
+  Config::from_path(path)
+}
+
+/// Extract every waveform given with --fst on its own thread, and merge their profiles into one
+/// per --format. The samples carry no time, so the shards only add up their durations.
+fn extract_merged(args: &ExtractArgs, config: &Config) -> anyhow::Result<()> {
+  anyhow::ensure!(
+    args
+      .format
+      .iter()
+      .all(|spec| matches!(spec.format, OutputFormat::Pprof | OutputFormat::Prototext)),
+    "only the pprof and prototext formats can merge several --fst files"
+  );
+  anyhow::ensure!(
+    args.cache_hierarchy.is_none(),
+    "--cache-hierarchy can't be used with several --fst files"
+  );
+  anyhow::ensure!(
+    args.report.is_none(),
+    "--report can't be used with several --fst files"
+  );
+  anyhow::ensure!(
+    args.checkpoint.is_none(),
+    "--checkpoint can't be used with several --fst files"
+  );
+
+  let outputs = output_paths(&args.fst[0], args, config)?;
+  for (_, output) in &outputs {
+    check_output(output)?;
+  }
+
+  info!("Fetching signals value of {} files", args.fst.len());
+  let shards = std::thread::scope(|scope| {
+    let threads: Vec<_> = args
+      .fst
+      .iter()
+      .map(|fst| {
+        scope.spawn(move || -> anyhow::Result<_> {
+          let (reader, _) = open(fst, args)?;
+          let mut reader = derived::DerivedWave::new(reader, &config.derived)?;
+          let mut input = Input::resolve(&mut reader, args, config)?;
+          // frames are matched by name when merging, the tables are dropped there instead
+          input.options.no_functions = false;
+          input.options.no_locations = false;
+          let mut sinks = input.sinks(args)?;
+          // the bars of the threads would overwrite each other
+          let feedback = sink::Feedback {
+            bar: false,
+            ..input.feedback
+          };
+          let mut reader = input.downsample(&mut reader, args)?;
+          sink::drive(&mut reader, &mut sinks, feedback).with_context(|| {
+            FailureKind::Waveform.with(format!("fail to read the changes of {fst}"))
+          })?;
+          let profiles = sinks
+            .into_iter()
+            .map(|sink| match sink {
+              Sink::Pprof(sink) => sink.finish(),
+              Sink::Clock(sink) => Ok(sink.finish()),
+              Sink::Instret(sink) => Ok(sink.finish()),
+              _ => unreachable!("only profile formats merge"),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .with_context(|| format!("fail to convert {fst}"))?;
+          Ok((input.header, profiles))
+        })
+      })
+      .collect();
+    threads
+      .into_iter()
+      .map(|thread| thread.join().expect("a wave reading thread panicked"))
+      .collect::<anyhow::Result<Vec<_>>>()
+  })?;
+
+  // the outputs are named after the first file, and take its capture time
+  let header = &shards[0].0;
+  for (i, (spec, output)) in outputs.into_iter().enumerate() {
+    let mut builder = pprof::ProfileBuilder::new();
+    builder.with_functions = !args.no_functions;
+    builder.with_locations = !args.no_locations;
+    for (_, profiles) in &shards {
+      builder.append(&profiles[i]);
+    }
+    info!("Writing {output}");
+    let mut out = create_output(&output)?;
+    write_pprof(builder.finish(), spec, header, args, config, &mut out)
+      .with_context(|| FailureKind::Output.with(format!("fail to write {output}")))?;
+  }
+  Ok(())
+}
+
+/// Open the waveform at `fst`, in the format given by --input-format or told by its extension.
+fn open(
+  fst: &str,
+  args: &ExtractArgs,
+) -> anyhow::Result<(cache::HierarchyCache<InputWave>, InputFormat)> {
+  let input_format = args
+    .input_format
+    .unwrap_or_else(|| InputFormat::from_path(fst));
+  info!("Reading {input_format:?} from file: {fst}");
+
+  let reader = open_wave(fst, input_format, args)?;
+  let reader = cache::HierarchyCache::new(reader, fst, args.cache_hierarchy.as_deref())?;
+
+  let header = reader.header();
   trace!(
     version = header.version,
     date = header.date,
     start_time = header.start_time,
     end_time = header.end_time,
+    tick = header.tick_unit(),
     "Header info"
   );
+  Ok((reader, input_format))
+}
 
-  info!("Reading config from file {}", args.config);
-  let config = std::fs::read(args.config)?;
-  let config: Config = serde_json::from_slice(&config)?;
+/// Open the waveform at `fst` with its backend, mapped in memory with --mmap.
+fn open_wave(
+  fst: &str,
+  input_format: InputFormat,
+  args: &ExtractArgs,
+) -> anyhow::Result<InputWave> {
+  let wave = if args.mmap && input_format == InputFormat::Fst {
+    InputWave::open_mapped(fst)
+  } else {
+    InputWave::open(fst, input_format)
+  };
+  wave.with_context(|| FailureKind::Waveform.with(format!("fail to open {fst}")))
+}
 
-  info!("Iterating hierachy to get signal information");
-  let metadata = collect_signals(&mut reader, &config.signals)?;
+/// Everything resolved against the hierarchy of one waveform before reading its value changes.
+struct Input {
+  header: WaveHeader,
+  metadata: SignalMetadata,
+  /// Whether the main outputs are written, see [`main_outputs`]
+  main: bool,
+  /// Signals of every profile of the config, in its order
+  profiles: Vec<SignalMetadata>,
+  clock: Option<FstSignalHandle>,
+  instret: Option<instret::Instret>,
+  feedback: sink::Feedback,
+  options: ConvertOptions,
+}
 
-  info!("Fetching signals value");
+impl Input {
+  fn resolve(
+    reader: &mut impl Waveform,
+    args: &ExtractArgs,
+    config: &Config,
+  ) -> anyhow::Result<Self> {
+    let header = reader.header();
 
-  let mut str_tbl = pprof::StringTable::new();
+    info!("Iterating hierachy to get signal information");
+    let options = |regexes, scopes| CollectOptions {
+      strict: args.strict,
+      progress: args.progress.then_some(100_000),
+      ..args.scope.collect(regexes, scopes)
+    };
+    let collect = options(config.regexes()?, config.scopes.clone());
+    let metadata = collect_signals(reader, &config.signals, &collect)?;
+    if metadata.too_deep > 0 {
+      info!(
+        "{} matching signals are excluded by the max depth limit",
+        metadata.too_deep
+      );
+    }
+    anyhow::ensure!(
+      config.profiles.is_empty() || !args.drop_constant,
+      "--drop-constant can't be used with the profiles of the config"
+    );
+    anyhow::ensure!(
+      config.profiles.is_empty() || args.mode == Mode::Signals,
+      "the profiles of the config select signals, which --mode instret doesn't profile"
+    );
+    let mut profiles = Vec::with_capacity(config.profiles.len());
+    for profile in &config.profiles {
+      let collect = options(profile.regexes()?, profile.scopes.clone());
+      let metadata = collect_signals(reader, &profile.signals, &collect)
+        .with_context(|| format!("fail to select the signals of profile {}", profile.name))?;
+      info!(
+        "Profile {} selects {} signals",
+        profile.name,
+        metadata.handle.len()
+      );
+      profiles.push(metadata);
+    }
+    // entries matching nothing only warn unless strict, but all of them matching nothing is a
+    // failure of its own
+    let selected = metadata.handle.len() + profiles.iter().map(|p| p.handle.len()).sum::<usize>();
+    if selected == 0 && (config.selects_signals() || !config.profiles.is_empty()) {
+      return Err(
+        FailureKind::NoSignals
+          .with("no signal of the hierarchy matches the config")
+          .into(),
+      );
+    }
 
-  let mut p = pprof::Profile::default();
-  p.time_nanos = 10000;
-  p.period_type = Some(pprof::ValueType {
-    r#type: str_tbl.id("cycle"),
-    unit: str_tbl.id("number"),
-  });
-  p.period = 1;
-  p.duration_nanos = (header.end_time - header.start_time).try_into().unwrap();
+    let window = TimeWindow {
+      start: args.time_start.or(config.time_start),
+      end: args.time_end.or(config.time_end),
+      boundary: args.boundary,
+    };
+    // a followed waveform only reaches the end of the window later on
+    let end_time = if args.follow {
+      u64::MAX
+    } else {
+      header.end_time
+    };
+    window.validate(header.start_time, end_time)?;
+    let feedback = sink::Feedback {
+      heartbeat: args
+        .heartbeat
+        .or(args.progress.then_some(5))
+        .map(std::time::Duration::from_secs),
+      bar: !args.quiet && std::io::stderr().is_terminal(),
+    };
+    let metadata = if args.drop_constant {
+      transitions::retain_active(reader, metadata, &window, 1, feedback)?
+    } else {
+      metadata
+    };
 
-  let filter = FstFilter::filter_signals(metadata.handle.clone());
-  reader.read_signals(&filter, |t, handle, value| {
-    let v = match value {
-      FstSignalValue::String(s) => s,
-      FstSignalValue::Real(r) => format!("real: {}", r),
+    let mut properties = match &args.properties {
+      Some(path) => {
+        info!("Reading properties from {path}");
+        properties::Properties::from_path(path)?
+      }
+      None => properties::Properties::default(),
     };
-    let result = metadata
-      .handle
-      .iter()
-      .enumerate()
-      .find(|(_, item)| item.get_index() == handle.get_index());
-    if let Some((i, _)) = result {
-      trace!(
-        "time: {} module: {} signal: {} value: {}",
-        t,
-        metadata.module_paths[i].join("."),
-        metadata.names[i],
-        v
+    if let Some(path) = &args.gtkw {
+      properties.fill(gtkw::SaveFile::from_path(path)?.properties);
+    }
+
+    let signals = SignalLookup::new(reader)?;
+    let clock = match &args.sample_clock {
+      Some(path) => Some(clock::resolve(&signals, path)?),
+      None => None,
+    };
+    let cycle_clock = match &args.clock_signal {
+      Some(path) => Some(clock::resolve(&signals, path)?),
+      None => None,
+    };
+    let instret = match args.mode {
+      Mode::Instret => {
+        let instret = config.instret.as_ref();
+        let instret = instret.context("--mode instret needs an instret section in the config")?;
+        Some(instret::resolve(&signals, instret)?)
+      }
+      Mode::Signals => None,
+    };
+
+    let options = ConvertOptions {
+      edges: args.edges,
+      group_by_value: args.group_by_value,
+      coalesce: args.coalesce,
+      strict: args.strict,
+      final_state: args.final_state,
+      no_functions: args.no_functions,
+      no_locations: args.no_locations,
+      max_changes_per_signal: args.max_changes_per_signal,
+      max_label_values: args.max_label_values,
+      window,
+      sample_order: args.sample_order,
+      embed_counts: args.embed_counts,
+      properties,
+      real_scale: args.real_scale,
+      bus_ranges: args.bus_ranges,
+      hierarchy_stacks: args.hierarchy_stacks,
+      bit_labels: args.bit_labels,
+      cycle_clock,
+      bucket: args.bucket.map(std::num::NonZeroU64::get),
+      checkpoint: args
+        .checkpoint
+        .as_ref()
+        .map(|path| checkpoint::CheckpointOptions {
+          path: path.clone(),
+          every: std::time::Duration::from_secs(args.checkpoint_every),
+        }),
+      ..ConvertOptions::from_config(&signals, config)?
+    };
+    Ok(Self {
+      header,
+      metadata,
+      main: main_outputs(config),
+      profiles,
+      clock,
+      instret,
+      feedback,
+      options,
+    })
+  }
+
+  /// Wrap `reader` to downsample its value changes as --sample-every says.
+  fn downsample<W: Waveform>(
+    &self,
+    reader: W,
+    args: &ExtractArgs,
+  ) -> anyhow::Result<downsample::Downsampled<W>> {
+    let mut control = self.options.control_handles();
+    let instret = self.instret.iter().flat_map(|instret| {
+      [
+        Some(instret.instruction),
+        instret.pc,
+        instret.clock,
+        instret.valid,
+      ]
+    });
+    for handle in instret.flatten().chain(self.clock) {
+      if !control.contains(&handle) {
+        control.push(handle);
+      }
+    }
+    downsample::Downsampled::new(
+      reader,
+      args.sample_every,
+      args.sample_keep,
+      self.options.cycle_clock,
+      control,
+    )
+    .context("--sample-every in cycles needs --clock-signal")
+  }
+
+  /// Return the sink of every --format, each one fed the signals its filter accepts, then the
+  /// ones of every profile, in the order of [`output_paths`].
+  fn sinks(&self, args: &ExtractArgs) -> anyhow::Result<Vec<Sink<'_>>> {
+    let Self {
+      header,
+      metadata,
+      clock,
+      instret,
+      options,
+      ..
+    } = self;
+    let main = self.main.then_some(metadata);
+    let mut sinks = Vec::with_capacity(args.format.len() * (1 + self.profiles.len()));
+    for (spec, metadata) in main
+      .into_iter()
+      .chain(&self.profiles)
+      .flat_map(|metadata| args.format.iter().map(move |spec| (spec, metadata)))
+    {
+      let subset = metadata.subset(|i| spec.filter.accept(metadata.widths[i]));
+      sinks.push(match spec.format {
+        OutputFormat::Pprof | OutputFormat::Prototext => match (instret, clock) {
+          (Some(instret), _) => Sink::Instret(instret::InstretSink::new(
+            header,
+            instret.clone(),
+            options.window,
+          )?),
+          (None, Some(clock)) => Sink::Clock(clock::ClockSink::new(
+            header,
+            subset,
+            *clock,
+            options.window,
+          )?),
+          (None, None) => {
+            let mut sink = convert::PprofSink::new(header, subset, options)?;
+            if let (true, Some(path)) = (args.resume, &args.checkpoint) {
+              info!("Resuming from checkpoint {path}");
+              sink.resume(checkpoint::Checkpoint::load(path)?)?;
+            }
+            Sink::Pprof(Box::new(sink))
+          }
+        },
+        OutputFormat::Transitions => {
+          Sink::Transitions(transitions::TransitionsSink::new(subset, options.window))
+        }
+        OutputFormat::Json => Sink::Json(dump::DumpSink::new(subset, options.window)),
+        OutputFormat::Vcd => Sink::Vcd(vcd::VcdSink::new(header, subset, options.window)),
+        OutputFormat::Csv => Sink::Csv(dump::DumpSink::new(subset, options.window)),
+        OutputFormat::Parquet => Sink::Parquet(dump::DumpSink::new(subset, options.window)),
+        OutputFormat::Folded => {
+          Sink::Folded(folded::FoldedSink::new(header, subset, options.window))
+        }
+      });
+    }
+    // last, so the outputs still line up with the formats
+    if args.report.is_some() {
+      let report = report::ReportSink::new(
+        header,
+        metadata.clone(),
+        options.window,
+        &options.properties,
+        &options.state_machines,
       );
+      sinks.push(Sink::Report(report));
     }
-  })?;
+    Ok(sinks)
+  }
+}
 
-  p.string_table = str_tbl.to_string_table();
-
-  let mut buf = Vec::new();
-  buf.reserve(p.encoded_len());
-  p.encode(&mut buf).unwrap();
-
-  let mut encoder = GzEncoder::new(Vec::with_capacity(p.encoded_len()), Compression::default());
-  encoder.write_all(&buf).unwrap();
-
-  std::fs::write(
-    // if output path is not given, pprof proto file will be default writed into current path
-    // with same name as the .fst file
-    args.output.unwrap_or_else(|| {
-      let input_file_path = std::path::Path::new(&args.fst);
-      let filename = input_file_path.file_stem().unwrap().to_str().unwrap();
-      format!("{filename}.pprof.gz")
-    }),
-    encoder.finish().unwrap(),
-  )
-  .unwrap();
+/// Add the comments asked for to the profile and write it to `out` in the format of `spec`.
+fn write_pprof(
+  mut p: pprof::Profile,
+  spec: &FormatSpec,
+  header: &WaveHeader,
+  args: &ExtractArgs,
+  config: &Config,
+  out: &mut dyn Write,
+) -> anyhow::Result<()> {
+  let tz = args
+    .tz
+    .unwrap_or_else(|| chrono::FixedOffset::east_opt(0).unwrap());
+  pprof::add_comment(
+    &mut p,
+    format!("capture time: {}", date::capture_time(&header.date, tz)),
+  );
+  if args.embed_config {
+    pprof::add_comment(
+      &mut p,
+      format!("config: {}", serde_json::to_string(config)?),
+    );
+  }
+  if spec.format == OutputFormat::Prototext {
+    out.write_all(pprof::to_text(&p).as_bytes())?;
+  } else {
+    write_profile(&p, &mut *out, codec(args), args.compression_level)?;
+  }
+  out.flush()?;
   Ok(())
 }
 
-#[derive(Default, Debug)]
-struct SignalMetadata {
-  module_paths: Vec<Vec<String>>,
-  names: Vec<String>,
-  handle: Vec<FstSignalHandle>,
+/// Open the output at `path` for writing, `-` being stdout. Logs go to stderr so they never mix
+/// with the output.
+fn create_output(path: &str) -> anyhow::Result<Box<dyn Write>> {
+  if path == "-" {
+    return Ok(Box::new(std::io::stdout().lock()));
+  }
+  let file = std::fs::File::create(path)
+    .with_context(|| FailureKind::Output.with(format!("fail to create {path}")))?;
+  Ok(Box::new(std::io::BufWriter::new(file)))
+}
+
+/// Check the output at `path` can be written, before spending the read of the waveform on it. An
+/// output that doesn't exist yet is created then removed again, an existing one is left as is.
+fn check_output(path: &str) -> anyhow::Result<()> {
+  if path == "-" {
+    return Ok(());
+  }
+  let existed = std::path::Path::new(path).exists();
+  std::fs::OpenOptions::new()
+    .append(true)
+    .create(true)
+    .open(path)
+    .with_context(|| FailureKind::Output.with(format!("output {path} can't be written")))?;
+  if !existed {
+    std::fs::remove_file(path).with_context(|| format!("fail to remove probe of output {path}"))?;
+  }
+  Ok(())
 }
 
-impl SignalMetadata {
-  fn push(&mut self, module_path: Vec<String>, name: String, handle_id: FstSignalHandle) {
-    self.module_paths.push(module_path);
-    self.names.push(name);
-    self.handle.push(handle_id);
+/// Return the codec of the pprof outputs, --no-compress standing for `--compress none`.
+fn codec(args: &ExtractArgs) -> Codec {
+  if args.no_compress {
+    Codec::None
+  } else {
+    args.compress
   }
 }
 
-fn collect_signals(
-  reader: &mut MyFstReader,
-  expected: &[String],
-) -> anyhow::Result<SignalMetadata> {
-  let mut metadata = SignalMetadata::default();
-  let mut module_path: Vec<String> = Vec::new();
-  let mut dedup_pool = HashSet::new();
-  reader.read_hierarchy(|hier| match hier {
-    FstHierarchyEntry::Var { name, handle, .. } => {
-      if expected.contains(&name) && !dedup_pool.contains(&handle.get_index()) {
-        let id = handle.get_index();
-        metadata.push(module_path.clone(), name, handle);
-        dedup_pool.insert(id);
-      }
+/// Write the profile to `out`, compressed with `codec` at the given level if any. The profile is
+/// streamed, so its encoding is never buffered whole.
+fn write_profile(
+  p: &pprof::Profile,
+  out: impl Write,
+  codec: Codec,
+  level: Option<u32>,
+) -> anyhow::Result<()> {
+  let mut encoder = codec.encoder(out, level)?;
+  pprof::encode_to(p, &mut encoder)?;
+  encoder.finish()?;
+  Ok(())
+}
+
+/// Return true if the main outputs are written, which they are unless the config only selects
+/// signals in its profiles.
+fn main_outputs(config: &Config) -> bool {
+  config.profiles.is_empty() || config.selects_signals()
+}
+
+/// Return the format and the path of every output but the report, in the order of
+/// [`Input::sinks`]: the one of every --format, then those of every profile of the config.
+fn output_paths<'a>(
+  fst: &str,
+  args: &'a ExtractArgs,
+  config: &Config,
+) -> anyhow::Result<Vec<(&'a FormatSpec, String)>> {
+  let mut outputs = Vec::new();
+  if main_outputs(config) {
+    for spec in &args.format {
+      let output = match &args.output {
+        Some(output) => output.clone(),
+        None => default_output_path(fst, spec, codec(args))?,
+      };
+      outputs.push((spec, output));
     }
-    FstHierarchyEntry::Scope { name, .. } => module_path.push(name.to_string()),
-    FstHierarchyEntry::UpScope => {
-      module_path.pop();
+  }
+  for profile in &config.profiles {
+    let stem = fst_stem(fst)?;
+    for spec in &args.format {
+      outputs.push((
+        spec,
+        profile.output_path(stem, &extension(spec, codec(args))),
+      ));
     }
-    _ => (),
-  })?;
+  }
+  let mut seen = std::collections::HashSet::new();
+  for (_, output) in &outputs {
+    anyhow::ensure!(
+      seen.insert(output),
+      "{output} would be written by two outputs, put {{ext}} or {{profile_name}} in the output \
+       of the profiles"
+    );
+  }
+  Ok(outputs)
+}
+
+/// Return the output path used when none is given: a file in the current directory with the same
+/// name as the .fst file and an extension telling the format, and the filter if any.
+fn default_output_path(fst: &str, spec: &FormatSpec, codec: Codec) -> anyhow::Result<String> {
+  Ok(format!("{}.{}", fst_stem(fst)?, extension(spec, codec)))
+}
+
+/// Return the file name of the waveform without its extension, the outputs are named after it.
+fn fst_stem(fst: &str) -> anyhow::Result<&str> {
+  std::path::Path::new(fst)
+    .file_stem()
+    .and_then(|stem| stem.to_str())
+    .with_context(|| format!("can't name the output after {fst}, give it with --output"))
+}
+
+/// Return the extension of the outputs of `spec`, telling the format and the filter if any.
+fn extension(spec: &FormatSpec, codec: Codec) -> String {
+  let pprof = match codec.extension() {
+    Some(extension) => format!("pprof.{extension}"),
+    None => "pprof".to_string(),
+  };
+  let extension = match spec.format {
+    OutputFormat::Pprof => pprof.as_str(),
+    OutputFormat::Prototext => "pprof.txt",
+    OutputFormat::Transitions => "transitions.json",
+    OutputFormat::Json => "json",
+    OutputFormat::Vcd => "vcd",
+    OutputFormat::Folded => "folded",
+    OutputFormat::Csv => "csv",
+    OutputFormat::Parquet => "parquet",
+  };
+  match spec.filter {
+    SignalFilter::All => extension.to_string(),
+    SignalFilter::Scalars => format!("scalars.{extension}"),
+    SignalFilter::Buses => format!("buses.{extension}"),
+  }
+}
+
+#[test]
+fn write_profile_round_trip() {
+  use prost::Message;
+  use std::io::Read;
+
+  let mut builder = pprof::ProfileBuilder::new();
+  let location_id = builder.stack(&["top.valid"]);
+  builder.profile.sample.push(pprof::Sample {
+    location_id,
+    value: vec![1],
+    label: Vec::new(),
+  });
+  let profile = builder.finish();
+
+  let mut out = Vec::new();
+  write_profile(&profile, &mut out, Codec::Gzip, Some(9)).unwrap();
+  let mut raw = Vec::new();
+  flate2::read::GzDecoder::new(&out[..])
+    .read_to_end(&mut raw)
+    .unwrap();
+  assert_eq!(pprof::Profile::decode(&raw[..]).unwrap(), profile);
+
+  let mut out = Vec::new();
+  write_profile(&profile, &mut out, Codec::None, None).unwrap();
+  assert_eq!(pprof::Profile::decode(&out[..]).unwrap(), profile);
+}
+
+#[test]
+fn print_signal_tree() {
+  let path = |path: &[&str]| path.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+  let signals = SignalMetadata {
+    module_paths: vec![
+      path(&["top", "core"]),
+      path(&["top", "core"]),
+      path(&["top"]),
+    ],
+    names: vec!["valid".to_string(), "data".to_string(), "clock".to_string()],
+    widths: vec![1, 8, 1],
+    handle: (0..3).map(FstSignalHandle::from_index).collect(),
+    ..Default::default()
+  };
+
+  let mut out = Vec::new();
+  print_signals(&signals, false, &mut out).unwrap();
+  assert_eq!(
+    String::from_utf8(out).unwrap(),
+    "top.core.valid\t1\t0\ntop.core.data\t8\t1\ntop.clock\t1\t2\n"
+  );
+
+  let mut out = Vec::new();
+  print_signals(&signals, true, &mut out).unwrap();
+  assert_eq!(
+    String::from_utf8(out).unwrap(),
+    "top\n  core\n    valid\t1\t0\n    data\t8\t1\n  clock\t1\t2\n"
+  );
+}
+
+#[test]
+fn check_outputs_before_reading() {
+  let dir = tempfile::tempdir().unwrap();
+  let dir = dir.path();
+  let fresh = dir.join("fresh.pprof.gz");
+  let fresh = fresh.to_str().unwrap();
+  let existing = dir.join("existing.pprof.gz");
+  let existing = existing.to_str().unwrap();
+  std::fs::write(existing, b"previous run").unwrap();
+
+  check_output(fresh).unwrap();
+  assert!(!std::path::Path::new(fresh).exists());
+  check_output(existing).unwrap();
+  assert_eq!(std::fs::read(existing).unwrap(), b"previous run");
+  check_output("-").unwrap();
 
-  Ok(metadata)
+  let missing = dir.join("missing").join("out.pprof.gz");
+  let err = check_output(missing.to_str().unwrap()).unwrap_err();
+  assert!(err.to_string().contains("can't be written"));
 }
@@ -0,0 +1,399 @@
+//! Parquet output of the value change tables.
+//!
+//! The parquet crate would pull arrow in for two plain tables, so the file is written here: every
+//! column chunk is a single uncompressed data page of PLAIN encoded values, and the footer is
+//! the thrift compact encoding of the file metadata. The times are unsigned 64-bit integers, the
+//! signals and values UTF-8 strings, null in the wide table before the first change of a signal.
+
+use std::io::Write;
+
+use crate::dump::{DumpRecord, WideTable};
+
+/// Number of rows of a row group, so a reader can load a large table a part at a time.
+const ROWS_PER_GROUP: usize = 1 << 16;
+
+/// Write the records as a parquet table of `time`, `signal` and `value` columns, one row per
+/// record, the signal being its full dot separated path.
+pub fn write_records(records: &[DumpRecord], out: impl Write) -> std::io::Result<()> {
+  let times: Vec<_> = records.iter().map(|record| record.time).collect();
+  let paths: Vec<_> = records
+    .iter()
+    .map(|record| match record.module_path.as_str() {
+      "" => record.name.clone(),
+      path => format!("{path}.{}", record.name),
+    })
+    .collect();
+  let values: Vec<_> = records.iter().map(|record| record.value.as_str()).collect();
+  let signals: Vec<_> = paths.iter().map(String::as_str).collect();
+  let columns = [
+    ("time", Column::Times(&times)),
+    ("signal", Column::Strings(&signals)),
+    ("value", Column::Strings(&values)),
+  ];
+  write_table(&columns, records.len(), out)
+}
+
+/// Write the wide table as a parquet table of a `time` column then a column named after each
+/// signal.
+pub fn write_wide(table: &WideTable, out: impl Write) -> std::io::Result<()> {
+  let times: Vec<_> = table.rows.iter().map(|(time, _)| *time).collect();
+  let values: Vec<Vec<_>> = (0..table.signals.len())
+    .map(|i| {
+      table
+        .rows
+        .iter()
+        .map(|(_, values)| values[i].as_deref())
+        .collect()
+    })
+    .collect();
+  let columns: Vec<_> = std::iter::once(("time", Column::Times(&times)))
+    .chain(
+      table
+        .signals
+        .iter()
+        .zip(&values)
+        .map(|(signal, values)| (signal.as_str(), Column::Nullable(values))),
+    )
+    .collect();
+  write_table(&columns, table.rows.len(), out)
+}
+
+/// The cells of a column, all of the same number of rows.
+enum Column<'a> {
+  Times(&'a [u64]),
+  Strings(&'a [&'a str]),
+  Nullable(&'a [Option<&'a str>]),
+}
+
+// parquet.thrift enums
+const INT64: i32 = 2;
+const BYTE_ARRAY: i32 = 6;
+const REQUIRED: i32 = 0;
+const OPTIONAL: i32 = 1;
+const UTF8: i32 = 0;
+const UINT_64: i32 = 14;
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+const DATA_PAGE: i32 = 0;
+const UNCOMPRESSED: i32 = 0;
+
+impl Column<'_> {
+  fn physical_type(&self) -> i32 {
+    match self {
+      Column::Times(_) => INT64,
+      _ => BYTE_ARRAY,
+    }
+  }
+
+  fn converted_type(&self) -> i32 {
+    match self {
+      Column::Times(_) => UINT_64,
+      _ => UTF8,
+    }
+  }
+
+  fn nullable(&self) -> bool {
+    matches!(self, Column::Nullable(_))
+  }
+
+  /// Return the data page of the rows in `rows`: the definition levels of a nullable column,
+  /// then the values that aren't null.
+  fn page(&self, rows: std::ops::Range<usize>) -> Vec<u8> {
+    let string = |page: &mut Vec<u8>, text: &str| {
+      page.extend((text.len() as u32).to_le_bytes());
+      page.extend(text.as_bytes());
+    };
+    let mut page = Vec::new();
+    match self {
+      Column::Times(times) => {
+        for time in &times[rows] {
+          page.extend(time.to_le_bytes());
+        }
+      }
+      Column::Strings(texts) => texts[rows].iter().for_each(|text| string(&mut page, text)),
+      Column::Nullable(cells) => {
+        let cells = &cells[rows];
+        let levels = definition_levels(cells.iter().map(Option::is_some));
+        page.extend((levels.len() as u32).to_le_bytes());
+        page.extend(levels);
+        for text in cells.iter().flatten() {
+          string(&mut page, text);
+        }
+      }
+    }
+    page
+  }
+}
+
+/// Encode the definition levels of a nullable column, 1 for a value and 0 for a null, as runs of
+/// the RLE / bit-packing hybrid encoding.
+fn definition_levels(defined: impl Iterator<Item = bool>) -> Vec<u8> {
+  let mut levels = Vec::new();
+  let mut run: Option<(bool, u64)> = None;
+  let flush = |levels: &mut Vec<u8>, (defined, count): (bool, u64)| {
+    // a run header is its count shifted left once, then its value on one byte
+    varint(levels, count << 1);
+    levels.push(defined as u8);
+  };
+  for defined in defined {
+    match &mut run {
+      Some((value, count)) if *value == defined => *count += 1,
+      _ => {
+        if let Some(run) = run.take() {
+          flush(&mut levels, run);
+        }
+        run = Some((defined, 1));
+      }
+    }
+  }
+  if let Some(run) = run {
+    flush(&mut levels, run);
+  }
+  levels
+}
+
+/// Write the `columns` of `rows` rows as a parquet file.
+fn write_table(
+  columns: &[(&str, Column<'_>)],
+  rows: usize,
+  mut out: impl Write,
+) -> std::io::Result<()> {
+  out.write_all(b"PAR1")?;
+  let mut offset = 4;
+  let mut groups = Vec::new();
+  for start in (0..rows).step_by(ROWS_PER_GROUP) {
+    let rows = start..rows.min(start + ROWS_PER_GROUP);
+    let mut chunks = Vec::new();
+    for (name, column) in columns {
+      let page = column.page(rows.clone());
+      let mut header = Compact::new();
+      header.i32(1, DATA_PAGE);
+      header.i32(2, page.len() as i32);
+      header.i32(3, page.len() as i32);
+      header.begin(5);
+      header.i32(1, rows.len() as i32);
+      header.i32(2, PLAIN);
+      header.i32(3, RLE);
+      header.i32(4, RLE);
+      header.end();
+      let header = header.finish();
+      out.write_all(&header)?;
+      out.write_all(&page)?;
+      chunks.push((*name, column, offset, (header.len() + page.len()) as i64));
+      offset += (header.len() + page.len()) as i64;
+    }
+    groups.push((rows.len(), chunks));
+  }
+
+  let mut meta = Compact::new();
+  meta.i32(1, 1);
+  meta.list(2, STRUCT, columns.len() + 1);
+  meta.element();
+  meta.string(4, "schema");
+  meta.i32(5, columns.len() as i32);
+  meta.end();
+  for (name, column) in columns {
+    meta.element();
+    meta.i32(1, column.physical_type());
+    meta.i32(
+      3,
+      if column.nullable() {
+        OPTIONAL
+      } else {
+        REQUIRED
+      },
+    );
+    meta.string(4, name);
+    meta.i32(6, column.converted_type());
+    meta.end();
+  }
+  meta.i64(3, rows as i64);
+  meta.list(4, STRUCT, groups.len());
+  for (rows, chunks) in &groups {
+    meta.element();
+    meta.list(1, STRUCT, chunks.len());
+    for (name, column, offset, size) in chunks {
+      meta.element();
+      meta.i64(2, *offset);
+      meta.begin(3);
+      meta.i32(1, column.physical_type());
+      meta.list(2, I32, 2);
+      meta.raw_i32(PLAIN);
+      meta.raw_i32(RLE);
+      meta.list(3, BINARY, 1);
+      meta.raw_string(name);
+      meta.i32(4, UNCOMPRESSED);
+      meta.i64(5, *rows as i64);
+      meta.i64(6, *size);
+      meta.i64(7, *size);
+      meta.i64(9, *offset);
+      meta.end();
+      meta.end();
+    }
+    let size = chunks.iter().map(|(.., size)| size).sum();
+    meta.i64(2, size);
+    meta.i64(3, *rows as i64);
+    meta.end();
+  }
+  meta.string(6, concat!("toyfst ", env!("CARGO_PKG_VERSION")));
+  let meta = meta.finish();
+  out.write_all(&meta)?;
+  out.write_all(&(meta.len() as u32).to_le_bytes())?;
+  out.write_all(b"PAR1")
+}
+
+// thrift compact protocol types
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+/// Encoder of a thrift struct in the compact protocol, the one of the parquet metadata. The
+/// fields of every struct must be given by increasing id.
+struct Compact {
+  out: Vec<u8>,
+  /// Id of the last field of each struct being encoded, the innermost last
+  last: Vec<i16>,
+}
+
+impl Compact {
+  fn new() -> Self {
+    Self {
+      out: Vec::new(),
+      last: vec![0],
+    }
+  }
+
+  fn field(&mut self, id: i16, kind: u8) {
+    let last = self.last.last_mut().expect("a struct is being encoded");
+    match id - *last {
+      delta @ 1..=15 => self.out.push((delta as u8) << 4 | kind),
+      _ => {
+        self.out.push(kind);
+        varint(&mut self.out, zigzag(id.into()));
+      }
+    }
+    *last = id;
+  }
+
+  fn i32(&mut self, id: i16, value: i32) {
+    self.field(id, I32);
+    self.raw_i32(value);
+  }
+
+  fn i64(&mut self, id: i16, value: i64) {
+    self.field(id, I64);
+    varint(&mut self.out, zigzag(value));
+  }
+
+  fn string(&mut self, id: i16, text: &str) {
+    self.field(id, BINARY);
+    self.raw_string(text);
+  }
+
+  /// Start a list of `len` elements of the type `kind`, which are given right after.
+  fn list(&mut self, id: i16, kind: u8, len: usize) {
+    self.field(id, LIST);
+    if len < 15 {
+      self.out.push((len as u8) << 4 | kind);
+    } else {
+      self.out.push(0xf0 | kind);
+      varint(&mut self.out, len as u64);
+    }
+  }
+
+  /// Start a struct field, ended by [`Compact::end`].
+  fn begin(&mut self, id: i16) {
+    self.field(id, STRUCT);
+    self.last.push(0);
+  }
+
+  /// Start a struct element of a list, ended by [`Compact::end`].
+  fn element(&mut self) {
+    self.last.push(0);
+  }
+
+  fn end(&mut self) {
+    self.out.push(0);
+    self.last.pop();
+  }
+
+  fn raw_i32(&mut self, value: i32) {
+    varint(&mut self.out, zigzag(value.into()));
+  }
+
+  fn raw_string(&mut self, text: &str) {
+    varint(&mut self.out, text.len() as u64);
+    self.out.extend(text.as_bytes());
+  }
+
+  /// Return the encoding of the struct, ended.
+  fn finish(mut self) -> Vec<u8> {
+    self.end();
+    self.out
+  }
+}
+
+fn zigzag(value: i64) -> u64 {
+  ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Append `value` as an unsigned LEB128 number.
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+  while value >= 0x80 {
+    out.push(value as u8 | 0x80);
+    value >>= 7;
+  }
+  out.push(value as u8);
+}
+
+#[test]
+fn encode_thrift_compact() {
+  let mut compact = Compact::new();
+  compact.i32(1, -1);
+  // too far from the last id for a delta
+  compact.i64(20, 300);
+  compact.list(21, BINARY, 1);
+  compact.raw_string("ab");
+  compact.begin(22);
+  compact.i32(1, 2);
+  compact.end();
+  compact.list(23, I32, 15);
+  (0..15).for_each(|_| compact.raw_i32(0));
+  let mut expected = vec![
+    0x15, 0x01, 0x06, 0x28, 0xd8, 0x04, 0x19, 0x18, 0x02, b'a', b'b',
+  ];
+  expected.extend([0x1c, 0x15, 0x04, 0x00, 0x19, 0xf5, 0x0f]);
+  expected.extend([0; 15]);
+  expected.push(0x00);
+  assert_eq!(compact.finish(), expected);
+}
+
+#[test]
+fn encode_definition_levels_as_runs() {
+  let levels = definition_levels([false, false, true, true, true].into_iter());
+  assert_eq!(levels, [0x04, 0x00, 0x06, 0x01]);
+  let levels = definition_levels(std::iter::repeat(true).take(200));
+  assert_eq!(levels, [0x90, 0x03, 0x01]);
+}
+
+#[test]
+fn frame_parquet_file() {
+  let table = WideTable {
+    signals: vec!["top.valid".to_string()],
+    rows: vec![(0, vec![None]), (5, vec![Some("1".to_string())])],
+  };
+  let mut out = Vec::new();
+  write_wide(&table, &mut out).unwrap();
+  assert_eq!(&out[..4], b"PAR1");
+  assert_eq!(&out[out.len() - 4..], b"PAR1");
+  let footer = u32::from_le_bytes(out[out.len() - 8..out.len() - 4].try_into().unwrap());
+  let footer = &out[out.len() - 8 - footer as usize..out.len() - 8];
+  // the schema holds the root, the time and the signal
+  assert_eq!(&footer[..4], [0x15, 0x02, 0x19, 0x3c]);
+  assert!(footer.windows(9).any(|name| name == b"top.valid"));
+  // the page of the signal: its levels, a null then a value, and the value
+  let page = [4, 0, 0, 0, 0x02, 0x00, 0x02, 0x01, 1, 0, 0, 0, b'1'];
+  assert!(out.windows(page.len()).any(|window| window == page));
+}
@@ -0,0 +1,249 @@
+/// A config entry selecting signals.
+///
+/// An entry without dots selects every signal with that bare name, wherever it is declared. An
+/// entry with dots selects signals by their full path, scopes and name. In both cases `*` matches
+/// any run of characters and `?` any single character, without ever crossing a `.`, so
+/// `top.core.*.valid` selects the `valid` signal of every direct child scope of `top.core`.
+///
+/// An entry ending with a bit slice, like `top.decode.instr[6:0]` or `instr[31]`, selects those
+/// bits of the signals its name matches without the slice. Signals whose name really ends with
+/// the slice, like the elements of an array `lane[3]`, are still selected whole.
+#[derive(Debug)]
+pub struct SignalPattern {
+  /// Dot separated segments, split once up front so matching doesn't allocate
+  segments: Vec<Vec<char>>,
+  /// Trailing bit slice, with the segments without it
+  slice: Option<(BitSlice, Vec<Vec<char>>)>,
+}
+
+impl SignalPattern {
+  pub fn new(raw: &str) -> Self {
+    let split = |raw: &str| -> Vec<Vec<char>> {
+      raw
+        .split('.')
+        .map(|segment| segment.chars().collect())
+        .collect()
+    };
+    Self {
+      segments: split(raw),
+      slice: BitSlice::split(raw).map(|(base, slice)| (slice, split(base))),
+    }
+  }
+
+  /// Return true if the pattern names a single signal by its full path, without any wildcard.
+  pub fn is_full_path(&self) -> bool {
+    let segments = match &self.slice {
+      Some((_, base)) => base,
+      None => &self.segments,
+    };
+    segments.len() > 1 && segments.iter().flatten().all(|c| !matches!(c, '*' | '?'))
+  }
+
+  /// Return true if the pattern selects the signal `name` declared under `module_path`.
+  pub fn matches(&self, module_path: &[String], name: &str) -> bool {
+    self.select(module_path, name).is_some()
+  }
+
+  /// Return how the pattern selects the signal `name` declared under `module_path`: whole, as
+  /// `Some(None)`, or only the bits of a slice.
+  pub fn select(&self, module_path: &[String], name: &str) -> Option<Option<BitSlice>> {
+    if matches_segments(&self.segments, module_path, name) {
+      return Some(None);
+    }
+    match &self.slice {
+      Some((slice, base)) if matches_segments(base, module_path, name) => Some(Some(*slice)),
+      _ => None,
+    }
+  }
+}
+
+/// Return true if the dot separated `segments` select the signal `name` under `module_path`.
+fn matches_segments(segments: &[Vec<char>], module_path: &[String], name: &str) -> bool {
+  match segments {
+    [bare] => glob(bare, name),
+    segments => {
+      segments.len() == module_path.len() + 1
+        && module_path
+          .iter()
+          .map(String::as_str)
+          .chain(std::iter::once(name))
+          .zip(segments)
+          .all(|(text, segment)| glob(segment, text))
+    }
+  }
+}
+
+/// Return the number of single character insertions, deletions and substitutions turning `a`
+/// into `b`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  // distances from the prefix of `a` seen so far to every prefix of `b`
+  let mut row: Vec<usize> = (0..=b.len()).collect();
+  for (i, ca) in a.chars().enumerate() {
+    let mut diagonal = row[0];
+    row[0] = i + 1;
+    for (j, cb) in b.iter().enumerate() {
+      let substitution = diagonal + usize::from(ca != *cb);
+      diagonal = row[j + 1];
+      row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+    }
+  }
+  row[b.len()]
+}
+
+/// The bits `msb` down to `lsb` of a signal, both included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BitSlice {
+  pub msb: u32,
+  pub lsb: u32,
+}
+
+impl BitSlice {
+  /// Split a trailing `[msb:lsb]` or `[bit]` off `raw`, return the text before it and the slice.
+  pub fn split(raw: &str) -> Option<(&str, BitSlice)> {
+    let (base, range) = raw.strip_suffix(']')?.rsplit_once('[')?;
+    // parse would also accept a sign
+    let bit = |text: &str| -> Option<u32> {
+      if text.bytes().all(|b| b.is_ascii_digit()) {
+        text.parse().ok()
+      } else {
+        None
+      }
+    };
+    let slice = match range.split_once(':') {
+      Some((msb, lsb)) => BitSlice {
+        msb: bit(msb)?,
+        lsb: bit(lsb)?,
+      },
+      None => BitSlice {
+        msb: bit(range)?,
+        lsb: bit(range)?,
+      },
+    };
+    (!base.is_empty() && slice.msb >= slice.lsb).then_some((base, slice))
+  }
+
+  pub fn width(&self) -> u32 {
+    self.msb - self.lsb + 1
+  }
+
+  /// Return the bits of the slice out of a value written most significant bit first. Bits
+  /// missing from a value shorter than the slice are taken as 0.
+  pub fn extract(&self, bits: &str) -> String {
+    (self.lsb..=self.msb)
+      .rev()
+      .map(|bit| match bits.len().checked_sub(bit as usize + 1) {
+        Some(i) => bits.as_bytes()[i] as char,
+        None => '0',
+      })
+      .collect()
+  }
+}
+
+impl std::fmt::Display for BitSlice {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    if self.msb == self.lsb {
+      write!(f, "[{}]", self.msb)
+    } else {
+      write!(f, "[{}:{}]", self.msb, self.lsb)
+    }
+  }
+}
+
+/// Return true if `text` matches the wildcard `pattern` as a whole.
+fn glob(pattern: &[char], text: &str) -> bool {
+  let text: Vec<char> = text.chars().collect();
+  let (mut p, mut t) = (0, 0);
+  // position of the latest `*` and of the text it started to match
+  let mut star: Option<(usize, usize)> = None;
+  while t < text.len() {
+    match pattern.get(p) {
+      Some('?') => (p, t) = (p + 1, t + 1),
+      Some('*') => {
+        star = Some((p, t));
+        p += 1;
+      }
+      Some(c) if *c == text[t] => (p, t) = (p + 1, t + 1),
+      // let the latest `*` match one more character
+      _ => match star {
+        Some((star_p, star_t)) => {
+          star = Some((star_p, star_t + 1));
+          (p, t) = (star_p + 1, star_t + 1);
+        }
+        None => return false,
+      },
+    }
+  }
+  pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[test]
+fn match_signal_patterns() {
+  let path = |path: &str| -> Vec<String> { path.split('.').map(str::to_string).collect() };
+
+  let bare = SignalPattern::new("valid");
+  assert!(bare.matches(&path("top.core"), "valid"));
+  assert!(!bare.matches(&path("top.core"), "ready"));
+
+  let full = SignalPattern::new("top.core.valid");
+  assert!(full.matches(&path("top.core"), "valid"));
+  assert!(!full.matches(&path("top"), "valid"));
+  // a full path tells apart signals sharing a name in different modules
+  let lsu = SignalPattern::new("TOP.dut.lsu.valid");
+  assert!(lsu.matches(&path("TOP.dut.lsu"), "valid"));
+  assert!(!lsu.matches(&path("TOP.dut.alu"), "valid"));
+  assert!(!lsu.matches(&path("top.dut.lsu"), "valid"));
+
+  let wildcard = SignalPattern::new("top.core*.*.valid");
+  assert!(wildcard.matches(&path("top.core0.lsu"), "valid"));
+  assert!(wildcard.matches(&path("top.core.alu"), "valid"));
+  assert!(!wildcard.matches(&path("top.core0.lsu.sub"), "valid"));
+  assert!(!wildcard.matches(&path("top.gpu.lsu"), "valid"));
+
+  let single = SignalPattern::new("data_?");
+  assert!(single.matches(&path("top"), "data_a"));
+  assert!(!single.matches(&path("top"), "data_ab"));
+  assert!(SignalPattern::new("*_valid").matches(&path("top"), "req_a_valid"));
+
+  assert!(full.is_full_path());
+  assert!(SignalPattern::new("top.instr[6:0]").is_full_path());
+  assert!(!bare.is_full_path());
+  assert!(!wildcard.is_full_path());
+}
+
+#[test]
+fn select_bit_slices() {
+  let path = |path: &str| -> Vec<String> { path.split('.').map(str::to_string).collect() };
+
+  let opcode = SignalPattern::new("dut.decode.instr[6:0]");
+  let slice = BitSlice { msb: 6, lsb: 0 };
+  assert_eq!(
+    opcode.select(&path("dut.decode"), "instr"),
+    Some(Some(slice))
+  );
+  assert_eq!(opcode.select(&path("dut.fetch"), "instr"), None);
+  // an array element keeps its name
+  let lane = SignalPattern::new("lane[3]");
+  assert_eq!(lane.select(&path("top"), "lane[3]"), Some(None));
+  assert_eq!(
+    lane.select(&path("top"), "lane"),
+    Some(Some(BitSlice { msb: 3, lsb: 3 }))
+  );
+
+  assert_eq!(slice.extract("1111000010100101"), "0100101");
+  assert_eq!(BitSlice { msb: 3, lsb: 2 }.extract("10"), "00");
+  assert_eq!(slice.width(), 7);
+  assert_eq!(slice.to_string(), "[6:0]");
+  assert!(BitSlice::split("instr[0:6]").is_none());
+  assert!(BitSlice::split("instr[a:0]").is_none());
+  assert!(BitSlice::split("[3]").is_none());
+}
+
+#[test]
+fn measure_edit_distance() {
+  assert_eq!(edit_distance("valid", "valid"), 0);
+  assert_eq!(edit_distance("vaild", "valid"), 2);
+  assert_eq!(edit_distance("top.core0.redy", "top.core0.ready"), 1);
+  assert_eq!(edit_distance("", "abc"), 3);
+  assert_eq!(edit_distance("kitten", "sitting"), 3);
+}
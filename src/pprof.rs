@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::io::Write;
 
 // ProtoBuf struct defined in target/<debug/release>/build/<name>/out
 include!(concat!(env!("OUT_DIR"), "/pprof.profiles.rs"));
@@ -6,8 +7,10 @@ include!(concat!(env!("OUT_DIR"), "/pprof.profiles.rs"));
 /// The protobuf definition given by pprof specified that every string usage needs to be store in
 /// an array, and all the string fields should be an index to the field in the String Table.
 ///
-/// This struct wraps a HashMap for quick string searching and insertion.
-#[derive(Debug, Default)]
+/// This struct wraps a HashMap for quick string searching and insertion. IDs are given in the
+/// order the strings are first used, and the table is written in ID order, so the same
+/// conversion always gives the same table whatever the order the HashMap keeps.
+#[derive(Debug, Clone, Default)]
 pub struct StringTable {
   data: HashMap<String, i64>,
   next: i64,
@@ -36,17 +39,674 @@ impl StringTable {
       .to_owned()
   }
 
+  /// Return the id of the given string, without allocating it if it doesn't exist.
+  pub fn get(&self, q: &str) -> Option<i64> {
+    self.data.get(q).copied()
+  }
+
+  /// Return true if the string is already interned.
+  pub fn contains(&self, q: &str) -> bool {
+    self.data.contains_key(q)
+  }
+
+  /// Return the number of strings interned, the blank first one included.
+  pub fn len(&self) -> usize {
+    self.data.len()
+  }
+
+  /// Return true if no string is interned. Only a `Default` table is empty: [`StringTable::new`]
+  /// interns the blank first string, so a new table with nothing else in it has a length of 1.
+  pub fn is_empty(&self) -> bool {
+    self.data.is_empty()
+  }
+
+  /// Return the sample or period type of the given type and unit, registering both strings.
+  pub fn value_type(&mut self, r#type: &str, unit: &str) -> ValueType {
+    ValueType {
+      r#type: self.id(r#type),
+      unit: self.id(unit),
+    }
+  }
+
   /// Convert the StringTable struct to a list of strings.
   pub fn to_string_table(&self) -> Vec<String> {
     let mut cache = self.data.iter().collect::<Vec<_>>();
     // sort by index
-    cache.sort_by(|(_, prev), (_, next)| prev.cmp(next));
+    cache.sort_by_key(|(_, index)| **index);
     // waive index
     cache.into_iter().map(|(str, _)| str.to_owned()).collect()
   }
+
+  /// Like [`StringTable::to_string_table`], but move the strings out instead of cloning them.
+  pub fn into_string_table(self) -> Vec<String> {
+    let mut cache = self.data.into_iter().collect::<Vec<_>>();
+    cache.sort_unstable_by_key(|(_, id)| *id);
+    cache.into_iter().map(|(str, _)| str).collect()
+  }
+}
+
+/// Incrementally build a [`Profile`], interning strings, functions and locations on the fly.
+///
+/// Every distinct frame name gets one `Function` and one `Location` sharing the same ID, so
+/// samples referring to the same name are grouped together by pprof.
+///
+/// Both tables can be turned off to get a leaner profile:
+///
+/// * Without functions, locations carry no `Line` and pprof can only show them as addresses, so
+///   frames lose their names in the graph, flame graph and `-top` views.
+/// * Without locations, samples have no stack at all and can only be inspected through their
+///   labels, e.g. with `-tags` or `-tagfocus`. This implies no functions.
+#[derive(Debug, Clone)]
+pub struct ProfileBuilder {
+  pub str_tbl: StringTable,
+  pub profile: Profile,
+  /// Populate the function table
+  pub with_functions: bool,
+  /// Populate the location table
+  pub with_locations: bool,
+  /// Number of distinct value strings [`ProfileBuilder::value`] lets in, the others are labeled
+  /// [`OTHER_VALUE`]
+  pub value_cap: Option<usize>,
+  /// Number of distinct value strings let in so far
+  pub values: usize,
+  /// Number of values past the cap labeled [`OTHER_VALUE`] instead
+  pub capped_values: u64,
+  locations: HashMap<String, u64>,
+}
+
+/// The label value standing for every value past the cap of [`ProfileBuilder::value_cap`].
+pub const OTHER_VALUE: &str = "<other>";
+
+impl ProfileBuilder {
+  /// Return a builder holding an empty profile.
+  pub fn new() -> Self {
+    Self {
+      str_tbl: StringTable::new(),
+      profile: Profile::default(),
+      with_functions: true,
+      with_locations: true,
+      value_cap: None,
+      values: 0,
+      capped_values: 0,
+      locations: HashMap::new(),
+    }
+  }
+
+  /// Return the value to label a sample with: `value` itself if it is already interned or the
+  /// cap of distinct values isn't reached yet, [`OTHER_VALUE`] otherwise. Wide buses changing to
+  /// millions of distinct values would otherwise get as many strings in the table. An admitted
+  /// value is interned, so it is known again the next time.
+  pub fn value<'v>(&mut self, value: &'v str) -> &'v str {
+    let Some(cap) = self.value_cap else {
+      return value;
+    };
+    if self.str_tbl.contains(value) {
+      return value;
+    }
+    if self.values < cap {
+      self.values += 1;
+      self.str_tbl.id(value);
+      return value;
+    }
+    self.capped_values += 1;
+    OTHER_VALUE
+  }
+
+  /// Return the location IDs of a stack whose frames are given leaf first. Return an empty stack
+  /// if the location table is disabled.
+  pub fn stack(&mut self, frames: &[&str]) -> Vec<u64> {
+    if !self.with_locations {
+      return Vec::new();
+    }
+    frames.iter().map(|frame| self.location(frame)).collect()
+  }
+
+  /// Return the location ID of the frame named `name`. The function and location will be
+  /// allocated if the frame doesn't exists yet.
+  pub fn location(&mut self, name: &str) -> u64 {
+    if let Some(id) = self.locations.get(name) {
+      return *id;
+    }
+
+    // ID 0 is reserved by pprof
+    let id = self.locations.len() as u64 + 1;
+    let line = if self.with_functions {
+      let name_id = self.str_tbl.id(name);
+      self.profile.function.push(Function {
+        id,
+        name: name_id,
+        system_name: name_id,
+        filename: 0,
+        start_line: 0,
+      });
+      vec![Line {
+        function_id: id,
+        line: 0,
+      }]
+    } else {
+      Vec::new()
+    };
+    self.profile.location.push(Location {
+      id,
+      mapping_id: 0,
+      // without functions the address is the only thing telling locations apart
+      address: id,
+      line,
+      is_folded: false,
+    });
+    self.locations.insert(name.to_string(), id);
+    id
+  }
+
+  /// Return a string label.
+  pub fn label(&mut self, key: &str, value: &str) -> Label {
+    Label {
+      key: self.str_tbl.id(key),
+      str: self.str_tbl.id(value),
+      num: 0,
+      num_unit: 0,
+    }
+  }
+
+  /// Return a numeric label.
+  pub fn num_label(&mut self, key: &str, num: i64, unit: &str) -> Label {
+    Label {
+      key: self.str_tbl.id(key),
+      str: 0,
+      num,
+      num_unit: self.str_tbl.id(unit),
+    }
+  }
+
+  /// Append the samples and comments of a profile built by another builder, interning its strings
+  /// and frames again. Frames are matched by function name, so `profile` needs its function
+  /// table. The sample types, period and start time are taken from the first profile appended,
+  /// and the durations add up like the ones of consecutive shards of a simulation.
+  pub fn append(&mut self, profile: &Profile) {
+    let strings: Vec<i64> = profile
+      .string_table
+      .iter()
+      .map(|s| self.str_tbl.id(s))
+      .collect();
+    let value_type = |t: &ValueType| ValueType {
+      r#type: strings[t.r#type as usize],
+      unit: strings[t.unit as usize],
+    };
+    if self.profile.sample_type.is_empty() {
+      self.profile.sample_type = profile.sample_type.iter().map(value_type).collect();
+      self.profile.period_type = profile.period_type.as_ref().map(value_type);
+      self.profile.period = profile.period;
+      self.profile.time_nanos = profile.time_nanos;
+      self.profile.default_sample_type = strings[profile.default_sample_type as usize];
+    }
+    self.profile.duration_nanos += profile.duration_nanos;
+
+    let names: HashMap<u64, &str> = profile
+      .function
+      .iter()
+      .map(|f| (f.id, profile.string_table[f.name as usize].as_str()))
+      .collect();
+    let mut frames = HashMap::new();
+    if self.with_locations {
+      for location in &profile.location {
+        if let Some(name) = location
+          .line
+          .first()
+          .and_then(|l| names.get(&l.function_id))
+        {
+          frames.insert(location.id, self.location(name));
+        }
+      }
+    }
+
+    for sample in &profile.sample {
+      let location_id = sample
+        .location_id
+        .iter()
+        .filter_map(|id| frames.get(id).copied())
+        .collect();
+      let label = sample
+        .label
+        .iter()
+        .map(|label| Label {
+          key: strings[label.key as usize],
+          str: strings[label.str as usize],
+          num: label.num,
+          num_unit: strings[label.num_unit as usize],
+        })
+        .collect();
+      self.profile.sample.push(Sample {
+        location_id,
+        value: sample.value.clone(),
+        label,
+      });
+    }
+    self
+      .profile
+      .comment
+      .extend(profile.comment.iter().map(|id| strings[*id as usize]));
+  }
+
+  /// Attach the string table and return the final profile.
+  pub fn finish(mut self) -> Profile {
+    self.profile.string_table = self.str_tbl.into_string_table();
+    self.profile
+  }
+}
+
+impl Default for ProfileBuilder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Append a comment to an already built profile.
+pub fn add_comment(profile: &mut Profile, comment: String) {
+  profile.comment.push(profile.string_table.len() as i64);
+  profile.string_table.push(comment);
+}
+
+/// Merge runs of adjacent samples carrying the same locations and the same set of labels into
+/// one sample, whose values are the element-wise sum of the merged samples.
+pub fn coalesce(samples: Vec<Sample>) -> Vec<Sample> {
+  let mut merged: Vec<Sample> = Vec::with_capacity(samples.len());
+  for sample in samples {
+    if !merged.last_mut().is_some_and(|last| merge(last, &sample)) {
+      merged.push(sample);
+    }
+  }
+  merged
+}
+
+/// Add the values of `sample` to `into` if both carry the same locations and the same set of
+/// labels, and return whether they did.
+pub fn merge(into: &mut Sample, sample: &Sample) -> bool {
+  let same = into.location_id == sample.location_id
+    && into.label.len() == sample.label.len()
+    && sample.label.iter().all(|label| into.label.contains(label));
+  if same {
+    into
+      .value
+      .iter_mut()
+      .zip(&sample.value)
+      .for_each(|(sum, value)| *sum += value);
+  }
+  same
+}
+
+/// Return a profile holding, per stack, the values of `new` minus the ones of `base`, like
+/// `pprof -diff_base` shows them. Labels are dropped, so the samples of a signal add up whatever
+/// value it held, and stacks with the same values in both profiles are left out. The duration
+/// and comments are the ones of `new`.
+pub fn diff(base: &Profile, new: &Profile) -> anyhow::Result<Profile> {
+  let types = |p: &Profile| -> Vec<String> {
+    p.sample_type
+      .iter()
+      .map(|t| p.string_table[t.r#type as usize].clone())
+      .collect()
+  };
+  anyhow::ensure!(
+    types(base) == types(new),
+    "can't diff profiles of different sample types: {} and {}",
+    types(base).join(", "),
+    types(new).join(", ")
+  );
+
+  let mut builder = ProfileBuilder::new();
+  builder.append(new);
+  let from_new = builder.profile.sample.len();
+  builder.append(base);
+  builder.profile.duration_nanos = new.duration_nanos;
+  builder.profile.comment.truncate(new.comment.len());
+
+  // stacks in the order they first appear
+  let mut stacks = Vec::new();
+  let mut deltas: HashMap<Vec<u64>, Vec<i64>> = HashMap::new();
+  for (i, sample) in std::mem::take(&mut builder.profile.sample)
+    .into_iter()
+    .enumerate()
+  {
+    let sign = if i < from_new { 1 } else { -1 };
+    let delta = deltas.entry(sample.location_id.clone()).or_insert_with(|| {
+      stacks.push(sample.location_id.clone());
+      vec![0; sample.value.len()]
+    });
+    for (delta, value) in delta.iter_mut().zip(&sample.value) {
+      *delta += sign * value;
+    }
+  }
+  builder.profile.sample = stacks
+    .into_iter()
+    .filter_map(|location_id| {
+      let value = deltas.remove(&location_id)?;
+      value.iter().any(|v| *v != 0).then_some(Sample {
+        location_id,
+        value,
+        label: Vec::new(),
+      })
+    })
+    .collect();
+  Ok(builder.finish())
+}
+
+/// Encode the profile into `out` one entry at a time. The bytes are the same as the ones of
+/// [`prost::Message::encode`], but the whole encoding never lives in memory at once.
+pub fn encode_to(profile: &Profile, mut out: impl Write) -> std::io::Result<()> {
+  use prost::encoding::{message, string};
+  use prost::Message;
+
+  // encode every repeated field entry by entry, in field order like prost does
+  fn entries<M: Message>(
+    tag: u32,
+    entries: &[M],
+    buf: &mut Vec<u8>,
+    out: &mut impl Write,
+  ) -> std::io::Result<()> {
+    for entry in entries {
+      message::encode(tag, entry, buf);
+      out.write_all(buf)?;
+      buf.clear();
+    }
+    Ok(())
+  }
+
+  let mut buf = Vec::new();
+  entries(1, &profile.sample_type, &mut buf, &mut out)?;
+  entries(2, &profile.sample, &mut buf, &mut out)?;
+  entries(3, &profile.mapping, &mut buf, &mut out)?;
+  entries(4, &profile.location, &mut buf, &mut out)?;
+  entries(5, &profile.function, &mut buf, &mut out)?;
+  for s in &profile.string_table {
+    string::encode(6, s, &mut buf);
+    out.write_all(&buf)?;
+    buf.clear();
+  }
+
+  // the remaining fields are small, and all come after the string table
+  let tail = Profile {
+    drop_frames: profile.drop_frames,
+    keep_frames: profile.keep_frames,
+    time_nanos: profile.time_nanos,
+    duration_nanos: profile.duration_nanos,
+    period_type: profile.period_type.clone(),
+    period: profile.period,
+    comment: profile.comment.clone(),
+    default_sample_type: profile.default_sample_type,
+    ..Default::default()
+  };
+  out.write_all(&tail.encode_to_vec())
+}
+
+/// Render the profile in protobuf text format, with every string table index replaced by the
+/// string it refers to. The string table itself is left out.
+pub fn to_text(profile: &Profile) -> String {
+  use std::fmt::Write;
+
+  let resolve = |i: i64| -> String {
+    match usize::try_from(i)
+      .ok()
+      .and_then(|i| profile.string_table.get(i))
+    {
+      Some(s) => format!("{s:?}"),
+      None => format!("<invalid string index {i}>"),
+    }
+  };
+  let mut out = String::new();
+  // writing to a String never fails
+  let mut line = |indent: usize, text: String| {
+    writeln!(out, "{:indent$}{text}", "", indent = indent * 2).unwrap();
+  };
+
+  let value_type = |line: &mut dyn FnMut(usize, String), field: &str, vt: &ValueType| {
+    line(0, format!("{field} {{"));
+    line(1, format!("type: {}", resolve(vt.r#type)));
+    line(1, format!("unit: {}", resolve(vt.unit)));
+    line(0, "}".to_string());
+  };
+  for vt in &profile.sample_type {
+    value_type(&mut line, "sample_type", vt);
+  }
+  for sample in &profile.sample {
+    line(0, "sample {".to_string());
+    for id in &sample.location_id {
+      line(1, format!("location_id: {id}"));
+    }
+    for value in &sample.value {
+      line(1, format!("value: {value}"));
+    }
+    for label in &sample.label {
+      line(1, "label {".to_string());
+      line(2, format!("key: {}", resolve(label.key)));
+      if label.str != 0 {
+        line(2, format!("str: {}", resolve(label.str)));
+      } else {
+        line(2, format!("num: {}", label.num));
+        if label.num_unit != 0 {
+          line(2, format!("num_unit: {}", resolve(label.num_unit)));
+        }
+      }
+      line(1, "}".to_string());
+    }
+    line(0, "}".to_string());
+  }
+  for mapping in &profile.mapping {
+    line(0, "mapping {".to_string());
+    line(1, format!("id: {}", mapping.id));
+    line(1, format!("memory_start: {}", mapping.memory_start));
+    line(1, format!("memory_limit: {}", mapping.memory_limit));
+    line(1, format!("filename: {}", resolve(mapping.filename)));
+    line(0, "}".to_string());
+  }
+  for location in &profile.location {
+    line(0, "location {".to_string());
+    line(1, format!("id: {}", location.id));
+    if location.mapping_id != 0 {
+      line(1, format!("mapping_id: {}", location.mapping_id));
+    }
+    line(1, format!("address: {}", location.address));
+    for l in &location.line {
+      line(1, "line {".to_string());
+      line(2, format!("function_id: {}", l.function_id));
+      line(2, format!("line: {}", l.line));
+      line(1, "}".to_string());
+    }
+    line(0, "}".to_string());
+  }
+  for function in &profile.function {
+    line(0, "function {".to_string());
+    line(1, format!("id: {}", function.id));
+    line(1, format!("name: {}", resolve(function.name)));
+    line(1, format!("system_name: {}", resolve(function.system_name)));
+    if function.filename != 0 {
+      line(1, format!("filename: {}", resolve(function.filename)));
+    }
+    line(0, "}".to_string());
+  }
+  line(0, format!("time_nanos: {}", profile.time_nanos));
+  line(0, format!("duration_nanos: {}", profile.duration_nanos));
+  if let Some(vt) = &profile.period_type {
+    value_type(&mut line, "period_type", vt);
+  }
+  line(0, format!("period: {}", profile.period));
+  for comment in &profile.comment {
+    line(0, format!("comment: {}", resolve(*comment)));
+  }
+  out
+}
+
+#[test]
+fn move_strings_out_of_the_table() {
+  let mut str_tbl = StringTable::new();
+  for s in ["top.valid", "1", "top.data", "1", "0"] {
+    str_tbl.id(s);
+  }
+  let borrowed = str_tbl.to_string_table();
+  assert_eq!(borrowed, ["", "top.valid", "1", "top.data", "0"]);
+  assert_eq!(str_tbl.into_string_table(), borrowed);
+}
+
+#[test]
+fn builder_interns_locations() {
+  let mut builder = ProfileBuilder::new();
+  let valid = builder.location("top.valid");
+  let ready = builder.location("top.ready");
+  assert_eq!(builder.location("top.valid"), valid);
+  assert_ne!(valid, ready);
+
+  let label = builder.label("top.valid", "1");
+  let p = builder.finish();
+  assert_eq!(p.function.len(), 2);
+  assert_eq!(p.location.len(), 2);
+  assert_eq!(p.string_table[0], "");
+  assert_eq!(p.string_table[p.function[0].name as usize], "top.valid");
+  assert_eq!(p.string_table[label.str as usize], "1");
+}
+
+#[test]
+fn append_profiles_of_shards() {
+  // each shard interns the same names in its own order
+  let shard = |names: &[&str], duration_nanos| {
+    let mut builder = ProfileBuilder::new();
+    builder.profile.sample_type = vec![ValueType {
+      r#type: builder.str_tbl.id("changes"),
+      unit: builder.str_tbl.id("count"),
+    }];
+    builder.profile.duration_nanos = duration_nanos;
+    for name in names {
+      let location_id = builder.stack(&[*name]);
+      let label = vec![builder.label(name, "1")];
+      builder.profile.sample.push(Sample {
+        location_id,
+        value: vec![1],
+        label,
+      });
+    }
+    builder.finish()
+  };
+
+  let mut builder = ProfileBuilder::new();
+  builder.append(&shard(&["top.valid", "top.ready"], 10));
+  builder.append(&shard(&["top.ready", "top.data"], 5));
+  let p = builder.finish();
+  assert_eq!(p.duration_nanos, 15);
+  assert_eq!(p.location.len(), 3);
+  assert_eq!(p.string_table[p.sample_type[0].r#type as usize], "changes");
+  let frames: Vec<_> = p
+    .sample
+    .iter()
+    .map(|sample| {
+      let function = &p.function[sample.location_id[0] as usize - 1];
+      assert_eq!(sample.label[0].key, function.name);
+      p.string_table[function.name as usize].as_str()
+    })
+    .collect();
+  assert_eq!(frames, ["top.valid", "top.ready", "top.ready", "top.data"]);
+}
+
+#[test]
+fn diff_profiles_per_stack() {
+  let run = |changes: &[(&str, &str, i64)]| {
+    let mut builder = ProfileBuilder::new();
+    builder.profile.sample_type = vec![ValueType {
+      r#type: builder.str_tbl.id("changes"),
+      unit: builder.str_tbl.id("count"),
+    }];
+    for (name, value, n) in changes {
+      let location_id = builder.stack(&[*name]);
+      let label = vec![builder.label(name, value)];
+      builder.profile.sample.push(Sample {
+        location_id,
+        value: vec![*n],
+        label,
+      });
+    }
+    builder.finish()
+  };
+  let base = run(&[
+    ("top.valid", "0", 3),
+    ("top.valid", "1", 3),
+    ("top.ready", "1", 2),
+  ]);
+  let new = run(&[
+    ("top.ready", "1", 2),
+    ("top.valid", "1", 4),
+    ("top.data", "1", 1),
+  ]);
+
+  let p = diff(&base, &new).unwrap();
+  let deltas: Vec<_> = p
+    .sample
+    .iter()
+    .map(|sample| {
+      let function = &p.function[sample.location_id[0] as usize - 1];
+      (
+        p.string_table[function.name as usize].as_str(),
+        sample.value[0],
+      )
+    })
+    .collect();
+  // top.ready is as active in both runs
+  assert_eq!(deltas, [("top.valid", -2), ("top.data", 1)]);
+
+  let mut other = new.clone();
+  other.sample_type[0].r#type = other.string_table.len() as i64;
+  other.string_table.push("time".to_string());
+  assert!(diff(&base, &other).is_err());
+}
+
+#[test]
+fn builder_without_tables() {
+  let mut builder = ProfileBuilder::new();
+  builder.with_functions = false;
+  assert_eq!(builder.stack(&["top.valid=1", "top.valid"]), [1, 2]);
+  let p = builder.finish();
+  assert!(p.function.is_empty());
+  assert_eq!(p.location.len(), 2);
+  assert!(p.location.iter().all(|loc| loc.line.is_empty()));
+
+  let mut builder = ProfileBuilder::new();
+  builder.with_locations = false;
+  assert!(builder.stack(&["top.valid"]).is_empty());
+  let p = builder.finish();
+  assert!(p.function.is_empty() && p.location.is_empty());
+}
+
+#[test]
+fn coalesce_preserves_weight() {
+  let mut builder = ProfileBuilder::new();
+  let location = builder.location("top.valid");
+  let high = builder.label("top.valid", "1");
+  let low = builder.label("top.valid", "0");
+  let sample = |value: i64, label: &Label| Sample {
+    location_id: vec![location],
+    value: vec![value],
+    label: vec![label.clone()],
+  };
+  let samples = vec![
+    sample(1, &high),
+    sample(2, &high),
+    sample(3, &low),
+    sample(4, &high),
+    sample(5, &high),
+  ];
+  let total: i64 = samples.iter().map(|s| s.value[0]).sum();
+
+  let merged = coalesce(samples);
+  assert_eq!(
+    merged.iter().map(|s| s.value[0]).collect::<Vec<_>>(),
+    [3, 3, 9]
+  );
+  assert_eq!(merged.iter().map(|s| s.value[0]).sum::<i64>(), total);
 }
 
 #[test]
+// the example profile is kept as it was first written
+#[allow(
+  clippy::inconsistent_digit_grouping,
+  clippy::field_reassign_with_default,
+  clippy::reserve_after_initialization,
+  clippy::useless_vec
+)]
 fn sample_profile() {
   use flate2::write::GzEncoder;
   use flate2::Compression;
@@ -190,7 +850,7 @@ fn sample_profile() {
   p.location = loc;
   p.function = instructions;
 
-  p.string_table = str_tbl.to_string_table();
+  p.string_table = str_tbl.into_string_table();
 
   let mut buf = Vec::new();
   buf.reserve(p.encoded_len());
@@ -200,3 +860,50 @@ fn sample_profile() {
   encoder.write_all(&buf).unwrap();
   std::fs::write("./sample_profile.pb.gz", encoder.finish().unwrap()).unwrap();
 }
+
+#[test]
+fn text_format_resolves_strings() {
+  let mut builder = ProfileBuilder::new();
+  let location_id = builder.stack(&["top.valid"]);
+  let label = vec![builder.label("top.valid", "1")];
+  builder.profile.sample.push(Sample {
+    location_id,
+    value: vec![1],
+    label,
+  });
+  let mut profile = builder.finish();
+  add_comment(&mut profile, "hello".to_string());
+
+  let text = to_text(&profile);
+  assert!(text.contains("  label {\n    key: \"top.valid\"\n    str: \"1\"\n  }\n"));
+  assert!(text.contains("  name: \"top.valid\"\n"));
+  assert!(text.contains("comment: \"hello\"\n"));
+}
+
+#[test]
+fn stream_encoding_matches_prost() {
+  use prost::Message;
+
+  let mut builder = ProfileBuilder::new();
+  builder.profile.sample_type = vec![ValueType {
+    r#type: builder.str_tbl.id("changes"),
+    unit: builder.str_tbl.id("count"),
+  }];
+  builder.profile.period_type = builder.profile.sample_type.first().cloned();
+  builder.profile.duration_nanos = 100;
+  for name in ["top.valid", "top.ready", "top.valid"] {
+    let location_id = builder.stack(&[name]);
+    let label = vec![builder.label(name, "1")];
+    builder.profile.sample.push(Sample {
+      location_id,
+      value: vec![1],
+      label,
+    });
+  }
+  let mut profile = builder.finish();
+  add_comment(&mut profile, "hello".to_string());
+
+  let mut out = Vec::new();
+  encode_to(&profile, &mut out).unwrap();
+  assert_eq!(out, profile.encode_to_vec());
+}
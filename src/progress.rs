@@ -0,0 +1,100 @@
+//! Progress bar of the value change read, drawn on stderr.
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Width of the bar itself, between the brackets
+const BAR_WIDTH: usize = 30;
+/// Redrawing more often than this only flickers
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Tells how far the read is through the waveform time range, and when it should be done.
+pub struct ProgressBar {
+  start: u64,
+  end: u64,
+  started: Instant,
+  last_draw: Option<Instant>,
+}
+
+impl ProgressBar {
+  /// Create a bar going from `start` to `end`, in raw FST ticks.
+  pub fn new(start: u64, end: u64) -> Self {
+    Self {
+      start,
+      end,
+      started: Instant::now(),
+      last_draw: None,
+    }
+  }
+
+  /// Redraw the bar with the read at time `t`, unless it was drawn a moment ago.
+  pub fn update(&mut self, t: u64) {
+    if self
+      .last_draw
+      .is_some_and(|last| last.elapsed() < REDRAW_INTERVAL)
+    {
+      return;
+    }
+    self.last_draw = Some(Instant::now());
+    let line = self.render(t, self.started.elapsed());
+    // the bar is only a hint, failing to draw it isn't worth stopping the read
+    let _ = write!(std::io::stderr(), "\r{line}");
+  }
+
+  /// Erase the bar, so the logs written next start on a clean line.
+  pub fn clear(&self) {
+    if self.last_draw.is_some() {
+      let _ = write!(std::io::stderr(), "\r\x1b[2K");
+    }
+  }
+
+  /// Return the line drawn at time `t`, `elapsed` after the read started.
+  fn render(&self, t: u64, elapsed: Duration) -> String {
+    let fraction = match self.end.checked_sub(self.start) {
+      Some(len) if len > 0 => (t.saturating_sub(self.start) as f64 / len as f64).min(1.0),
+      _ => 1.0,
+    };
+    let filled = (fraction * BAR_WIDTH as f64) as usize;
+    let bar = match filled {
+      BAR_WIDTH => "=".repeat(BAR_WIDTH),
+      _ => format!(
+        "{}>{}",
+        "=".repeat(filled),
+        " ".repeat(BAR_WIDTH - filled - 1)
+      ),
+    };
+    let eta = if fraction > 0.0 {
+      let left = elapsed.as_secs_f64() * (1.0 - fraction) / fraction;
+      let left = left as u64;
+      format!("{}m{:02}s", left / 60, left % 60)
+    } else {
+      "-".to_string()
+    };
+    format!(
+      "[{bar}] {:3}% at time {t}/{}, ETA {eta}",
+      (fraction * 100.0) as u32,
+      self.end
+    )
+  }
+}
+
+#[test]
+fn render_progress() {
+  let bar = ProgressBar::new(100, 300);
+  assert_eq!(
+    bar.render(100, Duration::ZERO),
+    format!("[>{}]   0% at time 100/300, ETA -", " ".repeat(29))
+  );
+  assert_eq!(
+    bar.render(250, Duration::from_secs(90)),
+    format!(
+      "[{}>{}]  75% at time 250/300, ETA 0m30s",
+      "=".repeat(22),
+      " ".repeat(7)
+    )
+  );
+  assert_eq!(
+    bar.render(300, Duration::from_secs(125)),
+    format!("[{}] 100% at time 300/300, ETA 0m00s", "=".repeat(30))
+  );
+}
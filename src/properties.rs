@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Extra display metadata of the signals, loaded from the `--properties` file.
+///
+/// The file is a JSON object keyed by signal, either by its full dot separated path or by its
+/// bare name. A full path entry wins over a bare name entry. Every field is optional:
+///
+/// ```json
+/// {
+///   "top.core.state": { "label": "core state", "group": "core", "radix": "hex" },
+///   "top.core.retired": { "unit": "instructions" },
+///   "top.lsu.addr": { "histogram": { "bins": 16 }, "radix": "hex" },
+///   "valid": { "group": "handshake" }
+/// }
+/// ```
+///
+/// * `label` replaces the signal path as frame name and label key.
+/// * `group` adds a frame above the signal, so signals of one group share a call tree root.
+/// * `radix` prints binary values as `bin`, `oct`, `dec` or `hex`. Values with unknown bits are
+///   kept as they are.
+/// * `unit` is the unit of the number a value is labeled with, instead of `bit` for buses. It also
+///   labels 1-bit signals with a number, and scaled real values with `{unit}/{scale}`.
+/// * `histogram` counts how many times the signal takes every value instead of sampling each
+///   change, see [`Histogram`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Properties {
+  pub(crate) signals: BTreeMap<String, SignalProperties>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SignalProperties {
+  pub label: Option<String>,
+  pub group: Option<String>,
+  pub radix: Option<Radix>,
+  pub unit: Option<String>,
+  pub histogram: Option<Histogram>,
+}
+
+/// Counting of the values of a signal, emitted in the profile as one sample per bin, labeled by
+/// the bin and weighted by the number of changes to a value in it.
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Histogram {
+  /// Number of equal bins the values of a bus are split into, rounded down to a power of 2. Every
+  /// value is a bin of its own without it
+  pub bins: Option<NonZeroU32>,
+}
+
+/// The bin of a histogram a value falls in. Bins of plain binary values are ordered by their
+/// first value, before every other value.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Bin {
+  /// First value of the bin
+  Number(u128),
+  /// Value with unknown bits or wider than 128 bits, labeled as it is
+  Other(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Radix {
+  Bin,
+  Oct,
+  Dec,
+  Hex,
+}
+
+impl Properties {
+  pub fn from_path(path: &str) -> anyhow::Result<Self> {
+    let raw = std::fs::read(path).with_context(|| format!("fail to read properties {path}"))?;
+    serde_json::from_slice(&raw).with_context(|| format!("fail to parse properties {path}"))
+  }
+
+  /// Return the properties of the signal with the given full path and bare name.
+  pub fn get(&self, full_name: &str, name: &str) -> Option<&SignalProperties> {
+    self
+      .signals
+      .get(full_name)
+      .or_else(|| self.signals.get(name))
+  }
+
+  /// Add the entries of `other` whose key has no entry here, so entries here win key by key.
+  pub fn fill(&mut self, other: Properties) {
+    for (key, properties) in other.signals {
+      self.signals.entry(key).or_insert(properties);
+    }
+  }
+}
+
+impl Histogram {
+  /// Return the bin of a value of a `width` bits wide signal. Values that aren't plain binary
+  /// numbers are bins of their own.
+  pub fn bin(&self, value: &str, width: u32) -> Bin {
+    match u128::from_str_radix(value, 2) {
+      Ok(n) if value.len() == width as usize && value.bytes().all(|b| b == b'0' || b == b'1') => {
+        Bin::Number(n & !self.low_mask(width))
+      }
+      _ => Bin::Other(value.to_string()),
+    }
+  }
+
+  /// Return the label of a bin of a `width` bits wide signal, like `0x40..0x7f`, printed in
+  /// `radix` if any.
+  pub fn label(&self, bin: &Bin, width: u32, radix: Option<Radix>) -> String {
+    let print = |n: u128| {
+      let bits = format!("{n:0width$b}", width = width as usize);
+      radix.map_or_else(|| bits.clone(), |radix| radix.format(&bits))
+    };
+    match *bin {
+      Bin::Number(first) => match self.low_mask(width) {
+        0 => print(first),
+        mask => format!("{}..{}", print(first), print(first | mask)),
+      },
+      Bin::Other(ref value) => value.clone(),
+    }
+  }
+
+  /// Return the mask of the low bits a bin of a `width` bits wide signal spans, the bin of a
+  /// value being its most significant bits.
+  fn low_mask(&self, width: u32) -> u128 {
+    let kept = self.bins.map_or(width, |bins| bins.ilog2());
+    match width.saturating_sub(kept) {
+      0 => 0,
+      low => u128::MAX >> (128 - low),
+    }
+  }
+}
+
+impl Radix {
+  /// Print a binary value in this radix, or return it unchanged if it isn't a plain binary number
+  /// fitting in 128 bits.
+  pub fn format(self, value: &str) -> String {
+    let Ok(n) = u128::from_str_radix(value, 2) else {
+      return value.to_string();
+    };
+    match self {
+      Radix::Bin => value.to_string(),
+      Radix::Oct => format!("0o{n:o}"),
+      Radix::Dec => n.to_string(),
+      Radix::Hex => format!("0x{n:x}"),
+    }
+  }
+}
+
+#[test]
+fn lookup_and_format_properties() {
+  let properties: Properties = serde_json::from_str(
+    r#"{
+      "top.core.state": { "label": "core state", "radix": "hex" },
+      "state": { "group": "fsm" }
+    }"#,
+  )
+  .unwrap();
+  let core = properties.get("top.core.state", "state").unwrap();
+  assert_eq!(core.label.as_deref(), Some("core state"));
+  assert!(core.group.is_none());
+  let other = properties.get("top.lsu.state", "state").unwrap();
+  assert_eq!(other.group.as_deref(), Some("fsm"));
+  assert!(properties.get("top.valid", "valid").is_none());
+
+  let zero = serde_json::from_str::<Properties>(r#"{"addr": {"histogram": {"bins": 0}}}"#);
+  assert!(zero.is_err());
+
+  assert_eq!(Radix::Hex.format("11111010"), "0xfa");
+  assert_eq!(Radix::Dec.format("1010"), "10");
+  assert_eq!(Radix::Oct.format("1x10"), "1x10");
+}
+
+#[test]
+fn bin_histogram_values() {
+  let label = |histogram: Histogram, value: &str, radix| {
+    let width = value.len() as u32;
+    histogram.label(&histogram.bin(value, width), width, radix)
+  };
+  let each = Histogram::default();
+  assert_eq!(label(each, "0101", None), "0101");
+  assert_eq!(label(each, "0101", Some(Radix::Hex)), "0x5");
+
+  let binned = Histogram {
+    bins: NonZeroU32::new(5),
+  };
+  assert_eq!(label(binned, "01101", Some(Radix::Hex)), "0x8..0xf");
+  assert_eq!(label(binned, "11000", None), "11000..11111");
+  assert_eq!(label(binned, "1x000", None), "1x000");
+  assert_eq!(label(binned, "1", None), "1");
+  assert_eq!(binned.bin("01101", 5), Bin::Number(0b01000));
+  assert!(binned.bin("11111", 5) < binned.bin("1x000", 5));
+}
@@ -0,0 +1,67 @@
+//! Python bindings of the extraction, built with the `python` feature, so notebooks can analyze a
+//! waveform without running the command line tool and parsing its pprof output.
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::config::{Config, ConfigFormat};
+use crate::failure::{self, FailureKind};
+use crate::wave::{InputFormat, InputWave};
+
+/// Read the waveform at `fst_path`, an FST or a VCD file as told by its extension, and return the
+/// value changes and statistics of every signal selected by `config`, a dict following the schema
+/// of the config files.
+///
+/// The result is `{"signals": {path: {"width": int, "times": [int], "values": [str]}}, "report":
+/// {...}, "aggregates": {path: {aggregation: int}}}`, the report being the statistics written by
+/// `--report` and the aggregates the `aggregations` of the config.
+#[pyfunction]
+fn extract(py: Python<'_>, fst_path: &str, config: &PyDict) -> PyResult<PyObject> {
+  let json = py.import("json")?;
+  let raw: String = json.call_method1("dumps", (config,))?.extract()?;
+  // the GIL is released for the read, other Python threads go on meanwhile
+  let extraction = py
+    .allow_threads(|| -> anyhow::Result<_> {
+      let config = Config::parse(raw.as_bytes(), ConfigFormat::Json)
+        .and_then(|config| config.validate().map(|_| config))
+        .map_err(|err| err.context(FailureKind::Config.with("invalid config")))?;
+      let mut reader = InputWave::open(fst_path, InputFormat::from_path(fst_path))?;
+      crate::fst_to_series(&mut reader, &config)
+    })
+    .map_err(|err| match failure::kind(&err) {
+      FailureKind::Config => PyValueError::new_err(format!("{err:#}")),
+      _ => PyRuntimeError::new_err(format!("{err:#}")),
+    })?;
+
+  let signals = PyDict::new(py);
+  for series in extraction.series {
+    let signal = PyDict::new(py);
+    signal.set_item("width", series.width)?;
+    signal.set_item("times", series.times)?;
+    signal.set_item("values", series.values)?;
+    signals.set_item(series.signal, signal)?;
+  }
+  let report = serde_json::to_string(&extraction.report)
+    .map_err(|err| PyRuntimeError::new_err(format!("fail to encode the report: {err}")))?;
+  let aggregates = PyDict::new(py);
+  for aggregate in extraction.aggregates {
+    let values = PyDict::new(py);
+    for (aggregation, value) in aggregate.values {
+      values.set_item(aggregation.as_str(), value)?;
+    }
+    aggregates.set_item(aggregate.signal, values)?;
+  }
+  let result = PyDict::new(py);
+  result.set_item("signals", signals)?;
+  result.set_item("report", json.call_method1("loads", (report,))?)?;
+  result.set_item("aggregates", aggregates)?;
+  Ok(result.into())
+}
+
+/// The `toyfst` Python module.
+#[pymodule]
+fn toyfst(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+  m.add_function(wrap_pyfunction!(extract, m)?)?;
+  Ok(())
+}
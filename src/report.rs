@@ -0,0 +1,358 @@
+//! Machine readable statistics of every selected signal, for CI jobs gating regressions.
+
+use std::collections::{BTreeMap, HashMap};
+
+use fst_native::FstSignalHandle;
+use serde::Serialize;
+
+use crate::fsm::{StateDecoder, StateMachine};
+use crate::properties::Properties;
+use crate::sink::OutputSink;
+use crate::wave::{SignalValue, WaveHeader};
+use crate::window::TimeWindow;
+use crate::SignalMetadata;
+
+/// Statistics of one signal within the time window.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SignalReport {
+  /// Full dot separated path of the signal
+  pub signal: String,
+  pub width: u32,
+  /// Number of value changes, the initial value included
+  pub changes: u64,
+  /// Number of those changes to a value with `x` or `z` bits
+  pub unknown_changes: u64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub first_change: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub last_change: Option<u64>,
+  /// Ticks spent at `1` and at `0`, only for 1-bit signals
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub time_high: Option<u64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub time_low: Option<u64>,
+  /// Smallest and largest values, only for real signals
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub min: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub max: Option<f64>,
+  /// Number of changes to every bin, only for the signals whose properties ask for a histogram
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub histogram: Option<BTreeMap<String, u64>>,
+}
+
+/// Occupancy and transitions of one state machine within the time window.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StateReport {
+  pub state_machine: String,
+  /// Ticks spent in every state
+  pub time: BTreeMap<String, u64>,
+  /// Number of transitions from every state to every other state
+  pub transitions: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+/// Statistics of every signal and state machine.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Report {
+  pub signals: Vec<SignalReport>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub state_machines: Vec<StateReport>,
+}
+
+/// Gathers a [`SignalReport`] of every signal and a [`StateReport`] of every state machine from
+/// the value changes it is fed.
+pub struct ReportSink<'a> {
+  metadata: SignalMetadata,
+  window: TimeWindow,
+  /// Bounds of the window, within the waveform time range
+  start: u64,
+  end: u64,
+  /// Positions in `metadata` of each signal, by handle index
+  index: HashMap<usize, Vec<usize>>,
+  /// Current level of every 1-bit signal and the time it took it
+  levels: Vec<Option<(bool, u64)>>,
+  reports: Vec<SignalReport>,
+  properties: &'a Properties,
+  machines: &'a [StateMachine],
+  decoder: StateDecoder<'a>,
+  /// Current state of every state machine and the time it entered it
+  states: Vec<Option<(String, u64)>>,
+  state_reports: Vec<StateReport>,
+}
+
+impl<'a> ReportSink<'a> {
+  pub fn new(
+    header: &WaveHeader,
+    metadata: SignalMetadata,
+    window: TimeWindow,
+    properties: &'a Properties,
+    machines: &'a [StateMachine],
+  ) -> Self {
+    let (start, end) = window.clamp(header.start_time, header.end_time);
+    let reports = (0..metadata.handle.len())
+      .map(|i| SignalReport {
+        signal: metadata.full_name(i),
+        width: metadata.widths[i],
+        time_high: (metadata.widths[i] == 1).then_some(0),
+        time_low: (metadata.widths[i] == 1).then_some(0),
+        histogram: properties
+          .get(&metadata.full_name(i), &metadata.names[i])
+          .and_then(|p| p.histogram)
+          .map(|_| BTreeMap::new()),
+        ..Default::default()
+      })
+      .collect();
+    Self {
+      index: metadata.positions(),
+      levels: vec![None; metadata.handle.len()],
+      metadata,
+      window,
+      start,
+      end,
+      reports,
+      properties,
+      machines,
+      decoder: StateDecoder::new(machines),
+      states: vec![None; machines.len()],
+      state_reports: machines
+        .iter()
+        .map(|machine| StateReport {
+          state_machine: machine.name.clone(),
+          ..Default::default()
+        })
+        .collect(),
+    }
+  }
+
+  /// Return the reports in declaration order.
+  pub fn finish(mut self) -> Report {
+    for i in 0..self.reports.len() {
+      self.settle(i, self.end);
+    }
+    for i in 0..self.machines.len() {
+      self.enter(i, None, self.end);
+    }
+    Report {
+      signals: self.reports,
+      state_machines: self.state_reports,
+    }
+  }
+
+  /// Record the i-th state machine leaving its state at `t` for `state`.
+  fn enter(&mut self, i: usize, state: Option<String>, t: u64) {
+    let report = &mut self.state_reports[i];
+    if let Some((from, since)) = &self.states[i] {
+      let held = t.min(self.end).saturating_sub((*since).max(self.start));
+      *report.time.entry(from.clone()).or_default() += held;
+      if let Some(to) = &state {
+        if self.window.contains(t) {
+          let row = report.transitions.entry(from.clone()).or_default();
+          *row.entry(to.clone()).or_default() += 1;
+        }
+      }
+    }
+    self.states[i] = state.map(|state| (state, t));
+  }
+
+  /// Add the time the i-th signal spent at its current level until `t`.
+  fn settle(&mut self, i: usize, t: u64) {
+    if let Some((high, since)) = self.levels[i].take() {
+      let held = t.min(self.end).saturating_sub(since.max(self.start));
+      let report = &mut self.reports[i];
+      let time = if high {
+        &mut report.time_high
+      } else {
+        &mut report.time_low
+      };
+      *time.get_or_insert(0) += held;
+    }
+  }
+}
+
+impl OutputSink for ReportSink<'_> {
+  fn handles(&self) -> Vec<FstSignalHandle> {
+    let mut handles = self.metadata.handle.clone();
+    for handle in self.decoder.handles() {
+      if !handles.contains(&handle) {
+        handles.push(handle);
+      }
+    }
+    handles
+  }
+
+  fn change(&mut self, t: u64, handle: FstSignalHandle, value: &SignalValue) {
+    if let SignalValue::String(v) = value {
+      for (machine, state) in self.decoder.update(handle, v) {
+        let i = self
+          .machines
+          .iter()
+          .position(|m| std::ptr::eq(m, machine))
+          .expect("the decoder only knows these state machines");
+        self.enter(i, Some(state), t);
+      }
+    }
+    let Some(aliases) = self.index.get(&handle.get_index()).map(Vec::len) else {
+      return;
+    };
+    for alias in 0..aliases {
+      let i = self.index[&handle.get_index()][alias];
+      let value = self.metadata.value_of(i, value).into_owned();
+      if self.metadata.widths[i] == 1 {
+        self.settle(i, t);
+        // unknown values are neither high nor low
+        self.levels[i] = match &value {
+          SignalValue::String(bit) if bit == "1" => Some((true, t)),
+          SignalValue::String(bit) if bit == "0" => Some((false, t)),
+          _ => None,
+        };
+      }
+      if !self.window.contains(t) {
+        continue;
+      }
+      let report = &mut self.reports[i];
+      report.changes += 1;
+      if matches!(&value, SignalValue::String(bits) if crate::xz::is_unknown(bits)) {
+        report.unknown_changes += 1;
+      }
+      report.first_change.get_or_insert(t);
+      report.last_change = Some(t);
+      if let Some(bins) = &mut report.histogram {
+        let properties = self.properties.get(&report.signal, &self.metadata.names[i]);
+        let histogram = properties.and_then(|p| p.histogram).unwrap_or_default();
+        let bits = match &value {
+          SignalValue::String(bits) => bits.clone(),
+          SignalValue::Real(r) => r.to_string(),
+        };
+        let bin = histogram.bin(&bits, report.width);
+        let bin = histogram.label(&bin, report.width, properties.and_then(|p| p.radix));
+        *bins.entry(bin).or_default() += 1;
+      }
+      if let SignalValue::Real(r) = value {
+        report.min = Some(report.min.map_or(r, |min| min.min(r)));
+        report.max = Some(report.max.map_or(r, |max| max.max(r)));
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixture::FixtureWave;
+  use crate::fsm::{self, StateRegisterConfig};
+  use crate::sink::{drive, Feedback};
+  use crate::wave::Waveform;
+
+  #[test]
+  fn report_signal_statistics() {
+    let mut wave = FixtureWave::new(0, 100);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    let data = wave.var("data", 4);
+    wave.var("idle", 1);
+    wave.upscope();
+    wave
+      .change(0, valid, "0")
+      .change(0, data, "0000")
+      .change(20, valid, "1")
+      .change(50, valid, "x")
+      .change(60, valid, "0")
+      .change(70, data, "0101")
+      .change(95, valid, "1");
+
+    let metadata = wave.collect(&["valid", "data", "idle"]);
+    let window = TimeWindow {
+      start: Some(10),
+      end: Some(90),
+      ..Default::default()
+    };
+    let properties: Properties =
+      serde_json::from_str(r#"{ "data": { "histogram": { "bins": 2 }, "radix": "hex" } }"#)
+        .unwrap();
+    let mut sink = ReportSink::new(&wave.header(), metadata, window, &properties, &[]);
+    drive(
+      &mut wave,
+      std::slice::from_mut(&mut sink),
+      Feedback::default(),
+    )
+    .unwrap();
+    let reports = sink.finish().signals;
+
+    assert_eq!(
+      reports[0],
+      SignalReport {
+        signal: "top.valid".to_string(),
+        width: 1,
+        changes: 3,
+        unknown_changes: 1,
+        first_change: Some(20),
+        last_change: Some(60),
+        time_high: Some(30),
+        time_low: Some(40),
+        ..Default::default()
+      }
+    );
+    assert_eq!(
+      serde_json::to_string(&reports[1]).unwrap(),
+      concat!(
+        r#"{"signal":"top.data","width":4,"changes":1,"unknown_changes":0,"#,
+        r#""first_change":70,"last_change":70,"histogram":{"0x0..0x7":1}}"#
+      )
+    );
+    assert_eq!(reports[2].changes, 0);
+    assert_eq!(reports[2].time_high, Some(0));
+  }
+
+  #[test]
+  fn report_state_machines() {
+    let mut wave = FixtureWave::new(0, 100);
+    wave.scope("top");
+    let state = wave.var("state", 2);
+    wave.upscope();
+    wave
+      .change(0, state, "00")
+      .change(20, state, "01")
+      .change(30, state, "00")
+      .change(50, state, "01")
+      .change(80, state, "10");
+
+    let registers = vec![StateRegisterConfig {
+      fsm: "top.state".to_string(),
+      states: [("00", "IDLE"), ("01", "BUSY"), ("10", "DONE")]
+        .map(|(value, state)| (value.to_string(), state.to_string()))
+        .into(),
+    }];
+    let machines = fsm::resolve(
+      &crate::SignalLookup::new(&mut wave).unwrap(),
+      &[],
+      &registers,
+    )
+    .unwrap();
+    let window = TimeWindow {
+      start: Some(10),
+      ..Default::default()
+    };
+    let properties = Properties::default();
+    let metadata = SignalMetadata::default();
+    let mut sink = ReportSink::new(&wave.header(), metadata, window, &properties, &machines);
+    drive(
+      &mut wave,
+      std::slice::from_mut(&mut sink),
+      Feedback::default(),
+    )
+    .unwrap();
+    let report = sink.finish();
+
+    assert!(report.signals.is_empty());
+    let time: Vec<_> = report.state_machines[0]
+      .time
+      .iter()
+      .map(|(state, ticks)| (state.as_str(), *ticks))
+      .collect();
+    assert_eq!(time, [("BUSY", 40), ("DONE", 20), ("IDLE", 30)]);
+    assert_eq!(
+      serde_json::to_string(&report.state_machines[0].transitions).unwrap(),
+      r#"{"BUSY":{"DONE":1,"IDLE":1},"IDLE":{"BUSY":2}}"#
+    );
+  }
+}
@@ -0,0 +1,745 @@
+//! Output sinks sharing a single read of the waveform.
+//!
+//! Each `--format` given on the command line becomes one sink, holding its own subset of the
+//! matched signals. The value changes are read once for the union of all the subsets and every
+//! change is dispatched to the sinks that asked for its signal.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use fst_native::FstSignalHandle;
+use tracing::info;
+
+use crate::aggregate::Aggregator;
+use crate::clock::ClockSink;
+use crate::convert::PprofSink;
+use crate::dump::DumpSink;
+use crate::folded::FoldedSink;
+use crate::instret::InstretSink;
+use crate::progress::ProgressBar;
+use crate::report::ReportSink;
+use crate::transitions::TransitionsSink;
+use crate::vcd::VcdSink;
+use crate::wave::{SignalValue, Waveform};
+
+/// A consumer of value changes.
+pub trait OutputSink {
+  /// Return the handles of all the signals this sink needs.
+  fn handles(&self) -> Vec<FstSignalHandle>;
+
+  /// Feed one value change of a signal returned by [`OutputSink::handles`], in file order.
+  fn change(&mut self, t: u64, handle: FstSignalHandle, value: &SignalValue);
+}
+
+/// How a read of the value changes tells it is still going, so a long read doesn't look stuck.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Feedback {
+  /// Log the current time, how far it is through the waveform and the number of changes read so
+  /// far at this interval
+  pub heartbeat: Option<Duration>,
+  /// Draw a progress bar with the time left on stderr
+  pub bar: bool,
+}
+
+/// Read the value changes once and dispatch each of them to every sink needing its signal.
+/// Return the number of changes read.
+///
+/// The changes of one time are dispatched by handle, like [`drive_parallel`] does, so the output
+/// doesn't depend on the number of jobs.
+pub fn drive<S: OutputSink>(
+  reader: &mut impl Waveform,
+  sinks: &mut [S],
+  feedback: Feedback,
+) -> anyhow::Result<u64> {
+  let header = reader.header();
+  let mut dispatcher = Dispatcher::new(sinks, feedback.heartbeat);
+  dispatcher.span = (header.start_time, header.end_time);
+  let mut bar = feedback
+    .bar
+    .then(|| ProgressBar::new(header.start_time, header.end_time));
+  let handles = dispatcher.handles.clone();
+  let mut dispatch = |(t, handle, value): Change| {
+    if let Some(bar) = bar.as_mut().filter(|_| dispatcher.changes % 4096 == 0) {
+      bar.update(t);
+    }
+    dispatcher.change(t, handle, &value)
+  };
+  let mut order = TimeOrder::default();
+  reader.for_each_change(&handles, |t, handle, value| {
+    order.push((t, handle, value), &mut dispatch)
+  })?;
+  order.settle(&mut dispatch);
+  if let Some(bar) = &bar {
+    bar.clear();
+  }
+  Ok(dispatcher.changes)
+}
+
+/// How [`drive_parallel`] shares the read of the value changes between its jobs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Split {
+  /// Each job reads every signal over its own ranges of time, cut at some of these times, so
+  /// every block of the file is decoded about once. The waveform must skip the blocks outside of
+  /// a range, and give the same changes inside of it whatever the range starts at: the FST blocks
+  /// give the initial values of the signals on their first time, so an FST file is cut with
+  /// [`crate::wave::fst_block_cuts`]
+  Time(Vec<u64>),
+  /// Each job reads its own chunk of the signals over the whole waveform, so every job decodes
+  /// every block. This still works for a waveform computing its changes from the earlier ones,
+  /// like derived signals or downsampling do
+  Signals,
+}
+
+/// Like [`drive`], but read the value changes on `jobs` threads, each with its own waveform
+/// returned by `open`, split as `split` says.
+///
+/// The threads go on decoding while the changes they already sent are dispatched. The changes
+/// are dispatched by time then by handle, like [`drive`] does, so the output doesn't depend on
+/// the thread scheduling nor on the number of jobs.
+pub fn drive_parallel<W: Waveform, S: OutputSink>(
+  open: impl Fn() -> anyhow::Result<W> + Sync,
+  sinks: &mut [S],
+  jobs: usize,
+  split: Split,
+  feedback: Feedback,
+) -> anyhow::Result<u64> {
+  let mut dispatcher = Dispatcher::new(sinks, feedback.heartbeat);
+  let mut bar = None;
+  match split {
+    Split::Time(cuts) => by_time(
+      open,
+      &mut dispatcher,
+      &cuts,
+      jobs.max(1),
+      feedback,
+      &mut bar,
+    )?,
+    Split::Signals => by_signals(open, &mut dispatcher, jobs.max(1), feedback, &mut bar)?,
+  }
+  if let Some(bar) = &bar {
+    bar.clear();
+  }
+  Ok(dispatcher.changes)
+}
+
+/// Number of ranges of time per job [`Split::Time`] cuts the waveform into at most. A job done
+/// with its range takes on the next one instead of waiting for the slowest job.
+const RANGES_PER_JOB: usize = 4;
+
+/// Read the ranges of time in order on `jobs` threads. Every range is dispatched once the
+/// previous one is, while the threads decode the ranges after it: only the changes of `jobs + 1`
+/// ranges are held at once.
+fn by_time<W: Waveform, S: OutputSink>(
+  open: impl Fn() -> anyhow::Result<W> + Sync,
+  dispatcher: &mut Dispatcher<'_, S>,
+  cuts: &[u64],
+  jobs: usize,
+  feedback: Feedback,
+  bar: &mut Option<ProgressBar>,
+) -> anyhow::Result<()> {
+  let handles = dispatcher.handles.clone();
+  let header = open()?.header();
+  dispatcher.span = (header.start_time, header.end_time);
+  *bar = feedback
+    .bar
+    .then(|| ProgressBar::new(header.start_time, header.end_time));
+  let every = cuts.len().div_ceil(jobs * RANGES_PER_JOB).max(1);
+  // from 0 to the maximum so no change is left out, even one outside of the header times
+  let mut bounds: Vec<u64> = cuts
+    .iter()
+    .skip(every - 1)
+    .step_by(every)
+    .copied()
+    .collect();
+  bounds.insert(0, 0);
+  bounds.push(u64::MAX);
+  bounds.dedup();
+  let mut ranges = bounds.windows(2).map(|bound| bound[0]..bound[1]);
+
+  let (work_tx, work_rx) = std::sync::mpsc::channel::<(Range<u64>, Sender<Vec<Change>>)>();
+  let work_rx = Mutex::new(work_rx);
+  let failed = AtomicBool::new(false);
+  std::thread::scope(|scope| -> anyhow::Result<()> {
+    let threads: Vec<_> = (0..jobs)
+      .map(|_| {
+        let (open, handles, work_rx, failed) = (&open, &handles, &work_rx, &failed);
+        scope.spawn(move || -> anyhow::Result<()> {
+          let mut reader = None;
+          let mut failure = None;
+          loop {
+            let work = work_rx.lock().expect("a reader thread panicked").recv();
+            let Ok((range, tx)) = work else {
+              break;
+            };
+            // dropping the sender of a range ends it, so the dispatch goes on to the end
+            if failed.load(Ordering::Relaxed) {
+              continue;
+            }
+            if let Err(err) = read_range(&mut reader, open, handles, range, tx) {
+              failed.store(true, Ordering::Relaxed);
+              failure = Some(err);
+            }
+          }
+          failure.map_or(Ok(()), Err)
+        })
+      })
+      .collect();
+
+    let mut queued = VecDeque::new();
+    let mut queue = |queued: &mut VecDeque<_>| {
+      if let Some(range) = ranges.next() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        work_tx
+          .send((range, tx))
+          .expect("the reader threads wait for work");
+        queued.push_back(rx);
+      }
+    };
+    for _ in 0..=jobs {
+      queue(&mut queued);
+    }
+    while let Some(rx) = queued.pop_front() {
+      for (t, handle, value) in rx.iter().flatten() {
+        if let Some(bar) = bar.as_mut().filter(|_| dispatcher.changes % 4096 == 0) {
+          bar.update(t);
+        }
+        dispatcher.change(t, handle, &value);
+      }
+      queue(&mut queued);
+    }
+    drop(work_tx);
+    for thread in threads {
+      thread.join().expect("reader thread panicked")?;
+    }
+    Ok(())
+  })
+}
+
+/// Send the changes in `range` to `tx`, opening the waveform of the thread if not yet done.
+fn read_range<W: Waveform>(
+  reader: &mut Option<W>,
+  open: impl Fn() -> anyhow::Result<W>,
+  handles: &[FstSignalHandle],
+  range: Range<u64>,
+  tx: Sender<Vec<Change>>,
+) -> anyhow::Result<()> {
+  let reader = match reader {
+    Some(reader) => reader,
+    None => reader.insert(open()?),
+  };
+  let mut batcher = Batcher::new(|batch| {
+    // the dispatch only stops listening when it fails, which reports its own error
+    let _ = tx.send(batch);
+  });
+  reader.for_each_change_in(handles, range.clone(), |t, handle, value| {
+    if range.contains(&t) {
+      batcher.push(t, handle, value)
+    }
+  })?;
+  batcher.finish();
+  Ok(())
+}
+
+/// Read a chunk of the signals on each of `jobs` threads, and merge their streams by time then
+/// by handle. Only a few batches of changes per thread are in flight at once.
+fn by_signals<W: Waveform, S: OutputSink>(
+  open: impl Fn() -> anyhow::Result<W> + Sync,
+  dispatcher: &mut Dispatcher<'_, S>,
+  jobs: usize,
+  feedback: Feedback,
+  bar: &mut Option<ProgressBar>,
+) -> anyhow::Result<()> {
+  let handles = dispatcher.handles.clone();
+  let chunk_size = handles.len().div_ceil(jobs).max(1);
+  // time range of the waveform, known once a thread opened it
+  let span: OnceLock<(u64, u64)> = OnceLock::new();
+  std::thread::scope(|scope| -> anyhow::Result<()> {
+    let (threads, mut streams): (Vec<_>, Vec<_>) = handles
+      .chunks(chunk_size)
+      .map(|handles| {
+        let (tx, rx) = std::sync::mpsc::sync_channel(BATCHES_IN_FLIGHT);
+        let (open, span) = (&open, &span);
+        let thread = scope.spawn(move || -> anyhow::Result<()> {
+          let mut reader = open()?;
+          let header = reader.header();
+          span.get_or_init(|| (header.start_time, header.end_time));
+          let mut batcher = Batcher::new(|batch| {
+            // the merge only stops listening when it fails, which reports its own error
+            let _ = tx.send(batch);
+          });
+          reader.for_each_change(handles, |t, handle, value| batcher.push(t, handle, value))?;
+          batcher.finish();
+          Ok(())
+        });
+        (thread, Stream::new(rx))
+      })
+      .unzip();
+
+    let mut heads = BinaryHeap::new();
+    for (i, stream) in streams.iter_mut().enumerate() {
+      if let Some(key) = stream.peek() {
+        heads.push(Reverse((key, i)));
+      }
+    }
+    while let Some(Reverse((_, i))) = heads.pop() {
+      let (t, handle, value) = streams[i].pop().expect("a stream in the heap has a change");
+      if dispatcher.changes % 4096 == 0 {
+        // set by the thread before it sent anything
+        if let Some(&(start, end)) = span.get() {
+          dispatcher.span = (start, end);
+          if feedback.bar {
+            bar
+              .get_or_insert_with(|| ProgressBar::new(start, end))
+              .update(t);
+          }
+        }
+      }
+      dispatcher.change(t, handle, &value);
+      if let Some(key) = streams[i].peek() {
+        heads.push(Reverse((key, i)));
+      }
+    }
+    for thread in threads {
+      thread.join().expect("reader thread panicked")?;
+    }
+    Ok(())
+  })
+}
+
+/// Number of changes a reader thread sends at once.
+const BATCH_SIZE: usize = 4096;
+/// Number of batches a reader thread sends ahead of the dispatch before it waits.
+const BATCHES_IN_FLIGHT: usize = 4;
+
+type Change = (u64, FstSignalHandle, SignalValue);
+
+/// Holds back the changes of the latest time, to give them sorted by handle once the time moves
+/// on.
+#[derive(Default)]
+struct TimeOrder {
+  pending: Vec<Change>,
+}
+
+impl TimeOrder {
+  /// Add a change, first giving the ones of the previous time to `settled` if it moved on.
+  fn push(&mut self, change: Change, settled: impl FnMut(Change)) {
+    if self
+      .pending
+      .first()
+      .is_some_and(|(latest, _, _)| *latest != change.0)
+    {
+      self.settle(settled);
+    }
+    self.pending.push(change);
+  }
+
+  /// Give the changes of the latest time to `settled`, sorted by handle.
+  fn settle(&mut self, settled: impl FnMut(Change)) {
+    // stable, so the file order of the changes of a signal at the same time is kept
+    self
+      .pending
+      .sort_by_key(|(_, handle, _)| handle.get_index());
+    self.pending.drain(..).for_each(settled);
+  }
+}
+
+/// Groups the changes read by one thread into batches given to `send`, sorted by handle within
+/// every time.
+struct Batcher<F: FnMut(Vec<Change>)> {
+  send: F,
+  order: TimeOrder,
+  batch: Vec<Change>,
+}
+
+impl<F: FnMut(Vec<Change>)> Batcher<F> {
+  fn new(send: F) -> Self {
+    Self {
+      send,
+      order: TimeOrder::default(),
+      batch: Vec::with_capacity(BATCH_SIZE),
+    }
+  }
+
+  fn push(&mut self, t: u64, handle: FstSignalHandle, value: SignalValue) {
+    self
+      .order
+      .push((t, handle, value), |change| self.batch.push(change));
+    self.send_full();
+  }
+
+  /// Send the batch once full.
+  fn send_full(&mut self) {
+    if self.batch.len() >= BATCH_SIZE {
+      let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(BATCH_SIZE));
+      (self.send)(batch);
+    }
+  }
+
+  fn finish(mut self) {
+    self.order.settle(|change| self.batch.push(change));
+    if !self.batch.is_empty() {
+      (self.send)(self.batch);
+    }
+  }
+}
+
+/// The changes received from one reader thread.
+struct Stream {
+  rx: Receiver<Vec<Change>>,
+  batch: std::iter::Peekable<std::vec::IntoIter<Change>>,
+}
+
+impl Stream {
+  fn new(rx: Receiver<Vec<Change>>) -> Self {
+    Self {
+      rx,
+      batch: Vec::new().into_iter().peekable(),
+    }
+  }
+
+  /// Return the time and the handle index of the next change, waiting for the thread to send it,
+  /// or `None` once the thread is done.
+  fn peek(&mut self) -> Option<(u64, usize)> {
+    loop {
+      if let Some((t, handle, _)) = self.batch.peek() {
+        return Some((*t, handle.get_index()));
+      }
+      self.batch = self.rx.recv().ok()?.into_iter().peekable();
+    }
+  }
+
+  fn pop(&mut self) -> Option<Change> {
+    self.batch.next()
+  }
+}
+
+/// Routes the value changes to the sinks needing them.
+struct Dispatcher<'s, S> {
+  sinks: &'s mut [S],
+  /// Union of the handles of all the sinks
+  handles: Vec<FstSignalHandle>,
+  /// Sinks needing each signal, by handle index
+  wanted: HashMap<usize, Vec<usize>>,
+  heartbeat: Option<Duration>,
+  /// Time range of the changes, to log the progress as a percentage
+  span: (u64, u64),
+  changes: u64,
+  last_beat: Instant,
+}
+
+impl<'s, S: OutputSink> Dispatcher<'s, S> {
+  fn new(sinks: &'s mut [S], heartbeat: Option<Duration>) -> Self {
+    let mut handles = Vec::new();
+    let mut wanted: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut seen = HashSet::new();
+    for (i, sink) in sinks.iter().enumerate() {
+      for handle in sink.handles() {
+        let sinks = wanted.entry(handle.get_index()).or_default();
+        // aliases make a sink ask for a handle several times, it still gets each change once
+        if sinks.last() != Some(&i) {
+          sinks.push(i);
+        }
+        if seen.insert(handle.get_index()) {
+          handles.push(handle);
+        }
+      }
+    }
+    Self {
+      sinks,
+      handles,
+      wanted,
+      heartbeat,
+      span: (0, 0),
+      changes: 0,
+      last_beat: Instant::now(),
+    }
+  }
+
+  fn change(&mut self, t: u64, handle: FstSignalHandle, value: &SignalValue) {
+    self.changes += 1;
+    // checking the clock on every change is wasteful
+    if let Some(interval) = self.heartbeat.filter(|_| self.changes % 4096 == 0) {
+      if self.last_beat.elapsed() >= interval {
+        let (start, end) = self.span;
+        let percent = match end.checked_sub(start) {
+          Some(len) if len > 0 => t.saturating_sub(start) as u128 * 100 / len as u128,
+          _ => 100,
+        };
+        info!(
+          "still reading value changes: at time {t} ({percent}%), {} changes so far",
+          self.changes
+        );
+        self.last_beat = Instant::now();
+      }
+    }
+    for &i in self.wanted.get(&handle.get_index()).into_iter().flatten() {
+      self.sinks[i].change(t, handle, value);
+    }
+  }
+}
+
+/// Any of the sinks the binary can write.
+pub enum Sink<'a> {
+  Pprof(Box<PprofSink<'a>>),
+  Clock(ClockSink),
+  Transitions(TransitionsSink),
+  Json(DumpSink),
+  Vcd(VcdSink),
+  Folded(FoldedSink),
+  Csv(DumpSink),
+  Parquet(DumpSink),
+  Instret(InstretSink),
+  Report(ReportSink<'a>),
+  Aggregate(Aggregator<'a>),
+}
+
+impl OutputSink for Sink<'_> {
+  fn handles(&self) -> Vec<FstSignalHandle> {
+    match self {
+      Sink::Pprof(sink) => sink.handles(),
+      Sink::Clock(sink) => sink.handles(),
+      Sink::Transitions(sink) => sink.handles(),
+      Sink::Json(sink) => sink.handles(),
+      Sink::Vcd(sink) => sink.handles(),
+      Sink::Folded(sink) => sink.handles(),
+      Sink::Csv(sink) => sink.handles(),
+      Sink::Parquet(sink) => sink.handles(),
+      Sink::Instret(sink) => sink.handles(),
+      Sink::Report(sink) => sink.handles(),
+      Sink::Aggregate(sink) => sink.handles(),
+    }
+  }
+
+  fn change(&mut self, t: u64, handle: FstSignalHandle, value: &SignalValue) {
+    match self {
+      Sink::Pprof(sink) => sink.change(t, handle, value),
+      Sink::Clock(sink) => sink.change(t, handle, value),
+      Sink::Transitions(sink) => sink.change(t, handle, value),
+      Sink::Json(sink) => sink.change(t, handle, value),
+      Sink::Vcd(sink) => sink.change(t, handle, value),
+      Sink::Folded(sink) => sink.change(t, handle, value),
+      Sink::Csv(sink) => sink.change(t, handle, value),
+      Sink::Parquet(sink) => sink.change(t, handle, value),
+      Sink::Instret(sink) => sink.change(t, handle, value),
+      Sink::Report(sink) => sink.change(t, handle, value),
+      Sink::Aggregate(sink) => {
+        if let SignalValue::String(v) = value {
+          sink.update(t, handle, v);
+        }
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+  /// Gzip compressed pprof profile
+  Pprof,
+  /// pprof profile in protobuf text format, with the strings resolved, for debugging
+  Prototext,
+  /// JSON object mapping each signal to the sorted list of times it changed, in raw FST ticks
+  Transitions,
+  /// JSON array of every value change inside the window, initial values included, as
+  /// `{time, module_path, name, value}` records, to check what a config matches
+  Json,
+  /// Minimal VCD file declaring the matched signals with their width, and their value changes
+  Vcd,
+  /// Folded stacks for flamegraph.pl and speedscope, one `scope;...;signal ticks` line per
+  /// signal weighted by how long it had a bit set
+  Folded,
+  /// CSV table of every value change inside the window, one `time,signal,value` row each, or
+  /// with --wide one row per time with a column per signal
+  Csv,
+  /// Parquet table of the same rows as the CSV one, uncompressed
+  Parquet,
+}
+
+/// Which of the matched signals a sink receives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SignalFilter {
+  /// Every matched signal
+  All,
+  /// Only 1-bit signals
+  Scalars,
+  /// Only multi-bit signals
+  Buses,
+}
+
+impl SignalFilter {
+  /// Return true if a signal `width` bits wide goes to the sink.
+  pub fn accept(self, width: u32) -> bool {
+    match self {
+      SignalFilter::All => true,
+      SignalFilter::Scalars => width == 1,
+      SignalFilter::Buses => width != 1,
+    }
+  }
+}
+
+/// One `--format` argument: an output format, optionally qualified with a signal filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatSpec {
+  pub format: OutputFormat,
+  pub filter: SignalFilter,
+}
+
+impl FormatSpec {
+  /// Parse `FORMAT[:FILTER]`, like `pprof` or `transitions:buses`. The filter defaults to `all`.
+  pub fn parse(raw: &str) -> Result<Self, String> {
+    let (format, filter) = raw.split_once(':').unwrap_or((raw, "all"));
+    Ok(Self {
+      format: OutputFormat::from_str(format, true)?,
+      filter: SignalFilter::from_str(filter, true)?,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::dump::DumpSink;
+  use crate::fixture::FixtureWave;
+  use crate::window::TimeWindow;
+
+  #[test]
+  fn parse_format_spec() {
+    assert_eq!(
+      FormatSpec::parse("pprof").unwrap(),
+      FormatSpec {
+        format: OutputFormat::Pprof,
+        filter: SignalFilter::All
+      }
+    );
+    assert_eq!(
+      FormatSpec::parse("transitions:buses").unwrap(),
+      FormatSpec {
+        format: OutputFormat::Transitions,
+        filter: SignalFilter::Buses
+      }
+    );
+    assert!(FormatSpec::parse("pprof:wires").is_err());
+    assert!(FormatSpec::parse("svg").is_err());
+  }
+
+  #[test]
+  fn dispatch_changes_to_each_sink() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    let data = wave.var("data", 8);
+    wave.upscope();
+    wave
+      .change(0, valid, "0")
+      .change(0, data, "00000000")
+      .change(3, valid, "1")
+      .change(4, data, "00000001");
+
+    let metadata = wave.collect(&["valid", "data"]);
+    let mut sinks = [SignalFilter::Scalars, SignalFilter::Buses].map(|filter| {
+      let subset = metadata.subset(|i| filter.accept(metadata.widths[i]));
+      Sink::Transitions(TransitionsSink::new(subset, TimeWindow::default()))
+    });
+    drive(&mut wave, &mut sinks, Feedback::default()).unwrap();
+
+    let [Sink::Transitions(scalars), Sink::Transitions(buses)] = sinks else {
+      unreachable!()
+    };
+    let scalars = scalars.finish();
+    let buses = buses.finish();
+    assert_eq!(scalars.keys().collect::<Vec<_>>(), ["top.valid"]);
+    assert_eq!(scalars["top.valid"], [3]);
+    assert_eq!(buses.keys().collect::<Vec<_>>(), ["top.data"]);
+    assert_eq!(buses["top.data"], [4]);
+  }
+
+  #[test]
+  fn parallel_read_matches_serial() {
+    // every signal of `wave` read once serially then with each number of jobs
+    let check = |mut wave: FixtureWave, jobs: &[usize]| {
+      let metadata = wave.collect(&["top.s*"]);
+      let new_sink = || DumpSink::new(metadata.clone(), TimeWindow::default());
+      let mut serial = new_sink();
+      drive(
+        &mut wave,
+        std::slice::from_mut(&mut serial),
+        Feedback::default(),
+      )
+      .unwrap();
+      let serial = serial.finish();
+      // cut at many times and at none
+      let every_7 = (0..3000).step_by(7).collect();
+      let splits = [
+        Split::Time(every_7),
+        Split::Time(Vec::new()),
+        Split::Signals,
+      ];
+      for (split, &jobs) in splits
+        .iter()
+        .flat_map(|split| jobs.iter().map(move |jobs| (split, jobs)))
+      {
+        let mut parallel = new_sink();
+        let changes = drive_parallel(
+          || Ok(wave.clone()),
+          std::slice::from_mut(&mut parallel),
+          jobs,
+          split.clone(),
+          Feedback::default(),
+        )
+        .unwrap();
+        assert_eq!(changes as usize, serial.len(), "{split:?} by {jobs} jobs");
+        assert_eq!(parallel.finish(), serial, "{split:?} by {jobs} jobs");
+      }
+      serial.len()
+    };
+
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let handles: Vec<_> = (0..5).map(|i| wave.var(&format!("s{i}"), 1)).collect();
+    wave.upscope();
+    for t in 0..4 {
+      for (i, handle) in handles.iter().enumerate() {
+        wave.change(
+          t,
+          *handle,
+          if (t as usize + i) % 2 == 0 { "0" } else { "1" },
+        );
+      }
+    }
+    assert_eq!(check(wave, &[1, 2, 3, 8]), 20);
+
+    let mut wave = FixtureWave::new(0, 3000);
+    wave.scope("top");
+    let handles: Vec<_> = (0..3).map(|i| wave.var(&format!("s{i}"), 1)).collect();
+    wave.upscope();
+    // more changes than a batch, and a signal changing twice at some times
+    for t in 0..3000 {
+      for (i, handle) in handles.iter().enumerate() {
+        if (t as usize) % (i + 1) == 0 {
+          wave.change(t, *handle, if t % 2 == 0 { "0" } else { "1" });
+        }
+      }
+      if t % 1000 == 999 {
+        wave.change(t, handles[2], "x");
+      }
+    }
+    assert!(check(wave, &[3]) > BATCH_SIZE);
+
+    // the changes of one time in descending handle order, as a real file may give them
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let handles: Vec<_> = (0..4).map(|i| wave.var(&format!("s{i}"), 1)).collect();
+    wave.upscope();
+    for t in 0..3 {
+      for handle in handles.iter().rev() {
+        wave.change(t, *handle, if t % 2 == 0 { "0" } else { "1" });
+      }
+    }
+    assert_eq!(check(wave, &[1, 2, 4]), 12);
+  }
+}
@@ -0,0 +1,269 @@
+//! Latency of transactions, from the cycle a request is accepted to the cycle its response is.
+
+use std::collections::{HashMap, VecDeque};
+
+use anyhow::Context;
+use fst_native::FstSignalHandle;
+use serde::{Deserialize, Serialize};
+
+use crate::derived::Expr;
+use crate::edge::Edge;
+use crate::handshake::Sampled;
+use crate::SignalLookup;
+
+/// A transaction going from a start condition to an end condition, like
+/// `top.a_valid && top.a_ready` then `top.d_valid && top.d_ready`. Both are sampled on every
+/// rising edge of `clock`, signals being given by their full dot separated path.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransactionConfig {
+  pub name: String,
+  pub clock: String,
+  pub start: String,
+  pub end: String,
+}
+
+/// A condition whose operands are resolved to handles.
+#[derive(Debug, Clone)]
+pub struct Condition {
+  expr: Expr,
+  operands: Vec<FstSignalHandle>,
+}
+
+/// A transaction whose signals are resolved to handles.
+#[derive(Debug, Clone)]
+pub struct Transaction {
+  pub name: String,
+  pub clock: FstSignalHandle,
+  pub start: Condition,
+  pub end: Condition,
+}
+
+/// Find the signals of every transaction in the hierarchy. The clock must be 1-bit, an operand
+/// of the conditions is true when one of its bits is set.
+pub fn resolve(
+  signals: &SignalLookup,
+  configs: &[TransactionConfig],
+) -> anyhow::Result<Vec<Transaction>> {
+  let find = |path: &str| {
+    signals
+      .find(path)
+      .with_context(|| format!("transaction signal {path} is not found"))
+  };
+
+  configs
+    .iter()
+    .map(|c| -> anyhow::Result<Transaction> {
+      let (clock, width) = find(&c.clock)?;
+      anyhow::ensure!(
+        width == 1,
+        "transaction clock {} should be 1-bit, got {width} bits",
+        c.clock
+      );
+      let condition = |what: &str, text: &str| -> anyhow::Result<Condition> {
+        let (expr, operands) = Expr::parse(text)
+          .with_context(|| format!("fail to parse the {what} of transaction {}", c.name))?;
+        let operands = operands
+          .iter()
+          .map(|path| Ok(find(path)?.0))
+          .collect::<anyhow::Result<_>>()?;
+        Ok(Condition { expr, operands })
+      };
+      Ok(Transaction {
+        name: c.name.clone(),
+        clock,
+        start: condition("start", &c.start)?,
+        end: condition("end", &c.end)?,
+      })
+    })
+    .collect()
+}
+
+/// A transaction that ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completed {
+  /// Position of the transaction in the tracked ones
+  pub transaction: usize,
+  /// Time of the clock edge the transaction started on
+  pub start: u64,
+  /// Number of clock cycles from the start to the end
+  pub latency: u64,
+}
+
+/// Pair the starts and ends of transactions from the value changes of their signals.
+///
+/// Several transactions of one kind can be outstanding, an end closes the oldest one. The start
+/// condition is checked before the end condition, so a transaction can end on the edge it started
+/// on with a latency of zero. Like [`crate::handshake::HandshakeCounter`], the conditions are
+/// sampled with the values the signals had right before the clock edge.
+#[derive(Debug, Clone)]
+pub struct TransactionTracker<'a> {
+  transactions: &'a [Transaction],
+  values: HashMap<usize, Sampled>,
+  /// Rising edges seen of the clock of every transaction
+  cycles: Vec<u64>,
+  /// Cycle and time every outstanding transaction started at, oldest first
+  open: Vec<VecDeque<(u64, u64)>>,
+  completed: Vec<Completed>,
+}
+
+impl<'a> TransactionTracker<'a> {
+  pub fn new(transactions: &'a [Transaction]) -> Self {
+    Self {
+      transactions,
+      values: HashMap::new(),
+      cycles: vec![0; transactions.len()],
+      open: vec![VecDeque::new(); transactions.len()],
+      completed: Vec::new(),
+    }
+  }
+
+  /// Return the handles of all the signals needed by the transactions.
+  pub fn handles(&self) -> Vec<FstSignalHandle> {
+    let mut handles = Vec::new();
+    for tx in self.transactions {
+      let signals = std::iter::once(tx.clock).chain(tx.start.operands.iter().copied());
+      for handle in signals.chain(tx.end.operands.iter().copied()) {
+        if !handles.contains(&handle) {
+          handles.push(handle);
+        }
+      }
+    }
+    handles
+  }
+
+  fn before(&self, handle: FstSignalHandle, t: u64) -> Option<&str> {
+    self
+      .values
+      .get(&handle.get_index())
+      .and_then(|sampled| sampled.before(t))
+  }
+
+  fn holds(&self, condition: &Condition, t: u64) -> bool {
+    condition.expr.eval(&|i| {
+      self
+        .before(condition.operands[i], t)
+        .is_some_and(|bits| bits.contains('1'))
+    })
+  }
+
+  /// Feed a value change at time `t`.
+  pub fn update(&mut self, t: u64, handle: FstSignalHandle, value: &str) {
+    let prev = self.before(handle, t);
+    if prev.and_then(|prev| Edge::classify(prev, value)) == Some(Edge::Pos) {
+      let transactions = self.transactions;
+      for (i, tx) in transactions.iter().enumerate() {
+        if tx.clock != handle {
+          continue;
+        }
+        let cycle = self.cycles[i];
+        self.cycles[i] += 1;
+        if self.holds(&tx.start, t) {
+          self.open[i].push_back((cycle, t));
+        }
+        if self.holds(&tx.end, t) {
+          if let Some((started, start)) = self.open[i].pop_front() {
+            self.completed.push(Completed {
+              transaction: i,
+              start,
+              latency: cycle - started,
+            });
+          }
+        }
+      }
+    }
+
+    match self.values.get_mut(&handle.get_index()) {
+      Some(sampled) => sampled.set(t, value),
+      None => {
+        self
+          .values
+          .insert(handle.get_index(), Sampled::new(t, value));
+      }
+    }
+  }
+
+  /// Return the transactions that ended, in the order they ended.
+  pub fn completed(&self) -> &[Completed] {
+    &self.completed
+  }
+
+  /// Return every transaction with the number of them still outstanding.
+  pub fn outstanding(&self) -> impl Iterator<Item = (&Transaction, usize)> {
+    self
+      .transactions
+      .iter()
+      .zip(self.open.iter().map(VecDeque::len))
+  }
+}
+
+#[test]
+fn track_transaction_latency() {
+  use crate::fixture::FixtureWave;
+
+  let mut wave = FixtureWave::new(0, 100);
+  wave.scope("top");
+  let clock = wave.var("clock", 1);
+  let a_valid = wave.var("a_valid", 1);
+  let a_ready = wave.var("a_ready", 1);
+  let d_valid = wave.var("d_valid", 1);
+  wave.var("data", 8);
+  wave.upscope();
+
+  let configs = vec![TransactionConfig {
+    name: "get".to_string(),
+    clock: "top.clock".to_string(),
+    start: "top.a_valid && top.a_ready".to_string(),
+    end: "d_valid".to_string(),
+  }];
+  let transactions = resolve(&SignalLookup::new(&mut wave).unwrap(), &configs).unwrap();
+  let mut tracker = TransactionTracker::new(&transactions);
+
+  let mut changes = vec![(0, a_valid, "0"), (0, a_ready, "1"), (0, d_valid, "0")];
+  // rising edges at 10, 30, 50 and so on, cycle 0 being the one at 10
+  for t in (0..100).step_by(10) {
+    changes.push((t, clock, if t % 20 == 0 { "0" } else { "1" }));
+  }
+  changes.extend([
+    // two requests accepted on the edges at 30 and 50
+    (25, a_valid, "1"),
+    (55, a_valid, "0"),
+    // the first response on the edge at 70, the second one together with a refused request
+    (65, d_valid, "1"),
+    (75, d_valid, "0"),
+    (75, a_ready, "0"),
+    (75, a_valid, "1"),
+    (85, d_valid, "1"),
+  ]);
+  changes.sort_by_key(|(t, _, _)| *t);
+  for (t, handle, value) in changes {
+    tracker.update(t, handle, value);
+  }
+
+  assert_eq!(
+    tracker.completed(),
+    [
+      Completed {
+        transaction: 0,
+        start: 30,
+        latency: 2,
+      },
+      Completed {
+        transaction: 0,
+        start: 50,
+        latency: 2,
+      },
+    ]
+  );
+  let outstanding: Vec<_> = tracker
+    .outstanding()
+    .map(|(tx, n)| (&*tx.name, n))
+    .collect();
+  assert_eq!(outstanding, [("get", 0)]);
+
+  let bus = vec![TransactionConfig {
+    clock: "top.data".to_string(),
+    ..configs[0].clone()
+  }];
+  assert!(resolve(&SignalLookup::new(&mut wave).unwrap(), &bus).is_err());
+}
@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, HashMap};
+
+use fst_native::FstSignalHandle;
+use tracing::{debug, info};
+
+use crate::sink::{self, OutputSink};
+use crate::wave::{SignalValue, Waveform};
+use crate::window::TimeWindow;
+use crate::SignalMetadata;
+
+/// Return, per signal full name, the sorted timestamps at which the signal changed its value.
+///
+/// Timestamps are raw FST time ticks, which need to be scaled by the file timescale to become
+/// physical time. The initial value of a signal isn't a transition, neither is a recorded change
+/// to the same value. Only transitions inside `window` are kept.
+///
+/// This reads the value changes once for this output only, use [`sink::drive`] to feed several
+/// outputs from the same read.
+pub fn collect_transitions(
+  reader: &mut impl Waveform,
+  metadata: &SignalMetadata,
+  window: &TimeWindow,
+) -> anyhow::Result<BTreeMap<String, Vec<u64>>> {
+  let mut sink = TransitionsSink::new(metadata.clone(), *window);
+  sink::drive(
+    reader,
+    std::slice::from_mut(&mut sink),
+    sink::Feedback::default(),
+  )?;
+  Ok(sink.finish())
+}
+
+/// Return the signals of `metadata` having at least `min` transitions inside `window`.
+///
+/// This takes an extra read of the value changes of the signals, before the real conversion.
+pub fn retain_active(
+  reader: &mut impl Waveform,
+  metadata: SignalMetadata,
+  window: &TimeWindow,
+  min: usize,
+  feedback: sink::Feedback,
+) -> anyhow::Result<SignalMetadata> {
+  let mut sink = TransitionsSink::new(metadata, *window);
+  sink::drive(reader, std::slice::from_mut(&mut sink), feedback)?;
+
+  let inactive: Vec<_> = (0..sink.transitions.len())
+    .filter(|i| sink.transitions[*i].len() < min)
+    .collect();
+  if !inactive.is_empty() {
+    info!(
+      "{} signals have less than {min} transitions and are dropped",
+      inactive.len()
+    );
+    for i in &inactive {
+      debug!("dropping inactive signal {}", sink.metadata.full_name(*i));
+    }
+  }
+  Ok(sink.metadata.subset(|i| !inactive.contains(&i)))
+}
+
+/// Records the transition times of the signals it is fed, see [`collect_transitions`].
+pub struct TransitionsSink {
+  metadata: SignalMetadata,
+  window: TimeWindow,
+  /// Positions in `metadata` of each signal, by handle index
+  index: HashMap<usize, Vec<usize>>,
+  /// Latest value of every signal, by position in `metadata`
+  last_values: HashMap<usize, SignalValue>,
+  transitions: Vec<Vec<u64>>,
+}
+
+impl TransitionsSink {
+  pub fn new(metadata: SignalMetadata, window: TimeWindow) -> Self {
+    Self {
+      index: metadata.positions(),
+      transitions: vec![Vec::new(); metadata.handle.len()],
+      metadata,
+      window,
+      last_values: HashMap::new(),
+    }
+  }
+
+  /// Return the transitions keyed by signal full name.
+  pub fn finish(self) -> BTreeMap<String, Vec<u64>> {
+    self
+      .transitions
+      .into_iter()
+      .enumerate()
+      .map(|(i, mut times)| {
+        times.sort_unstable();
+        (self.metadata.full_name(i), times)
+      })
+      .collect()
+  }
+}
+
+impl OutputSink for TransitionsSink {
+  fn handles(&self) -> Vec<FstSignalHandle> {
+    self.metadata.handle.clone()
+  }
+
+  fn change(&mut self, t: u64, handle: FstSignalHandle, value: &SignalValue) {
+    let Some(positions) = self.index.get(&handle.get_index()) else {
+      return;
+    };
+    // a slice of a signal only changes when its own bits do
+    for &i in positions {
+      let value = self.metadata.value_of(i, value).into_owned();
+      let changed = match self.last_values.insert(i, value.clone()) {
+        Some(SignalValue::String(prev)) => !matches!(&value, SignalValue::String(v) if *v == prev),
+        Some(SignalValue::Real(prev)) => !matches!(&value, SignalValue::Real(v) if *v == prev),
+        None => false,
+      };
+      if changed && self.window.contains(t) {
+        self.transitions[i].push(t);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixture::FixtureWave;
+
+  #[test]
+  fn transitions_skip_initial_and_repeated_values() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let clock = wave.var("clock", 1);
+    let idle = wave.var("idle", 1);
+    wave.upscope();
+    wave
+      .change(0, clock, "0")
+      .change(0, idle, "1")
+      .change(2, clock, "1")
+      .change(4, clock, "0")
+      .change(4, idle, "1")
+      .change(6, clock, "1");
+
+    let metadata = wave.collect(&["clock", "idle"]);
+    let transitions = collect_transitions(&mut wave, &metadata, &TimeWindow::default()).unwrap();
+
+    assert_eq!(transitions["top.clock"], [2, 4, 6]);
+    assert!(transitions["top.idle"].is_empty());
+
+    // the default window is half-open
+    let window = TimeWindow {
+      start: Some(2),
+      end: Some(6),
+      ..Default::default()
+    };
+    let transitions = collect_transitions(&mut wave, &metadata, &window).unwrap();
+    assert_eq!(transitions["top.clock"], [2, 4]);
+  }
+
+  #[test]
+  fn retain_signals_with_transitions() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let clock = wave.var("clock", 1);
+    let reset = wave.var("reset", 1);
+    let idle = wave.var("idle", 1);
+    wave.upscope();
+    wave
+      .change(0, clock, "0")
+      .change(0, reset, "1")
+      .change(0, idle, "0")
+      .change(1, reset, "0")
+      .change(2, clock, "1")
+      .change(4, clock, "0");
+
+    let metadata = wave.collect(&["clock", "reset", "idle"]);
+
+    let active = retain_active(
+      &mut wave,
+      metadata.clone(),
+      &TimeWindow::default(),
+      1,
+      sink::Feedback::default(),
+    )
+    .unwrap();
+    assert_eq!(active.handle, [clock, reset]);
+    let active = retain_active(
+      &mut wave,
+      metadata,
+      &TimeWindow::default(),
+      2,
+      sink::Feedback::default(),
+    )
+    .unwrap();
+    assert_eq!(active.handle, [clock]);
+  }
+}
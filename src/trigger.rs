@@ -0,0 +1,56 @@
+//! Trigger gated sampling: value changes are only sampled while a signal holds a value, the
+//! values held when it starts holding being sampled at that time.
+
+use anyhow::Context;
+use fst_native::FstSignalHandle;
+use serde::{Deserialize, Serialize};
+
+use crate::SignalLookup;
+
+/// A condition gating the value change samples: signals are only sampled while `signal`, given by
+/// its full dot separated path, holds `value`. The value is written in raw bits, like `1` or
+/// `0010`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerConfig {
+  pub signal: String,
+  pub value: String,
+}
+
+/// A trigger whose signal is resolved to a handle.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+  pub handle: FstSignalHandle,
+  pub value: String,
+}
+
+impl Trigger {
+  /// Return whether the trigger signal changing to `value` satisfies the condition.
+  pub fn holds(&self, value: &str) -> bool {
+    self.value == value
+  }
+}
+
+/// Find the signal of the trigger in the hierarchy. It must exist and be as wide as the value.
+pub fn resolve(
+  signals: &SignalLookup,
+  config: Option<&TriggerConfig>,
+) -> anyhow::Result<Option<Trigger>> {
+  let Some(config) = config else {
+    return Ok(None);
+  };
+
+  let path = &config.signal;
+  let (handle, width) = signals
+    .find(path)
+    .with_context(|| format!("trigger signal {path} is not found"))?;
+  anyhow::ensure!(
+    width as usize == config.value.len(),
+    "trigger value {} should have the {width} bits of signal {path}",
+    config.value
+  );
+  Ok(Some(Trigger {
+    handle,
+    value: config.value.clone(),
+  }))
+}
@@ -0,0 +1,423 @@
+//! VCD files: reading them as a [`Waveform`] for simulators that don't dump FST, and exporting
+//! the matched signals as a minimal VCD file for tools that don't read pprof.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use anyhow::Context;
+use fst_native::FstSignalHandle;
+
+use crate::sink::OutputSink;
+use crate::wave::{parse_timescale, HierarchyEntry, SignalValue, WaveHeader, Waveform};
+use crate::window::TimeWindow;
+use crate::SignalMetadata;
+
+/// A VCD file parsed in memory.
+///
+/// Every identifier code gets its own handle, so variables declared with the same code are
+/// aliases like in FST. Vector values shorter than their variable are extended to its width as
+/// the VCD standard says.
+#[derive(Debug)]
+pub struct VcdWave {
+  header: WaveHeader,
+  hierarchy: Vec<HierarchyEntry>,
+  changes: Vec<(u64, FstSignalHandle, SignalValue)>,
+}
+
+impl VcdWave {
+  /// Read and parse the VCD file at `path`.
+  pub fn open(path: &str) -> anyhow::Result<Self> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("fail to read {path}"))?;
+    Self::parse(&text).with_context(|| format!("fail to parse VCD {path}"))
+  }
+
+  pub fn parse(text: &str) -> anyhow::Result<Self> {
+    let mut tokens = text.split_whitespace();
+    let mut header = WaveHeader::default();
+    let mut hierarchy = Vec::new();
+    // handle and width of every identifier code
+    let mut codes: HashMap<&str, (FstSignalHandle, u32)> = HashMap::new();
+
+    // read the text of a section up to its `$end`
+    fn section<'t>(tokens: &mut impl Iterator<Item = &'t str>) -> anyhow::Result<Vec<&'t str>> {
+      let mut words = Vec::new();
+      loop {
+        match tokens.next() {
+          Some("$end") => return Ok(words),
+          Some(word) => words.push(word),
+          None => anyhow::bail!("unterminated section"),
+        }
+      }
+    }
+
+    loop {
+      match tokens.next().context("missing $enddefinitions")? {
+        "$enddefinitions" => {
+          section(&mut tokens)?;
+          break;
+        }
+        "$scope" => match section(&mut tokens)?[..] {
+          [_, name] => hierarchy.push(HierarchyEntry::Scope {
+            name: name.to_string(),
+          }),
+          ref words => anyhow::bail!("malformed $scope {}", words.join(" ")),
+        },
+        "$upscope" => {
+          section(&mut tokens)?;
+          hierarchy.push(HierarchyEntry::UpScope);
+        }
+        "$var" => {
+          let words = section(&mut tokens)?;
+          // a trailing bit range, like `[7:0]`, isn't part of the name
+          let [_, size, code, name, ..] = words[..] else {
+            anyhow::bail!("malformed $var {}", words.join(" "));
+          };
+          let length = size
+            .parse()
+            .with_context(|| format!("malformed size of $var {name}: {size}"))?;
+          let next = FstSignalHandle::from_index(codes.len());
+          let (handle, _) = *codes.entry(code).or_insert((next, length));
+          hierarchy.push(HierarchyEntry::Var {
+            name: name.to_string(),
+            length,
+            handle,
+          });
+        }
+        "$date" => header.date = section(&mut tokens)?.join(" "),
+        "$version" => header.version = section(&mut tokens)?.join(" "),
+        "$timescale" => {
+          let text = section(&mut tokens)?.join(" ");
+          let timescale = parse_timescale(&text);
+          header.timescale = Some(timescale.with_context(|| format!("bad $timescale {text}"))?);
+        }
+        // $comment and unknown sections
+        _ => {
+          section(&mut tokens)?;
+        }
+      }
+    }
+
+    let mut changes = Vec::new();
+    let mut time = None;
+    while let Some(token) = tokens.next() {
+      let (value, code) = match token.as_bytes()[0] {
+        b'#' => {
+          let t = token[1..]
+            .parse()
+            .with_context(|| format!("malformed time {token}"))?;
+          header.start_time = time.map_or(t, |_| header.start_time);
+          header.end_time = t;
+          time = Some(t);
+          continue;
+        }
+        b'$' if token == "$comment" => {
+          section(&mut tokens)?;
+          continue;
+        }
+        // $dumpvars, $dumpall and the $end closing them only wrap value changes
+        b'$' => continue,
+        b'b' | b'B' | b'r' | b'R' => (token, tokens.next().context("missing identifier")?),
+        _ if token.is_char_boundary(1) => token.split_at(1),
+        _ => anyhow::bail!("malformed value change {token}"),
+      };
+      let (handle, width) = *codes
+        .get(code)
+        .with_context(|| format!("unknown identifier code {code}"))?;
+      let t = time.with_context(|| format!("value change {token} before any time"))?;
+      let value = match value.as_bytes()[0] {
+        b'r' | b'R' => SignalValue::Real(
+          value[1..]
+            .parse()
+            .with_context(|| format!("malformed real {value}"))?,
+        ),
+        b'b' | b'B' => SignalValue::String(extend(&value[1..], width)),
+        _ => SignalValue::String(value.to_string()),
+      };
+      changes.push((t, handle, value));
+    }
+
+    Ok(Self {
+      header,
+      hierarchy,
+      changes,
+    })
+  }
+}
+
+/// Left extend a vector value to `width` bits, with `x` or `z` if it starts with one and `0`
+/// otherwise.
+fn extend(bits: &str, width: u32) -> String {
+  let width = width as usize;
+  if bits.len() >= width {
+    return bits.to_string();
+  }
+  let fill = match bits.as_bytes().first() {
+    Some(b'x' | b'X') => 'x',
+    Some(b'z' | b'Z') => 'z',
+    _ => '0',
+  };
+  std::iter::repeat(fill)
+    .take(width - bits.len())
+    .chain(bits.chars())
+    .collect()
+}
+
+impl Waveform for VcdWave {
+  fn header(&self) -> WaveHeader {
+    self.header.clone()
+  }
+
+  fn for_each_hierarchy(&mut self, callback: impl FnMut(HierarchyEntry)) -> anyhow::Result<()> {
+    self.hierarchy.iter().cloned().for_each(callback);
+    Ok(())
+  }
+
+  fn for_each_change(
+    &mut self,
+    handles: &[FstSignalHandle],
+    mut callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    let wanted: HashSet<_> = handles.iter().map(|handle| handle.get_index()).collect();
+    for (t, handle, value) in &self.changes {
+      if wanted.contains(&handle.get_index()) {
+        callback(*t, *handle, value.clone());
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Records the value changes it is fed, and writes them back as VCD.
+pub struct VcdSink {
+  metadata: SignalMetadata,
+  window: TimeWindow,
+  /// Length of one time tick, like `1ns` or `10ps`, unless VCD can't write it
+  timescale: Option<String>,
+  /// Positions in `metadata` of each signal, by handle index
+  index: HashMap<usize, Vec<usize>>,
+  /// Time, position in `metadata` and value of every change, in file order
+  changes: Vec<(u64, usize, SignalValue)>,
+}
+
+impl VcdSink {
+  pub fn new(header: &WaveHeader, metadata: SignalMetadata, window: TimeWindow) -> Self {
+    // VCD only has lengths of 1, 10 or 100 of a unit down to fs
+    let tick = header.tick_unit();
+    let timescale = match tick.chars().next() {
+      _ if tick.contains('e') => None,
+      Some(c) if c.is_ascii_digit() => Some(tick),
+      _ => Some(format!("1{tick}")),
+    };
+    Self {
+      index: metadata.positions(),
+      metadata,
+      window,
+      timescale,
+      changes: Vec::new(),
+    }
+  }
+
+  /// Write the declarations of the signals then their changes to `out`. Times are the ticks of
+  /// the waveform, declared with its timescale. The signals changing to real values are declared
+  /// as reals.
+  pub fn finish(self, mut out: impl Write) -> std::io::Result<()> {
+    let reals: HashSet<usize> = self
+      .changes
+      .iter()
+      .filter(|(_, _, value)| matches!(value, SignalValue::Real(_)))
+      .map(|(_, i, _)| *i)
+      .collect();
+    writeln!(out, "$version toyfst $end")?;
+    if let Some(timescale) = &self.timescale {
+      writeln!(out, "$timescale {timescale} $end")?;
+    }
+    let mut scope: &[String] = &[];
+    for i in 0..self.metadata.handle.len() {
+      let path = &self.metadata.module_paths[i];
+      let common = scope.iter().zip(path).take_while(|(a, b)| a == b).count();
+      for _ in common..scope.len() {
+        writeln!(out, "$upscope $end")?;
+      }
+      for name in &path[common..] {
+        writeln!(out, "$scope module {name} $end")?;
+      }
+      scope = path;
+      let (kind, width) = match reals.contains(&i) {
+        true => ("real", 64),
+        false => ("wire", self.metadata.widths[i]),
+      };
+      writeln!(
+        out,
+        "$var {kind} {width} {} {} $end",
+        identifier(i),
+        // VCD references can't hold spaces, like the ones before a bit range
+        self.metadata.names[i].replace(' ', "")
+      )?;
+    }
+    for _ in 0..scope.len() {
+      writeln!(out, "$upscope $end")?;
+    }
+    writeln!(out, "$enddefinitions $end")?;
+
+    let mut time = None;
+    for (t, i, value) in &self.changes {
+      if time != Some(*t) {
+        writeln!(out, "#{t}")?;
+        time = Some(*t);
+      }
+      let id = identifier(*i);
+      match value {
+        SignalValue::Real(r) => writeln!(out, "r{r} {id}")?,
+        SignalValue::String(v) if self.metadata.widths[*i] == 1 => writeln!(out, "{v}{id}")?,
+        SignalValue::String(v) => writeln!(out, "b{v} {id}")?,
+      }
+    }
+    Ok(())
+  }
+}
+
+impl OutputSink for VcdSink {
+  fn handles(&self) -> Vec<FstSignalHandle> {
+    self.metadata.handle.clone()
+  }
+
+  fn change(&mut self, t: u64, handle: FstSignalHandle, value: &SignalValue) {
+    let Some(positions) = self.index.get(&handle.get_index()) else {
+      return;
+    };
+    if !self.window.contains(t) {
+      return;
+    }
+    for &i in positions {
+      let value = self.metadata.value_of(i, value).into_owned();
+      self.changes.push((t, i, value));
+    }
+  }
+}
+
+/// Return the VCD identifier of the i-th signal, written in base 94 with the printable ASCII
+/// characters from `!` to `~`.
+fn identifier(mut i: usize) -> String {
+  let mut id = String::new();
+  loop {
+    id.push((b'!' + (i % 94) as u8) as char);
+    i /= 94;
+    if i == 0 {
+      return id;
+    }
+    i -= 1;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixture::FixtureWave;
+  use crate::sink::{drive, Feedback};
+  use crate::{collect_signals, CollectOptions};
+
+  #[test]
+  fn write_vcd() {
+    let mut wave = FixtureWave::new(0, 10);
+    wave.scope("top");
+    let valid = wave.var("valid", 1);
+    wave.scope("core");
+    let data = wave.var("data", 4);
+    let load = wave.var("load", 64);
+    wave.upscope();
+    wave.upscope();
+    wave
+      .change(0, valid, "0")
+      .change(0, data, "0000")
+      .change_real(0, load, 0.5)
+      .change(3, valid, "1")
+      .change(3, data, "1010");
+
+    let metadata = wave.collect(&["valid", "data", "load"]);
+    let mut sink = VcdSink::new(&wave.header(), metadata, TimeWindow::default());
+    drive(
+      &mut wave,
+      std::slice::from_mut(&mut sink),
+      Feedback::default(),
+    )
+    .unwrap();
+    let mut out = Vec::new();
+    sink.finish(&mut out).unwrap();
+
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      "$version toyfst $end
+$timescale 1ns $end
+$scope module top $end
+$var wire 1 ! valid $end
+$scope module core $end
+$var wire 4 \" data $end
+$var real 64 # load $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+#0
+0!
+b0000 \"
+r0.5 #
+#3
+1!
+b1010 \"
+"
+    );
+
+    assert_eq!(identifier(93), "~");
+    assert_eq!(identifier(94), "!!");
+  }
+
+  #[test]
+  fn read_vcd() {
+    let text = "
+$date today $end
+$timescale 1ns $end
+$scope module top $end
+$var wire 1 ! clock $end
+$var wire 4 \" data [3:0] $end
+$scope module core $end
+$var wire 1 ! clk $end
+$upscope $end
+$upscope $end
+$enddefinitions $end
+$comment initial values $end
+#0
+$dumpvars
+0!
+bx \"
+$end
+#5
+1!
+b10 \"
+";
+    let mut wave = VcdWave::parse(text).unwrap();
+    assert_eq!(wave.header().date, "today");
+    assert_eq!(wave.header().timescale, Some(-9));
+    assert_eq!((wave.header().start_time, wave.header().end_time), (0, 5));
+
+    let expected = vec!["clock".to_string(), "data".to_string(), "clk".to_string()];
+    let options = CollectOptions {
+      keep_aliases: true,
+      ..Default::default()
+    };
+    let metadata = collect_signals(&mut wave, &expected, &options).unwrap();
+    assert_eq!(metadata.widths, [1, 4, 1]);
+    assert_eq!(metadata.handle[0], metadata.handle[2]);
+
+    let mut changes = Vec::new();
+    wave
+      .for_each_change(&metadata.handle, |t, _, value| match value {
+        SignalValue::String(v) => changes.push((t, v)),
+        SignalValue::Real(_) => unreachable!(),
+      })
+      .unwrap();
+    let changes: Vec<_> = changes.iter().map(|(t, v)| (*t, v.as_str())).collect();
+    assert_eq!(changes, [(0, "0"), (0, "xxxx"), (5, "1"), (5, "0010")]);
+
+    assert!(VcdWave::parse("$scope module top $end").is_err());
+    assert!(VcdWave::parse("$enddefinitions $end #0 1?").is_err());
+  }
+}
@@ -0,0 +1,151 @@
+use std::io::Read;
+
+use anyhow::Context;
+use flate2::read::GzDecoder;
+use prost::Message;
+use tracing::{error, info};
+
+use crate::pprof::Profile;
+
+/// Decode the profile at `path`, report its size and check the string table invariants. Return
+/// error if the profile can't be decoded or any invariant is violated.
+pub fn verify(path: &str) -> anyhow::Result<()> {
+  info!("Verifying profile {path}");
+  let raw = std::fs::read(path).with_context(|| format!("fail to read profile {path}"))?;
+  let profile = decode(&raw)?;
+
+  info!(
+    samples = profile.sample.len(),
+    functions = profile.function.len(),
+    locations = profile.location.len(),
+    strings = profile.string_table.len(),
+    "Profile summary"
+  );
+
+  let violations = check_string_table(&profile);
+  for violation in &violations {
+    error!("{violation}");
+  }
+  anyhow::ensure!(
+    violations.is_empty(),
+    "{} invariant violations found in {path}",
+    violations.len()
+  );
+
+  info!("Profile {path} is valid");
+  Ok(())
+}
+
+/// Decode a profile, the gzip or zstd layer is optional and detected by its magic bytes.
+fn decode(raw: &[u8]) -> anyhow::Result<Profile> {
+  let buf = if raw.starts_with(&[0x1f, 0x8b]) {
+    let mut buf = Vec::new();
+    GzDecoder::new(raw)
+      .read_to_end(&mut buf)
+      .context("fail to decompress profile")?;
+    buf
+  } else if raw.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+    zstd::decode_all(raw).context("fail to decompress profile")?
+  } else {
+    raw.to_vec()
+  };
+  Profile::decode(buf.as_slice()).context("fail to decode profile")
+}
+
+/// Return a description of every string table invariant violated by the profile: the first
+/// string must be empty and every string field must be a valid index into the table.
+fn check_string_table(profile: &Profile) -> Vec<String> {
+  let mut violations = Vec::new();
+  match profile.string_table.first() {
+    None => violations.push("string table is empty".to_string()),
+    Some(first) if !first.is_empty() => violations.push(format!(
+      "string table index 0 should be empty, got {first:?}"
+    )),
+    _ => (),
+  }
+
+  let len = profile.string_table.len() as i64;
+  let mut check = |field: String, id: i64| {
+    if !(0..len).contains(&id) {
+      violations.push(format!(
+        "{field} refers to string {id}, out of range 0..{len}"
+      ));
+    }
+  };
+
+  for (i, value_type) in profile.sample_type.iter().enumerate() {
+    check(format!("sample_type[{i}].type"), value_type.r#type);
+    check(format!("sample_type[{i}].unit"), value_type.unit);
+  }
+  if let Some(period_type) = &profile.period_type {
+    check("period_type.type".to_string(), period_type.r#type);
+    check("period_type.unit".to_string(), period_type.unit);
+  }
+  for (i, sample) in profile.sample.iter().enumerate() {
+    for (j, label) in sample.label.iter().enumerate() {
+      check(format!("sample[{i}].label[{j}].key"), label.key);
+      check(format!("sample[{i}].label[{j}].str"), label.str);
+      check(format!("sample[{i}].label[{j}].num_unit"), label.num_unit);
+    }
+  }
+  for (i, mapping) in profile.mapping.iter().enumerate() {
+    check(format!("mapping[{i}].filename"), mapping.filename);
+    check(format!("mapping[{i}].build_id"), mapping.build_id);
+  }
+  for (i, function) in profile.function.iter().enumerate() {
+    check(format!("function[{i}].name"), function.name);
+    check(format!("function[{i}].system_name"), function.system_name);
+    check(format!("function[{i}].filename"), function.filename);
+  }
+  for (i, comment) in profile.comment.iter().enumerate() {
+    check(format!("comment[{i}]"), *comment);
+  }
+  check("drop_frames".to_string(), profile.drop_frames);
+  check("keep_frames".to_string(), profile.keep_frames);
+
+  violations
+}
+
+#[test]
+fn verify_round_trip() {
+  use crate::pprof::{Label, Sample, StringTable, ValueType};
+  use flate2::write::GzEncoder;
+  use flate2::Compression;
+  use std::io::Write;
+
+  let mut str_tbl = StringTable::new();
+  let sample_type = vec![ValueType {
+    r#type: str_tbl.id("cycle"),
+    unit: str_tbl.id("number"),
+  }];
+  let sample = vec![Sample {
+    location_id: vec![],
+    value: vec![1],
+    label: vec![Label {
+      key: str_tbl.id("valid"),
+      str: str_tbl.id("1"),
+      num: 0,
+      num_unit: 0,
+    }],
+  }];
+  let mut p = Profile {
+    sample_type,
+    sample,
+    string_table: str_tbl.into_string_table(),
+    ..Default::default()
+  };
+
+  let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+  encoder.write_all(&p.encode_to_vec()).unwrap();
+  let decoded = decode(&encoder.finish().unwrap()).unwrap();
+  assert_eq!(decoded, p);
+  let zstd = zstd::encode_all(&p.encode_to_vec()[..], 0).unwrap();
+  assert_eq!(decode(&zstd).unwrap(), p);
+  assert!(check_string_table(&decoded).is_empty());
+
+  // an index pointing past the string table
+  p.sample[0].label[0].str = 42;
+  let violations = check_string_table(&p);
+  assert_eq!(violations.len(), 1);
+  assert!(violations[0].starts_with("sample[0].label[0].str"));
+}
@@ -0,0 +1,591 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use fst_native::*;
+
+/// The header fields of a waveform that the converter cares about.
+#[derive(Debug, Clone, Default)]
+pub struct WaveHeader {
+  pub start_time: u64,
+  pub end_time: u64,
+  pub version: String,
+  pub date: String,
+  /// Exponent of the length of one time tick in seconds, like -9 for 1ns or -11 for 10ps. Ticks
+  /// are taken as nanoseconds when the waveform doesn't tell
+  pub timescale: Option<i8>,
+}
+
+impl WaveHeader {
+  /// Return the length of `ticks` time ticks in nanoseconds, rounded down.
+  pub fn ticks_to_nanos(&self, ticks: u64) -> u64 {
+    let exponent = i32::from(self.timescale.unwrap_or(-9)) + 9;
+    match 10u64.checked_pow(exponent.unsigned_abs()) {
+      Some(factor) if exponent >= 0 => ticks.saturating_mul(factor),
+      Some(factor) => ticks / factor,
+      None if exponent >= 0 && ticks > 0 => u64::MAX,
+      None => 0,
+    }
+  }
+
+  /// Return the length of one time tick, like `ns` or `10ps`.
+  pub fn tick_unit(&self) -> String {
+    let exponent = i32::from(self.timescale.unwrap_or(-9));
+    let units = [
+      (0, "s"),
+      (-3, "ms"),
+      (-6, "us"),
+      (-9, "ns"),
+      (-12, "ps"),
+      (-15, "fs"),
+    ];
+    match units
+      .iter()
+      .find(|(unit, _)| (0..3).contains(&(exponent - unit)))
+    {
+      Some((unit, name)) if exponent == *unit => name.to_string(),
+      Some((unit, name)) => format!("{}{name}", 10u64.pow((exponent - unit) as u32)),
+      None => format!("1e{exponent}s"),
+    }
+  }
+}
+
+/// Parse a VCD or FST timescale like `1ns`, `10 ps` or `100us` into its exponent in seconds.
+pub fn parse_timescale(text: &str) -> Option<i8> {
+  let text: String = text.split_whitespace().collect();
+  let digits = text.find(|c: char| !c.is_ascii_digit())?;
+  let magnitude = match &text[..digits] {
+    "1" => 0,
+    "10" => 1,
+    "100" => 2,
+    _ => return None,
+  };
+  let unit = match &text[digits..] {
+    "s" => 0,
+    "ms" => -3,
+    "us" => -6,
+    "ns" => -9,
+    "ps" => -12,
+    "fs" => -15,
+    _ => return None,
+  };
+  Some(unit + magnitude)
+}
+
+/// One entry of the design hierarchy, emitted in declaration order.
+#[derive(Debug, Clone)]
+pub enum HierarchyEntry {
+  Scope {
+    name: String,
+  },
+  UpScope,
+  Var {
+    name: String,
+    /// Bit width of the signal
+    length: u32,
+    handle: FstSignalHandle,
+  },
+  /// An enum table naming the values of the vars referring to it by `handle`. The mapping is
+  /// given as `(value, name)` pairs.
+  EnumTable {
+    handle: u64,
+    mapping: Vec<(String, String)>,
+  },
+  /// The next var holds values of the enum table `handle`.
+  EnumTableRef {
+    handle: u64,
+  },
+}
+
+/// The names of the values of an enum signal.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnumTable {
+  /// Names by value, without the leading zeros of the value
+  names: HashMap<String, String>,
+}
+
+impl EnumTable {
+  pub fn new(mapping: Vec<(String, String)>) -> Self {
+    let names = mapping
+      .into_iter()
+      .map(|(value, name)| (trim_zeros(&value).to_string(), name))
+      .collect();
+    Self { names }
+  }
+
+  /// Return the name of `value`. Tables don't always spell values with the width of the signal,
+  /// so leading zeros are ignored.
+  pub fn name(&self, value: &str) -> Option<&str> {
+    self.names.get(trim_zeros(value)).map(String::as_str)
+  }
+}
+
+fn trim_zeros(value: &str) -> &str {
+  match value.trim_start_matches('0') {
+    "" if !value.is_empty() => "0",
+    trimmed => trimmed,
+  }
+}
+
+/// The value of a signal after a change.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum SignalValue {
+  String(String),
+  Real(f64),
+}
+
+/// A source of hierarchy and value change data.
+///
+/// The converter only talks to the waveform through this trait, so the same extraction code can
+/// run against a real FST file or against a synthetic waveform built in tests.
+pub trait Waveform {
+  /// Return the header of the waveform.
+  fn header(&self) -> WaveHeader;
+
+  /// Walk the whole hierarchy and call `callback` for every entry we understand.
+  fn for_each_hierarchy(&mut self, callback: impl FnMut(HierarchyEntry)) -> anyhow::Result<()>;
+
+  /// Call `callback` for every value change of the given signals, in file order.
+  fn for_each_change(
+    &mut self,
+    handles: &[FstSignalHandle],
+    callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()>;
+
+  /// Like [`Waveform::for_each_change`], when only the changes at the times of `range` are
+  /// needed. Changes outside of it may still be given: only the backends able to skip whole
+  /// blocks of them do.
+  fn for_each_change_in(
+    &mut self,
+    handles: &[FstSignalHandle],
+    range: Range<u64>,
+    callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    let _ = range;
+    self.for_each_change(handles, callback)
+  }
+}
+
+impl<W: Waveform + ?Sized> Waveform for &mut W {
+  fn header(&self) -> WaveHeader {
+    (**self).header()
+  }
+
+  fn for_each_hierarchy(&mut self, callback: impl FnMut(HierarchyEntry)) -> anyhow::Result<()> {
+    (**self).for_each_hierarchy(callback)
+  }
+
+  fn for_each_change(
+    &mut self,
+    handles: &[FstSignalHandle],
+    callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    (**self).for_each_change(handles, callback)
+  }
+
+  fn for_each_change_in(
+    &mut self,
+    handles: &[FstSignalHandle],
+    range: Range<u64>,
+    callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    (**self).for_each_change_in(handles, range, callback)
+  }
+}
+
+impl<R: std::io::BufRead + std::io::Seek> Waveform for FstReader<R> {
+  fn header(&self) -> WaveHeader {
+    let header = self.get_header();
+    WaveHeader {
+      start_time: header.start_time,
+      end_time: header.end_time,
+      version: header.version.clone(),
+      date: header.date.clone(),
+      timescale: Some(header.timescale_exponent),
+    }
+  }
+
+  fn for_each_hierarchy(&mut self, mut callback: impl FnMut(HierarchyEntry)) -> anyhow::Result<()> {
+    self.read_hierarchy(|hier| match hier {
+      FstHierarchyEntry::Scope { name, .. } => callback(HierarchyEntry::Scope {
+        name: name.to_string(),
+      }),
+      FstHierarchyEntry::UpScope => callback(HierarchyEntry::UpScope),
+      FstHierarchyEntry::Var {
+        name,
+        length,
+        handle,
+        ..
+      } => callback(HierarchyEntry::Var {
+        name,
+        length,
+        handle,
+      }),
+      FstHierarchyEntry::EnumTable {
+        handle, mapping, ..
+      } => callback(HierarchyEntry::EnumTable { handle, mapping }),
+      FstHierarchyEntry::EnumTableRef { handle } => {
+        callback(HierarchyEntry::EnumTableRef { handle })
+      }
+      _ => (),
+    })?;
+    Ok(())
+  }
+
+  fn for_each_change(
+    &mut self,
+    handles: &[FstSignalHandle],
+    callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    let filter = FstFilter::filter_signals(handles.to_vec());
+    read_fst_changes(self, &filter, callback)
+  }
+
+  fn for_each_change_in(
+    &mut self,
+    handles: &[FstSignalHandle],
+    range: Range<u64>,
+    callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    // the blocks outside of the range are skipped, the end of the filter is inclusive
+    let end = range.end.saturating_sub(1).min(self.get_header().end_time);
+    let filter = FstFilter::new(range.start, end.max(range.start), handles.to_vec());
+    read_fst_changes(self, &filter, callback)
+  }
+}
+
+/// Read the value changes `filter` selects from an FST file.
+fn read_fst_changes<R: std::io::BufRead + std::io::Seek>(
+  reader: &mut FstReader<R>,
+  filter: &FstFilter,
+  mut callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+) -> anyhow::Result<()> {
+  reader.read_signals(filter, |t, handle, value| {
+    let value = match value {
+      FstSignalValue::String(s) => SignalValue::String(s),
+      FstSignalValue::Real(r) => SignalValue::Real(r),
+    };
+    callback(t, handle, value)
+  })?;
+  Ok(())
+}
+
+/// Return the times the read of the FST file at `path` can be split at, one between every two
+/// blocks of its value changes, so each part only decodes its own blocks. Empty when the blocks
+/// are in a gzip wrapper, which can't be walked without inflating the whole file.
+///
+/// A block gives the values of the signals on its first time when it is the first one read and
+/// has no change then, so a part starts at the first time of a block only when that block starts
+/// with a change and the block before ends earlier. Otherwise it starts at the last time of the
+/// block before, which it decodes again.
+pub fn fst_block_cuts(path: &str) -> anyhow::Result<Vec<u64>> {
+  use std::io::{Read, Seek, SeekFrom};
+
+  const VALUE_CHANGES: [u8; 3] = [1, 5, 8];
+  const GZIP_WRAPPER: u8 = 254;
+  let read_u64 = |file: &mut std::io::BufReader<std::fs::File>| -> std::io::Result<u64> {
+    let mut bytes = [0; 8];
+    file.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+  };
+  let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+  let size = file.get_ref().metadata()?.len();
+  let mut cuts = Vec::new();
+  let mut last_end = None;
+  let mut at = 0;
+  // every block is its kind, its length counting the length itself, and its content
+  while at + 9 <= size {
+    let mut kind = [0];
+    file.read_exact(&mut kind)?;
+    let length = read_u64(&mut file)?;
+    anyhow::ensure!(
+      length >= 8,
+      "malformed FST block of length {length} at {at}"
+    );
+    if kind[0] == GZIP_WRAPPER {
+      return Ok(Vec::new());
+    }
+    let next = at + 1 + length;
+    if VALUE_CHANGES.contains(&kind[0]) {
+      let (start, end) = (read_u64(&mut file)?, read_u64(&mut file)?);
+      // the time chain ends the block, its lengths and number of times last
+      file.seek(SeekFrom::Start(next - 24))?;
+      let (raw_length, length, times) = (
+        read_u64(&mut file)?,
+        read_u64(&mut file)?,
+        read_u64(&mut file)?,
+      );
+      file.seek(SeekFrom::Start(next - 24 - length))?;
+      let mut chain = (&mut file).take(length);
+      // the times are deltas, the first one is then the first time
+      let first = match (times, raw_length == length) {
+        (0, _) => None,
+        (_, true) => Some(read_varint(&mut chain)?),
+        (_, false) => Some(read_varint(&mut flate2::read::ZlibDecoder::new(chain))?),
+      };
+      if let Some(last_end) = last_end {
+        cuts.push(if first == Some(start) && last_end < start {
+          start
+        } else {
+          last_end
+        });
+      }
+      last_end = Some(end);
+    }
+    file.seek(SeekFrom::Start(next))?;
+    at = next;
+  }
+  Ok(cuts)
+}
+
+/// Read an unsigned LEB128 number, as the FST blocks store most of theirs.
+fn read_varint(input: &mut impl std::io::Read) -> std::io::Result<u64> {
+  let mut value = 0;
+  for shift in (0..64).step_by(7) {
+    let mut byte = [0];
+    input.read_exact(&mut byte)?;
+    value |= u64::from(byte[0] & 0x7f) << shift;
+    if byte[0] & 0x80 == 0 {
+      break;
+    }
+  }
+  Ok(value)
+}
+
+/// The file formats a waveform can be read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+  Fst,
+  Vcd,
+}
+
+impl InputFormat {
+  /// Return the format of a waveform file from its extension, FST unless it is `.vcd`.
+  pub fn from_path(path: &str) -> Self {
+    let extension = std::path::Path::new(path)
+      .extension()
+      .and_then(|ext| ext.to_str())
+      .unwrap_or_default();
+    if extension.eq_ignore_ascii_case("vcd") {
+      InputFormat::Vcd
+    } else {
+      InputFormat::Fst
+    }
+  }
+}
+
+/// A waveform file opened with the backend of its format.
+pub enum InputWave {
+  Fst(FstReader<std::io::BufReader<std::fs::File>>),
+  /// An FST file mapped in memory, see [`InputWave::open_mapped`]
+  MappedFst(FstReader<std::io::Cursor<memmap2::Mmap>>),
+  Vcd(crate::vcd::VcdWave),
+}
+
+impl InputWave {
+  pub fn open(path: &str, format: InputFormat) -> anyhow::Result<Self> {
+    Ok(match format {
+      InputFormat::Fst => {
+        let file = std::fs::File::open(path)?;
+        InputWave::Fst(FstReader::open(std::io::BufReader::new(file))?)
+      }
+      InputFormat::Vcd => InputWave::Vcd(crate::vcd::VcdWave::open(path)?),
+    })
+  }
+
+  /// Open an FST file mapped in memory. The reader seeks back and forth between the blocks, which
+  /// pages them in once instead of re-reading them through a buffer on every seek, a large gain
+  /// on network file systems. The file must not be modified at all while it is mapped, not even
+  /// appended to, so a waveform still being written can't be mapped.
+  pub fn open_mapped(path: &str) -> anyhow::Result<Self> {
+    use anyhow::Context;
+
+    let file = std::fs::File::open(path)?;
+    // a pipe or a device may change under the mapping, or not be mappable at all
+    anyhow::ensure!(
+      file.metadata()?.is_file(),
+      "{path} is not a regular file, it can't be mapped"
+    );
+    // SAFETY: the mapping is undefined behavior if the file is modified while it lives. Nothing
+    // here can lock it against other processes: --follow, whose simulation writes the file,
+    // refuses --mmap, and the --mmap help tells the file must not change while it is read.
+    let map =
+      unsafe { memmap2::Mmap::map(&file) }.with_context(|| format!("fail to map {path}"))?;
+    Ok(InputWave::MappedFst(FstReader::open(
+      std::io::Cursor::new(map),
+    )?))
+  }
+}
+
+/// A copy of the standard input in a temporary file, removed when dropped. The FST reader seeks
+/// around its file, which a pipe can't do, so a waveform streamed by `--fst -` lands on disk
+/// first. The file is named `stdin` to name the outputs after it, in a directory of a random name
+/// only this user can open, so no one else can guess or swap it.
+pub struct Spool {
+  _dir: tempfile::TempDir,
+  path: String,
+}
+
+impl Spool {
+  pub fn stdin() -> anyhow::Result<Self> {
+    use anyhow::Context;
+
+    // removed when dropped, so a failed copy is still cleaned up
+    let dir = tempfile::Builder::new()
+      .prefix("toyfst-stdin-")
+      .tempdir()
+      .context("fail to create the temporary directory of stdin")?;
+    let path = dir.path().join("stdin");
+    let path = path
+      .to_str()
+      .context("non UTF-8 temporary directory")?
+      .to_string();
+    let mut file = std::fs::OpenOptions::new()
+      .write(true)
+      .create_new(true)
+      .open(&path)
+      .with_context(|| format!("fail to create {path}"))?;
+    std::io::copy(&mut std::io::stdin().lock(), &mut file).context("fail to read stdin")?;
+    Ok(Self { _dir: dir, path })
+  }
+
+  pub fn path(&self) -> &str {
+    &self.path
+  }
+}
+
+impl Waveform for InputWave {
+  fn header(&self) -> WaveHeader {
+    match self {
+      InputWave::Fst(wave) => wave.header(),
+      InputWave::MappedFst(wave) => wave.header(),
+      InputWave::Vcd(wave) => wave.header(),
+    }
+  }
+
+  fn for_each_hierarchy(&mut self, callback: impl FnMut(HierarchyEntry)) -> anyhow::Result<()> {
+    match self {
+      InputWave::Fst(wave) => wave.for_each_hierarchy(callback),
+      InputWave::MappedFst(wave) => wave.for_each_hierarchy(callback),
+      InputWave::Vcd(wave) => wave.for_each_hierarchy(callback),
+    }
+  }
+
+  fn for_each_change(
+    &mut self,
+    handles: &[FstSignalHandle],
+    callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    match self {
+      InputWave::Fst(wave) => wave.for_each_change(handles, callback),
+      InputWave::MappedFst(wave) => wave.for_each_change(handles, callback),
+      InputWave::Vcd(wave) => wave.for_each_change(handles, callback),
+    }
+  }
+
+  fn for_each_change_in(
+    &mut self,
+    handles: &[FstSignalHandle],
+    range: Range<u64>,
+    callback: impl FnMut(u64, FstSignalHandle, SignalValue),
+  ) -> anyhow::Result<()> {
+    match self {
+      InputWave::Fst(wave) => wave.for_each_change_in(handles, range, callback),
+      InputWave::MappedFst(wave) => wave.for_each_change_in(handles, range, callback),
+      InputWave::Vcd(wave) => wave.for_each_change_in(handles, range, callback),
+    }
+  }
+}
+
+#[test]
+fn input_format_from_extension() {
+  assert_eq!(InputFormat::from_path("sim/top.vcd"), InputFormat::Vcd);
+  assert_eq!(InputFormat::from_path("top.VCD"), InputFormat::Vcd);
+  assert_eq!(InputFormat::from_path("top.fst"), InputFormat::Fst);
+  assert_eq!(InputFormat::from_path("top"), InputFormat::Fst);
+}
+
+#[test]
+fn convert_ticks_by_timescale() {
+  let header = |timescale| WaveHeader {
+    timescale,
+    ..Default::default()
+  };
+  assert_eq!(header(None).ticks_to_nanos(1500), 1500);
+  assert_eq!(header(Some(-12)).ticks_to_nanos(1500), 1);
+  assert_eq!(header(Some(-6)).ticks_to_nanos(3), 3000);
+  assert_eq!(header(Some(-11)).tick_unit(), "10ps");
+  assert_eq!(header(Some(-9)).tick_unit(), "ns");
+  assert_eq!(header(Some(2)).tick_unit(), "100s");
+  assert_eq!(header(Some(-21)).tick_unit(), "1e-21s");
+
+  assert_eq!(parse_timescale("1ns"), Some(-9));
+  assert_eq!(parse_timescale("10 ps"), Some(-11));
+  assert_eq!(parse_timescale("100us"), Some(-4));
+  assert_eq!(parse_timescale("3ns"), None);
+  assert_eq!(parse_timescale("1 parsec"), None);
+}
+
+#[test]
+fn name_enum_values() {
+  let table = EnumTable::new(vec![
+    ("00".to_string(), "IDLE".to_string()),
+    ("01".to_string(), "FETCH".to_string()),
+    ("10".to_string(), "EXEC".to_string()),
+  ]);
+  assert_eq!(table.name("00"), Some("IDLE"));
+  assert_eq!(table.name("0"), Some("IDLE"));
+  assert_eq!(table.name("001"), Some("FETCH"));
+  assert_eq!(table.name("10"), Some("EXEC"));
+  assert_eq!(table.name("11"), None);
+}
+
+#[test]
+fn cut_fst_at_its_blocks() {
+  let path = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/wave.fst");
+  // the whole simulation was flushed in a single block
+  assert_eq!(fst_block_cuts(path).unwrap(), Vec::<u64>::new());
+}
+
+#[test]
+fn read_fst_buffered_and_mapped() {
+  let path = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/wave.fst");
+  for mut wave in [
+    InputWave::open(path, InputFormat::Fst).unwrap(),
+    InputWave::open_mapped(path).unwrap(),
+  ] {
+    let header = wave.header();
+    assert_eq!((header.start_time, header.end_time), (0, 726));
+    assert_eq!(header.timescale, Some(-12));
+
+    let (mut scopes, mut vars) = (0, Vec::new());
+    wave
+      .for_each_hierarchy(|entry| match entry {
+        HierarchyEntry::Scope { .. } => scopes += 1,
+        HierarchyEntry::Var { name, handle, .. } => vars.push((name, handle)),
+        _ => {}
+      })
+      .unwrap();
+    assert_eq!((scopes, vars.len()), (10863, 314319));
+    assert_eq!(vars[0].0, "clock");
+
+    // the aliases of a signal share its handle
+    let mut handles: Vec<_> = vars.iter().map(|(_, handle)| *handle).collect();
+    handles.sort_by_key(|handle| handle.get_index());
+    handles.dedup();
+    assert_eq!(handles.len(), 205314);
+    let mut changes = HashMap::<FstSignalHandle, usize>::new();
+    wave
+      .for_each_change(&handles, |_, handle, _| {
+        *changes.entry(handle).or_default() += 1
+      })
+      .unwrap();
+    assert_eq!(changes.values().sum::<usize>(), 273312);
+    let count = |name: &str| {
+      let (_, handle) = vars.iter().find(|(n, _)| n == name).unwrap();
+      changes[handle]
+    };
+    assert_eq!(count("clock"), 146);
+    assert_eq!(count("requestRegDequeue_valid"), 3);
+    assert_eq!(count("requestRegDequeue_ready"), 11);
+  }
+}
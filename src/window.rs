@@ -0,0 +1,114 @@
+use clap::ValueEnum;
+
+/// Whether changes landing exactly on the bounds of a time window are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Boundary {
+  /// Keep changes at the start, drop changes at the end: `[start, end)`
+  #[default]
+  HalfOpen,
+  /// Keep changes at both bounds: `[start, end]`
+  Inclusive,
+  /// Drop changes at both bounds: `(start, end)`
+  Exclusive,
+}
+
+/// A time window selecting which value changes are emitted. A missing bound is unbounded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeWindow {
+  pub start: Option<u64>,
+  pub end: Option<u64>,
+  pub boundary: Boundary,
+}
+
+impl TimeWindow {
+  /// Return true if a change happening at time `t` is inside the window.
+  pub fn contains(&self, t: u64) -> bool {
+    let after_start = match self.start {
+      None => true,
+      Some(start) if self.boundary == Boundary::Exclusive => t > start,
+      Some(start) => t >= start,
+    };
+    let before_end = match self.end {
+      None => true,
+      Some(end) if self.boundary == Boundary::Inclusive => t <= end,
+      Some(end) => t < end,
+    };
+    after_start && before_end
+  }
+
+  /// Check the bounds are ordered and inside the `start..=end` time range of the waveform.
+  pub fn validate(&self, start: u64, end: u64) -> anyhow::Result<()> {
+    for bound in [self.start, self.end].into_iter().flatten() {
+      anyhow::ensure!(
+        (start..=end).contains(&bound),
+        "time {bound} is outside of the waveform time range {start}..={end}"
+      );
+    }
+    if let (Some(window_start), Some(window_end)) = (self.start, self.end) {
+      anyhow::ensure!(
+        window_start <= window_end,
+        "window start {window_start} is after window end {window_end}"
+      );
+    }
+    Ok(())
+  }
+
+  /// Return the window bounds, within the `start..=end` time range of the waveform.
+  pub fn clamp(&self, start: u64, end: u64) -> (u64, u64) {
+    (
+      self.start.map_or(start, |s| s.clamp(start, end)),
+      self.end.map_or(end, |e| e.clamp(start, end)),
+    )
+  }
+}
+
+#[test]
+fn changes_on_window_bounds() {
+  let window = |boundary| TimeWindow {
+    start: Some(10),
+    end: Some(20),
+    boundary,
+  };
+
+  let half_open = window(Boundary::HalfOpen);
+  assert!(half_open.contains(10));
+  assert!(half_open.contains(19));
+  assert!(!half_open.contains(20));
+  assert!(!half_open.contains(9));
+
+  let inclusive = window(Boundary::Inclusive);
+  assert!(inclusive.contains(10));
+  assert!(inclusive.contains(20));
+  assert!(!inclusive.contains(21));
+
+  let exclusive = window(Boundary::Exclusive);
+  assert!(!exclusive.contains(10));
+  assert!(exclusive.contains(11));
+  assert!(!exclusive.contains(20));
+
+  assert!(TimeWindow::default().contains(0));
+  assert!(TimeWindow::default().contains(u64::MAX));
+}
+
+#[test]
+fn validate_and_clamp_window() {
+  let window = TimeWindow {
+    start: Some(10),
+    end: Some(20),
+    ..Default::default()
+  };
+  assert!(window.validate(0, 100).is_ok());
+  assert!(window.validate(15, 100).is_err());
+  assert!(window.validate(0, 15).is_err());
+  assert_eq!(window.clamp(0, 100), (10, 20));
+
+  let reversed = TimeWindow {
+    start: Some(20),
+    end: Some(10),
+    ..Default::default()
+  };
+  assert!(reversed.validate(0, 100).is_err());
+
+  assert!(TimeWindow::default().validate(5, 10).is_ok());
+  assert_eq!(TimeWindow::default().clamp(5, 10), (5, 10));
+}
@@ -0,0 +1,145 @@
+//! Handling of the unknown `x` and high impedance `z` bits of four-state values.
+
+use std::collections::BTreeMap;
+
+use fst_native::FstSignalHandle;
+use serde::{Deserialize, Serialize};
+
+use crate::SignalLookup;
+
+/// What a value change with `x` or `z` bits turns into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum XzPolicy {
+  /// Read the `x` and `z` bits as `0`
+  TreatAsZero,
+  /// Drop the change, the signal keeps its previous value
+  TreatAsPrevious,
+  /// Make every bit `x`, so all the unknown values of a signal share one label value
+  SeparateBucket,
+  /// Fail the conversion
+  Error,
+}
+
+/// The policy of the values with `x` or `z` bits, like
+/// `{ policy = "treat-as-zero", overrides = { "top.bus.data" = "error" } }`. Overrides are keyed by
+/// full dot separated path or by bare name, a full path winning over a bare name.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct XzConfig {
+  pub policy: XzPolicy,
+  #[serde(default)]
+  pub overrides: BTreeMap<String, XzPolicy>,
+}
+
+/// The policies with the overridden signals resolved to handles.
+#[derive(Debug, Clone)]
+pub struct XzHandling {
+  policy: XzPolicy,
+  overrides: BTreeMap<usize, XzPolicy>,
+}
+
+/// Find the overridden signals in the hierarchy.
+pub fn resolve(
+  signals: &SignalLookup,
+  config: Option<&XzConfig>,
+) -> anyhow::Result<Option<XzHandling>> {
+  let Some(config) = config else {
+    return Ok(None);
+  };
+
+  let mut overrides = BTreeMap::new();
+  // bare names first, so the full paths overwrite them
+  let mut keys: Vec<_> = config.overrides.iter().collect();
+  keys.sort_by_key(|(path, _)| path.contains('.'));
+  for (path, policy) in keys {
+    let found = signals.find_all(path);
+    anyhow::ensure!(!found.is_empty(), "x/z override signal {path} is not found");
+    for (handle, _) in found {
+      overrides.insert(handle.get_index(), *policy);
+    }
+  }
+  Ok(Some(XzHandling {
+    policy: config.policy,
+    overrides,
+  }))
+}
+
+/// Return true if the value has an `x` or `z` bit.
+pub fn is_unknown(value: &str) -> bool {
+  value
+    .bytes()
+    .any(|b| matches!(b, b'x' | b'X' | b'z' | b'Z'))
+}
+
+impl XzHandling {
+  /// Return the policy of the signal.
+  pub fn policy(&self, handle: FstSignalHandle) -> XzPolicy {
+    self
+      .overrides
+      .get(&handle.get_index())
+      .copied()
+      .unwrap_or(self.policy)
+  }
+
+  /// Apply the policy of the signal to a value with unknown bits. Return the value it is read as,
+  /// or `None` if the change is dropped.
+  pub fn apply(&self, handle: FstSignalHandle, value: &str) -> anyhow::Result<Option<String>> {
+    Ok(match self.policy(handle) {
+      XzPolicy::TreatAsZero => Some(
+        value
+          .chars()
+          .map(|c| if c == '1' { '1' } else { '0' })
+          .collect(),
+      ),
+      XzPolicy::TreatAsPrevious => None,
+      XzPolicy::SeparateBucket => Some("x".repeat(value.len())),
+      XzPolicy::Error => anyhow::bail!("unknown value {value} under the error x/z policy"),
+    })
+  }
+}
+
+#[test]
+fn apply_xz_policies() {
+  use crate::fixture::FixtureWave;
+
+  let mut wave = FixtureWave::new(0, 10);
+  wave.scope("top");
+  let valid = wave.var("valid", 1);
+  wave.scope("bus");
+  let data = wave.var("data", 4);
+  let bus_valid = wave.var("valid", 1);
+  wave.upscope();
+  wave.upscope();
+
+  let config: XzConfig = toml::from_str(
+    r#"
+      policy = "treat-as-zero"
+      [overrides]
+      "top.bus.data" = "separate-bucket"
+      valid = "treat-as-previous"
+      "top.valid" = "error"
+    "#,
+  )
+  .unwrap();
+  let xz = resolve(&SignalLookup::new(&mut wave).unwrap(), Some(&config))
+    .unwrap()
+    .unwrap();
+  assert_eq!(xz.policy(bus_valid), XzPolicy::TreatAsPrevious);
+  assert_eq!(xz.policy(valid), XzPolicy::Error);
+
+  assert!(is_unknown("01z1"));
+  assert!(!is_unknown("0101"));
+  assert_eq!(xz.apply(data, "01z1").unwrap().as_deref(), Some("xxxx"));
+  assert_eq!(xz.apply(bus_valid, "x").unwrap(), None);
+  assert!(xz.apply(valid, "x").is_err());
+
+  let config = XzConfig {
+    overrides: BTreeMap::new(),
+    ..config
+  };
+  let xz = resolve(&SignalLookup::new(&mut wave).unwrap(), Some(&config))
+    .unwrap()
+    .unwrap();
+  assert_eq!(xz.apply(data, "x1z0").unwrap().as_deref(), Some("0100"));
+}